@@ -0,0 +1,22 @@
+//! Replays captures recorded by the library.
+//!
+//! Usage: `d3d9-replay <capture> [--stop-at <call>] [--dump <directory>] [--mock]`
+//!
+//! The capture is replayed through the library's own implementation of D3D9,
+//! on the desktop window. Use `--dump` to look at the rendered frames.
+//! With `--mock`, the devices run on the mock backend, which does not need a GPU.
+
+#[cfg(windows)]
+mod replay;
+
+#[cfg(windows)]
+fn main() {
+    replay::main();
+}
+
+/// The library is built on top of D3D11 and Win32, which only exist on Windows.
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("d3d9-replay only runs on Windows, or under Wine");
+    std::process::exit(1);
+}
@@ -0,0 +1,95 @@
+//! Replays a capture on the desktop window, or on the mock backend.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+
+use winapi::um::winuser::GetDesktopWindow;
+
+use d3d9::backend::mock::MockBackend;
+use d3d9::trace::{D3D9Target, Options, Reader, Replayer};
+
+fn usage() -> ! {
+    eprintln!("usage: d3d9-replay <capture> [--stop-at <call>] [--dump <directory>] [--mock]");
+    process::exit(2);
+}
+
+fn fail(msg: String) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1);
+}
+
+/// Parses the command line, returning the capture's path, the options,
+/// and whether to replay on the mock backend.
+fn parse_args() -> (PathBuf, Options, bool) {
+    let mut args = env::args().skip(1);
+
+    let mut path = None;
+    let mut options = Options::default();
+    let mut mock = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stop-at" => {
+                let call = args.next().and_then(|call| call.parse().ok());
+                options.stop_at = Some(call.unwrap_or_else(|| usage()));
+            }
+            "--dump" => {
+                let dir = args.next().map(PathBuf::from);
+                options.dump_dir = Some(dir.unwrap_or_else(|| usage()));
+            }
+            "--mock" => mock = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+
+    (path.unwrap_or_else(|| usage()), options, mock)
+}
+
+pub fn main() {
+    let (path, options, mock) = parse_args();
+
+    let file = File::open(&path)
+        .unwrap_or_else(|err| fail(format!("Failed to open {}: {}", path.display(), err)));
+
+    let mut reader = Reader::new(BufReader::new(file))
+        .unwrap_or_else(|err| fail(format!("Failed to read {}: {}", path.display(), err)));
+
+    if let Some(ref dir) = options.dump_dir {
+        fs::create_dir_all(dir)
+            .unwrap_or_else(|err| fail(format!("Failed to create {}: {}", dir.display(), err)));
+    }
+
+    let window = unsafe { GetDesktopWindow() };
+
+    let target = if mock {
+        D3D9Target::with_backend(window, Rc::new(MockBackend::new()))
+    } else {
+        D3D9Target::new(window)
+    };
+
+    let mut replayer = Replayer::new(target, options);
+
+    // A capture can end abruptly if the app crashed, so report what was replayed anyway.
+    if let Err(err) = replayer.replay(&mut reader) {
+        eprintln!("Failed to read call from capture: {}", err);
+    }
+
+    let stats = replayer.stats();
+
+    println!(
+        "Replayed {} calls and {} frames, skipped {} calls",
+        stats.calls, stats.frames, stats.skipped
+    );
+
+    if stats.mismatches != 0 {
+        println!(
+            "{} calls returned a different result than when they were recorded",
+            stats.mismatches
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Descriptions of the D3D11 resources which store D3D9's resources.
+//!
+//! These only depend on the D3D9 parameters, so they are shared by all backends.
+
+use winapi::shared::{d3d9types::*, dxgiformat::*};
+use winapi::um::d3d11::*;
+
+use crate::core::{
+    fmt::{d3d_format_to_dxgi, format_info},
+    msample::d3d9_to_dxgi_samples,
+    *,
+};
+use crate::Result;
+
+/// Converts D3D9's buffer/texture usage and pool flags to corresponding D3D11 flags.
+///
+/// Returns an error if a certain combination is invalid.
+pub fn d3d_usage_to_d3d11(
+    uflags: UsageFlags,
+    pool: MemoryPool,
+) -> Result<(D3D11_USAGE, D3D11_BIND_FLAG, D3D11_CPU_ACCESS_FLAG)> {
+    let mut usage = D3D11_USAGE_DEFAULT;
+    let mut cpu_flags = 0;
+
+    let write_to = UsageFlags::DYNAMIC | UsageFlags::WRITE_ONLY;
+
+    match pool {
+        MemoryPool::Default => {
+            if uflags.intersects(write_to) {
+                usage = D3D11_USAGE_DYNAMIC;
+                cpu_flags = D3D11_CPU_ACCESS_WRITE;
+            }
+        }
+        MemoryPool::Managed => {
+            usage = D3D11_USAGE_DYNAMIC;
+            cpu_flags = D3D11_CPU_ACCESS_WRITE;
+        }
+        MemoryPool::SystemMem => {
+            if uflags.intersects(write_to) {
+                usage = D3D11_USAGE_DYNAMIC;
+                cpu_flags = D3D11_CPU_ACCESS_WRITE;
+            } else {
+                usage = D3D11_USAGE_STAGING;
+                cpu_flags = D3D11_CPU_ACCESS_WRITE | D3D11_CPU_ACCESS_READ;
+            }
+        }
+        _ => error!("Unsupported memory pool: {:?}", pool),
+    }
+
+    let bind_flags = if usage != D3D11_USAGE_STAGING {
+        // Even if the app doesn't end up using this in a shader,
+        // this is the only bind flag we could choose for it.
+        D3D11_BIND_SHADER_RESOURCE
+    } else {
+        0
+    };
+
+    Ok((usage, bind_flags, cpu_flags))
+}
+
+/// Determines the DXGI format with which a texture's storage should be created.
+///
+/// Formats which have an sRGB variant are created as typeless if the texture
+/// can be bound to the pipeline, so that both linear and sRGB views can be created.
+fn storage_format(fmt: D3DFORMAT, bindable: bool) -> Result<DXGI_FORMAT> {
+    let dxgi = d3d_format_to_dxgi(fmt)?;
+
+    let typeless = format_info(fmt)
+        .ok()
+        .map(|info| info.typeless)
+        .filter(|&typeless| bindable && typeless != DXGI_FORMAT_UNKNOWN);
+
+    Ok(typeless.unwrap_or(dxgi))
+}
+
+/// Returns the misc flags required for a texture which can be shared with other devices.
+fn shared_flags(shared: bool) -> u32 {
+    if shared {
+        D3D11_RESOURCE_MISC_SHARED
+    } else {
+        0
+    }
+}
+
+/// Describes a texture, or the plain surfaces which are stored like one.
+pub fn texture(
+    (width, height): (u32, u32),
+    levels: u32,
+    uflags: UsageFlags,
+    fmt: D3DFORMAT,
+    pool: MemoryPool,
+    shared: bool,
+) -> Result<D3D11_TEXTURE2D_DESC> {
+    let (usage, bind_flags, cpu_flags) = d3d_usage_to_d3d11(uflags, pool)?;
+
+    Ok(D3D11_TEXTURE2D_DESC {
+        Width: width,
+        Height: height,
+        MipLevels: levels,
+        ArraySize: 1,
+        Format: storage_format(fmt, usage != D3D11_USAGE_STAGING)?,
+        SampleDesc: d3d9_to_dxgi_samples(0, 0),
+        Usage: usage,
+        BindFlags: bind_flags,
+        CPUAccessFlags: cpu_flags,
+        MiscFlags: shared_flags(shared),
+    })
+}
+
+/// Describes a cube texture, which is stored as an array of 6 faces.
+pub fn cube_texture(
+    dimension: u32,
+    levels: u32,
+    uflags: UsageFlags,
+    fmt: D3DFORMAT,
+    pool: MemoryPool,
+    shared: bool,
+) -> Result<D3D11_TEXTURE2D_DESC> {
+    let mut desc = texture((dimension, dimension), levels, uflags, fmt, pool, shared)?;
+    desc.ArraySize = 6;
+
+    // Cube maps have to be explicitly marked in order to be sampled as cubes.
+    if desc.BindFlags & D3D11_BIND_SHADER_RESOURCE != 0 {
+        desc.MiscFlags |= D3D11_RESOURCE_MISC_TEXTURECUBE;
+    }
+
+    Ok(desc)
+}
+
+/// Describes a render target surface.
+pub fn render_target(
+    (width, height): (u32, u32),
+    fmt: D3DFORMAT,
+    ms_ty: D3DMULTISAMPLE_TYPE,
+    ms_qlt: u32,
+    shared: bool,
+) -> Result<D3D11_TEXTURE2D_DESC> {
+    Ok(D3D11_TEXTURE2D_DESC {
+        Width: width,
+        Height: height,
+        MipLevels: 1,
+        ArraySize: 1,
+        Format: storage_format(fmt, true)?,
+        SampleDesc: d3d9_to_dxgi_samples(ms_ty, ms_qlt),
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: D3D11_BIND_RENDER_TARGET,
+        CPUAccessFlags: 0,
+        MiscFlags: shared_flags(shared),
+    })
+}
+
+/// Describes a depth / stencil surface.
+pub fn depth_stencil(
+    (width, height): (u32, u32),
+    fmt: D3DFORMAT,
+    ms_ty: D3DMULTISAMPLE_TYPE,
+    ms_qlt: u32,
+    shared: bool,
+) -> Result<D3D11_TEXTURE2D_DESC> {
+    Ok(D3D11_TEXTURE2D_DESC {
+        Width: width,
+        Height: height,
+        MipLevels: 1,
+        ArraySize: 1,
+        Format: d3d_format_to_dxgi(fmt)?,
+        SampleDesc: d3d9_to_dxgi_samples(ms_ty, ms_qlt),
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: D3D11_BIND_DEPTH_STENCIL,
+        CPUAccessFlags: 0,
+        MiscFlags: shared_flags(shared),
+    })
+}
+
+/// Describes a vertex or index buffer.
+pub fn buffer(
+    len: u32,
+    uflags: UsageFlags,
+    pool: MemoryPool,
+    bind_flags: u32,
+) -> Result<D3D11_BUFFER_DESC> {
+    let (usage, _, cpu_flags) = d3d_usage_to_d3d11(uflags, pool)?;
+
+    Ok(D3D11_BUFFER_DESC {
+        ByteWidth: len,
+        Usage: usage,
+        BindFlags: bind_flags,
+        CPUAccessFlags: cpu_flags,
+        MiscFlags: 0,
+        StructureByteStride: 0,
+    })
+}
+
+/// Returns the format linear views of a texture storing a D3D9 format should use.
+///
+/// This is the texture's own format, unless it was created as typeless.
+/// Note that back buffers use the format of the display, which could be
+/// different from the one D3D9 stores their format's data in.
+pub fn linear_view_format(desc: &D3D11_TEXTURE2D_DESC, fmt: D3DFORMAT) -> DXGI_FORMAT {
+    match format_info(fmt) {
+        Ok(info) if info.typeless != DXGI_FORMAT_UNKNOWN && desc.Format == info.typeless => {
+            info.dxgi
+        }
+        _ => desc.Format,
+    }
+}
+
+/// Returns the format sRGB views of a texture should use,
+/// or `None` if the texture cannot be viewed as sRGB.
+pub fn srgb_view_format(desc: &D3D11_TEXTURE2D_DESC, fmt: D3DFORMAT) -> Option<DXGI_FORMAT> {
+    let info = format_info(fmt).ok()?;
+
+    // Only typeless textures can be reinterpreted.
+    if info.typeless != DXGI_FORMAT_UNKNOWN && desc.Format == info.typeless {
+        Some(info.srgb)
+    } else {
+        None
+    }
+}
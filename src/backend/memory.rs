@@ -0,0 +1,592 @@
+//! Resources stored in system memory, for the backends which do not use a GPU.
+
+use std::{
+    cell::{Cell, RefCell},
+    cmp, ptr,
+    rc::Rc,
+};
+
+use winapi::shared::{dxgi::*, dxgiformat::*};
+use winapi::um::d3d11::*;
+
+use crate::core::fmt::{dxgi_format_info, FormatInfo};
+use crate::{Error, Result};
+
+use super::Texture;
+
+/// Hands out unique IDs to the objects created by a backend.
+#[derive(Default)]
+pub struct Ids(Cell<u32>);
+
+impl Ids {
+    /// Returns a new ID, which was never returned before.
+    pub fn next(&self) -> u32 {
+        let id = self.0.get() + 1;
+        self.0.set(id);
+        id
+    }
+}
+
+/// A single mip level of a texture.
+struct Subresource {
+    width: u32,
+    height: u32,
+    row_pitch: u32,
+    data: RefCell<Vec<u8>>,
+}
+
+struct TextureData {
+    id: u32,
+    desc: D3D11_TEXTURE2D_DESC,
+    info: &'static FormatInfo,
+    subresources: Vec<Subresource>,
+}
+
+/// Texture stored in system memory.
+///
+/// Multisampled textures only store a single sample for each pixel.
+#[derive(Clone)]
+pub struct MemoryTexture(Rc<TextureData>);
+
+/// Computes the number of mip levels in a full mip chain.
+fn full_mip_chain(width: u32, height: u32) -> u32 {
+    32 - cmp::max(width, height).leading_zeros()
+}
+
+impl MemoryTexture {
+    /// Creates a new texture, filled with zeroes.
+    pub fn new(id: u32, desc: &D3D11_TEXTURE2D_DESC) -> Result<Self> {
+        let info = dxgi_format_info(desc.Format).map_err(|_| Error::InvalidCall)?;
+
+        if desc.Width == 0 || desc.Height == 0 || desc.ArraySize == 0 {
+            return Err(Error::InvalidCall);
+        }
+
+        let levels = match desc.MipLevels {
+            0 => full_mip_chain(desc.Width, desc.Height),
+            levels => levels,
+        };
+
+        let desc = D3D11_TEXTURE2D_DESC {
+            MipLevels: levels,
+            ..*desc
+        };
+
+        // Subresources are ordered by array slice, then by mip level.
+        let subresources = (0..desc.ArraySize)
+            .flat_map(|_| 0..levels)
+            .map(|level| {
+                let width = cmp::max(desc.Width >> level, 1);
+                let height = cmp::max(desc.Height >> level, 1);
+                let row_pitch = info.row_pitch(width);
+                let size = info.image_size(width, height) as usize;
+
+                Subresource {
+                    width,
+                    height,
+                    row_pitch,
+                    data: RefCell::new(vec![0; size]),
+                }
+            })
+            .collect();
+
+        Ok(MemoryTexture(Rc::new(TextureData {
+            id,
+            desc,
+            info,
+            subresources,
+        })))
+    }
+
+    /// Returns the unique ID of this texture.
+    pub fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    /// Retrieves the description of this texture.
+    pub fn desc(&self) -> D3D11_TEXTURE2D_DESC {
+        self.0.desc
+    }
+
+    /// Retrieves the width and height of a subresource.
+    pub fn size(&self, subresource: u32) -> (u32, u32) {
+        let subres = self.subresource(subresource);
+        (subres.width, subres.height)
+    }
+
+    /// Retrieves a copy of a subresource's contents.
+    pub fn data(&self, subresource: u32) -> Vec<u8> {
+        self.subresource(subresource).data.borrow().clone()
+    }
+
+    /// Returns the number of bytes between two rows of blocks in a subresource.
+    pub fn row_pitch(&self, subresource: u32) -> u32 {
+        self.subresource(subresource).row_pitch
+    }
+
+    /// Runs a function which can modify the contents of a subresource.
+    pub fn with_data_mut<F, T>(&self, subresource: u32, f: F) -> T
+    where
+        F: FnOnce(&mut [u8]) -> T,
+    {
+        f(&mut self.subresource(subresource).data.borrow_mut())
+    }
+
+    /// Returns a pointer to the memory of a subresource.
+    pub fn map(&self, subresource: u32) -> Result<D3D11_MAPPED_SUBRESOURCE> {
+        let subres = self
+            .0
+            .subresources
+            .get(subresource as usize)
+            .ok_or(Error::InvalidCall)?;
+
+        let row_count = self.0.info.row_count(subres.height);
+
+        Ok(D3D11_MAPPED_SUBRESOURCE {
+            pData: subres.data.borrow_mut().as_mut_ptr() as *mut _,
+            RowPitch: subres.row_pitch,
+            DepthPitch: subres.row_pitch * row_count,
+        })
+    }
+
+    /// Copies a region of another texture's subresource into this texture.
+    ///
+    /// The region is clipped to both subresources, like D3D11 would.
+    pub fn copy_region(
+        &self,
+        dest_subresource: u32,
+        (x, y): (u32, u32),
+        src: &MemoryTexture,
+        src_subresource: u32,
+        src_box: Option<&D3D11_BOX>,
+    ) {
+        let src_subres = src.subresource(src_subresource);
+        let dest_subres = self.subresource(dest_subresource);
+
+        let (left, top, right, bottom) = match src_box {
+            Some(b) => (b.left, b.top, b.right, b.bottom),
+            None => (0, 0, src_subres.width, src_subres.height),
+        };
+
+        let width = cmp::min(right, src_subres.width).saturating_sub(left);
+        let height = cmp::min(bottom, src_subres.height).saturating_sub(top);
+        let width = cmp::min(width, dest_subres.width.saturating_sub(x));
+        let height = cmp::min(height, dest_subres.height.saturating_sub(y));
+
+        // Copies are done a block at a time, so the coordinates are converted to blocks.
+        let info = src.0.info;
+        let block_offset = |pos: u32| (pos / info.block_width * info.block_size()) as usize;
+        let row_size = info.row_pitch(width) as usize;
+        let rows = info.row_count(height);
+
+        // The source is copied first, in case it's the same as the destination.
+        let src_data = src_subres.data.borrow().clone();
+        let mut dest_data = dest_subres.data.borrow_mut();
+
+        for row in 0..rows {
+            let src_row = (top / info.block_height + row) * src_subres.row_pitch;
+            let src_start = src_row as usize + block_offset(left);
+
+            let dest_row = (y / info.block_height + row) * dest_subres.row_pitch;
+            let dest_start = dest_row as usize + block_offset(x);
+
+            dest_data[dest_start..dest_start + row_size]
+                .copy_from_slice(&src_data[src_start..src_start + row_size]);
+        }
+    }
+
+    /// Copies all the subresources of another texture with the same layout into this texture.
+    pub fn copy_from(&self, src: &MemoryTexture) {
+        for (dest, src) in self.0.subresources.iter().zip(&src.0.subresources) {
+            if dest.data.borrow().len() == src.data.borrow().len() {
+                let data = src.data.borrow().clone();
+                dest.data.replace(data);
+            }
+        }
+    }
+
+    /// Draws a rectangle of another texture into a rectangle of this texture's first subresource.
+    ///
+    /// The source rectangle is given as `[left, top, width, height]`, in texture coordinates.
+    /// Texels are always point sampled. Formats can only be converted between the
+    /// 8-bit RGBA and BGRA formats; other formats are copied unchanged.
+    pub fn blit_from(
+        &self,
+        (src, src_fmt): (&MemoryTexture, DXGI_FORMAT),
+        src_rect: [f32; 4],
+        dest_fmt: DXGI_FORMAT,
+        dest_rect: &D3D11_VIEWPORT,
+        to_srgb: bool,
+    ) -> Result<()> {
+        let (src_info, dest_info) = (src.0.info, self.0.info);
+
+        if src_info.block_width != 1 || dest_info.block_width != 1 {
+            error!("Cannot blit compressed textures");
+            return Err(Error::InvalidCall);
+        }
+
+        let (src_size, dest_size) = (src_info.block_size(), dest_info.block_size());
+        let conversion = match (Rgba8::new(src_fmt), Rgba8::new(dest_fmt)) {
+            (Some(src), Some(dest)) => Some((src, dest)),
+            _ if src_size == dest_size && !to_srgb => None,
+            _ => {
+                error!("Cannot blit from format {} to {}", src_fmt, dest_fmt);
+                return Err(Error::InvalidCall);
+            }
+        };
+
+        let (src_width, src_height) = src.size(0);
+        let (width, height) = self.size(0);
+
+        let left = dest_rect.TopLeftX.max(0.0) as u32;
+        let top = dest_rect.TopLeftY.max(0.0) as u32;
+        let right = cmp::min(
+            (dest_rect.TopLeftX + dest_rect.Width).max(0.0) as u32,
+            width,
+        );
+        let bottom = cmp::min(
+            (dest_rect.TopLeftY + dest_rect.Height).max(0.0) as u32,
+            height,
+        );
+
+        // Maps the center of a destination pixel to the nearest source texel.
+        let texel = |pos: u32, start: f32, size: f32, uv: f32, uv_size: f32, max: u32| {
+            let t = (pos as f32 + 0.5 - start) / size;
+            let coord = ((uv + t * uv_size) * max as f32).floor();
+            cmp::min(coord.max(0.0) as u32, max - 1) as usize
+        };
+
+        let src_pitch = src.row_pitch(0) as usize;
+        let dest_pitch = self.row_pitch(0) as usize;
+        let (src_size, dest_size) = (src_size as usize, dest_size as usize);
+
+        // The source is copied first, in case it's the same as the destination.
+        let src_data = src.data(0);
+
+        self.with_data_mut(0, |data| {
+            for y in top..bottom {
+                let sy = texel(
+                    y,
+                    dest_rect.TopLeftY,
+                    dest_rect.Height,
+                    src_rect[1],
+                    src_rect[3],
+                    src_height,
+                );
+
+                for x in left..right {
+                    let sx = texel(
+                        x,
+                        dest_rect.TopLeftX,
+                        dest_rect.Width,
+                        src_rect[0],
+                        src_rect[2],
+                        src_width,
+                    );
+
+                    let src_start = sy * src_pitch + sx * src_size;
+                    let texel = &src_data[src_start..src_start + src_size];
+
+                    let dest_start = y as usize * dest_pitch + x as usize * dest_size;
+                    let dest = &mut data[dest_start..dest_start + dest_size];
+
+                    match conversion {
+                        Some((src_fmt, dest_fmt)) => {
+                            let mut color = src_fmt.read(texel);
+                            if to_srgb {
+                                for c in &mut color[..3] {
+                                    *c = linear_to_srgb(*c);
+                                }
+                            }
+                            dest_fmt.write(dest, color);
+                        }
+                        None => dest.copy_from_slice(texel),
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Resolves the first subresource of a multisampled texture into this texture.
+    pub fn resolve_from(&self, src: &MemoryTexture) {
+        // Since we only store one sample, resolving is a simple copy.
+        let data = src.subresource(0).data.borrow().clone();
+        self.subresource(0).data.replace(data);
+    }
+
+    fn subresource(&self, subresource: u32) -> &Subresource {
+        &self.0.subresources[subresource as usize]
+    }
+}
+
+/// Converts a linear color channel to sRGB.
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts an sRGB color channel to linear.
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// One of the formats with four 8-bit normalized channels.
+#[derive(Copy, Clone)]
+struct Rgba8 {
+    bgra: bool,
+    srgb: bool,
+}
+
+impl Rgba8 {
+    fn new(fmt: DXGI_FORMAT) -> Option<Self> {
+        let (bgra, srgb) = match fmt {
+            DXGI_FORMAT_R8G8B8A8_UNORM => (false, false),
+            DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => (false, true),
+            DXGI_FORMAT_B8G8R8A8_UNORM | DXGI_FORMAT_B8G8R8X8_UNORM => (true, false),
+            DXGI_FORMAT_B8G8R8A8_UNORM_SRGB | DXGI_FORMAT_B8G8R8X8_UNORM_SRGB => (true, true),
+            _ => return None,
+        };
+
+        Some(Self { bgra, srgb })
+    }
+
+    /// Decodes a texel to a linear RGBA color, like sampling a view of this format would.
+    fn read(self, texel: &[u8]) -> [f32; 4] {
+        let c = |i: usize| f32::from(texel[i]) / 255.0;
+        let (r, g, b) = if self.bgra {
+            (c(2), c(1), c(0))
+        } else {
+            (c(0), c(1), c(2))
+        };
+
+        let mut color = [r, g, b, c(3)];
+        if self.srgb {
+            for c in &mut color[..3] {
+                *c = srgb_to_linear(*c);
+            }
+        }
+        color
+    }
+
+    /// Encodes a linear RGBA color, like rendering to a view of this format would.
+    fn write(self, texel: &mut [u8], color: [f32; 4]) {
+        let unorm = |c: f32, gamma: bool| {
+            let c = c.max(0.0).min(1.0);
+            let c = if gamma && self.srgb {
+                linear_to_srgb(c)
+            } else {
+                c
+            };
+            (c * 255.0 + 0.5) as u8
+        };
+
+        let [r, g, b, a] = color;
+        let (r, g, b) = (unorm(r, true), unorm(g, true), unorm(b, true));
+
+        if self.bgra {
+            texel.copy_from_slice(&[b, g, r, unorm(a, false)]);
+        } else {
+            texel.copy_from_slice(&[r, g, b, unorm(a, false)]);
+        }
+    }
+}
+
+struct BufferData {
+    id: u32,
+    desc: D3D11_BUFFER_DESC,
+    data: RefCell<Vec<u8>>,
+}
+
+/// Buffer stored in system memory.
+#[derive(Clone)]
+pub struct MemoryBuffer(Rc<BufferData>);
+
+impl MemoryBuffer {
+    /// Creates a new buffer, filled with zeroes.
+    pub fn new(id: u32, desc: &D3D11_BUFFER_DESC) -> Result<Self> {
+        if desc.ByteWidth == 0 {
+            return Err(Error::InvalidCall);
+        }
+
+        Ok(MemoryBuffer(Rc::new(BufferData {
+            id,
+            desc: *desc,
+            data: RefCell::new(vec![0; desc.ByteWidth as usize]),
+        })))
+    }
+
+    /// Returns the unique ID of this buffer.
+    pub fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    /// Retrieves the description of this buffer.
+    pub fn desc(&self) -> D3D11_BUFFER_DESC {
+        self.0.desc
+    }
+
+    /// Retrieves a copy of this buffer's contents.
+    pub fn data(&self) -> Vec<u8> {
+        self.0.data.borrow().clone()
+    }
+
+    /// Returns a pointer to the memory of this buffer.
+    pub fn map(&self) -> D3D11_MAPPED_SUBRESOURCE {
+        let size = self.0.desc.ByteWidth;
+
+        D3D11_MAPPED_SUBRESOURCE {
+            pData: self.0.data.borrow_mut().as_mut_ptr() as *mut _,
+            RowPitch: size,
+            DepthPitch: size,
+        }
+    }
+}
+
+/// State object, which only remembers the description it was created from.
+#[derive(Clone)]
+pub struct StateObject<D> {
+    id: u32,
+    desc: D,
+}
+
+impl<D: Copy> StateObject<D> {
+    /// Creates a new state object.
+    pub fn new(id: u32, desc: &D) -> Self {
+        Self { id, desc: *desc }
+    }
+
+    /// Returns the unique ID of this object.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Retrieves the description this object was created from.
+    pub fn desc(&self) -> D {
+        self.desc
+    }
+}
+
+/// Query whose results are available as soon as it ends.
+pub struct MemoryQuery {
+    id: u32,
+    ty: D3D11_QUERY,
+    // Number of times this query was ended, which is used as its timestamp.
+    ends: Cell<u64>,
+}
+
+impl MemoryQuery {
+    /// Creates a new query of a certain type.
+    pub fn new(id: u32, ty: D3D11_QUERY) -> Self {
+        Self {
+            id,
+            ty,
+            ends: Cell::new(0),
+        }
+    }
+
+    /// Returns the unique ID of this query.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Marks the end of the commands this query covers.
+    pub fn end(&self) {
+        self.ends.set(self.ends.get() + 1);
+    }
+
+    /// Writes the results of this query.
+    ///
+    /// Occlusion queries never count any samples, and timestamps advance
+    /// by a millisecond each time a query ends.
+    pub fn data(&self, data: &mut [u8]) {
+        let mut bytes = [0; 16];
+        let write_u64 = |bytes: &mut [u8; 16], value: u64| unsafe {
+            ptr::write_unaligned(bytes.as_mut_ptr() as *mut u64, value)
+        };
+
+        match self.ty {
+            D3D11_QUERY_EVENT => bytes[0] = 1,
+            D3D11_QUERY_TIMESTAMP => write_u64(&mut bytes, self.ends.get()),
+            // The frequency comes first, and the disjoint flag is left cleared.
+            D3D11_QUERY_TIMESTAMP_DISJOINT => write_u64(&mut bytes, 1000),
+            _ => (),
+        }
+
+        let len = cmp::min(data.len(), bytes.len());
+        data[..len].copy_from_slice(&bytes[..len]);
+    }
+}
+
+/// Swap chain whose only buffer is stored in system memory.
+pub struct MemorySwapChain {
+    id: u32,
+    desc: DXGI_SWAP_CHAIN_DESC,
+    buffer: Texture,
+    fullscreen: Cell<bool>,
+}
+
+impl MemorySwapChain {
+    /// Creates a new swap chain, and its back buffer.
+    pub fn new(ids: &Ids, desc: &DXGI_SWAP_CHAIN_DESC) -> Result<Self> {
+        let mode = &desc.BufferDesc;
+
+        let buffer_desc = D3D11_TEXTURE2D_DESC {
+            Width: mode.Width,
+            Height: mode.Height,
+            MipLevels: 1,
+            ArraySize: 1,
+            Format: mode.Format,
+            SampleDesc: desc.SampleDesc,
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_RENDER_TARGET | D3D11_BIND_SHADER_RESOURCE,
+            CPUAccessFlags: 0,
+            MiscFlags: 0,
+        };
+
+        let buffer = MemoryTexture::new(ids.next(), &buffer_desc)?;
+
+        Ok(Self {
+            id: ids.next(),
+            desc: *desc,
+            buffer: Texture::new(buffer),
+            fullscreen: Cell::new(desc.Windowed == 0),
+        })
+    }
+
+    /// Returns the unique ID of this swap chain.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Retrieves the description this swap chain was created with.
+    pub fn desc(&self) -> DXGI_SWAP_CHAIN_DESC {
+        self.desc
+    }
+
+    /// Retrieves a buffer of this swap chain.
+    ///
+    /// Like with DXGI's blit-model swap chains, only the first buffer is accessible.
+    pub fn buffer(&self, index: u32) -> Result<Texture> {
+        match index {
+            0 => Ok(self.buffer.clone()),
+            _ => Err(Error::InvalidCall),
+        }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.fullscreen.get()
+    }
+
+    pub fn set_fullscreen(&self, fullscreen: bool) {
+        self.fullscreen.set(fullscreen);
+    }
+}
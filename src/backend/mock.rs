@@ -0,0 +1,872 @@
+//! Backend which records the commands it receives, instead of executing them.
+//!
+//! Resources are kept in system memory, so mapping and copying them works
+//! like it would on a real device, and the results can be checked by tests.
+//! Draw calls are only recorded.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+use winapi::shared::{dxgi::*, dxgiformat::DXGI_FORMAT, dxgitype::DXGI_MODE_DESC};
+use winapi::um::{d3d11::*, d3dcommon::*, winnt::HANDLE};
+
+use crate::core::fmt::{dxgi_format_info, Swizzle};
+use crate::hud::Quad;
+use crate::{Error, Result};
+
+use super::memory::{Ids, MemoryBuffer, MemoryQuery, MemorySwapChain, MemoryTexture, StateObject};
+use super::*;
+
+/// A command received by the mock backend.
+///
+/// Objects are identified by the unique ID they were given when they were created.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    SetMaximumFrameLatency {
+        frames: u32,
+    },
+    SetGpuThreadPriority {
+        priority: i32,
+    },
+    CreateTexture {
+        id: u32,
+        width: u32,
+        height: u32,
+        format: DXGI_FORMAT,
+    },
+    OpenSharedTexture {
+        id: u32,
+    },
+    CreateBuffer {
+        id: u32,
+        size: u32,
+    },
+    CreateSampler {
+        id: u32,
+        filter: D3D11_FILTER,
+        max_anisotropy: u32,
+    },
+    CreateBlendState {
+        id: u32,
+    },
+    CreateDepthStencilState {
+        id: u32,
+    },
+    CreateRasterizerState {
+        id: u32,
+    },
+    CreateQuery {
+        id: u32,
+        ty: D3D11_QUERY,
+    },
+    BeginQuery {
+        id: u32,
+    },
+    EndQuery {
+        id: u32,
+    },
+    Map {
+        id: u32,
+        subresource: u32,
+        map_type: D3D11_MAP,
+        flags: u32,
+    },
+    Unmap {
+        id: u32,
+        subresource: u32,
+    },
+    CopyTexture {
+        dest: u32,
+        src: u32,
+    },
+    CopyRegion {
+        dest: u32,
+        dest_subresource: u32,
+        dest_pos: (u32, u32),
+        src: u32,
+        src_subresource: u32,
+    },
+    Resolve {
+        dest: u32,
+        src: u32,
+        format: DXGI_FORMAT,
+    },
+    Blit {
+        src: u32,
+        src_format: DXGI_FORMAT,
+        dest: u32,
+        dest_format: DXGI_FORMAT,
+        mode: BlitMode,
+    },
+    SetRenderTargets {
+        targets: Vec<Option<(u32, DXGI_FORMAT)>>,
+        depth_stencil: Option<u32>,
+    },
+    ClearRenderTarget {
+        id: u32,
+        format: DXGI_FORMAT,
+        color: [f32; 4],
+    },
+    BindTexture {
+        stage: ShaderStage,
+        slot: u32,
+        texture: Option<(u32, DXGI_FORMAT, Swizzle)>,
+    },
+    SetViewport {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+    SetVertexBuffer {
+        slot: u32,
+        id: Option<u32>,
+        stride: u32,
+        offset: u32,
+    },
+    SetIndexBuffer {
+        id: Option<u32>,
+        format: DXGI_FORMAT,
+        offset: u32,
+    },
+    SetPrimitiveTopology {
+        topology: D3D11_PRIMITIVE_TOPOLOGY,
+    },
+    BindSampler {
+        stage: ShaderStage,
+        slot: u32,
+        id: u32,
+    },
+    BindBlendState {
+        id: u32,
+        blend_factor: [f32; 4],
+    },
+    BindDepthStencilState {
+        id: u32,
+        stencil_ref: u32,
+    },
+    BindRasterizerState {
+        id: u32,
+    },
+    ClearState,
+    Draw {
+        vertex_count: u32,
+        start_vertex: u32,
+    },
+    DrawIndexed {
+        index_count: u32,
+        start_index: u32,
+        base_vertex: i32,
+    },
+    DrawOverlay {
+        id: u32,
+        quads: usize,
+    },
+    CreateSwapChain {
+        id: u32,
+        buffer: u32,
+        width: u32,
+        height: u32,
+        format: DXGI_FORMAT,
+        windowed: bool,
+    },
+    Present {
+        id: u32,
+        sync_interval: u32,
+        flags: u32,
+    },
+    SetFullscreenState {
+        id: u32,
+        fullscreen: bool,
+    },
+    ResizeTarget {
+        id: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+/// Retrieves the in-memory texture behind a handle.
+fn tex(texture: &Texture) -> &MemoryTexture {
+    texture.downcast()
+}
+
+fn buf(buffer: &Buffer) -> &MemoryBuffer {
+    buffer.downcast()
+}
+
+/// Backend which records every call made to it.
+pub struct MockBackend {
+    calls: RefCell<Vec<Call>>,
+    ids: Ids,
+    // Every texture created so far, so tests can inspect them, and so they can be shared.
+    textures: RefCell<HashMap<u32, Texture>>,
+    frame_latency: Cell<u32>,
+    gpu_thread_priority: Cell<i32>,
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self {
+            calls: RefCell::new(Vec::new()),
+            ids: Ids::default(),
+            textures: RefCell::new(HashMap::new()),
+            // This is DXGI's default.
+            frame_latency: Cell::new(3),
+            gpu_thread_priority: Cell::new(0),
+        }
+    }
+}
+
+impl MockBackend {
+    /// Creates a new backend, which has not received any calls yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retrieves the calls made so far, in the order they were received.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.borrow().clone()
+    }
+
+    /// Retrieves the calls made so far, and forgets about them.
+    pub fn take_calls(&self) -> Vec<Call> {
+        self.calls.replace(Vec::new())
+    }
+
+    /// Retrieves a texture created by this backend, given its ID.
+    pub fn texture(&self, id: u32) -> Option<MemoryTexture> {
+        self.textures
+            .borrow()
+            .get(&id)
+            .map(|texture| tex(texture).clone())
+    }
+
+    fn record(&self, call: Call) {
+        self.calls.borrow_mut().push(call);
+    }
+
+    fn add_texture(&self, texture: MemoryTexture) -> Texture {
+        let id = texture.id();
+        let handle = Texture::new(texture);
+        self.textures.borrow_mut().insert(id, handle.clone());
+        handle
+    }
+}
+
+impl Backend for MockBackend {
+    fn adapter_name(&self) -> String {
+        "Mock device".into()
+    }
+
+    fn feature_level(&self) -> D3D_FEATURE_LEVEL {
+        D3D_FEATURE_LEVEL_11_0
+    }
+
+    /// Every format we can store is fully supported.
+    fn format_support(&self, fmt: DXGI_FORMAT) -> u32 {
+        if dxgi_format_info(fmt).is_ok() {
+            !0
+        } else {
+            0
+        }
+    }
+
+    fn multisample_quality_levels(&self, fmt: DXGI_FORMAT, samples: u32) -> u32 {
+        let supported = dxgi_format_info(fmt).is_ok() && samples.is_power_of_two() && samples <= 8;
+        supported as u32
+    }
+
+    fn maximum_frame_latency(&self) -> Result<u32> {
+        Ok(self.frame_latency.get())
+    }
+
+    fn set_maximum_frame_latency(&self, frames: u32) -> Result<()> {
+        self.record(Call::SetMaximumFrameLatency { frames });
+        self.frame_latency.set(frames);
+        Ok(())
+    }
+
+    fn gpu_thread_priority(&self) -> Result<i32> {
+        Ok(self.gpu_thread_priority.get())
+    }
+
+    fn set_gpu_thread_priority(&self, priority: i32) -> Result<()> {
+        self.record(Call::SetGpuThreadPriority { priority });
+        self.gpu_thread_priority.set(priority);
+        Ok(())
+    }
+
+    fn create_texture_2d(&self, desc: &D3D11_TEXTURE2D_DESC) -> Result<Texture> {
+        let texture = MemoryTexture::new(self.ids.next(), desc)?;
+
+        self.record(Call::CreateTexture {
+            id: texture.id(),
+            width: desc.Width,
+            height: desc.Height,
+            format: desc.Format,
+        });
+
+        Ok(self.add_texture(texture))
+    }
+
+    /// Shared handles are the IDs of the textures.
+    fn open_shared_texture(&self, handle: HANDLE) -> Result<Texture> {
+        let id = handle as usize as u32;
+        let texture = self.textures.borrow().get(&id).cloned();
+
+        match texture {
+            Some(ref texture)
+                if self.texture_desc(texture).MiscFlags & D3D11_RESOURCE_MISC_SHARED != 0 =>
+            {
+                self.record(Call::OpenSharedTexture { id });
+                Ok(texture.clone())
+            }
+            _ => Err(Error::InvalidCall),
+        }
+    }
+
+    fn shared_handle(&self, texture: &Texture) -> Result<HANDLE> {
+        let texture = tex(texture);
+
+        if texture.desc().MiscFlags & D3D11_RESOURCE_MISC_SHARED == 0 {
+            return Err(Error::InvalidCall);
+        }
+
+        Ok(texture.id() as usize as HANDLE)
+    }
+
+    fn create_buffer(&self, desc: &D3D11_BUFFER_DESC) -> Result<Buffer> {
+        let buffer = MemoryBuffer::new(self.ids.next(), desc)?;
+
+        self.record(Call::CreateBuffer {
+            id: buffer.id(),
+            size: desc.ByteWidth,
+        });
+
+        Ok(Buffer::new(buffer))
+    }
+
+    fn texture_desc(&self, texture: &Texture) -> D3D11_TEXTURE2D_DESC {
+        tex(texture).desc()
+    }
+
+    fn buffer_desc(&self, buffer: &Buffer) -> D3D11_BUFFER_DESC {
+        buf(buffer).desc()
+    }
+
+    fn create_sampler(&self, desc: &D3D11_SAMPLER_DESC) -> Result<Sampler> {
+        let sampler = StateObject::new(self.ids.next(), desc);
+
+        self.record(Call::CreateSampler {
+            id: sampler.id(),
+            filter: desc.Filter,
+            max_anisotropy: desc.MaxAnisotropy,
+        });
+
+        Ok(Sampler::new(sampler))
+    }
+
+    fn create_blend_state(&self, desc: &D3D11_BLEND_DESC) -> Result<BlendState> {
+        let state = StateObject::new(self.ids.next(), desc);
+        self.record(Call::CreateBlendState { id: state.id() });
+        Ok(BlendState::new(state))
+    }
+
+    fn create_depth_stencil_state(
+        &self,
+        desc: &D3D11_DEPTH_STENCIL_DESC,
+    ) -> Result<DepthStencilState> {
+        let state = StateObject::new(self.ids.next(), desc);
+        self.record(Call::CreateDepthStencilState { id: state.id() });
+        Ok(DepthStencilState::new(state))
+    }
+
+    fn create_rasterizer_state(&self, desc: &D3D11_RASTERIZER_DESC) -> Result<RasterizerState> {
+        let state = StateObject::new(self.ids.next(), desc);
+        self.record(Call::CreateRasterizerState { id: state.id() });
+        Ok(RasterizerState::new(state))
+    }
+
+    fn create_query(&self, ty: D3D11_QUERY) -> Result<Query> {
+        let query = MemoryQuery::new(self.ids.next(), ty);
+        self.record(Call::CreateQuery { id: query.id(), ty });
+        Ok(Query::new(query))
+    }
+
+    fn begin_query(&self, query: &Query) {
+        let query: &MemoryQuery = query.downcast();
+        self.record(Call::BeginQuery { id: query.id() });
+    }
+
+    fn end_query(&self, query: &Query) {
+        let query: &MemoryQuery = query.downcast();
+        self.record(Call::EndQuery { id: query.id() });
+        query.end();
+    }
+
+    fn query_data(&self, query: &Query, data: &mut [u8], _flush: bool) -> Result<bool> {
+        query.downcast::<MemoryQuery>().data(data);
+        Ok(true)
+    }
+
+    fn map_texture(
+        &self,
+        texture: &Texture,
+        subresource: u32,
+        map_type: D3D11_MAP,
+        flags: u32,
+    ) -> Result<D3D11_MAPPED_SUBRESOURCE> {
+        let texture = tex(texture);
+        let mapped = texture.map(subresource)?;
+
+        self.record(Call::Map {
+            id: texture.id(),
+            subresource,
+            map_type,
+            flags,
+        });
+
+        Ok(mapped)
+    }
+
+    fn unmap_texture(&self, texture: &Texture, subresource: u32) {
+        self.record(Call::Unmap {
+            id: tex(texture).id(),
+            subresource,
+        });
+    }
+
+    fn map_buffer(
+        &self,
+        buffer: &Buffer,
+        map_type: D3D11_MAP,
+        flags: u32,
+    ) -> Result<D3D11_MAPPED_SUBRESOURCE> {
+        let buffer = buf(buffer);
+
+        self.record(Call::Map {
+            id: buffer.id(),
+            subresource: 0,
+            map_type,
+            flags,
+        });
+
+        Ok(buffer.map())
+    }
+
+    fn unmap_buffer(&self, buffer: &Buffer) {
+        self.record(Call::Unmap {
+            id: buf(buffer).id(),
+            subresource: 0,
+        });
+    }
+
+    fn copy_texture(&self, dest: &Texture, src: &Texture) {
+        let (dest, src) = (tex(dest), tex(src));
+
+        self.record(Call::CopyTexture {
+            dest: dest.id(),
+            src: src.id(),
+        });
+
+        dest.copy_from(src);
+    }
+
+    fn copy_texture_region(
+        &self,
+        dest: &Texture,
+        dest_subresource: u32,
+        dest_pos: (u32, u32),
+        src: &Texture,
+        src_subresource: u32,
+        src_box: Option<&D3D11_BOX>,
+    ) {
+        let (dest, src) = (tex(dest), tex(src));
+
+        self.record(Call::CopyRegion {
+            dest: dest.id(),
+            dest_subresource,
+            dest_pos,
+            src: src.id(),
+            src_subresource,
+        });
+
+        dest.copy_region(dest_subresource, dest_pos, src, src_subresource, src_box);
+    }
+
+    fn resolve_texture(&self, dest: &Texture, src: &Texture, fmt: DXGI_FORMAT) {
+        let (dest, src) = (tex(dest), tex(src));
+
+        self.record(Call::Resolve {
+            dest: dest.id(),
+            src: src.id(),
+            format: fmt,
+        });
+
+        dest.resolve_from(src);
+    }
+
+    fn blit(
+        &self,
+        (src, src_format): (&Texture, DXGI_FORMAT),
+        src_rect: [f32; 4],
+        (dest, dest_format): (&Texture, DXGI_FORMAT),
+        dest_rect: &D3D11_VIEWPORT,
+        mode: BlitMode,
+        _linear_filter: bool,
+    ) -> Result<()> {
+        let (src, dest) = (tex(src), tex(dest));
+
+        self.record(Call::Blit {
+            src: src.id(),
+            src_format,
+            dest: dest.id(),
+            dest_format,
+            mode,
+        });
+
+        let to_srgb = mode == BlitMode::LinearToSrgb;
+        dest.blit_from((src, src_format), src_rect, dest_format, dest_rect, to_srgb)
+    }
+
+    fn set_render_targets(
+        &self,
+        targets: &[Option<(&Texture, DXGI_FORMAT)>],
+        depth_stencil: Option<&Texture>,
+    ) -> Result<()> {
+        let targets = targets
+            .iter()
+            .map(|target| target.map(|(texture, fmt)| (tex(texture).id(), fmt)))
+            .collect();
+
+        self.record(Call::SetRenderTargets {
+            targets,
+            depth_stencil: depth_stencil.map(|ds| tex(ds).id()),
+        });
+
+        Ok(())
+    }
+
+    fn clear_render_target(
+        &self,
+        target: &Texture,
+        fmt: DXGI_FORMAT,
+        color: [f32; 4],
+    ) -> Result<()> {
+        self.record(Call::ClearRenderTarget {
+            id: tex(target).id(),
+            format: fmt,
+            color,
+        });
+
+        Ok(())
+    }
+
+    fn bind_texture(
+        &self,
+        stage: ShaderStage,
+        slot: u32,
+        texture: Option<(&Texture, DXGI_FORMAT, Swizzle)>,
+    ) -> Result<()> {
+        self.record(Call::BindTexture {
+            stage,
+            slot,
+            texture: texture.map(|(texture, fmt, swizzle)| (tex(texture).id(), fmt, swizzle)),
+        });
+
+        Ok(())
+    }
+
+    fn set_viewport(&self, viewport: &D3D11_VIEWPORT) {
+        self.record(Call::SetViewport {
+            x: viewport.TopLeftX,
+            y: viewport.TopLeftY,
+            width: viewport.Width,
+            height: viewport.Height,
+        });
+    }
+
+    fn set_vertex_buffer(&self, slot: u32, buffer: Option<&Buffer>, stride: u32, offset: u32) {
+        self.record(Call::SetVertexBuffer {
+            slot,
+            id: buffer.map(|b| buf(b).id()),
+            stride,
+            offset,
+        });
+    }
+
+    fn set_index_buffer(&self, buffer: Option<&Buffer>, fmt: DXGI_FORMAT, offset: u32) {
+        self.record(Call::SetIndexBuffer {
+            id: buffer.map(|b| buf(b).id()),
+            format: fmt,
+            offset,
+        });
+    }
+
+    fn set_primitive_topology(&self, topology: D3D11_PRIMITIVE_TOPOLOGY) {
+        self.record(Call::SetPrimitiveTopology { topology });
+    }
+
+    fn bind_sampler(&self, stage: ShaderStage, slot: u32, sampler: &Sampler) {
+        let sampler: &StateObject<D3D11_SAMPLER_DESC> = sampler.downcast();
+
+        self.record(Call::BindSampler {
+            stage,
+            slot,
+            id: sampler.id(),
+        });
+    }
+
+    fn bind_blend_state(&self, state: &BlendState, blend_factor: [f32; 4]) {
+        let state: &StateObject<D3D11_BLEND_DESC> = state.downcast();
+
+        self.record(Call::BindBlendState {
+            id: state.id(),
+            blend_factor,
+        });
+    }
+
+    fn bind_depth_stencil_state(&self, state: &DepthStencilState, stencil_ref: u32) {
+        let state: &StateObject<D3D11_DEPTH_STENCIL_DESC> = state.downcast();
+
+        self.record(Call::BindDepthStencilState {
+            id: state.id(),
+            stencil_ref,
+        });
+    }
+
+    fn bind_rasterizer_state(&self, state: &RasterizerState) {
+        let state: &StateObject<D3D11_RASTERIZER_DESC> = state.downcast();
+        self.record(Call::BindRasterizerState { id: state.id() });
+    }
+
+    fn clear_state(&self) {
+        self.record(Call::ClearState);
+    }
+
+    fn draw(&self, vertex_count: u32, start_vertex: u32) {
+        self.record(Call::Draw {
+            vertex_count,
+            start_vertex,
+        });
+    }
+
+    fn draw_indexed(&self, index_count: u32, start_index: u32, base_vertex: i32) {
+        self.record(Call::DrawIndexed {
+            index_count,
+            start_index,
+            base_vertex,
+        });
+    }
+
+    fn draw_overlay(&self, target: &Texture, _fmt: DXGI_FORMAT, quads: &[Quad]) -> Result<()> {
+        self.record(Call::DrawOverlay {
+            id: tex(target).id(),
+            quads: quads.len(),
+        });
+
+        Ok(())
+    }
+
+    fn create_swap_chain(&self, desc: &DXGI_SWAP_CHAIN_DESC) -> Result<SwapChain> {
+        let swap_chain = MemorySwapChain::new(&self.ids, desc)?;
+        let buffer = swap_chain.buffer(0)?;
+
+        self.textures
+            .borrow_mut()
+            .insert(tex(&buffer).id(), buffer.clone());
+
+        self.record(Call::CreateSwapChain {
+            id: swap_chain.id(),
+            buffer: tex(&buffer).id(),
+            width: desc.BufferDesc.Width,
+            height: desc.BufferDesc.Height,
+            format: desc.BufferDesc.Format,
+            windowed: desc.Windowed != 0,
+        });
+
+        Ok(SwapChain::new(swap_chain))
+    }
+
+    fn swap_chain_buffer(&self, swap_chain: &SwapChain, index: u32) -> Result<Texture> {
+        swap_chain.downcast::<MemorySwapChain>().buffer(index)
+    }
+
+    fn present(&self, swap_chain: &SwapChain, sync_interval: u32, flags: u32) -> Result<()> {
+        let swap_chain: &MemorySwapChain = swap_chain.downcast();
+
+        self.record(Call::Present {
+            id: swap_chain.id(),
+            sync_interval,
+            flags,
+        });
+
+        Ok(())
+    }
+
+    fn fullscreen_state(&self, swap_chain: &SwapChain) -> Result<bool> {
+        Ok(swap_chain.downcast::<MemorySwapChain>().is_fullscreen())
+    }
+
+    fn set_fullscreen_state(&self, swap_chain: &SwapChain, fullscreen: bool) -> Result<()> {
+        let swap_chain: &MemorySwapChain = swap_chain.downcast();
+
+        self.record(Call::SetFullscreenState {
+            id: swap_chain.id(),
+            fullscreen,
+        });
+
+        swap_chain.set_fullscreen(fullscreen);
+        Ok(())
+    }
+
+    fn resize_target(&self, swap_chain: &SwapChain, mode: &DXGI_MODE_DESC) -> Result<()> {
+        self.record(Call::ResizeTarget {
+            id: swap_chain.downcast::<MemorySwapChain>().id(),
+            width: mode.Width,
+            height: mode.Height,
+        });
+
+        Ok(())
+    }
+
+    fn wait_for_vblank(&self, _swap_chain: &SwapChain) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{mem, slice};
+
+    use winapi::shared::d3d9types::*;
+    use winapi::shared::dxgiformat::*;
+
+    use super::*;
+    use crate::backend::SamplerCache;
+    use crate::core::sampler::SamplerKey;
+
+    fn texture_desc(width: u32, height: u32, format: DXGI_FORMAT) -> D3D11_TEXTURE2D_DESC {
+        let mut desc: D3D11_TEXTURE2D_DESC = unsafe { mem::zeroed() };
+        desc.Width = width;
+        desc.Height = height;
+        desc.MipLevels = 1;
+        desc.ArraySize = 1;
+        desc.Format = format;
+        desc.SampleDesc.Count = 1;
+        desc
+    }
+
+    /// Fills a texture's first subresource with increasing byte values.
+    fn fill(backend: &MockBackend, texture: &Texture) {
+        let mapped = backend.map_texture(texture, 0, D3D11_MAP_WRITE, 0).unwrap();
+        let size = tex(texture).data(0).len();
+
+        let data = unsafe { slice::from_raw_parts_mut(mapped.pData as *mut u8, size) };
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        backend.unmap_texture(texture, 0);
+    }
+
+    #[test]
+    fn texture_layout() {
+        let backend = MockBackend::new();
+
+        let mut desc = texture_desc(16, 8, DXGI_FORMAT_B8G8R8A8_TYPELESS);
+        desc.MipLevels = 0;
+        let texture = backend.create_texture_2d(&desc).unwrap();
+
+        assert_eq!(backend.texture_desc(&texture).MipLevels, 5);
+        assert_eq!(tex(&texture).row_pitch(0), 64);
+        assert_eq!(tex(&texture).data(1).len(), 8 * 4 * 4);
+        assert_eq!(tex(&texture).data(4).len(), 4);
+
+        // Compressed textures are stored in blocks of 4x4 pixels.
+        let desc = texture_desc(6, 6, DXGI_FORMAT_BC1_UNORM);
+        let texture = backend.create_texture_2d(&desc).unwrap();
+        assert_eq!(tex(&texture).row_pitch(0), 16);
+        assert_eq!(tex(&texture).data(0).len(), 32);
+
+        let desc = texture_desc(4, 4, DXGI_FORMAT_UNKNOWN);
+        assert!(backend.create_texture_2d(&desc).is_err());
+    }
+
+    #[test]
+    fn copy_region() {
+        let backend = MockBackend::new();
+        let desc = texture_desc(4, 4, DXGI_FORMAT_R8_UNORM);
+
+        let src = backend.create_texture_2d(&desc).unwrap();
+        let dest = backend.create_texture_2d(&desc).unwrap();
+        fill(&backend, &src);
+
+        let src_box = D3D11_BOX {
+            left: 1,
+            top: 2,
+            front: 0,
+            right: 3,
+            bottom: 4,
+            back: 1,
+        };
+        backend.copy_texture_region(&dest, 0, (2, 0), &src, 0, Some(&src_box));
+
+        let expected: [u8; 16] = [0, 0, 9, 10, 0, 0, 13, 14, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(tex(&dest).data(0), expected);
+
+        let calls = backend.take_calls();
+        assert_eq!(
+            calls.last(),
+            Some(&Call::CopyRegion {
+                dest: tex(&dest).id(),
+                dest_subresource: 0,
+                dest_pos: (2, 0),
+                src: tex(&src).id(),
+                src_subresource: 0,
+            })
+        );
+        assert!(calls.contains(&Call::Unmap {
+            id: tex(&src).id(),
+            subresource: 0
+        }));
+    }
+
+    #[test]
+    fn samplers_are_cached() {
+        let backend = MockBackend::new();
+        let cache = SamplerCache::new();
+
+        let key = SamplerKey::new(|ty| match ty {
+            D3DSAMP_MINFILTER | D3DSAMP_MAGFILTER => D3DTEXF_LINEAR,
+            D3DSAMP_ADDRESSU | D3DSAMP_ADDRESSV | D3DSAMP_ADDRESSW => D3DTADDRESS_WRAP,
+            _ => 0,
+        });
+
+        let id = |sampler: &Sampler| sampler.downcast::<StateObject<D3D11_SAMPLER_DESC>>().id();
+
+        let first = cache.get(&backend, key, None).unwrap();
+        let second = cache.get(&backend, key, None).unwrap();
+        assert!(first.same(&second));
+
+        let other = SamplerKey {
+            max_mip_level: 2,
+            ..key
+        };
+        let third = cache.get(&backend, other, None).unwrap();
+        assert!(!first.same(&third));
+
+        let created = |id| Call::CreateSampler {
+            id,
+            filter: D3D11_FILTER_MIN_MAG_LINEAR_MIP_POINT,
+            max_anisotropy: 1,
+        };
+        assert_eq!(backend.calls(), [created(id(&first)), created(id(&third))]);
+
+        assert_eq!(cache.take_counts(), (1, 2));
+        assert_eq!(cache.take_counts(), (0, 0));
+    }
+}
@@ -0,0 +1,381 @@
+//! Abstraction over the API which executes the translated commands.
+//!
+//! The translation layer describes resources and state objects using D3D11's structures,
+//! since that is what it targets, but only creates and uses them through the `Backend` trait.
+//! The device holds its backend as a trait object, so the same code runs on top of any of them.
+//! The `d3d11` module implements it on top of a real D3D11 device, while the `mock` backend
+//! records every call and keeps resources in memory, which allows testing the translation
+//! logic without a GPU.
+
+use std::{any::Any, rc::Rc};
+
+use winapi::shared::{
+    dxgi::DXGI_SWAP_CHAIN_DESC, dxgiformat::DXGI_FORMAT, dxgitype::DXGI_MODE_DESC,
+};
+use winapi::um::{d3d11::*, d3dcommon::D3D_FEATURE_LEVEL, winnt::HANDLE};
+
+use crate::core::{fmt::Swizzle, *};
+use crate::hud::Quad;
+use crate::Result;
+
+mod sampler;
+pub use self::sampler::SamplerCache;
+
+pub mod desc;
+pub mod memory;
+pub mod mock;
+
+/// The shader stages to which resources and samplers can be bound.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Pixel,
+}
+
+/// How the source texels of a blit are transformed before being written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlitMode {
+    /// The texels are copied unchanged.
+    Copy,
+    /// The texels are encoded from linear space to sRGB.
+    LinearToSrgb,
+}
+
+macro_rules! handles {
+    ($($(#[$attr:meta])* $name:ident;)*) => {
+        $(
+            $(#[$attr])*
+            #[derive(Clone)]
+            pub struct $name(Rc<dyn Any>);
+
+            impl $name {
+                /// Wraps an object created by a backend.
+                pub fn new<T: Any>(object: T) -> Self {
+                    $name(Rc::new(object))
+                }
+
+                /// Retrieves the object this handle refers to.
+                ///
+                /// Panics if the object was created by a different kind of backend.
+                pub fn downcast<T: Any>(&self) -> &T {
+                    self.0
+                        .downcast_ref()
+                        .expect(concat!(stringify!($name), " was created by another backend"))
+                }
+
+                /// Checks if two handles refer to the same object.
+                pub fn same(&self, other: &Self) -> bool {
+                    Rc::ptr_eq(&self.0, &other.0)
+                }
+            }
+        )*
+    };
+}
+
+handles! {
+    /// A 2D texture, or an array of them.
+    Texture;
+    /// A vertex or index buffer.
+    Buffer;
+    Sampler;
+    BlendState;
+    DepthStencilState;
+    RasterizerState;
+    /// A GPU query, such as an event or an occlusion query.
+    Query;
+    /// A set of buffers which are presented to a window.
+    SwapChain;
+}
+
+/// Interface implemented by the APIs we can translate to.
+///
+/// Objects are referred to by handles, which are cheap to clone. Handles must only
+/// be passed to the backend which created them.
+///
+/// Textures are bound together with the format their data should be interpreted as,
+/// and backends are responsible for creating (and caching) any views they need.
+pub trait Backend {
+    // -- Device properties --
+
+    /// Returns a name for the device executing the commands, shown to apps as the adapter's name.
+    fn adapter_name(&self) -> String;
+
+    /// Returns the D3D11 feature level the backend supports, which determines the caps we report.
+    fn feature_level(&self) -> D3D_FEATURE_LEVEL;
+
+    /// Returns the D3D11 format support flags of a format, or 0 if it is not supported at all.
+    fn format_support(&self, fmt: DXGI_FORMAT) -> u32;
+
+    /// Returns the number of quality levels supported for a sample count, or 0 if unsupported.
+    fn multisample_quality_levels(&self, fmt: DXGI_FORMAT, samples: u32) -> u32;
+
+    fn maximum_frame_latency(&self) -> Result<u32>;
+
+    fn set_maximum_frame_latency(&self, frames: u32) -> Result<()>;
+
+    fn gpu_thread_priority(&self) -> Result<i32>;
+
+    fn set_gpu_thread_priority(&self, priority: i32) -> Result<()>;
+
+    // -- Resource creation --
+
+    /// Creates a new 2D texture, with undefined contents.
+    fn create_texture_2d(&self, desc: &D3D11_TEXTURE2D_DESC) -> Result<Texture>;
+
+    /// Opens a texture created by another device, through its shared handle.
+    fn open_shared_texture(&self, handle: HANDLE) -> Result<Texture>;
+
+    /// Retrieves a handle through which other devices can open a texture.
+    ///
+    /// The texture must have been created with `D3D11_RESOURCE_MISC_SHARED`.
+    fn shared_handle(&self, texture: &Texture) -> Result<HANDLE>;
+
+    /// Creates a new buffer, with undefined contents.
+    fn create_buffer(&self, desc: &D3D11_BUFFER_DESC) -> Result<Buffer>;
+
+    /// Retrieves the description of a texture.
+    fn texture_desc(&self, texture: &Texture) -> D3D11_TEXTURE2D_DESC;
+
+    /// Retrieves the description of a buffer.
+    fn buffer_desc(&self, buffer: &Buffer) -> D3D11_BUFFER_DESC;
+
+    // -- State objects --
+
+    fn create_sampler(&self, desc: &D3D11_SAMPLER_DESC) -> Result<Sampler>;
+
+    fn create_blend_state(&self, desc: &D3D11_BLEND_DESC) -> Result<BlendState>;
+
+    fn create_depth_stencil_state(
+        &self,
+        desc: &D3D11_DEPTH_STENCIL_DESC,
+    ) -> Result<DepthStencilState>;
+
+    fn create_rasterizer_state(&self, desc: &D3D11_RASTERIZER_DESC) -> Result<RasterizerState>;
+
+    // -- Queries --
+
+    fn create_query(&self, ty: D3D11_QUERY) -> Result<Query>;
+
+    fn begin_query(&self, query: &Query);
+
+    fn end_query(&self, query: &Query);
+
+    /// Retrieves the result of a query.
+    ///
+    /// Returns `false` if the result is not available yet.
+    /// If `flush` is set, the pending commands are submitted to the GPU first.
+    fn query_data(&self, query: &Query, data: &mut [u8], flush: bool) -> Result<bool>;
+
+    // -- Memory mapping --
+
+    /// Maps a subresource of a texture to CPU memory.
+    ///
+    /// Returns `Error::WasStillDrawing` if `D3D11_MAP_FLAG_DO_NOT_WAIT` is set
+    /// and the texture is still in use.
+    fn map_texture(
+        &self,
+        texture: &Texture,
+        subresource: u32,
+        map_type: D3D11_MAP,
+        flags: u32,
+    ) -> Result<D3D11_MAPPED_SUBRESOURCE>;
+
+    fn unmap_texture(&self, texture: &Texture, subresource: u32);
+
+    /// Maps a buffer to CPU memory.
+    fn map_buffer(
+        &self,
+        buffer: &Buffer,
+        map_type: D3D11_MAP,
+        flags: u32,
+    ) -> Result<D3D11_MAPPED_SUBRESOURCE>;
+
+    fn unmap_buffer(&self, buffer: &Buffer);
+
+    // -- Copies --
+
+    /// Copies the whole contents of a texture into another one with the same layout.
+    fn copy_texture(&self, dest: &Texture, src: &Texture);
+
+    /// Copies a region of a texture's subresource into another texture.
+    ///
+    /// If `src_box` is `None`, the whole subresource is copied.
+    fn copy_texture_region(
+        &self,
+        dest: &Texture,
+        dest_subresource: u32,
+        dest_pos: (u32, u32),
+        src: &Texture,
+        src_subresource: u32,
+        src_box: Option<&D3D11_BOX>,
+    );
+
+    /// Resolves the first subresource of a multisampled texture into a single-sampled one.
+    fn resolve_texture(&self, dest: &Texture, src: &Texture, fmt: DXGI_FORMAT);
+
+    /// Draws a rectangle of a texture into a rectangle of a render target,
+    /// which can scale it and convert its format.
+    ///
+    /// The source rectangle is given as `[left, top, width, height]`, in texture coordinates.
+    /// The pipeline state is left unchanged.
+    fn blit(
+        &self,
+        src: (&Texture, DXGI_FORMAT),
+        src_rect: [f32; 4],
+        dest: (&Texture, DXGI_FORMAT),
+        dest_rect: &D3D11_VIEWPORT,
+        mode: BlitMode,
+        linear_filter: bool,
+    ) -> Result<()>;
+
+    // -- Pipeline state --
+
+    /// Binds render targets, and optionally a depth / stencil buffer.
+    ///
+    /// The format of each target determines how the texture's data is interpreted.
+    fn set_render_targets(
+        &self,
+        targets: &[Option<(&Texture, DXGI_FORMAT)>],
+        depth_stencil: Option<&Texture>,
+    ) -> Result<()>;
+
+    /// Fills the first subresource of a render target with a color.
+    fn clear_render_target(
+        &self,
+        target: &Texture,
+        fmt: DXGI_FORMAT,
+        color: [f32; 4],
+    ) -> Result<()>;
+
+    /// Binds all the mip levels of a texture to a shader stage.
+    ///
+    /// The swizzle describes how the channels read from the texture have to be remapped,
+    /// for formats which D3D11 does not support directly. The D3D11 backend applies it
+    /// by binding a remapped copy of the texture, which is updated before each draw.
+    fn bind_texture(
+        &self,
+        stage: ShaderStage,
+        slot: u32,
+        texture: Option<(&Texture, DXGI_FORMAT, Swizzle)>,
+    ) -> Result<()>;
+
+    fn set_viewport(&self, viewport: &D3D11_VIEWPORT);
+
+    /// Binds a vertex buffer to one of the input assembler's slots.
+    fn set_vertex_buffer(&self, slot: u32, buffer: Option<&Buffer>, stride: u32, offset: u32);
+
+    fn set_index_buffer(&self, buffer: Option<&Buffer>, fmt: DXGI_FORMAT, offset: u32);
+
+    fn set_primitive_topology(&self, topology: D3D11_PRIMITIVE_TOPOLOGY);
+
+    fn bind_sampler(&self, stage: ShaderStage, slot: u32, sampler: &Sampler);
+
+    fn bind_blend_state(&self, state: &BlendState, blend_factor: [f32; 4]);
+
+    fn bind_depth_stencil_state(&self, state: &DepthStencilState, stencil_ref: u32);
+
+    fn bind_rasterizer_state(&self, state: &RasterizerState);
+
+    /// Unbinds every object, and resets the pipeline to its default state.
+    fn clear_state(&self);
+
+    // -- Drawing --
+
+    fn draw(&self, vertex_count: u32, start_vertex: u32);
+
+    fn draw_indexed(&self, index_count: u32, start_index: u32, base_vertex: i32);
+
+    /// Draws quads on top of a render target, such as the ones the HUD is made of.
+    ///
+    /// The pipeline state is left unchanged.
+    fn draw_overlay(&self, target: &Texture, fmt: DXGI_FORMAT, quads: &[Quad]) -> Result<()>;
+
+    // -- Presentation --
+
+    /// Creates a swap chain for the window in the description.
+    fn create_swap_chain(&self, desc: &DXGI_SWAP_CHAIN_DESC) -> Result<SwapChain>;
+
+    /// Retrieves one of a swap chain's buffers.
+    ///
+    /// The same buffer always has the same handle.
+    fn swap_chain_buffer(&self, swap_chain: &SwapChain, index: u32) -> Result<Texture>;
+
+    /// Presents the contents of a swap chain's back buffer.
+    fn present(&self, swap_chain: &SwapChain, sync_interval: u32, flags: u32) -> Result<()>;
+
+    /// Checks if a swap chain is in fullscreen mode.
+    fn fullscreen_state(&self, swap_chain: &SwapChain) -> Result<bool>;
+
+    fn set_fullscreen_state(&self, swap_chain: &SwapChain, fullscreen: bool) -> Result<()>;
+
+    /// Resizes a swap chain's window, or changes the display mode if it is in fullscreen.
+    fn resize_target(&self, swap_chain: &SwapChain, mode: &DXGI_MODE_DESC) -> Result<()>;
+
+    /// Waits until the output the swap chain is displayed on starts its next vertical blank.
+    fn wait_for_vblank(&self, swap_chain: &SwapChain) -> Result<()>;
+}
+
+/// Determines how a D3D9 resource lock is expressed as a D3D11 map.
+///
+/// Returns the map type, and the map flags.
+pub fn lock_to_map(flags: LockFlags, usage: UsageFlags) -> (D3D11_MAP, u32) {
+    let map_type = if usage.intersects(UsageFlags::WRITE_ONLY) {
+        // NOOVERWRITE must come first, since in D3D11 it's a superset of discard.
+        if flags.intersects(LockFlags::NO_OVERWRITE) {
+            D3D11_MAP_WRITE_NO_OVERWRITE
+        } else if flags.intersects(LockFlags::DISCARD) {
+            D3D11_MAP_WRITE_DISCARD
+        } else {
+            D3D11_MAP_WRITE
+        }
+    } else {
+        // Either the app forgot to use writeonly, or it really wants to
+        // read the data, in which case, we can only hope it works.
+
+        // TODO: implement some stricter checks by checking the resource's memory pool,
+        // then remove this warning.
+        run_once!(|| error!("Reading data from a resource might not work"));
+
+        if flags.intersects(LockFlags::READ_ONLY) {
+            D3D11_MAP_READ
+        } else {
+            D3D11_MAP_READ_WRITE
+        }
+    };
+
+    let map_flags = if flags.intersects(LockFlags::DO_NOT_WAIT) {
+        D3D11_MAP_FLAG_DO_NOT_WAIT
+    } else {
+        0
+    };
+
+    (map_type, map_flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_flags() {
+        let write_only = UsageFlags::WRITE_ONLY;
+
+        let discard = LockFlags::DISCARD;
+        assert_eq!(
+            lock_to_map(discard, write_only),
+            (D3D11_MAP_WRITE_DISCARD, 0)
+        );
+
+        let append = LockFlags::NO_OVERWRITE | LockFlags::DISCARD;
+        assert_eq!(
+            lock_to_map(append, write_only).0,
+            D3D11_MAP_WRITE_NO_OVERWRITE
+        );
+
+        let read = LockFlags::READ_ONLY | LockFlags::DO_NOT_WAIT;
+        assert_eq!(
+            lock_to_map(read, UsageFlags::empty()),
+            (D3D11_MAP_READ, D3D11_MAP_FLAG_DO_NOT_WAIT)
+        );
+    }
+}
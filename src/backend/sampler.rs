@@ -0,0 +1,56 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use crate::core::sampler::SamplerKey;
+use crate::Result;
+
+use super::{Backend, Sampler};
+
+/// Cache of the sampler objects created from D3D9 sampler states.
+///
+/// Apps change their sampler states all the time, but usually only use a few combinations.
+pub struct SamplerCache {
+    samplers: RefCell<HashMap<SamplerKey, Sampler>>,
+    // Number of lookups which found an existing sampler, since they were last taken.
+    hits: Cell<u64>,
+    // Number of lookups which had to create a new sampler, since they were last taken.
+    misses: Cell<u64>,
+}
+
+impl SamplerCache {
+    /// Creates a new, empty cache.
+    pub fn new() -> Self {
+        Self {
+            samplers: RefCell::new(HashMap::new()),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    /// Retrieves the sampler for a set of states, creating it if it does not yet exist.
+    pub fn get(
+        &self,
+        backend: &dyn Backend,
+        key: SamplerKey,
+        forced_anisotropy: Option<u32>,
+    ) -> Result<Sampler> {
+        if let Some(sampler) = self.samplers.borrow().get(&key) {
+            self.hits.set(self.hits.get() + 1);
+            return Ok(sampler.clone());
+        }
+
+        self.misses.set(self.misses.get() + 1);
+
+        let desc = key.to_d3d11(forced_anisotropy);
+        let sampler = backend.create_sampler(&desc)?;
+
+        self.samplers.borrow_mut().insert(key, sampler.clone());
+
+        Ok(sampler)
+    }
+
+    /// Retrieves the number of cache hits and misses, and resets them.
+    pub fn take_counts(&self) -> (u64, u64) {
+        (self.hits.replace(0), self.misses.replace(0))
+    }
+}
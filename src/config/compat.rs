@@ -0,0 +1,86 @@
+//! Built-in database of compatibility workarounds for known games.
+//!
+//! Each entry sets options in the same way as a line in the configuration file does,
+//! so the workarounds ship with the library while the user can still override them.
+
+/// Workarounds for a single game.
+pub struct Entry {
+    /// Name of the game's executable, compared case insensitively.
+    pub exe: &'static str,
+    /// The SDK version the game passes to `Direct3DCreate9`,
+    /// for games which shipped builds with different behaviour.
+    /// `None` matches all versions.
+    pub sdk_version: Option<u32>,
+    /// The options which are set for this game.
+    pub options: &'static [(&'static str, &'static str)],
+}
+
+impl Entry {
+    /// Checks if this entry applies to an executable.
+    fn matches(&self, exe_name: &str, sdk_version: u32) -> bool {
+        self.exe.eq_ignore_ascii_case(exe_name)
+            && self.sdk_version.map_or(true, |sdk| sdk == sdk_version)
+    }
+}
+
+/// All of the games we know need workarounds.
+static DATABASE: &[Entry] = &[
+    // Limits its settings based on the video memory it detects, and gets
+    // the amount wrong on GPUs with lots of memory. PCGamingWiki's page for
+    // the game describes this, and the `-availablevidmem` command line workaround.
+    Entry {
+        exe: "GTAIV.exe",
+        sdk_version: None,
+        options: &[("max_available_memory", "2048")],
+    },
+    Entry {
+        exe: "EFLC.exe",
+        sdk_version: None,
+        options: &[("max_available_memory", "2048")],
+    },
+    // The builds against the 9.0c SDK pick a broken shader model 3 path.
+    Entry {
+        exe: "Oblivion.exe",
+        sdk_version: Some(32),
+        options: &[("max_shader_model", "2")],
+    },
+];
+
+/// Returns the workarounds which apply to a game.
+pub fn lookup(exe_name: &str, sdk_version: u32) -> impl Iterator<Item = &'static Entry> {
+    let exe_name = exe_name.to_owned();
+
+    DATABASE
+        .iter()
+        .filter(move |entry| entry.matches(&exe_name, sdk_version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn database_options_are_valid() {
+        for entry in DATABASE {
+            let mut config = Config::default();
+            for &(key, value) in entry.options {
+                assert!(
+                    config.set(key, value).is_ok(),
+                    "Invalid option {} = {} for {}",
+                    key,
+                    value,
+                    entry.exe
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sdk_version_is_matched() {
+        assert_eq!(lookup("oblivion.exe", 32).count(), 1);
+        assert_eq!(lookup("oblivion.exe", 31).count(), 0);
+        assert_eq!(lookup("gtaiv.exe", 32).count(), 1);
+        assert_eq!(lookup("unknown.exe", 32).count(), 0);
+    }
+}
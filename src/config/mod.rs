@@ -0,0 +1,548 @@
+//! User-configurable options.
+//!
+//! Options are read from a `d3d9.conf` file next to the executable, and from the file
+//! pointed to by the `D3D9_CONFIG_FILE` environment variable, which takes precedence.
+//! See the `parser` module for the file's format.
+//!
+//! Before reading the files, we apply the workarounds from the `compat` database,
+//! so that the user can override them.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use winapi::shared::d3d9types::{D3DFORMAT, D3DMULTISAMPLE_NONE, D3DMULTISAMPLE_TYPE};
+
+use crate::hud::{self, HudElement};
+
+mod compat;
+mod parser;
+
+/// Environment variable which can point to an additional configuration file.
+const CONFIG_FILE_VAR: &str = "D3D9_CONFIG_FILE";
+
+/// Environment variable which can enable recording API calls to a file.
+const TRACE_FILE_VAR: &str = "D3D9_TRACE_FILE";
+
+/// Environment variable which can enable the validation of API calls.
+const STRICT_VALIDATION_VAR: &str = "D3D9_STRICT_VALIDATION";
+
+/// Environment variable which lists the elements of the HUD.
+const HUD_VAR: &str = "D3D9_HUD";
+
+/// Environment variable which caps the frame rate.
+const FPS_LIMIT_VAR: &str = "D3D9_FPS_LIMIT";
+
+/// Name of the configuration file we look for next to the executable.
+const CONFIG_FILE_NAME: &str = "d3d9.conf";
+
+/// All of the user's options.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Forces vertical sync on or off, regardless of what the app requested.
+    pub vsync: Option<bool>,
+    /// Maximum number of frames the CPU is allowed to queue up for presenting.
+    pub max_frame_latency: Option<u32>,
+    /// Maximum number of frames presented per second, regardless of vertical sync.
+    pub fps_limit: Option<u32>,
+    /// Forces a certain number of samples for multisampled render targets.
+    pub msaa_samples: Option<u32>,
+    /// Forces a certain level of anisotropic filtering.
+    pub max_anisotropy: Option<u32>,
+    /// Adapter information we report instead of the real one.
+    pub adapter: AdapterOverrides,
+    /// Formats which are reported as unsupported, even if we support them.
+    pub disabled_formats: Vec<D3DFORMAT>,
+    /// Compatibility workarounds for buggy games.
+    pub workarounds: Workarounds,
+    /// File to which the app's API calls are recorded, for replaying them later.
+    pub trace_file: Option<PathBuf>,
+    /// Checks the app's calls against the rules of the D3D9 API, and logs every misuse.
+    ///
+    /// The real runtime only does this in its debug version, and many games break the rules,
+    /// so this is meant for debugging rendering issues.
+    pub strict_validation: bool,
+    /// Elements of the HUD drawn on top of each frame, in order. The HUD is hidden if empty.
+    pub hud: Vec<HudElement>,
+}
+
+impl Config {
+    /// Loads the configuration for the current executable,
+    /// which was built against a certain SDK version.
+    pub fn load(sdk_version: u32) -> Self {
+        let mut config = Self::default();
+
+        let exe = env::current_exe().ok();
+        let exe_name = exe
+            .as_ref()
+            .and_then(|exe| exe.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        for entry in compat::lookup(&exe_name, sdk_version) {
+            info!("Applying compatibility workarounds for {}", entry.exe);
+            for &(key, value) in entry.options {
+                if let Err(msg) = config.set(key, value) {
+                    error!("Invalid built-in option {} = {}: {}", key, value, msg);
+                }
+            }
+        }
+
+        let exe_dir = exe.as_ref().and_then(|exe| exe.parent());
+
+        if let Some(dir) = exe_dir {
+            config.load_file(&dir.join(CONFIG_FILE_NAME), &exe_name);
+        }
+
+        if let Some(path) = env::var_os(CONFIG_FILE_VAR) {
+            config.load_file(Path::new(&path), &exe_name);
+        }
+
+        // Environment variables override everything else.
+        config.adapter.apply_env();
+
+        if let Some(path) = env::var_os(TRACE_FILE_VAR) {
+            config.trace_file = Some(path.into());
+        }
+
+        if let Some(strict) = read_var(STRICT_VALIDATION_VAR, parse_bool) {
+            config.strict_validation = strict;
+        }
+
+        if let Some(hud) = read_var(HUD_VAR, hud::parse_elements) {
+            config.hud = hud;
+        }
+
+        let fps_limit = read_var(FPS_LIMIT_VAR, |s| parse_range(s, 1, 1000).ok());
+        if fps_limit.is_some() {
+            config.fps_limit = fps_limit;
+        }
+
+        config
+    }
+
+    /// Applies the options from a file, if it exists.
+    fn load_file(&mut self, path: &Path, exe_name: &str) {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                info!("Loading configuration from {}", path.display());
+                self.apply(&text, exe_name);
+            }
+            Err(_) => debug!("No configuration file found at {}", path.display()),
+        }
+    }
+
+    /// Applies the options in a configuration file's text which are relevant for an executable.
+    ///
+    /// Options in the executable's section override the global ones.
+    pub fn apply(&mut self, text: &str, exe_name: &str) {
+        let entries = parser::parse(text);
+
+        let global = entries.iter().filter(|entry| entry.section.is_none());
+        let specific = entries.iter().filter(|entry| match entry.section {
+            Some(section) => section.eq_ignore_ascii_case(exe_name),
+            None => false,
+        });
+
+        for entry in global.chain(specific) {
+            if let Err(msg) = self.set(entry.key, entry.value) {
+                warn!(
+                    "Config line {}: invalid value for {}: {}",
+                    entry.line, entry.key, msg
+                );
+            }
+        }
+    }
+
+    /// Parses and validates the value of an option.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), &'static str> {
+        match key {
+            "vsync" => self.vsync = Some(parse_flag(value)?),
+            "max_frame_latency" => {
+                self.max_frame_latency = Some(parse_range(value, 1, 16)?);
+            }
+            "fps_limit" => self.fps_limit = Some(parse_range(value, 1, 1000)?),
+            "msaa_samples" => {
+                let samples = parse_range(value, 1, 16)?;
+                if !samples.is_power_of_two() {
+                    return Err("expected a power of 2");
+                }
+                self.msaa_samples = Some(samples);
+            }
+            "max_anisotropy" => self.max_anisotropy = Some(parse_range(value, 1, 16)?),
+            "vendor_id" => self.adapter.vendor_id = Some(parse_u32(value).ok_or(NOT_A_NUMBER)?),
+            "device_id" => self.adapter.device_id = Some(parse_u32(value).ok_or(NOT_A_NUMBER)?),
+            "description" => self.adapter.description = Some(value.to_owned()),
+            "driver_version" => {
+                let version = parse_driver_version(value).ok_or("expected a.b.c.d")?;
+                self.adapter.driver_version = Some(version);
+            }
+            "available_memory" => {
+                let memory = parse_u32(value).ok_or(NOT_A_NUMBER)?;
+                self.adapter.available_memory = Some(memory);
+            }
+            "disabled_formats" => {
+                self.disabled_formats = value
+                    .split(',')
+                    .map(|fmt| parse_format(fmt.trim()))
+                    .collect::<Option<_>>()
+                    .ok_or("expected a list of FourCC codes or numbers")?;
+            }
+            "disable_msaa" => self.workarounds.disable_msaa = parse_flag(value)?,
+            "force_discard_locks" => self.workarounds.force_discard_locks = parse_flag(value)?,
+            "ignore_do_not_wait" => self.workarounds.ignore_do_not_wait = parse_flag(value)?,
+            "max_available_memory" => {
+                let memory = parse_u32(value).ok_or(NOT_A_NUMBER)?;
+                self.workarounds.max_available_memory = Some(memory);
+            }
+            "max_shader_model" => {
+                self.workarounds.max_shader_model = Some(parse_range(value, 1, 3)?);
+            }
+            "trace_file" => {
+                if value.is_empty() {
+                    return Err("expected a path");
+                }
+                self.trace_file = Some(value.into());
+            }
+            "strict_validation" => self.strict_validation = parse_flag(value)?,
+            "hud" => {
+                self.hud = hud::parse_elements(value).ok_or("expected a list of HUD elements")?
+            }
+            _ => return Err("unknown option"),
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the user has disabled a certain format.
+    pub fn is_format_disabled(&self, fmt: D3DFORMAT) -> bool {
+        self.disabled_formats.contains(&fmt)
+    }
+
+    /// Returns the multisample type we should actually use when the app requests one.
+    pub fn multisample_type(&self, ty: D3DMULTISAMPLE_TYPE) -> D3DMULTISAMPLE_TYPE {
+        if self.workarounds.disable_msaa {
+            D3DMULTISAMPLE_NONE
+        } else {
+            ty
+        }
+    }
+
+    /// Returns the multisample type of the implicit back buffer and depth / stencil buffer.
+    ///
+    /// Unlike other render targets, these can be upgraded to the number of samples
+    /// forced by the user, since we can resolve them whenever the app reads them.
+    pub fn implicit_multisample_type(&self, ty: D3DMULTISAMPLE_TYPE) -> D3DMULTISAMPLE_TYPE {
+        match self.msaa_samples {
+            Some(samples) if !self.workarounds.disable_msaa => samples,
+            _ => self.multisample_type(ty),
+        }
+    }
+}
+
+/// Workarounds for games which rely on driver bugs, or which have bugs of their own.
+///
+/// Most of these are set by the built-in `compat` database.
+#[derive(Debug, Clone, Default)]
+pub struct Workarounds {
+    /// Ignores requests for multisampled render targets and back buffers.
+    pub disable_msaa: bool,
+    /// Treats locks of dynamic resources without any flags as discarding the contents.
+    pub force_discard_locks: bool,
+    /// Makes locks with `D3DLOCK_DONOTWAIT` block until the resource is available.
+    pub ignore_do_not_wait: bool,
+    /// Upper bound for the amount of memory we report, in MiB.
+    pub max_available_memory: Option<u32>,
+    /// Highest shader model we report in the caps.
+    pub max_shader_model: Option<u32>,
+}
+
+/// Overrides for the adapter information we report to apps.
+///
+/// Some games refuse to run, or choose slower code paths, on GPUs they don't recognize.
+/// These values are only reported to the app, we still use the real ones internally.
+#[derive(Debug, Clone, Default)]
+pub struct AdapterOverrides {
+    /// PCI vendor ID.
+    pub vendor_id: Option<u32>,
+    /// PCI device ID.
+    pub device_id: Option<u32>,
+    /// Human readable description of the adapter.
+    pub description: Option<String>,
+    /// Driver version, packed in the same way as `D3DADAPTER_IDENTIFIER9::DriverVersion`.
+    pub driver_version: Option<u64>,
+    /// Available memory, in MiB.
+    pub available_memory: Option<u32>,
+}
+
+impl AdapterOverrides {
+    /// Overrides the values with the ones set in environment variables.
+    fn apply_env(&mut self) {
+        fn update<T>(field: &mut Option<T>, value: Option<T>) {
+            if value.is_some() {
+                *field = value;
+            }
+        }
+
+        update(&mut self.vendor_id, read_var("D3D9_VENDOR_ID", parse_u32));
+        update(&mut self.device_id, read_var("D3D9_DEVICE_ID", parse_u32));
+        update(
+            &mut self.description,
+            read_var("D3D9_DESCRIPTION", |s| Some(s.to_owned())),
+        );
+        update(
+            &mut self.driver_version,
+            read_var("D3D9_DRIVER_VERSION", parse_driver_version),
+        );
+        update(
+            &mut self.available_memory,
+            read_var("D3D9_AVAILABLE_MEMORY", parse_u32),
+        );
+    }
+}
+
+const NOT_A_NUMBER: &str = "expected a number";
+
+/// Reads and parses an environment variable, if it is set.
+fn read_var<T, F>(name: &str, parse: F) -> Option<T>
+where
+    F: FnOnce(&str) -> Option<T>,
+{
+    let value = env::var(name).ok()?;
+    let parsed = parse(value.trim());
+
+    if parsed.is_none() {
+        warn!("Ignoring invalid value for {}: {:?}", name, value);
+    }
+
+    parsed
+}
+
+/// Parses an unsigned integer, either in decimal or in hexadecimal with a `0x` prefix.
+pub fn parse_u32(s: &str) -> Option<u32> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u32::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Parses an integer, and checks it is in a range.
+fn parse_range(s: &str, min: u32, max: u32) -> Result<u32, &'static str> {
+    let value = parse_u32(s).ok_or(NOT_A_NUMBER)?;
+
+    if value < min || value > max {
+        return Err("value out of range");
+    }
+
+    Ok(value)
+}
+
+/// Parses a boolean value.
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "true" | "on" | "yes" | "1" => Some(true),
+        "false" | "off" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses a boolean option.
+fn parse_flag(s: &str) -> Result<bool, &'static str> {
+    parse_bool(s).ok_or("expected a boolean")
+}
+
+/// Parses a D3D format, either as a number or as a four character code (e.g. `INTZ`).
+fn parse_format(s: &str) -> Option<D3DFORMAT> {
+    if let Some(fmt) = parse_u32(s) {
+        return Some(fmt);
+    }
+
+    let bytes = s.as_bytes();
+
+    if bytes.len() != 4 {
+        return None;
+    }
+
+    let fourcc = bytes
+        .iter()
+        .rev()
+        .fold(0, |code, &byte| (code << 8) | u32::from(byte));
+
+    Some(fourcc)
+}
+
+/// Parses a driver version in the `product.version.subversion.build` format.
+pub fn parse_driver_version(s: &str) -> Option<u64> {
+    let parts = s
+        .split('.')
+        .map(|part| part.parse::<u16>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    if parts.len() != 4 {
+        return None;
+    }
+
+    let version = parts
+        .iter()
+        .fold(0, |version, &part| (version << 16) | u64::from(part));
+
+    Some(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers() {
+        assert_eq!(parse_u32("4318"), Some(4318));
+        assert_eq!(parse_u32("0x10de"), Some(0x10DE));
+        assert_eq!(parse_u32("0X1002"), Some(0x1002));
+        assert_eq!(parse_u32("nvidia"), None);
+        assert_eq!(parse_u32(""), None);
+    }
+
+    #[test]
+    fn driver_versions() {
+        assert_eq!(
+            parse_driver_version("8.17.12.9573"),
+            Some((8 << 48) | (17 << 32) | (12 << 16) | 9573)
+        );
+        assert_eq!(parse_driver_version("1.2.3"), None);
+        assert_eq!(parse_driver_version("1.2.3.70000"), None);
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(parse_format("21"), Some(21));
+        assert_eq!(parse_format("INTZ"), Some(0x5A54_4E49));
+        assert_eq!(parse_format("DF24"), Some(0x3432_4644));
+        assert_eq!(parse_format("TOOLONG"), None);
+    }
+
+    #[test]
+    fn exe_sections_override_global_options() {
+        let text = "
+            vsync = off
+            max_frame_latency = 3
+
+            [game.exe]
+            vsync = on
+            vendor_id = 0x10de
+            disabled_formats = INTZ, DF24
+
+            [other.exe]
+            max_frame_latency = 1
+        ";
+
+        let mut config = Config::default();
+        config.apply(text, "Game.EXE");
+
+        assert_eq!(config.vsync, Some(true));
+        assert_eq!(config.max_frame_latency, Some(3));
+        assert_eq!(config.adapter.vendor_id, Some(0x10DE));
+        assert!(config.is_format_disabled(0x5A54_4E49));
+        assert!(!config.is_format_disabled(21));
+    }
+
+    #[test]
+    fn invalid_values_are_ignored() {
+        let text = "
+            max_frame_latency = 100
+            msaa_samples = 3
+            max_anisotropy = 16
+            unknown_option = 1
+        ";
+
+        let mut config = Config::default();
+        config.apply(text, "game.exe");
+
+        assert_eq!(config.max_frame_latency, None);
+        assert_eq!(config.msaa_samples, None);
+        assert_eq!(config.max_anisotropy, Some(16));
+    }
+
+    #[test]
+    fn workarounds() {
+        let text = "
+            disable_msaa = on
+            max_available_memory = 2048
+            max_shader_model = 4
+        ";
+
+        let mut config = Config::default();
+        config.apply(text, "game.exe");
+
+        assert!(config.workarounds.disable_msaa);
+        assert!(!config.workarounds.force_discard_locks);
+        assert_eq!(config.workarounds.max_available_memory, Some(2048));
+        assert_eq!(config.workarounds.max_shader_model, None);
+        assert_eq!(config.multisample_type(4), D3DMULTISAMPLE_NONE);
+    }
+
+    #[test]
+    fn trace_file() {
+        let mut config = Config::default();
+
+        config.apply("trace_file =", "game.exe");
+        assert_eq!(config.trace_file, None);
+
+        config.apply("trace_file = C:\\captures\\game.trace", "game.exe");
+        assert_eq!(
+            config.trace_file,
+            Some(PathBuf::from("C:\\captures\\game.trace"))
+        );
+    }
+
+    #[test]
+    fn strict_validation() {
+        let mut config = Config::default();
+        assert!(!config.strict_validation);
+
+        config.apply("strict_validation = on", "game.exe");
+        assert!(config.strict_validation);
+
+        config.apply("strict_validation = maybe", "game.exe");
+        assert!(config.strict_validation);
+    }
+
+    #[test]
+    fn hud() {
+        let mut config = Config::default();
+        assert!(config.hud.is_empty());
+
+        config.apply("hud = fps, drawcalls", "game.exe");
+        assert_eq!(config.hud, [HudElement::Fps, HudElement::DrawCalls]);
+
+        config.apply("hud = fps, memory", "game.exe");
+        assert_eq!(config.hud.len(), 2);
+    }
+
+    #[test]
+    fn fps_limit() {
+        let mut config = Config::default();
+        assert_eq!(config.fps_limit, None);
+
+        config.apply("fps_limit = 60", "game.exe");
+        assert_eq!(config.fps_limit, Some(60));
+
+        config.apply("fps_limit = 0", "game.exe");
+        assert_eq!(config.fps_limit, Some(60));
+    }
+
+    #[test]
+    fn forced_msaa() {
+        let mut config = Config::default();
+        assert_eq!(config.implicit_multisample_type(2), 2);
+
+        config.apply("msaa_samples = 8", "game.exe");
+        assert_eq!(config.implicit_multisample_type(D3DMULTISAMPLE_NONE), 8);
+        assert_eq!(config.multisample_type(D3DMULTISAMPLE_NONE), 0);
+
+        // Games which break with multisampling take precedence.
+        config.apply("disable_msaa = on", "game.exe");
+        assert_eq!(config.implicit_multisample_type(4), D3DMULTISAMPLE_NONE);
+    }
+}
@@ -0,0 +1,113 @@
+//! Parser for the configuration file format.
+//!
+//! The file is made up of `key = value` lines, grouped in sections.
+//! Options before the first section apply to all executables, while the ones in a
+//! `[name.exe]` section only apply to the executable with that name:
+//!
+//! ```ini
+//! # Comments start with a hash sign.
+//! max_frame_latency = 1
+//!
+//! [game.exe]
+//! vendor_id = 0x10de
+//! ```
+
+/// A single option read from the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry<'a> {
+    /// The section this option was found in, or `None` for global options.
+    pub section: Option<&'a str>,
+    /// Name of the option.
+    pub key: &'a str,
+    /// The unparsed value of the option.
+    pub value: &'a str,
+    /// Line on which the option was found, for reporting errors.
+    pub line: usize,
+}
+
+/// Splits a configuration file into its entries.
+///
+/// Malformed lines are reported and skipped.
+pub fn parse(text: &str) -> Vec<Entry> {
+    let mut section = None;
+    let mut entries = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+
+        // Strip comments and whitespace.
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            if line.ends_with(']') && line.len() > 2 {
+                section = Some(line[1..line.len() - 1].trim());
+            } else {
+                warn!("Config line {}: invalid section header", line_number);
+            }
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if !key.trim().is_empty() => entries.push(Entry {
+                section,
+                key: key.trim(),
+                value: value.trim(),
+                line: line_number,
+            }),
+            _ => warn!("Config line {}: expected `key = value`", line_number),
+        }
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_and_comments() {
+        let text = "
+            # A comment
+            vsync = on
+
+            [Game.exe]
+            max_frame_latency = 1 # Trailing comment
+            description = Some GPU
+        ";
+
+        let entries = parse(text);
+
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0].section, None);
+        assert_eq!(entries[0].key, "vsync");
+        assert_eq!(entries[0].value, "on");
+
+        assert_eq!(entries[1].section, Some("Game.exe"));
+        assert_eq!(entries[1].key, "max_frame_latency");
+        assert_eq!(entries[1].value, "1");
+
+        assert_eq!(entries[2].value, "Some GPU");
+        assert_eq!(entries[2].line, 7);
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let entries = parse("[]\nno value\n= 3\n[ok.exe]\nkey=value");
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].section, Some("ok.exe"));
+        assert_eq!(entries[0].key, "key");
+    }
+}
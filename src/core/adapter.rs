@@ -1,18 +1,23 @@
-use std::{cell::RefCell, collections::HashMap, mem, ptr};
+use std::{cell::RefCell, cmp, collections::HashMap, mem, ptr, rc::Rc, sync::Arc};
 
 use comptr::ComPtr;
 
 use winapi::shared::d3d9caps::*;
 use winapi::shared::d3d9types::*;
 use winapi::shared::dxgi::*;
-use winapi::shared::dxgitype::DXGI_MODE_DESC;
+use winapi::shared::dxgitype::*;
 use winapi::shared::windef::HMONITOR;
-use winapi::um::{d3d11::*, d3dcommon};
+use winapi::shared::ntdef::LUID;
+use winapi::um::d3d11::*;
 
 use super::{
+    caps,
     fmt::{d3d_format_to_dxgi, is_display_mode_format},
     *,
 };
+use crate::backend::Backend;
+use crate::config::Config;
+use crate::d3d11::D3D11Backend;
 use crate::{Error, Result};
 
 /// This class represents a physical graphics adapter (GPU).
@@ -28,13 +33,20 @@ pub struct Adapter {
     // Caches the supported display modes compatible with a certain format.
     mode_cache: RefCell<HashMap<D3DFORMAT, Box<[DXGI_MODE_DESC]>>>,
     // With D3D11, obtaining a device's capabilities or checking for texture format support
-    // requires us to create the device first.
-    device: ComPtr<ID3D11Device>,
+    // requires us to create the device first. It is shared by all the D3D9 devices.
+    backend: Rc<dyn Backend>,
+    // The user's configuration.
+    config: Arc<Config>,
 }
 
 impl Adapter {
-    /// Creates a new adapter.
-    pub fn new(index: u32, adapter: *mut IDXGIAdapter) -> Result<Self> {
+    /// Creates a new adapter, which executes commands on a D3D11 device.
+    pub fn new(
+        index: u32,
+        adapter: *mut IDXGIAdapter,
+        factory: ComPtr<IDXGIFactory>,
+        config: Arc<Config>,
+    ) -> Result<Self> {
         // DXGI interface representing a physical device.
         let adapter = ComPtr::new(adapter);
 
@@ -75,34 +87,8 @@ impl Adapter {
                 Ok(desc)
             }).ok();
 
-        // We need to also create the D3D11 device now.;
-        let mut feature_level = 0;
-        let device = unsafe {
-            let mut device = ptr::null_mut();
-            let result = D3D11CreateDevice(
-                // Create a device for the adapter we own.
-                adapter.as_mut(),
-                d3dcommon::D3D_DRIVER_TYPE_UNKNOWN,
-                ptr::null_mut(),
-                // No additional flags.
-                0,
-                // We will use whichever feature level is supported.
-                ptr::null_mut(),
-                0,
-                D3D11_SDK_VERSION,
-                &mut device,
-                &mut feature_level,
-                ptr::null_mut(),
-            );
-
-            check_hresult(result, "Failed to create D3D11 device")?;
-
-            ComPtr::new(device)
-        };
-
-        if feature_level < d3dcommon::D3D_FEATURE_LEVEL_11_0 {
-            warn!("Your GPU doesn't support all of D3D11's features");
-        }
+        // We need to also create the D3D11 device now.
+        let backend = D3D11Backend::new(&adapter, factory)?;
 
         let adapter = Self {
             index,
@@ -110,57 +96,64 @@ impl Adapter {
             output,
             output_desc,
             mode_cache: RefCell::new(HashMap::new()),
-            device,
+            backend: Rc::new(backend),
+            config,
         };
 
         Ok(adapter)
     }
 
+    /// Creates an adapter which executes commands on another backend.
+    ///
+    /// Such adapters have no outputs, so they have no display modes.
+    pub fn with_backend(index: u32, backend: Rc<dyn Backend>, config: Arc<Config>) -> Self {
+        let mut adapter_desc: DXGI_ADAPTER_DESC = unsafe { mem::zeroed() };
+
+        let name: Vec<u16> = backend.adapter_name().encode_utf16().collect();
+        let len = cmp::min(name.len(), adapter_desc.Description.len() - 1);
+        adapter_desc.Description[..len].copy_from_slice(&name[..len]);
+
+        Self {
+            index,
+            adapter_desc,
+            output: None,
+            output_desc: None,
+            mode_cache: RefCell::new(HashMap::new()),
+            backend,
+            config,
+        }
+    }
+
     /// Retrieves a description of this adapter.
     pub fn identifier(&self) -> D3DADAPTER_IDENTIFIER9 {
         let desc = &self.adapter_desc;
 
         let mut id: D3DADAPTER_IDENTIFIER9 = unsafe { mem::zeroed() };
 
+        let overrides = &self.config.adapter;
+
         // Internal identifier of the driver.
         let driver = "D3D 9-to-11 Driver";
-        unsafe {
-            ptr::copy_nonoverlapping(
-                driver.as_ptr(),
-                id.Driver.as_mut_ptr() as *mut u8,
-                driver.len(),
-            );
-        }
+        copy_str(&mut id.Driver, driver);
 
         // Human readable device description.
-        let dxgi_desc = crate::core::str::wstr_to_string(&desc.Description);
-        let description = format!("{} {}", dxgi_desc, "(D3D 9-to-11 Device)");
-
-        unsafe {
-            ptr::copy_nonoverlapping(
-                description.as_ptr(),
-                id.Description.as_mut_ptr() as *mut u8,
-                description.len(),
-            );
-        }
+        let description = overrides.description.clone().unwrap_or_else(|| {
+            let dxgi_desc = crate::core::str::wstr_to_string(&desc.Description);
+            format!("{} {}", dxgi_desc, "(D3D 9-to-11 Device)")
+        });
+        copy_str(&mut id.Description, &description);
 
         // Fake GDI device name
         let device_name = format!("DISPLAY{}", self.index);
-        unsafe {
-            ptr::copy_nonoverlapping(
-                device_name.as_ptr(),
-                id.DeviceName.as_mut_ptr() as *mut u8,
-                device_name.len(),
-            );
-        }
+        copy_str(&mut id.DeviceName, &device_name);
 
         unsafe {
-            *id.DriverVersion.QuadPart_mut() = 1;
+            *id.DriverVersion.QuadPart_mut() = overrides.driver_version.unwrap_or(1) as i64;
         }
 
-        // These fields are passed-through.
-        id.VendorId = desc.VendorId;
-        id.DeviceId = desc.DeviceId;
+        // These fields are passed-through, unless the user wants to spoof them.
+        id.VendorId = overrides.vendor_id.unwrap_or(desc.VendorId);
+        id.DeviceId = overrides.device_id.unwrap_or(desc.DeviceId);
         id.SubSysId = desc.SubSysId;
         id.Revision = desc.Revision;
 
@@ -207,30 +200,66 @@ impl Adapter {
             .map(|mode| D3DDISPLAYMODE {
                 Width: mode.Width,
                 Height: mode.Height,
-                RefreshRate: {
-                    let rf = mode.RefreshRate;
-                    if rf.Denominator == 0 {
-                        0
-                    } else {
-                        rf.Numerator / rf.Denominator
-                    }
-                },
+                RefreshRate: refresh_rate(mode.RefreshRate),
+                Format: fmt,
+            })
+    }
+
+    /// Returns the number of display modes which match a D3D9Ex mode filter.
+    pub fn mode_count_ex(&self, filter: &D3DDISPLAYMODEFILTER) -> u32 {
+        self.modes_ex(filter).len() as u32
+    }
+
+    /// Retrieves the display mode of a certain index, out of those which match a filter.
+    pub fn mode_ex(&self, filter: &D3DDISPLAYMODEFILTER, index: u32) -> Option<D3DDISPLAYMODEEX> {
+        self.modes_ex(filter).get(index as usize).cloned()
+    }
+
+    /// Retrieves the list of display modes which match a filter.
+    fn modes_ex(&self, filter: &D3DDISPLAYMODEFILTER) -> Vec<D3DDISPLAYMODEEX> {
+        let fmt = filter.Format;
+
+        if self.output.is_none() || !is_display_mode_format(fmt) {
+            return Vec::new();
+        }
+
+        self.cache_display_modes(fmt);
+
+        let mode_cache = self.mode_cache.borrow();
+        let modes = &mode_cache[&fmt];
+
+        modes
+            .iter()
+            .map(|mode| D3DDISPLAYMODEEX {
+                Size: mem::size_of::<D3DDISPLAYMODEEX>() as u32,
+                Width: mode.Width,
+                Height: mode.Height,
+                RefreshRate: refresh_rate(mode.RefreshRate),
                 Format: fmt,
+                ScanLineOrdering: match mode.ScanlineOrdering {
+                    DXGI_MODE_SCANLINE_ORDER_UPPER_FIELD_FIRST
+                    | DXGI_MODE_SCANLINE_ORDER_LOWER_FIELD_FIRST => D3DSCANLINEORDERING_INTERLACED,
+                    _ => D3DSCANLINEORDERING_PROGRESSIVE,
+                },
             })
+            // An unknown ordering in the filter matches all modes.
+            .filter(|mode| {
+                filter.ScanLineOrdering == D3DSCANLINEORDERING_UNKNOWN
+                    || filter.ScanLineOrdering == mode.ScanLineOrdering
+            })
+            .collect()
     }
 
     /// Checks if a given format is supported for a specific resource usage.
     pub fn is_format_supported(&self, fmt: D3DFORMAT, rt: ResourceType, usage: UsageFlags) -> bool {
-        let fmt = d3d_format_to_dxgi(fmt);
-
-        let support = unsafe {
-            let mut sp = 0;
-            if self.device.CheckFormatSupport(fmt, &mut sp) != 0 {
-                return false;
-            }
-            sp
+        let fmt = match d3d_format_to_dxgi(fmt) {
+            Ok(fmt) => fmt,
+            // Formats which we cannot store are obviously unsupported.
+            Err(_) => return false,
         };
 
+        let support = self.backend.format_support(fmt);
+
         // Returns true if a resource type is _not_ supported.
         let check_rt = |d3d9_rt, sp| (rt == d3d9_rt) && ((support & sp) == 0);
         let check_usage = |d3d9_usage, uf| usage.intersects(d3d9_usage) && ((support & uf) == 0);
@@ -257,120 +286,31 @@ impl Adapter {
     /// Checks if we support multisampling for a given format.
     /// Returns the maximum quality level supported for a given format.
     pub fn is_multisampling_supported(&self, fmt: D3DFORMAT, ms: D3DMULTISAMPLE_TYPE) -> u32 {
-        let fmt = d3d_format_to_dxgi(fmt);
-        let mut quality = 0;
-        unsafe {
-            // Even if this fails, quality is initialized to 0.
-            self.device
-                .CheckMultisampleQualityLevels(fmt, ms, &mut quality);
-        }
-
-        quality
+        let fmt = match d3d_format_to_dxgi(fmt) {
+            Ok(fmt) => fmt,
+            Err(_) => return 0,
+        };
+        self.backend.multisample_quality_levels(fmt, ms)
     }
 
     /// Returns the capabilities of this device.
-    pub fn caps(&self) -> D3DCAPS9 {
-        D3DCAPS9 {
-            DeviceType: D3DDEVTYPE_HAL,
-            AdapterOrdinal: self.index,
-            Caps: 0,
-            // TODO: implement D3DCAPS2_CANSHARERESOURCE for D3D9Ex
-            Caps2: D3DCAPS2_CANAUTOGENMIPMAP
-                | D3DCAPS2_CANCALIBRATEGAMMA
-                | D3DCAPS2_FULLSCREENGAMMA
-                | D3DCAPS2_CANMANAGERESOURCE
-                | D3DCAPS2_DYNAMICTEXTURES,
-            Caps3: D3DCAPS3_ALPHA_FULLSCREEN_FLIP_OR_DISCARD
-                | D3DCAPS3_COPY_TO_VIDMEM
-                | D3DCAPS3_COPY_TO_SYSTEMMEM
-                | D3DCAPS3_LINEAR_TO_SRGB_PRESENTATION,
-            // A lot of these features are bitflags, so we set all bits.
-            PresentationIntervals: !0,
-            CursorCaps: !0,
-            DevCaps: !0,
-            PrimitiveMiscCaps: !0,
-            RasterCaps: !0,
-            ZCmpCaps: !0,
-            SrcBlendCaps: !0,
-            DestBlendCaps: !0,
-            AlphaCmpCaps: !0,
-            ShadeCaps: !0,
-            // This cap indicates lack of support, so we mask it.
-            TextureCaps: !(D3DPTEXTURECAPS_CUBEMAP_POW2
-                | D3DPTEXTURECAPS_NOPROJECTEDBUMPENV
-                | D3DPTEXTURECAPS_NONPOW2CONDITIONAL
-                | D3DPTEXTURECAPS_POW2
-                | D3DPTEXTURECAPS_VOLUMEMAP_POW2),
-            TextureFilterCaps: !0,
-            CubeTextureFilterCaps: !0,
-            VolumeTextureFilterCaps: !0,
-            TextureAddressCaps: !0,
-            VolumeTextureAddressCaps: !0,
-            LineCaps: !0,
-            // The following caps are guaranteed on D3D11 hardware.
-            MaxTextureWidth: 16384,
-            MaxTextureHeight: 16384,
-            MaxVolumeExtent: 2048,
-            MaxTextureRepeat: 8192,
-            MaxTextureAspectRatio: 16384,
-            MaxAnisotropy: 16,
-            // The depth buffer is at most a 32-bit float.
-            MaxVertexW: std::f32::MAX,
-            // Modern GPUs have really big guard bands
-            GuardBandLeft: -100_000.0,
-            GuardBandTop: -100_000.0,
-            GuardBandRight: 100_000.0,
-            GuardBandBottom: 100_000.0,
-            ExtentsAdjust: 0.0,
-            StencilCaps: !0,
-            FVFCaps: !0,
-            TextureOpCaps: !0,
-            // These are set to the API limits.
-            MaxSimultaneousTextures: 8,
-            MaxTextureBlendStages: 8,
-            MaxActiveLights: 8,
-            MaxUserClipPlanes: 8,
-            MaxPrimitiveCount: std::u32::MAX,
-            MaxVertexIndex: std::u32::MAX,
-            MaxVertexBlendMatrices: std::u32::MAX,
-            MaxVertexBlendMatrixIndex: std::u32::MAX,
-            VertexProcessingCaps: !0,
-            MaxPointSize: 2048.0,
-            MaxStreams: 16,
-            MaxStreamStride: 1 << 31,
-            VertexShaderVersion: 0xFFFE_0000 | (3 << 8),
-            MaxVertexShaderConst: 1 << 16,
-            PixelShaderVersion: 0xFFFF_0000 | (3 << 8),
-            PixelShader1xMaxValue: 8.0,
-            DevCaps2: !0,
-            MaxNpatchTessellationLevel: 256.0,
-            Reserved5: 0,
-            // TODO: multihead support
-            MasterAdapterOrdinal: self.index,
-            NumberOfAdaptersInGroup: 1,
-            AdapterOrdinalInGroup: 0,
-            DeclTypes: !0,
-            NumSimultaneousRTs: 8,
-            StretchRectFilterCaps: !0,
-            VS20Caps: D3DVSHADERCAPS2_0 {
-                Caps: !0,
-                DynamicFlowControlDepth: 24,
-                NumTemps: 16384,
-                StaticFlowControlDepth: 1 << 31,
-            },
-            PS20Caps: D3DPSHADERCAPS2_0 {
-                Caps: !0,
-                DynamicFlowControlDepth: 24,
-                NumTemps: !16384,
-                StaticFlowControlDepth: 1 << 31,
-                NumInstructionSlots: 1 << 31,
-            },
-            VertexTextureFilterCaps: !0,
-            MaxVShaderInstructionsExecuted: !0,
-            MaxPShaderInstructionsExecuted: !0,
-            MaxVertexShader30InstructionSlots: 32768,
-            MaxPixelShader30InstructionSlots: 32768,
+    ///
+    /// Resources can only be shared by devices created through the D3D9Ex interface.
+    pub fn caps(&self, ex: bool) -> D3DCAPS9 {
+        let format_support = |fmt| self.backend.format_support(fmt);
+        let feature_level = self.backend.feature_level();
+
+        let mut caps = caps::build_caps(self.index, feature_level, &format_support);
+
+        if let Some(model) = self.config.workarounds.max_shader_model {
+            caps::limit_shader_model(&mut caps, model);
+        }
+
+        if ex {
+            caps.Caps2 |= D3DCAPS2_CANSHARERESOURCE;
         }
+
+        caps
     }
 
     /// Returns the (primary) monitor of this adapter.
@@ -380,13 +320,32 @@ impl Adapter {
             .unwrap_or(ptr::null_mut())
     }
 
-    /// Clones this adapter's D3D11 device.
-    pub fn device(&self) -> ComPtr<ID3D11Device> {
-        self.device.clone()
+    /// Returns the user's configuration.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns the real name of this adapter, ignoring the user's overrides.
+    pub fn name(&self) -> String {
+        crate::core::str::wstr_to_string(&self.adapter_desc.Description)
+    }
+
+    /// Returns the locally unique identifier of this adapter.
+    pub fn luid(&self) -> LUID {
+        self.adapter_desc.AdapterLuid
+    }
+
+    /// Returns the backend which executes the commands of this adapter's devices.
+    pub fn backend(&self) -> Rc<dyn Backend> {
+        self.backend.clone()
     }
 
     /// Returns the amount of memory this adapter has.
     pub fn available_memory(&self) -> u32 {
+        if let Some(mib) = self.config.adapter.available_memory {
+            return mib.saturating_mul(1024 * 1024);
+        }
+
         let desc = &self.adapter_desc;
 
         // We return the sum of the VRAM and the amount of shared RAM.
@@ -396,6 +355,12 @@ impl Adapter {
         // Round to the nearest MiB.
         let mem = (mem / (1024 * 1024)) * (1024 * 1024);
 
+        // Some games can't handle more memory than they were designed for.
+        let mem = match self.config.workarounds.max_available_memory {
+            Some(mib) => cmp::min(mem, (mib as usize).saturating_mul(1024 * 1024)),
+            None => mem,
+        };
+
         // Need to clamp to 32-bits.
         std::cmp::min(mem, std::u32::MAX as usize) as u32
     }
@@ -416,7 +381,16 @@ impl Adapter {
             }
         }
 
-        let format = d3d_format_to_dxgi(fmt);
+        let format = match d3d_format_to_dxgi(fmt) {
+            Ok(format) => format,
+            Err(_) => {
+                // Cache an empty list, since there are no modes with this format.
+                self.mode_cache
+                    .borrow_mut()
+                    .insert(fmt, Vec::new().into_boxed_slice());
+                return;
+            }
+        };
         let flags = 0;
 
         // Determine how big the list should be.
@@ -446,3 +420,24 @@ impl Adapter {
         mode_cache.insert(fmt, mode_descs);
     }
 }
+
+/// Converts a DXGI refresh rate to an integer number of Hz.
+fn refresh_rate(rf: DXGI_RATIONAL) -> u32 {
+    if rf.Denominator == 0 {
+        0
+    } else {
+        rf.Numerator / rf.Denominator
+    }
+}
+
+/// Copies a string into a fixed-size, null-terminated C string buffer,
+/// truncating it if necessary.
+fn copy_str(dest: &mut [i8], src: &str) {
+    let len = cmp::min(src.len(), dest.len() - 1);
+
+    unsafe {
+        ptr::copy_nonoverlapping(src.as_ptr(), dest.as_mut_ptr() as *mut u8, len);
+    }
+
+    dest[len] = 0;
+}
@@ -0,0 +1,635 @@
+//! Computes the capabilities we report to the app.
+//!
+//! D3D9 has a lot of optional features, while D3D11 groups them in feature levels.
+//! We only report a feature if the feature level guarantees it, so that apps
+//! don't end up on code paths we cannot support.
+
+use std::cmp;
+
+use winapi::shared::{d3d9caps::*, d3d9types::*, dxgiformat::*};
+use winapi::um::d3d11::*;
+use winapi::um::d3dcommon::*;
+
+/// Builds a D3D9 shader version token.
+const fn shader_version(prefix: u32, major: u32, minor: u32) -> u32 {
+    prefix | (major << 8) | minor
+}
+
+/// Returns the capabilities of an adapter with the given D3D11 feature level.
+///
+/// `format_support` returns the D3D11 format support flags of a DXGI format.
+pub fn build_caps(
+    index: u32,
+    feature_level: D3D_FEATURE_LEVEL,
+    format_support: &dyn Fn(DXGI_FORMAT) -> u32,
+) -> D3DCAPS9 {
+    // Feature level 10 is required for shader model 3.
+    let sm3 = feature_level >= D3D_FEATURE_LEVEL_10_0;
+
+    let supports = |fmt, flags| format_support(fmt) & flags == flags;
+
+    let max_texture_size = match feature_level {
+        D3D_FEATURE_LEVEL_9_1 | D3D_FEATURE_LEVEL_9_2 => 2048,
+        D3D_FEATURE_LEVEL_9_3 => 4096,
+        D3D_FEATURE_LEVEL_10_0 | D3D_FEATURE_LEVEL_10_1 => 8192,
+        _ => 16384,
+    };
+
+    let max_volume_extent = if feature_level >= D3D_FEATURE_LEVEL_10_0 {
+        2048
+    } else {
+        256
+    };
+
+    let num_render_targets = match feature_level {
+        D3D_FEATURE_LEVEL_9_1 | D3D_FEATURE_LEVEL_9_2 => 1,
+        D3D_FEATURE_LEVEL_9_3 => 4,
+        _ => 8,
+    };
+
+    let max_anisotropy = if feature_level >= D3D_FEATURE_LEVEL_9_2 {
+        16
+    } else {
+        2
+    };
+
+    let (max_primitive_count, max_vertex_index) = if feature_level >= D3D_FEATURE_LEVEL_9_2 {
+        (0x0055_5555, 0x00FF_FFFF)
+    } else {
+        (0xFFFF, 0xFFFF)
+    };
+
+    let max_stream_stride = if sm3 { 2048 } else { 508 };
+
+    let mut misc_caps = D3DPMISCCAPS_MASKZ
+        | D3DPMISCCAPS_CULLNONE
+        | D3DPMISCCAPS_CULLCW
+        | D3DPMISCCAPS_CULLCCW
+        | D3DPMISCCAPS_COLORWRITEENABLE
+        | D3DPMISCCAPS_CLIPPLANESCALEDPOINTS
+        | D3DPMISCCAPS_CLIPTLVERTS
+        | D3DPMISCCAPS_TSSARGTEMP
+        | D3DPMISCCAPS_BLENDOP
+        | D3DPMISCCAPS_PERSTAGECONSTANT
+        | D3DPMISCCAPS_FOGANDSPECULARALPHA
+        | D3DPMISCCAPS_FOGVERTEXCLAMPED;
+
+    if feature_level >= D3D_FEATURE_LEVEL_9_2 {
+        misc_caps |= D3DPMISCCAPS_SEPARATEALPHABLEND;
+    }
+
+    if sm3 {
+        misc_caps |= D3DPMISCCAPS_INDEPENDENTWRITEMASKS
+            | D3DPMISCCAPS_MRTINDEPENDENTBITDEPTHS
+            | D3DPMISCCAPS_MRTPOSTPIXELSHADERBLENDING;
+    }
+
+    // sRGB writes are done by rendering to an sRGB view of the render target,
+    // which needs blending support in order for the conversion to happen after blending.
+    let srgb_target = D3D11_FORMAT_SUPPORT_RENDER_TARGET | D3D11_FORMAT_SUPPORT_BLENDABLE;
+    let srgb_writes = supports(DXGI_FORMAT_B8G8R8A8_UNORM_SRGB, srgb_target)
+        && supports(DXGI_FORMAT_R8G8B8A8_UNORM_SRGB, srgb_target);
+
+    if srgb_writes {
+        misc_caps |= D3DPMISCCAPS_POSTBLENDSRGBCONVERT;
+    }
+
+    let mut blend_caps = D3DPBLENDCAPS_ZERO
+        | D3DPBLENDCAPS_ONE
+        | D3DPBLENDCAPS_SRCCOLOR
+        | D3DPBLENDCAPS_INVSRCCOLOR
+        | D3DPBLENDCAPS_SRCALPHA
+        | D3DPBLENDCAPS_INVSRCALPHA
+        | D3DPBLENDCAPS_DESTALPHA
+        | D3DPBLENDCAPS_INVDESTALPHA
+        | D3DPBLENDCAPS_DESTCOLOR
+        | D3DPBLENDCAPS_INVDESTCOLOR
+        | D3DPBLENDCAPS_SRCALPHASAT
+        | D3DPBLENDCAPS_BOTHSRCALPHA
+        | D3DPBLENDCAPS_BOTHINVSRCALPHA
+        | D3DPBLENDCAPS_BLENDFACTOR;
+
+    // Dual source blending.
+    if sm3 {
+        blend_caps |= D3DPBLENDCAPS_SRCCOLOR2 | D3DPBLENDCAPS_INVSRCCOLOR2;
+    }
+
+    let cmp_caps = D3DPCMPCAPS_NEVER
+        | D3DPCMPCAPS_LESS
+        | D3DPCMPCAPS_EQUAL
+        | D3DPCMPCAPS_LESSEQUAL
+        | D3DPCMPCAPS_GREATER
+        | D3DPCMPCAPS_NOTEQUAL
+        | D3DPCMPCAPS_GREATEREQUAL
+        | D3DPCMPCAPS_ALWAYS;
+
+    let mut texture_caps = D3DPTEXTURECAPS_PERSPECTIVE
+        | D3DPTEXTURECAPS_ALPHA
+        | D3DPTEXTURECAPS_PROJECTED
+        | D3DPTEXTURECAPS_CUBEMAP
+        | D3DPTEXTURECAPS_VOLUMEMAP
+        | D3DPTEXTURECAPS_MIPMAP
+        | D3DPTEXTURECAPS_MIPVOLUMEMAP
+        | D3DPTEXTURECAPS_MIPCUBEMAP
+        | D3DPTEXTURECAPS_TEXREPEATNOTSCALEDBYSIZE;
+
+    // Level 9 hardware only has limited support for non-power-of-2 textures.
+    if feature_level < D3D_FEATURE_LEVEL_10_0 {
+        texture_caps |= D3DPTEXTURECAPS_POW2
+            | D3DPTEXTURECAPS_NONPOW2CONDITIONAL
+            | D3DPTEXTURECAPS_CUBEMAP_POW2
+            | D3DPTEXTURECAPS_VOLUMEMAP_POW2;
+    }
+
+    let filter_caps = D3DPTFILTERCAPS_MINFPOINT
+        | D3DPTFILTERCAPS_MINFLINEAR
+        | D3DPTFILTERCAPS_MIPFPOINT
+        | D3DPTFILTERCAPS_MIPFLINEAR
+        | D3DPTFILTERCAPS_MAGFPOINT
+        | D3DPTFILTERCAPS_MAGFLINEAR;
+
+    let aniso_filter_caps =
+        filter_caps | D3DPTFILTERCAPS_MINFANISOTROPIC | D3DPTFILTERCAPS_MAGFANISOTROPIC;
+
+    let stretch_rect_filter_caps = D3DPTFILTERCAPS_MINFPOINT
+        | D3DPTFILTERCAPS_MINFLINEAR
+        | D3DPTFILTERCAPS_MAGFPOINT
+        | D3DPTFILTERCAPS_MAGFLINEAR;
+
+    // Vertex texture fetch is only available with shader model 3.
+    let vertex_texture_filter_caps = if sm3 { stretch_rect_filter_caps } else { 0 };
+
+    let mut address_caps = D3DPTADDRESSCAPS_WRAP
+        | D3DPTADDRESSCAPS_MIRROR
+        | D3DPTADDRESSCAPS_CLAMP
+        | D3DPTADDRESSCAPS_BORDER
+        | D3DPTADDRESSCAPS_INDEPENDENTUV;
+
+    if feature_level >= D3D_FEATURE_LEVEL_9_3 {
+        address_caps |= D3DPTADDRESSCAPS_MIRRORONCE;
+    }
+
+    let mut decl_types = D3DDTCAPS_UBYTE4
+        | D3DDTCAPS_UBYTE4N
+        | D3DDTCAPS_SHORT2N
+        | D3DDTCAPS_SHORT4N
+        | D3DDTCAPS_USHORT2N
+        | D3DDTCAPS_USHORT4N;
+
+    let half_float = |fmt| supports(fmt, D3D11_FORMAT_SUPPORT_IA_VERTEX_BUFFER);
+
+    if half_float(DXGI_FORMAT_R16G16_FLOAT) {
+        decl_types |= D3DDTCAPS_FLOAT16_2;
+    }
+
+    if half_float(DXGI_FORMAT_R16G16B16A16_FLOAT) {
+        decl_types |= D3DDTCAPS_FLOAT16_4;
+    }
+
+    let (vs_version, ps_version) = if sm3 {
+        (
+            shader_version(0xFFFE_0000, 3, 0),
+            shader_version(0xFFFF_0000, 3, 0),
+        )
+    } else {
+        (
+            shader_version(0xFFFE_0000, 2, 0),
+            shader_version(0xFFFF_0000, 2, 0),
+        )
+    };
+
+    let (vs20_caps, ps20_caps) = if sm3 {
+        let vs20_caps = D3DVSHADERCAPS2_0 {
+            Caps: D3DVS20CAPS_PREDICATION,
+            DynamicFlowControlDepth: D3DVS20_MAX_DYNAMICFLOWCONTROLDEPTH as i32,
+            NumTemps: D3DVS20_MAX_NUMTEMPS as i32,
+            StaticFlowControlDepth: D3DVS20_MAX_STATICFLOWCONTROLDEPTH as i32,
+        };
+
+        let ps20_caps = D3DPSHADERCAPS2_0 {
+            Caps: D3DPS20CAPS_ARBITRARYSWIZZLE
+                | D3DPS20CAPS_GRADIENTINSTRUCTIONS
+                | D3DPS20CAPS_PREDICATION
+                | D3DPS20CAPS_NODEPENDENTREADLIMIT
+                | D3DPS20CAPS_NOTEXINSTRUCTIONLIMIT,
+            DynamicFlowControlDepth: D3DPS20_MAX_DYNAMICFLOWCONTROLDEPTH as i32,
+            NumTemps: D3DPS20_MAX_NUMTEMPS as i32,
+            StaticFlowControlDepth: D3DPS20_MAX_STATICFLOWCONTROLDEPTH as i32,
+            NumInstructionSlots: D3DPS20_MAX_NUMINSTRUCTIONSLOTS as i32,
+        };
+
+        (vs20_caps, ps20_caps)
+    } else {
+        let vs20_caps = D3DVSHADERCAPS2_0 {
+            Caps: 0,
+            DynamicFlowControlDepth: 0,
+            NumTemps: D3DVS20_MIN_NUMTEMPS as i32,
+            StaticFlowControlDepth: D3DVS20_MIN_STATICFLOWCONTROLDEPTH as i32,
+        };
+
+        let ps20_caps = D3DPSHADERCAPS2_0 {
+            Caps: 0,
+            DynamicFlowControlDepth: 0,
+            NumTemps: D3DPS20_MIN_NUMTEMPS as i32,
+            StaticFlowControlDepth: 0,
+            NumInstructionSlots: D3DPS20_MIN_NUMINSTRUCTIONSLOTS as i32,
+        };
+
+        (vs20_caps, ps20_caps)
+    };
+
+    let (max_vs30_slots, max_ps30_slots) = if sm3 { (32768, 32768) } else { (0, 0) };
+
+    D3DCAPS9 {
+        DeviceType: D3DDEVTYPE_HAL,
+        AdapterOrdinal: index,
+        Caps: 0,
+        // Sharing resources is only possible with D3D9Ex devices, so the adapter adds that cap.
+        // Mipmaps are never generated, and gamma ramps are ignored.
+        Caps2: D3DCAPS2_CANMANAGERESOURCE | D3DCAPS2_DYNAMICTEXTURES,
+        Caps3: D3DCAPS3_ALPHA_FULLSCREEN_FLIP_OR_DISCARD
+            | D3DCAPS3_COPY_TO_VIDMEM
+            | D3DCAPS3_COPY_TO_SYSTEMMEM
+            | D3DCAPS3_LINEAR_TO_SRGB_PRESENTATION,
+        // DXGI supports sync intervals of up to 4.
+        PresentationIntervals: D3DPRESENT_INTERVAL_IMMEDIATE
+            | D3DPRESENT_INTERVAL_ONE
+            | D3DPRESENT_INTERVAL_TWO
+            | D3DPRESENT_INTERVAL_THREE
+            | D3DPRESENT_INTERVAL_FOUR,
+        // The cursor is implemented using the OS cursor.
+        CursorCaps: D3DCURSORCAPS_COLOR | D3DCURSORCAPS_LOWRES,
+        // We can't do any of the patch-related caps.
+        DevCaps: D3DDEVCAPS_EXECUTESYSTEMMEMORY
+            | D3DDEVCAPS_EXECUTEVIDEOMEMORY
+            | D3DDEVCAPS_TLVERTEXSYSTEMMEMORY
+            | D3DDEVCAPS_TLVERTEXVIDEOMEMORY
+            | D3DDEVCAPS_TEXTURESYSTEMMEMORY
+            | D3DDEVCAPS_TEXTUREVIDEOMEMORY
+            | D3DDEVCAPS_DRAWPRIMTLVERTEX
+            | D3DDEVCAPS_CANRENDERAFTERFLIP
+            | D3DDEVCAPS_TEXTURENONLOCALVIDMEM
+            | D3DDEVCAPS_DRAWPRIMITIVES2
+            | D3DDEVCAPS_DRAWPRIMITIVES2EX
+            | D3DDEVCAPS_HWTRANSFORMANDLIGHT
+            | D3DDEVCAPS_CANBLTSYSTONONLOCAL
+            | D3DDEVCAPS_HWRASTERIZATION
+            | D3DDEVCAPS_PUREDEVICE,
+        PrimitiveMiscCaps: misc_caps,
+        // W-buffering is not available in D3D11, and `SetScissorRect` is not implemented.
+        RasterCaps: D3DPRASTERCAPS_DITHER
+            | D3DPRASTERCAPS_ZTEST
+            | D3DPRASTERCAPS_FOGVERTEX
+            | D3DPRASTERCAPS_FOGTABLE
+            | D3DPRASTERCAPS_MIPMAPLODBIAS
+            | D3DPRASTERCAPS_ZFOG
+            | D3DPRASTERCAPS_FOGRANGE
+            | D3DPRASTERCAPS_ANISOTROPY
+            | D3DPRASTERCAPS_COLORPERSPECTIVE
+            | D3DPRASTERCAPS_SLOPESCALEDEPTHBIAS
+            | D3DPRASTERCAPS_DEPTHBIAS
+            | D3DPRASTERCAPS_MULTISAMPLE_TOGGLE,
+        ZCmpCaps: cmp_caps,
+        SrcBlendCaps: blend_caps,
+        DestBlendCaps: blend_caps,
+        AlphaCmpCaps: cmp_caps,
+        ShadeCaps: D3DPSHADECAPS_COLORGOURAUDRGB
+            | D3DPSHADECAPS_SPECULARGOURAUDRGB
+            | D3DPSHADECAPS_ALPHAGOURAUDBLEND
+            | D3DPSHADECAPS_FOGGOURAUD,
+        TextureCaps: texture_caps,
+        TextureFilterCaps: aniso_filter_caps,
+        CubeTextureFilterCaps: aniso_filter_caps,
+        VolumeTextureFilterCaps: filter_caps,
+        TextureAddressCaps: address_caps,
+        VolumeTextureAddressCaps: address_caps,
+        LineCaps: D3DLINECAPS_TEXTURE
+            | D3DLINECAPS_ZTEST
+            | D3DLINECAPS_BLEND
+            | D3DLINECAPS_ALPHACMP
+            | D3DLINECAPS_FOG
+            | D3DLINECAPS_ANTIALIAS,
+        MaxTextureWidth: max_texture_size,
+        MaxTextureHeight: max_texture_size,
+        MaxVolumeExtent: max_volume_extent,
+        MaxTextureRepeat: 8192,
+        MaxTextureAspectRatio: max_texture_size,
+        MaxAnisotropy: max_anisotropy,
+        // The depth buffer is at most a 32-bit float.
+        MaxVertexW: std::f32::MAX,
+        // Modern GPUs have really big guard bands
+        GuardBandLeft: -100_000.0,
+        GuardBandTop: -100_000.0,
+        GuardBandRight: 100_000.0,
+        GuardBandBottom: 100_000.0,
+        ExtentsAdjust: 0.0,
+        StencilCaps: D3DSTENCILCAPS_KEEP
+            | D3DSTENCILCAPS_ZERO
+            | D3DSTENCILCAPS_REPLACE
+            | D3DSTENCILCAPS_INCRSAT
+            | D3DSTENCILCAPS_DECRSAT
+            | D3DSTENCILCAPS_INVERT
+            | D3DSTENCILCAPS_INCR
+            | D3DSTENCILCAPS_DECR
+            | D3DSTENCILCAPS_TWOSIDED,
+        // The lower bits contain the maximum number of texture coordinate sets.
+        FVFCaps: D3DFVFCAPS_PSIZE | 8,
+        // There is no fixed function pipeline, so the texture stages' operations are ignored.
+        TextureOpCaps: 0,
+        // These are set to the API limits.
+        MaxSimultaneousTextures: 8,
+        MaxTextureBlendStages: 8,
+        // Fixed function lighting is not implemented.
+        MaxActiveLights: 0,
+        // `SetClipPlane` is not implemented.
+        MaxUserClipPlanes: 0,
+        MaxPrimitiveCount: max_primitive_count,
+        MaxVertexIndex: max_vertex_index,
+        // Vertex blending is part of the fixed function pipeline, which is not implemented.
+        MaxVertexBlendMatrices: 0,
+        MaxVertexBlendMatrixIndex: 0,
+        VertexProcessingCaps: 0,
+        MaxPointSize: 256.0,
+        MaxStreams: 16,
+        MaxStreamStride: max_stream_stride,
+        VertexShaderVersion: vs_version,
+        MaxVertexShaderConst: 256,
+        PixelShaderVersion: ps_version,
+        // Pixel shaders use floating point arithmetic.
+        PixelShader1xMaxValue: std::f32::MAX,
+        // N-patches and displacement maps are not supported.
+        // Stream offsets are passed on to the input assembler.
+        DevCaps2: D3DDEVCAPS2_STREAMOFFSET
+            | D3DDEVCAPS2_VERTEXELEMENTSCANSHARESTREAMOFFSET
+            | D3DDEVCAPS2_CAN_STRETCHRECT_FROM_TEXTURES,
+        MaxNpatchTessellationLevel: 0.0,
+        Reserved5: 0,
+        // TODO: multihead support
+        MasterAdapterOrdinal: index,
+        NumberOfAdaptersInGroup: 1,
+        AdapterOrdinalInGroup: 0,
+        DeclTypes: decl_types,
+        NumSimultaneousRTs: num_render_targets,
+        StretchRectFilterCaps: stretch_rect_filter_caps,
+        VS20Caps: vs20_caps,
+        PS20Caps: ps20_caps,
+        VertexTextureFilterCaps: vertex_texture_filter_caps,
+        MaxVShaderInstructionsExecuted: if sm3 { std::u32::MAX } else { 0xFFFF },
+        MaxPShaderInstructionsExecuted: if sm3 { std::u32::MAX } else { 0xFFFF },
+        MaxVertexShader30InstructionSlots: max_vs30_slots,
+        MaxPixelShader30InstructionSlots: max_ps30_slots,
+    }
+}
+
+/// Lowers the shader versions reported in the caps to a certain shader model.
+///
+/// Some games have broken code paths for newer shader models.
+pub fn limit_shader_model(caps: &mut D3DCAPS9, model: u32) {
+    // Shader model 1 has different minor versions for vertex and pixel shaders.
+    let (vs_version, ps_version) = match model {
+        1 => (
+            shader_version(0xFFFE_0000, 1, 1),
+            shader_version(0xFFFF_0000, 1, 4),
+        ),
+        _ => (
+            shader_version(0xFFFE_0000, model, 0),
+            shader_version(0xFFFF_0000, model, 0),
+        ),
+    };
+
+    caps.VertexShaderVersion = cmp::min(caps.VertexShaderVersion, vs_version);
+    caps.PixelShaderVersion = cmp::min(caps.PixelShaderVersion, ps_version);
+
+    if model < 3 {
+        caps.MaxVertexShader30InstructionSlots = 0;
+        caps.MaxPixelShader30InstructionSlots = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The caps we expect for a certain feature level.
+    struct Expected {
+        feature_level: D3D_FEATURE_LEVEL,
+        vs_version: u32,
+        ps_version: u32,
+        max_texture_size: u32,
+        render_targets: u32,
+        max_anisotropy: u32,
+        pow2_textures: bool,
+        vertex_textures: bool,
+    }
+
+    const EXPECTED: &[Expected] = &[
+        Expected {
+            feature_level: D3D_FEATURE_LEVEL_9_1,
+            vs_version: 0xFFFE_0200,
+            ps_version: 0xFFFF_0200,
+            max_texture_size: 2048,
+            render_targets: 1,
+            max_anisotropy: 2,
+            pow2_textures: true,
+            vertex_textures: false,
+        },
+        Expected {
+            feature_level: D3D_FEATURE_LEVEL_9_3,
+            vs_version: 0xFFFE_0200,
+            ps_version: 0xFFFF_0200,
+            max_texture_size: 4096,
+            render_targets: 4,
+            max_anisotropy: 16,
+            pow2_textures: true,
+            vertex_textures: false,
+        },
+        Expected {
+            feature_level: D3D_FEATURE_LEVEL_10_0,
+            vs_version: 0xFFFE_0300,
+            ps_version: 0xFFFF_0300,
+            max_texture_size: 8192,
+            render_targets: 8,
+            max_anisotropy: 16,
+            pow2_textures: false,
+            vertex_textures: true,
+        },
+        Expected {
+            feature_level: D3D_FEATURE_LEVEL_11_0,
+            vs_version: 0xFFFE_0300,
+            ps_version: 0xFFFF_0300,
+            max_texture_size: 16384,
+            render_targets: 8,
+            max_anisotropy: 16,
+            pow2_textures: false,
+            vertex_textures: true,
+        },
+    ];
+
+    fn all_formats(_: DXGI_FORMAT) -> u32 {
+        !0
+    }
+
+    #[test]
+    fn caps_per_feature_level() {
+        for exp in EXPECTED {
+            let caps = build_caps(0, exp.feature_level, &all_formats);
+            let fl = exp.feature_level;
+
+            assert_eq!(caps.VertexShaderVersion, exp.vs_version, "FL {:x}", fl);
+            assert_eq!(caps.PixelShaderVersion, exp.ps_version, "FL {:x}", fl);
+            assert_eq!(caps.MaxTextureWidth, exp.max_texture_size, "FL {:x}", fl);
+            assert_eq!(caps.MaxTextureHeight, exp.max_texture_size, "FL {:x}", fl);
+            assert_eq!(caps.NumSimultaneousRTs, exp.render_targets, "FL {:x}", fl);
+            assert_eq!(caps.MaxAnisotropy, exp.max_anisotropy, "FL {:x}", fl);
+            assert_eq!(caps.MaxStreams, 16, "FL {:x}", fl);
+
+            let pow2 = caps.TextureCaps & D3DPTEXTURECAPS_POW2 != 0;
+            assert_eq!(pow2, exp.pow2_textures, "FL {:x}", fl);
+
+            let vertex_textures = caps.VertexTextureFilterCaps != 0;
+            assert_eq!(vertex_textures, exp.vertex_textures, "FL {:x}", fl);
+        }
+    }
+
+    #[test]
+    fn unsupported_features_are_not_reported() {
+        for exp in EXPECTED {
+            let caps = build_caps(0, exp.feature_level, &all_formats);
+
+            assert_eq!(caps.DevCaps & D3DDEVCAPS_NPATCHES, 0);
+            assert_eq!(caps.DevCaps & D3DDEVCAPS_RTPATCHES, 0);
+            assert_eq!(caps.RasterCaps & D3DPRASTERCAPS_WBUFFER, 0);
+            assert_eq!(caps.MaxNpatchTessellationLevel, 0.0);
+        }
+    }
+
+    /// A group of caps, and the flags in it which must not be reported.
+    struct Unimplemented {
+        name: &'static str,
+        caps: fn(&D3DCAPS9) -> u32,
+        flags: u32,
+    }
+
+    const UNIMPLEMENTED: &[Unimplemented] = &[
+        // `GenerateMipSubLevels` does nothing.
+        Unimplemented {
+            name: "Caps2",
+            caps: |caps| caps.Caps2,
+            flags: D3DCAPS2_CANAUTOGENMIPMAP,
+        },
+        // Gamma ramps are ignored.
+        Unimplemented {
+            name: "Caps2",
+            caps: |caps| caps.Caps2,
+            flags: D3DCAPS2_CANCALIBRATEGAMMA | D3DCAPS2_FULLSCREENGAMMA,
+        },
+        // There is no fixed function pipeline.
+        Unimplemented {
+            name: "TextureOpCaps",
+            caps: |caps| caps.TextureOpCaps,
+            flags: !0,
+        },
+        Unimplemented {
+            name: "VertexProcessingCaps",
+            caps: |caps| caps.VertexProcessingCaps,
+            flags: !0,
+        },
+        Unimplemented {
+            name: "MaxVertexBlendMatrices",
+            caps: |caps| caps.MaxVertexBlendMatrices,
+            flags: !0,
+        },
+        // `SetScissorRect` and `SetClipPlane` are not implemented.
+        Unimplemented {
+            name: "RasterCaps",
+            caps: |caps| caps.RasterCaps,
+            flags: D3DPRASTERCAPS_SCISSORTEST,
+        },
+        Unimplemented {
+            name: "MaxUserClipPlanes",
+            caps: |caps| caps.MaxUserClipPlanes,
+            flags: !0,
+        },
+        // Only D3D9Ex devices can share resources.
+        Unimplemented {
+            name: "Caps2",
+            caps: |caps| caps.Caps2,
+            flags: D3DCAPS2_CANSHARERESOURCE,
+        },
+        // `SetLight` and `LightEnable` are not implemented.
+        Unimplemented {
+            name: "MaxActiveLights",
+            caps: |caps| caps.MaxActiveLights,
+            flags: !0,
+        },
+    ];
+
+    #[test]
+    fn unimplemented_features_are_not_reported() {
+        for exp in EXPECTED {
+            let caps = build_caps(0, exp.feature_level, &all_formats);
+
+            for feature in UNIMPLEMENTED {
+                let reported = (feature.caps)(&caps) & feature.flags;
+                assert_eq!(reported, 0, "{} {:#x}", feature.name, feature.flags);
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_writes_follow_format_support() {
+        let rt = D3D11_FORMAT_SUPPORT_RENDER_TARGET;
+        let blend = D3D11_FORMAT_SUPPORT_BLENDABLE;
+
+        // The support of the BGRA and RGBA sRGB formats, and whether sRGB writes are reported.
+        let cases = [
+            (0, 0, false),
+            (blend, blend, false),
+            (rt, rt, false),
+            (rt | blend, 0, false),
+            (rt | blend, rt | blend, true),
+        ];
+
+        for &(bgra, rgba, expected) in &cases {
+            let support = |fmt| match fmt {
+                DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => bgra,
+                DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => rgba,
+                _ => !0,
+            };
+
+            let caps = build_caps(0, D3D_FEATURE_LEVEL_11_0, &support);
+            let reported = caps.PrimitiveMiscCaps & D3DPMISCCAPS_POSTBLENDSRGBCONVERT != 0;
+            assert_eq!(reported, expected, "{:#x} {:#x}", bgra, rgba);
+        }
+    }
+
+    #[test]
+    fn decl_types_follow_format_support() {
+        let none = |_| 0;
+        let caps = build_caps(0, D3D_FEATURE_LEVEL_11_0, &none);
+        assert_eq!(caps.DeclTypes & D3DDTCAPS_FLOAT16_2, 0);
+        assert_eq!(caps.DeclTypes & D3DDTCAPS_FLOAT16_4, 0);
+
+        let caps = build_caps(0, D3D_FEATURE_LEVEL_11_0, &all_formats);
+        assert_ne!(caps.DeclTypes & D3DDTCAPS_FLOAT16_2, 0);
+        assert_ne!(caps.DeclTypes & D3DDTCAPS_FLOAT16_4, 0);
+    }
+
+    #[test]
+    fn shader_model_can_be_lowered() {
+        let mut caps = build_caps(0, D3D_FEATURE_LEVEL_11_0, &all_formats);
+        limit_shader_model(&mut caps, 2);
+        assert_eq!(caps.VertexShaderVersion, 0xFFFE_0200);
+        assert_eq!(caps.PixelShaderVersion, 0xFFFF_0200);
+        assert_eq!(caps.MaxPixelShader30InstructionSlots, 0);
+
+        // The limit never raises the shader model.
+        let mut caps = build_caps(0, D3D_FEATURE_LEVEL_9_3, &all_formats);
+        limit_shader_model(&mut caps, 3);
+        assert_eq!(caps.PixelShaderVersion, 0xFFFF_0200);
+
+        limit_shader_model(&mut caps, 1);
+        assert_eq!(caps.VertexShaderVersion, 0xFFFE_0101);
+        assert_eq!(caps.PixelShaderVersion, 0xFFFF_0104);
+    }
+}
@@ -1,6 +1,10 @@
 use std::{
     mem, ptr,
-    sync::atomic::{AtomicU32, Ordering},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
 };
 
 use comptr::ComPtr;
@@ -9,11 +13,12 @@ use winapi::shared::d3d9::*;
 use winapi::shared::d3d9caps::D3DCAPS9;
 use winapi::shared::d3d9types::*;
 use winapi::shared::dxgi;
+use winapi::shared::ntdef::LUID;
 use winapi::shared::windef::{HMONITOR, HWND};
 use winapi::um::winuser;
 use winapi::Interface;
 use winapi::{
-    shared::d3d9::{IDirect3D9, IDirect3D9Vtbl},
+    shared::d3d9::{IDirect3D9, IDirect3D9Ex, IDirect3D9ExVtbl, IDirect3D9Vtbl},
     um::unknwnbase::{IUnknown, IUnknownVtbl},
 };
 
@@ -23,21 +28,27 @@ use super::{
     fmt::{is_depth_stencil_format, is_display_mode_format},
     *,
 };
-use crate::{dev::Device, Error, Result};
+use crate::{backend::Backend, config::Config, dev::Device, trace, Error, Result};
 
 /// D3D9 interface which stores all application context.
 ///
 /// Similar in role to a DXGI factory.
-#[interface(IDirect3D9)]
+///
+/// This implements both the original and the D3D9Ex interfaces,
+/// but the latter is only exposed by contexts created through `Direct3DCreate9Ex`.
+#[interface(IDirect3D9Ex)]
 pub struct Context {
     refs: AtomicU32,
-    factory: ComPtr<dxgi::IDXGIFactory>,
+    ex: bool,
     adapters: Vec<Adapter>,
+    config: Arc<Config>,
 }
 
 impl Context {
-    /// Creates a new D3D9 context.
-    pub fn new() -> Result<ComPtr<Context>> {
+    /// Creates a new D3D9 context, for an app built against a certain SDK version.
+    ///
+    /// If `ex` is set, the context can be queried for the D3D9Ex interface.
+    pub fn new(sdk_version: u32, ex: bool) -> Result<ComPtr<Context>> {
         // We first have to create a factory, which is the equivalent of this interface in DXGI terms.
         let factory = unsafe {
             let uuid = dxgi::IDXGIFactory::uuidof();
@@ -49,26 +60,59 @@ impl Context {
             ComPtr::new(factory)
         };
 
+        let config = Self::load_config(sdk_version);
+
         // Now we can enumerate all the graphics adapters on the system.
         let adapters = (0..)
             .scan(ptr::null_mut(), |adapter, id| unsafe {
                 let result = factory.EnumAdapters(id, adapter);
                 if result == 0 {
-                    Adapter::new(id, *adapter).ok()
+                    Adapter::new(id, *adapter, factory.clone(), config.clone()).ok()
                 } else {
                     None
                 }
             }).fuse()
             .collect();
 
+        Ok(Self::with_adapters(adapters, config, ex))
+    }
+
+    /// Creates a new D3D9 context with a single adapter, whose devices execute
+    /// their commands on the given backend.
+    pub fn with_backend(sdk_version: u32, ex: bool, backend: Rc<dyn Backend>) -> ComPtr<Context> {
+        let config = Self::load_config(sdk_version);
+        let adapter = Adapter::with_backend(0, backend, config.clone());
+
+        Self::with_adapters(vec![adapter], config, ex)
+    }
+
+    /// Loads the user's options and the compatibility workarounds for this app.
+    fn load_config(sdk_version: u32) -> Arc<Config> {
+        let config = Arc::new(Config::load(sdk_version));
+
+        if let Some(ref path) = config.trace_file {
+            trace::start(path);
+        }
+
+        config
+    }
+
+    /// Wraps a list of adapters which were already created.
+    fn with_adapters(adapters: Vec<Adapter>, config: Arc<Config>, ex: bool) -> ComPtr<Context> {
         let ctx = Self {
             __vtable: Box::new(Self::create_vtable()),
             refs: AtomicU32::new(1),
-            factory,
+            ex,
             adapters,
+            config,
         };
 
-        Ok(unsafe { new_com_interface(ctx) })
+        unsafe { new_com_interface(ctx) }
+    }
+
+    /// Returns the user's configuration.
+    pub fn config(&self) -> &Config {
+        &self.config
     }
 
     fn check_adapter(&self, adapter: u32) -> Result<&Adapter> {
@@ -83,22 +127,76 @@ impl Context {
             _ => Error::InvalidCall,
         }
     }
+
+    /// Common code for creating both normal and D3D9Ex devices.
+    fn create_device_helper(
+        &self,
+        adapter: u32,
+        ty: D3DDEVTYPE,
+        focus: HWND,
+        flags: u32,
+        pp: *mut D3DPRESENT_PARAMETERS,
+        ex: bool,
+    ) -> Result<ComPtr<Device>> {
+        self.check_devty(ty)?;
+
+        // TODO: support using multiple GPUs
+        if flags & D3DCREATE_ADAPTERGROUP_DEVICE != 0 {
+            warn!("Application requested the creation of a multi-GPU logical device");
+        }
+
+        if flags & D3DCREATE_FPU_PRESERVE == 0 {
+            // We need to set the right x87 control word to disable FPU exceptions.
+            unsafe {
+                // First we need to retrieve its current value.
+                let mut c = 0u16;
+                asm!("fnstcw $0" : "=*m"(&c) : : : "volatile");
+
+                // Clear (some of) the control word's bits:
+                // - Sets rounding mode to nearest even.
+                // - Enable single precision floats.
+                c &= 0b11_11_00_00_11 << 6;
+
+                // Mask all exceptions.
+                c |= (1 << 6) - 1;
+
+                asm!("fldcw $0" : : "*m"(&c) : : : "volatile")
+            }
+        }
+
+        // This struct stores the original device creation parameters.
+        let cp = D3DDEVICE_CREATION_PARAMETERS {
+            AdapterOrdinal: adapter,
+            DeviceType: D3DDEVTYPE_HAL,
+            hFocusWindow: focus,
+            BehaviorFlags: flags,
+        };
+
+        // This structure describes some settings for the back buffer(s).
+        // Since we don't support multiple adapters, we only use the first param in the array.
+        let pp = check_mut_ref(pp)?;
+
+        // Create the actual device.
+        crate::Device::new(self, self.check_adapter(adapter)?, cp, pp, ex)
+    }
 }
 
-impl_iunknown!(struct Context: IUnknown, IDirect3D9);
+impl_iunknown!(struct Context: IUnknown, IDirect3D9; ex: IDirect3D9Ex);
 
 #[implementation(IDirect3D9)]
 impl Context {
     /// Used to register a software rasterizer.
     fn register_software_device(&self, init_fn: *mut c_void) -> Error {
-        check_not_null(init_fn)?;
+        guarded!("IDirect3D9::RegisterSoftwareDevice", {
+            check_not_null(init_fn)?;
 
-        warn!("Application tried to register software device");
+            warn!("Application tried to register software device");
 
-        // We don't suppor software rendering, but we report success here since
-        // this call would simply allow software rasterization in cases where
-        // the graphics adapter does not support it.
-        Error::Success
+            // We don't suppor software rendering, but we report success here since
+            // this call would simply allow software rasterization in cases where
+            // the graphics adapter does not support it.
+            Error::Success
+        })
     }
 
     /// Returns the number of GPUs installed on the system.
@@ -115,20 +213,24 @@ impl Context {
         _flags: u32,
         ident: *mut D3DADAPTER_IDENTIFIER9,
     ) -> Error {
-        let adapter = self.check_adapter(adapter)?;
-        let ident = check_mut_ref(ident)?;
+        guarded!("IDirect3D9::GetAdapterIdentifier", {
+            let adapter = self.check_adapter(adapter)?;
+            let ident = check_mut_ref(ident)?;
 
-        *ident = adapter.identifier();
+            *ident = adapter.identifier();
 
-        Error::Success
+            Error::Success
+        })
     }
 
     /// Returns the number of display modes with a certain format an adapter supports.
     fn get_adapter_mode_count(&self, adapter: u32, fmt: D3DFORMAT) -> u32 {
-        self.adapters
-            .get(adapter as usize)
-            .map(|adapter| adapter.mode_count(fmt))
-            .unwrap_or_default()
+        guarded!("IDirect3D9::GetAdapterModeCount", {
+            self.adapters
+                .get(adapter as usize)
+                .map(|adapter| adapter.mode_count(fmt))
+                .unwrap_or_default()
+        })
     }
 
     /// Retrieves the list of display modes.
@@ -139,37 +241,41 @@ impl Context {
         i: u32,
         mode: *mut D3DDISPLAYMODE,
     ) -> Error {
-        let adapter = self.check_adapter(adapter)?;
-        let mode = check_mut_ref(mode)?;
+        guarded!("IDirect3D9::EnumAdapterModes", {
+            let adapter = self.check_adapter(adapter)?;
+            let mode = check_mut_ref(mode)?;
 
-        *mode = adapter.mode(fmt, i).ok_or(Error::NotAvailable)?;
+            *mode = adapter.mode(fmt, i).ok_or(Error::NotAvailable)?;
 
-        Error::Success
+            Error::Success
+        })
     }
 
     /// Retrieve the current display mode of the GPU.
     fn get_adapter_display_mode(&self, adapter: u32, mode: *mut D3DDISPLAYMODE) -> Error {
-        let monitor = self.get_adapter_monitor(adapter);
-        let mode = check_mut_ref(mode)?;
-
-        let mi = unsafe {
-            let mut mi: winuser::MONITORINFO = mem::uninitialized();
-            mi.cbSize = mem::size_of_val(&mi) as u32;
-            let result = winuser::GetMonitorInfoW(monitor, &mut mi);
-            assert_ne!(result, 0, "Failed to retrieve monitor info");
-            mi
-        };
+        guarded!("IDirect3D9::GetAdapterDisplayMode", {
+            let monitor = self.get_adapter_monitor(adapter);
+            let mode = check_mut_ref(mode)?;
+
+            let mi = unsafe {
+                let mut mi: winuser::MONITORINFO = mem::uninitialized();
+                mi.cbSize = mem::size_of_val(&mi) as u32;
+                let result = winuser::GetMonitorInfoW(monitor, &mut mi);
+                assert_ne!(result, 0, "Failed to retrieve monitor info");
+                mi
+            };
+
+            let rc = mi.rcMonitor;
+
+            mode.Width = (rc.right - rc.left) as u32;
+            mode.Height = (rc.bottom - rc.top) as u32;
+            // 0 indicates an adapter-default rate.
+            mode.RefreshRate = 0;
+            // This format is usually what modern displays use internally.
+            mode.Format = D3DFMT_X8R8G8B8;
 
-        let rc = mi.rcMonitor;
-
-        mode.Width = (rc.right - rc.left) as u32;
-        mode.Height = (rc.bottom - rc.top) as u32;
-        // 0 indicates an adapter-default rate.
-        mode.RefreshRate = 0;
-        // This format is usually what modern displays use internally.
-        mode.Format = D3DFMT_X8R8G8B8;
-
-        Error::Success
+            Error::Success
+        })
     }
 
     /// Checks if an adapter is hardware accelerated.
@@ -181,15 +287,17 @@ impl Context {
         _bb_fmt: D3DFORMAT,
         _windowed: u32,
     ) -> Error {
-        self.check_adapter(adapter)?;
-        self.check_devty(ty)?;
-
-        // We support hardware accel with all valid formats.
-        if is_display_mode_format(adapter_fmt) {
-            Error::Success
-        } else {
-            Error::NotAvailable
-        }
+        guarded!("IDirect3D9::CheckDeviceType", {
+            self.check_adapter(adapter)?;
+            self.check_devty(ty)?;
+
+            // We support hardware accel with all valid formats.
+            if is_display_mode_format(adapter_fmt) {
+                Error::Success
+            } else {
+                Error::NotAvailable
+            }
+        })
     }
 
     /// Checks if a certain format can be used for something.
@@ -202,14 +310,26 @@ impl Context {
         rt: ResourceType,
         check_fmt: D3DFORMAT,
     ) -> Error {
-        let adapter = self.check_adapter(adapter)?;
-        self.check_devty(ty)?;
+        guarded!("IDirect3D9::CheckDeviceFormat", {
+            let adapter = self.check_adapter(adapter)?;
+            self.check_devty(ty)?;
 
-        if adapter.is_format_supported(check_fmt, rt, usage) {
-            Error::Success
-        } else {
-            Error::NotAvailable
-        }
+            if self.config.is_format_disabled(check_fmt) {
+                return Error::NotAvailable;
+            }
+
+            // Mipmaps are never generated, but such textures can still be created.
+            let autogen = usage.intersects(UsageFlags::AUTO_GEN_MIP_MAP);
+            let usage = usage - UsageFlags::AUTO_GEN_MIP_MAP;
+
+            if !adapter.is_format_supported(check_fmt, rt, usage) {
+                Error::NotAvailable
+            } else if autogen {
+                Error::NoAutoGen
+            } else {
+                Error::Success
+            }
+        })
     }
 
     /// Checks if a format can be used with multisampling.
@@ -222,24 +342,31 @@ impl Context {
         mst: D3DMULTISAMPLE_TYPE,
         quality: *mut u32,
     ) -> Error {
-        let adapter = self.check_adapter(adapter)?;
-        self.check_devty(ty)?;
+        guarded!("IDirect3D9::CheckDeviceMultiSampleType", {
+            let adapter = self.check_adapter(adapter)?;
+            self.check_devty(ty)?;
 
-        let quality = check_mut_ref(quality);
+            let quality = check_mut_ref(quality);
 
-        let q = adapter.is_multisampling_supported(surface_fmt, mst);
+            // Games which break with multisampling should not be able to enable it.
+            if self.config.multisample_type(mst) != mst {
+                return Error::NotAvailable;
+            }
 
-        // Return the maximum quality level, if requested.
-        if let Ok(quality) = quality {
-            *quality = q;
-        }
+            let q = adapter.is_multisampling_supported(surface_fmt, mst);
 
-        // Max quality of 0 would mean no support for MS.
-        if q == 0 {
-            Error::NotAvailable
-        } else {
-            Error::Success
-        }
+            // Return the maximum quality level, if requested.
+            if let Ok(quality) = quality {
+                *quality = q;
+            }
+
+            // Max quality of 0 would mean no support for MS.
+            if q == 0 {
+                Error::NotAvailable
+            } else {
+                Error::Success
+            }
+        })
     }
 
     /// Checks if a depth/stencil format can be used with a RT format.
@@ -251,19 +378,21 @@ impl Context {
         _rt_fmt: D3DFORMAT,
         ds_fmt: D3DFORMAT,
     ) -> Error {
-        self.check_adapter(adapter)?;
-        self.check_devty(ty)?;
-
-        // We don't check the adapter fmt / render target fmt since on modern GPUs
-        // basically any valid combination of formats is allowed.
-
-        // We only have to check that the format which was passed in
-        // can be used with d/s buffers.
-        if is_depth_stencil_format(ds_fmt) {
-            Error::Success
-        } else {
-            Error::NotAvailable
-        }
+        guarded!("IDirect3D9::CheckDepthStencilMatch", {
+            self.check_adapter(adapter)?;
+            self.check_devty(ty)?;
+
+            // We don't check the adapter fmt / render target fmt since on modern GPUs
+            // basically any valid combination of formats is allowed.
+
+            // We only have to check that the format which was passed in
+            // can be used with d/s buffers.
+            if is_depth_stencil_format(ds_fmt) {
+                Error::Success
+            } else {
+                Error::NotAvailable
+            }
+        })
     }
 
     /// Checks if a conversion between two given formats is supported.
@@ -274,31 +403,38 @@ impl Context {
         _src_fmt: D3DFORMAT,
         _tgt_fmt: D3DFORMAT,
     ) -> Error {
-        self.check_adapter(adapter)?;
-        self.check_devty(ty)?;
+        guarded!("IDirect3D9::CheckDeviceFormatConversion", {
+            self.check_adapter(adapter)?;
+            self.check_devty(ty)?;
 
-        // For most types we can simply convert them to the right format on-the-fly.
-        // TODO: we should at least validate the formats to make sure they are valid for back buffers.
+            // For most types we can simply convert them to the right format on-the-fly.
+            // TODO: we should at least validate the formats to make sure
+            // they are valid for back buffers.
 
-        Error::Success
+            Error::Success
+        })
     }
 
     /// Returns a structure describing the features and limits of an adapter.
     fn get_device_caps(&self, adapter: u32, ty: D3DDEVTYPE, caps: *mut D3DCAPS9) -> Error {
-        let adapter = self.check_adapter(adapter)?;
-        self.check_devty(ty)?;
-        let caps = check_mut_ref(caps)?;
+        guarded!("IDirect3D9::GetDeviceCaps", {
+            let adapter = self.check_adapter(adapter)?;
+            self.check_devty(ty)?;
+            let caps = check_mut_ref(caps)?;
 
-        *caps = adapter.caps();
+            *caps = adapter.caps(self.ex);
 
-        Error::Success
+            Error::Success
+        })
     }
 
     /// Retrieves the monitor associated with an adapter.
     fn get_adapter_monitor(&self, adapter: u32) -> HMONITOR {
-        self.check_adapter(adapter)
-            .map(|adapter| adapter.monitor())
-            .unwrap_or(ptr::null_mut())
+        guarded!("IDirect3D9::GetAdapterMonitor", {
+            self.check_adapter(adapter)
+                .map(|adapter| adapter.monitor())
+                .unwrap_or(ptr::null_mut())
+        })
     }
 
     /// Creates a logical device from an adapter.
@@ -311,54 +447,229 @@ impl Context {
         pp: *mut D3DPRESENT_PARAMETERS,
         device: *mut *mut Device,
     ) -> Error {
-        self.check_devty(ty)?;
-        let ret = check_mut_ref(device)?;
+        traced!(
+            self,
+            "IDirect3D9::CreateDevice",
+            [
+                adapter,
+                ty,
+                trace::object(focus),
+                flags,
+                trace::data(pp),
+                trace::returned(device)
+            ],
+            {
+                let ret = check_mut_ref(device)?;
+
+                *ret = self
+                    .create_device_helper(adapter, ty, focus, flags, pp, false)?
+                    .into();
+
+                Error::Success
+            }
+        )
+    }
+}
 
-        // TODO: support using multiple GPUs
-        if flags & D3DCREATE_ADAPTERGROUP_DEVICE != 0 {
-            warn!("Application requested the creation of a multi-GPU logical device");
-        }
+#[implementation(IDirect3D9Ex)]
+impl Context {
+    /// Returns the number of display modes matching a filter an adapter supports.
+    fn get_adapter_mode_count_ex(&self, adapter: u32, filter: *const D3DDISPLAYMODEFILTER) -> u32 {
+        guarded!("IDirect3D9Ex::GetAdapterModeCountEx", {
+            let filter = match check_ref(filter) {
+                Ok(filter) => filter,
+                Err(_) => return 0,
+            };
+
+            self.adapters
+                .get(adapter as usize)
+                .map(|adapter| adapter.mode_count_ex(filter))
+                .unwrap_or_default()
+        })
+    }
 
-        if flags & D3DCREATE_FPU_PRESERVE == 0 {
-            // We need to set the right x87 control word to disable FPU exceptions.
-            unsafe {
-                // First we need to retrieve its current value.
-                let mut c = 0u16;
-                asm!("fnstcw $0" : "=*m"(&c) : : : "volatile");
+    /// Retrieves the list of display modes matching a filter.
+    fn enum_adapter_modes_ex(
+        &self,
+        adapter: u32,
+        filter: *const D3DDISPLAYMODEFILTER,
+        i: u32,
+        mode: *mut D3DDISPLAYMODEEX,
+    ) -> Error {
+        guarded!("IDirect3D9Ex::EnumAdapterModesEx", {
+            let adapter = self.check_adapter(adapter)?;
+            let filter = check_ref(filter)?;
+            let mode = check_mut_ref(mode)?;
 
-                // Clear (some of) the control word's bits:
-                // - Sets rounding mode to nearest even.
-                // - Enable single precision floats.
-                c &= 0b11_11_00_00_11 << 6;
+            *mode = adapter.mode_ex(filter, i).ok_or(Error::NotAvailable)?;
 
-                // Mask all exceptions.
-                c |= (1 << 6) - 1;
+            Error::Success
+        })
+    }
 
-                asm!("fldcw $0" : : "*m"(&c) : : : "volatile")
+    /// Retrieves the current display mode and rotation of the GPU.
+    fn get_adapter_display_mode_ex(
+        &self,
+        adapter: u32,
+        mode: *mut D3DDISPLAYMODEEX,
+        rotation: *mut D3DDISPLAYROTATION,
+    ) -> Error {
+        guarded!("IDirect3D9Ex::GetAdapterDisplayModeEx", {
+            let mode = check_mut_ref(mode)?;
+
+            let mut dm = unsafe { mem::zeroed() };
+            self.get_adapter_display_mode(adapter, &mut dm)?;
+
+            *mode = D3DDISPLAYMODEEX {
+                Size: mem::size_of::<D3DDISPLAYMODEEX>() as u32,
+                Width: dm.Width,
+                Height: dm.Height,
+                RefreshRate: dm.RefreshRate,
+                Format: dm.Format,
+                ScanLineOrdering: D3DSCANLINEORDERING_PROGRESSIVE,
+            };
+
+            // We do not support rotated displays.
+            if let Ok(rotation) = check_mut_ref(rotation) {
+                *rotation = D3DDISPLAYROTATION_IDENTITY;
             }
-        }
 
-        // This struct stores the original device creation parameters.
-        let cp = D3DDEVICE_CREATION_PARAMETERS {
-            AdapterOrdinal: adapter,
-            DeviceType: D3DDEVTYPE_HAL,
-            hFocusWindow: focus,
-            BehaviorFlags: flags,
-        };
-
-        // This structure describes some settings for the back buffer(s).
-        // Since we don't support multiple adapters, we only use the first param in the array.
-        let pp = check_mut_ref(pp)?;
+            Error::Success
+        })
+    }
 
-        // Create the actual device.
-        *ret = crate::Device::new(
+    /// Creates a D3D9Ex logical device from an adapter.
+    fn create_device_ex(
+        &self,
+        adapter: u32,
+        ty: D3DDEVTYPE,
+        focus: HWND,
+        flags: u32,
+        pp: *mut D3DPRESENT_PARAMETERS,
+        fs_mode: *mut D3DDISPLAYMODEEX,
+        device: *mut *mut Device,
+    ) -> Error {
+        traced!(
             self,
-            self.check_adapter(adapter)?,
-            cp,
-            pp,
-            self.factory.clone(),
-        )?.into();
+            "IDirect3D9Ex::CreateDeviceEx",
+            [
+                adapter,
+                ty,
+                trace::object(focus),
+                flags,
+                trace::data(pp),
+                trace::data(fs_mode),
+                trace::returned(device)
+            ],
+            {
+                let ret = check_mut_ref(device)?;
+
+                // The full-screen mode is required if and only if the device is full-screen.
+                {
+                    let pp = check_ref(pp)?;
+                    let windowed = pp.Windowed != 0;
+
+                    match (windowed, unsafe { fs_mode.as_ref() }) {
+                        (true, Some(_)) | (false, None) => return Error::InvalidCall,
+                        (false, Some(mode))
+                            if mode.RefreshRate != pp.FullScreen_RefreshRateInHz =>
+                        {
+                            warn!(
+                                "Full-screen display mode does not match presentation parameters"
+                            );
+                        }
+                        _ => (),
+                    }
+                }
+
+                *ret = self
+                    .create_device_helper(adapter, ty, focus, flags, pp, true)?
+                    .into();
+
+                Error::Success
+            }
+        )
+    }
+
+    /// Retrieves the locally unique identifier of an adapter.
+    fn get_adapter_l_u_i_d(&self, adapter: u32, luid: *mut LUID) -> Error {
+        guarded!("IDirect3D9Ex::GetAdapterLUID", {
+            let adapter = self.check_adapter(adapter)?;
+            let luid = check_mut_ref(luid)?;
+
+            *luid = adapter.luid();
+
+            Error::Success
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{Call, MockBackend};
+    use winapi::shared::dxgiformat::*;
+
+    #[test]
+    fn device_on_mock_backend() {
+        let backend = Rc::new(MockBackend::new());
+        let ctx = Context::with_backend(D3D_SDK_VERSION, false, backend.clone());
+
+        let mut pp = D3DPRESENT_PARAMETERS {
+            BackBufferWidth: 64,
+            BackBufferHeight: 32,
+            BackBufferFormat: D3DFMT_X8R8G8B8,
+            BackBufferCount: 1,
+            SwapEffect: D3DSWAPEFFECT_DISCARD,
+            // The mock never looks at the window.
+            hDeviceWindow: 1 as HWND,
+            Windowed: 1,
+            EnableAutoDepthStencil: 1,
+            AutoDepthStencilFormat: D3DFMT_D24S8,
+            ..unsafe { mem::zeroed() }
+        };
 
-        Error::Success
+        let flags = D3DCREATE_FPU_PRESERVE;
+        let _device = ctx
+            .create_device_helper(0, D3DDEVTYPE_HAL, ptr::null_mut(), flags, &mut pp, false)
+            .unwrap();
+
+        let calls = backend.take_calls();
+
+        // With DISCARD, the app draws straight into the swap chain's buffer.
+        let back_buffer = calls
+            .iter()
+            .filter_map(|call| match *call {
+                Call::CreateSwapChain {
+                    buffer,
+                    width: 64,
+                    height: 32,
+                    windowed: true,
+                    ..
+                } => Some(buffer),
+                _ => None,
+            })
+            .next()
+            .expect("No swap chain was created");
+
+        let depth_stencil = calls
+            .iter()
+            .filter_map(|call| match *call {
+                Call::CreateTexture {
+                    id,
+                    width: 64,
+                    height: 32,
+                    format: DXGI_FORMAT_D24_UNORM_S8_UINT,
+                } => Some(id),
+                _ => None,
+            })
+            .next()
+            .expect("No depth / stencil buffer was created");
+
+        // The implicit surfaces are bound as soon as the device is created.
+        assert!(calls.contains(&Call::SetRenderTargets {
+            targets: vec![Some((back_buffer, DXGI_FORMAT_B8G8R8A8_UNORM))],
+            depth_stencil: Some(depth_stencil),
+        }));
     }
 }
@@ -7,13 +7,318 @@
 use winapi::shared::d3d9types::*;
 use winapi::shared::dxgiformat::*;
 
+use crate::{Error, Result};
+
+/// The broad category a format belongs to.
+///
+/// This determines how the channels of a format should be interpreted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FormatClass {
+    /// Ordinary (possibly floating point) color format.
+    Color,
+    /// Luminance format, possibly with an alpha channel.
+    Luminance,
+    /// Signed format, used for bump maps or normal maps.
+    Bump,
+    /// Depth and / or stencil buffer format.
+    DepthStencil,
+    /// Block compressed format.
+    Compressed,
+    /// Packed YUV format, used for hardware video.
+    Video,
+    /// Palettized format.
+    Palette,
+    /// Vertex / index buffer format.
+    Buffer,
+}
+
+/// Describes the memory layout and semantics of a D3D9 format.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FormatInfo {
+    /// The format which is described.
+    pub format: D3DFORMAT,
+    /// The DXGI format used to store resources with this format.
+    ///
+    /// Set to `DXGI_FORMAT_UNKNOWN` if there is no DXGI format we could use.
+    pub dxgi: DXGI_FORMAT,
+    /// The category this format belongs to.
+    pub class: FormatClass,
+    /// Number of bits each pixel occupies in memory.
+    ///
+    /// For block compressed formats this is the average size of a pixel.
+    pub bits_per_pixel: u32,
+    /// Width of a block of pixels, which are always stored together.
+    pub block_width: u32,
+    /// Height of a block of pixels, which are always stored together.
+    pub block_height: u32,
+    /// Number of bits used for depth, for depth / stencil formats.
+    pub depth_bits: u32,
+    /// Number of bits used for stencil, for depth / stencil formats.
+    pub stencil_bits: u32,
+    /// Whether this format has a meaningful alpha channel.
+    pub has_alpha: bool,
+    /// Whether the host DXGI format stores an alpha channel which this format lacks.
+    ///
+    /// For example, `X8R8G8B8` and `A8R8G8B8` are both stored as `B8G8R8A8`,
+    /// but reading the alpha of the former must always return 1.
+    pub x_channel: bool,
+    /// The sRGB variant of the DXGI format, if it has one.
+    pub srgb: DXGI_FORMAT,
+    /// The typeless variant of the DXGI format, which is compatible with
+    /// both the normal and the sRGB variants.
+    pub typeless: DXGI_FORMAT,
+    /// The swizzle which has to be applied when sampling a texture with this format.
+    ///
+    /// D3D9 has some rules for the values returned for the channels a format lacks,
+    /// which are different from what D3D11 returns for the host format.
+    pub swizzle: Swizzle,
+}
+
+impl FormatInfo {
+    /// Creates the description of an uncompressed format with no special properties.
+    const fn new(
+        format: D3DFORMAT,
+        dxgi: DXGI_FORMAT,
+        class: FormatClass,
+        bits_per_pixel: u32,
+    ) -> Self {
+        Self {
+            format,
+            dxgi,
+            class,
+            bits_per_pixel,
+            block_width: 1,
+            block_height: 1,
+            depth_bits: 0,
+            stencil_bits: 0,
+            has_alpha: false,
+            x_channel: false,
+            srgb: DXGI_FORMAT_UNKNOWN,
+            typeless: DXGI_FORMAT_UNKNOWN,
+            swizzle: Swizzle::IDENTITY,
+        }
+    }
+
+    /// Checks if there is a DXGI format which can store this format.
+    pub fn is_supported(&self) -> bool {
+        self.dxgi != DXGI_FORMAT_UNKNOWN
+    }
+
+    /// Checks if this format is block compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.class == FormatClass::Compressed
+    }
+
+    /// Checks if this format is a depth / stencil format.
+    pub fn is_depth_stencil(&self) -> bool {
+        self.class == FormatClass::DepthStencil
+    }
+
+    /// Checks if textures with this format can be read from / written to as sRGB.
+    pub fn is_srgb_capable(&self) -> bool {
+        self.srgb != DXGI_FORMAT_UNKNOWN
+    }
+
+    /// Returns the number of bytes a block of pixels occupies.
+    pub fn block_size(&self) -> u32 {
+        (self.block_width * self.block_height * self.bits_per_pixel) / 8
+    }
+
+    /// Computes the number of bytes in a row of blocks, for an image of a certain width.
+    pub fn row_pitch(&self, width: u32) -> u32 {
+        let blocks = (width + self.block_width - 1) / self.block_width;
+        blocks * self.block_size()
+    }
+
+    /// Computes the number of rows of blocks, for an image of a certain height.
+    pub fn row_count(&self, height: u32) -> u32 {
+        (height + self.block_height - 1) / self.block_height
+    }
+
+    /// Computes the size in bytes of a tightly packed image with this format.
+    pub fn image_size(&self, width: u32, height: u32) -> u32 {
+        self.row_pitch(width) * self.row_count(height)
+    }
+}
+
+// Shorthand for describing a format in the table below.
+macro_rules! info {
+    ($fmt:ident => $dxgi:ident, $class:ident, $bpp:expr) => {
+        FormatInfo::new($fmt, $dxgi, FormatClass::$class, $bpp)
+    };
+    ($fmt:ident => $dxgi:ident, $class:ident, $bpp:expr, { $($field:ident: $value:expr),* }) => {
+        FormatInfo {
+            $($field: $value,)*
+            ..FormatInfo::new($fmt, $dxgi, FormatClass::$class, $bpp)
+        }
+    };
+}
+
+// Based upon the following reference:
+// https://docs.microsoft.com/en-us/windows/desktop/direct3d10/d3d10-graphics-programming-guide-resources-legacy-formats
+//
+// When multiple D3D9 formats share a DXGI format, the one listed first
+// is used when converting back from DXGI.
+static FORMATS: &[FormatInfo] = &[
+    // Unknown format
+    info!(D3DFMT_UNKNOWN => DXGI_FORMAT_UNKNOWN, Color, 0),
+
+    // 8 bit formats
+    info!(D3DFMT_A8 => DXGI_FORMAT_A8_UNORM, Color, 8, { has_alpha: true }),
+    info!(D3DFMT_L8 => DXGI_FORMAT_R8_UNORM, Luminance, 8, { swizzle: Swizzle::LUMINANCE }),
+
+    // 16 bit formats
+    info!(D3DFMT_R5G6B5 => DXGI_FORMAT_B5G6R5_UNORM, Color, 16),
+    info!(D3DFMT_A4R4G4B4 => DXGI_FORMAT_B4G4R4A4_UNORM, Color, 16, { has_alpha: true }),
+    info!(D3DFMT_X4R4G4B4 => DXGI_FORMAT_B4G4R4A4_UNORM, Color, 16, {
+        x_channel: true, swizzle: Swizzle::OPAQUE
+    }),
+    info!(D3DFMT_A1R5G5B5 => DXGI_FORMAT_B5G5R5A1_UNORM, Color, 16, { has_alpha: true }),
+    info!(D3DFMT_X1R5G5B5 => DXGI_FORMAT_B5G5R5A1_UNORM, Color, 16, {
+        x_channel: true, swizzle: Swizzle::OPAQUE
+    }),
+    info!(D3DFMT_A8L8 => DXGI_FORMAT_R8G8_UNORM, Luminance, 16, {
+        has_alpha: true, swizzle: Swizzle::LUMINANCE_ALPHA
+    }),
+    info!(D3DFMT_L16 => DXGI_FORMAT_R16_UNORM, Luminance, 16, { swizzle: Swizzle::LUMINANCE }),
+
+    // 32 bit formats
+    // Note that X8R8G8B8 is not stored as B8G8R8X8, since DXGI does not allow
+    // copying between B8G8R8X8 and B8G8R8A8, and swap chains cannot use the former.
+    // Its alpha reads as 1 thanks to the swizzle, which the backend applies when sampling.
+    info!(D3DFMT_A8R8G8B8 => DXGI_FORMAT_B8G8R8A8_UNORM, Color, 32, {
+        has_alpha: true,
+        srgb: DXGI_FORMAT_B8G8R8A8_UNORM_SRGB, typeless: DXGI_FORMAT_B8G8R8A8_TYPELESS
+    }),
+    info!(D3DFMT_X8R8G8B8 => DXGI_FORMAT_B8G8R8A8_UNORM, Color, 32, {
+        x_channel: true,
+        srgb: DXGI_FORMAT_B8G8R8A8_UNORM_SRGB, typeless: DXGI_FORMAT_B8G8R8A8_TYPELESS,
+        swizzle: Swizzle::OPAQUE
+    }),
+    info!(D3DFMT_A8B8G8R8 => DXGI_FORMAT_R8G8B8A8_UNORM, Color, 32, {
+        has_alpha: true,
+        srgb: DXGI_FORMAT_R8G8B8A8_UNORM_SRGB, typeless: DXGI_FORMAT_R8G8B8A8_TYPELESS
+    }),
+    info!(D3DFMT_X8B8G8R8 => DXGI_FORMAT_R8G8B8A8_UNORM, Color, 32, {
+        x_channel: true,
+        srgb: DXGI_FORMAT_R8G8B8A8_UNORM_SRGB, typeless: DXGI_FORMAT_R8G8B8A8_TYPELESS,
+        swizzle: Swizzle::OPAQUE
+    }),
+    info!(D3DFMT_G16R16 => DXGI_FORMAT_R16G16_UNORM, Color, 32, { swizzle: Swizzle::RG }),
+
+    // HDR formats
+    info!(D3DFMT_A2B10G10R10 => DXGI_FORMAT_R10G10B10A2_UNORM, Color, 32, { has_alpha: true }),
+    // DXGI has no format with this channel order. Back buffers can still use it,
+    // since they are stored with the display's format, which is handled separately.
+    info!(D3DFMT_A2R10G10B10 => DXGI_FORMAT_UNKNOWN, Color, 32, { has_alpha: true }),
+    info!(D3DFMT_A16B16G16R16 => DXGI_FORMAT_R16G16B16A16_UNORM, Color, 64, { has_alpha: true }),
+
+    // Depth / stencil formats
+    info!(D3DFMT_D24S8 => DXGI_FORMAT_D24_UNORM_S8_UINT, DepthStencil, 32, {
+        depth_bits: 24, stencil_bits: 8
+    }),
+    info!(D3DFMT_D24X8 => DXGI_FORMAT_D24_UNORM_S8_UINT, DepthStencil, 32, { depth_bits: 24 }),
+    info!(D3DFMT_D24X4S4 => DXGI_FORMAT_D24_UNORM_S8_UINT, DepthStencil, 32, {
+        depth_bits: 24, stencil_bits: 4
+    }),
+    info!(D3DFMT_D16 => DXGI_FORMAT_D16_UNORM, DepthStencil, 16, { depth_bits: 16 }),
+    info!(D3DFMT_D16_LOCKABLE => DXGI_FORMAT_D16_UNORM, DepthStencil, 16, { depth_bits: 16 }),
+    info!(D3DFMT_D32 => DXGI_FORMAT_D32_FLOAT, DepthStencil, 32, { depth_bits: 32 }),
+    info!(D3DFMT_D32F_LOCKABLE => DXGI_FORMAT_D32_FLOAT, DepthStencil, 32, { depth_bits: 32 }),
+    // DXGI has no 24-bit float depth format, so we use a bigger one.
+    // The size has to match the host format's, since that is how the data is laid out.
+    info!(D3DFMT_D24FS8 => DXGI_FORMAT_D32_FLOAT_S8X24_UINT, DepthStencil, 64, {
+        depth_bits: 24, stencil_bits: 8
+    }),
+    info!(D3DFMT_D15S1 => DXGI_FORMAT_UNKNOWN, DepthStencil, 16, {
+        depth_bits: 15, stencil_bits: 1
+    }),
+    info!(D3DFMT_S8_LOCKABLE => DXGI_FORMAT_UNKNOWN, DepthStencil, 8, { stencil_bits: 8 }),
+
+    // Compressed formats
+    // DXT2 and DXT4 are the premultiplied alpha versions of DXT3 and DXT5.
+    info!(D3DFMT_DXT1 => DXGI_FORMAT_BC1_UNORM, Compressed, 4, {
+        block_width: 4, block_height: 4, has_alpha: true,
+        srgb: DXGI_FORMAT_BC1_UNORM_SRGB, typeless: DXGI_FORMAT_BC1_TYPELESS
+    }),
+    info!(D3DFMT_DXT3 => DXGI_FORMAT_BC2_UNORM, Compressed, 8, {
+        block_width: 4, block_height: 4, has_alpha: true,
+        srgb: DXGI_FORMAT_BC2_UNORM_SRGB, typeless: DXGI_FORMAT_BC2_TYPELESS
+    }),
+    info!(D3DFMT_DXT2 => DXGI_FORMAT_BC2_UNORM, Compressed, 8, {
+        block_width: 4, block_height: 4, has_alpha: true,
+        srgb: DXGI_FORMAT_BC2_UNORM_SRGB, typeless: DXGI_FORMAT_BC2_TYPELESS
+    }),
+    info!(D3DFMT_DXT5 => DXGI_FORMAT_BC3_UNORM, Compressed, 8, {
+        block_width: 4, block_height: 4, has_alpha: true,
+        srgb: DXGI_FORMAT_BC3_UNORM_SRGB, typeless: DXGI_FORMAT_BC3_TYPELESS
+    }),
+    info!(D3DFMT_DXT4 => DXGI_FORMAT_BC3_UNORM, Compressed, 8, {
+        block_width: 4, block_height: 4, has_alpha: true,
+        srgb: DXGI_FORMAT_BC3_UNORM_SRGB, typeless: DXGI_FORMAT_BC3_TYPELESS
+    }),
+
+    // Special formats: mostly used for hardware video.
+    info!(D3DFMT_R8G8_B8G8 => DXGI_FORMAT_G8R8_G8B8_UNORM, Video, 16, { block_width: 2 }),
+    info!(D3DFMT_G8R8_G8B8 => DXGI_FORMAT_R8G8_B8G8_UNORM, Video, 16, { block_width: 2 }),
+    info!(D3DFMT_UYVY => DXGI_FORMAT_UNKNOWN, Video, 16, { block_width: 2 }),
+    info!(D3DFMT_YUY2 => DXGI_FORMAT_UNKNOWN, Video, 16, { block_width: 2 }),
+
+    // Signed formats
+    info!(D3DFMT_V8U8 => DXGI_FORMAT_R8G8_SNORM, Bump, 16, { swizzle: Swizzle::RG }),
+    info!(D3DFMT_Q8W8V8U8 => DXGI_FORMAT_R8G8B8A8_SNORM, Bump, 32, { has_alpha: true }),
+    info!(D3DFMT_V16U16 => DXGI_FORMAT_R16G16_SNORM, Bump, 32, { swizzle: Swizzle::RG }),
+    info!(D3DFMT_Q16W16V16U16 => DXGI_FORMAT_R16G16B16A16_SNORM, Bump, 64, { has_alpha: true }),
+
+    // Floating point formats
+    info!(D3DFMT_R16F => DXGI_FORMAT_R16_FLOAT, Color, 16, { swizzle: Swizzle::R }),
+    info!(D3DFMT_G16R16F => DXGI_FORMAT_R16G16_FLOAT, Color, 32, { swizzle: Swizzle::RG }),
+    info!(D3DFMT_A16B16G16R16F => DXGI_FORMAT_R16G16B16A16_FLOAT, Color, 64, { has_alpha: true }),
+    info!(D3DFMT_R32F => DXGI_FORMAT_R32_FLOAT, Color, 32, { swizzle: Swizzle::R }),
+    info!(D3DFMT_G32R32F => DXGI_FORMAT_R32G32_FLOAT, Color, 64, { swizzle: Swizzle::RG }),
+    info!(D3DFMT_A32B32G32R32F => DXGI_FORMAT_R32G32B32A32_FLOAT, Color, 128, { has_alpha: true }),
+
+    // Buffer formats
+    info!(D3DFMT_INDEX16 => DXGI_FORMAT_R16_UINT, Buffer, 16),
+    info!(D3DFMT_INDEX32 => DXGI_FORMAT_R32_UINT, Buffer, 32),
+
+    // Unsupported formats
+    // TODO: some formats have no support in modern DXGI.
+    // We might still be able to approximate them with some other formats though.
+    info!(D3DFMT_R8G8B8 => DXGI_FORMAT_UNKNOWN, Color, 24),
+    info!(D3DFMT_R3G3B2 => DXGI_FORMAT_UNKNOWN, Color, 8),
+    info!(D3DFMT_A8R3G3B2 => DXGI_FORMAT_UNKNOWN, Color, 16, { has_alpha: true }),
+    info!(D3DFMT_P8 => DXGI_FORMAT_UNKNOWN, Palette, 8),
+    info!(D3DFMT_A8P8 => DXGI_FORMAT_UNKNOWN, Palette, 16, { has_alpha: true }),
+    info!(D3DFMT_A4L4 => DXGI_FORMAT_UNKNOWN, Luminance, 8, {
+        has_alpha: true, swizzle: Swizzle::LUMINANCE_ALPHA
+    }),
+    info!(D3DFMT_CxV8U8 => DXGI_FORMAT_UNKNOWN, Bump, 16),
+    info!(D3DFMT_L6V5U5 => DXGI_FORMAT_UNKNOWN, Bump, 16),
+    info!(D3DFMT_X8L8V8U8 => DXGI_FORMAT_UNKNOWN, Bump, 32),
+    info!(D3DFMT_A2W10V10U10 => DXGI_FORMAT_UNKNOWN, Bump, 32, { has_alpha: true }),
+];
+
+/// Retrieves the description of a format.
+///
+/// Returns an error if the format is not a valid D3D9 format.
+pub fn format_info(fmt: D3DFORMAT) -> Result<&'static FormatInfo> {
+    FORMATS
+        .iter()
+        .find(|info| info.format == fmt)
+        .ok_or(Error::NotAvailable)
+}
+
 /// Converts a display mode format to its corresponding DXGI format.
-pub fn d3d_display_format_to_dxgi(fmt: D3DFORMAT) -> DXGI_FORMAT {
+pub fn d3d_display_format_to_dxgi(fmt: D3DFORMAT) -> Result<DXGI_FORMAT> {
     match fmt {
         // We have to map all these formats to a format DXGI supports.
-        D3DFMT_R8G8B8..=D3DFMT_A1R5G5B5 | D3DFMT_UNKNOWN => DXGI_FORMAT_B8G8R8A8_UNORM,
-        D3DFMT_A2R10G10B10 => DXGI_FORMAT_R10G10B10A2_UNORM,
-        _ => panic!("Unknown D3D9 display format: {}", fmt),
+        D3DFMT_R8G8B8..=D3DFMT_A1R5G5B5 | D3DFMT_UNKNOWN => Ok(DXGI_FORMAT_B8G8R8A8_UNORM),
+        D3DFMT_A2R10G10B10 => Ok(DXGI_FORMAT_R10G10B10A2_UNORM),
+        _ => {
+            error!("Unknown D3D9 display format: {}", fmt);
+            Err(Error::NotAvailable)
+        }
     }
 }
 
@@ -34,120 +339,365 @@ pub fn is_display_mode_format(fmt: D3DFORMAT) -> bool {
 
 /// Checks if a given format is a valid D/S buffer format.
 pub fn is_depth_stencil_format(fmt: D3DFORMAT) -> bool {
-    match fmt {
-        // TODO: there are also some unused formats in this range.
-        // Need to check all formats in this range to be valid.
-        D3DFMT_D16_LOCKABLE..=D3DFMT_S8_LOCKABLE => true,
-        _ => false,
+    format_info(fmt)
+        .map(|info| info.is_depth_stencil() && info.is_supported())
+        .unwrap_or(false)
+}
+
+/// Converts a general resource format to a DXGI format.
+///
+/// Returns an error if the format is unknown, or if we cannot store it.
+pub fn d3d_format_to_dxgi(fmt: D3DFORMAT) -> Result<DXGI_FORMAT> {
+    let info = format_info(fmt)?;
+
+    if info.is_supported() || fmt == D3DFMT_UNKNOWN {
+        Ok(info.dxgi)
+    } else {
+        Err(Error::NotAvailable)
     }
 }
 
-// This macro is used to generate bi-directional mapping between D3D and DXGI formats.
-macro_rules! format_conv {
-    ($($a:path => $b:path,)*) => {
-        /// Converts a general resource format to a DXGI format.
-        pub fn d3d_format_to_dxgi(fmt: D3DFORMAT) -> DXGI_FORMAT {
-            #[allow(non_upper_case_globals)]
-            match fmt {
-                $($a => $b,)*
+/// Converts a DXGI format back into a Direct3D format.
+///
+/// Some DXGI formats map to multiple D3D9 formats,
+/// in which case the first one in the format table is returned.
+pub fn dxgi_format_to_d3d(fmt: DXGI_FORMAT) -> Result<D3DFORMAT> {
+    FORMATS
+        .iter()
+        .find(|info| info.dxgi == fmt)
+        .map(|info| info.format)
+        .ok_or(Error::NotAvailable)
+}
+
+/// Retrieves the description of a format which is stored using a certain DXGI format.
+///
+/// The sRGB and typeless variants of the DXGI format are also matched,
+/// since they share the same memory layout.
+pub fn dxgi_format_info(fmt: DXGI_FORMAT) -> Result<&'static FormatInfo> {
+    if fmt == DXGI_FORMAT_UNKNOWN {
+        return Err(Error::NotAvailable);
+    }
+
+    FORMATS
+        .iter()
+        .find(|info| info.dxgi == fmt || info.srgb == fmt || info.typeless == fmt)
+        .ok_or(Error::NotAvailable)
+}
+
+/// A source for a channel read by a texture sampler.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[repr(u8)]
+pub enum Channel {
+    R = 0,
+    G = 1,
+    B = 2,
+    A = 3,
+    /// Channel always reads as 0.
+    Zero = 4,
+    /// Channel always reads as 1.
+    One = 5,
+}
+
+/// Describes how the channels returned by a sampler have to be remapped,
+/// in order to match what D3D9 hardware would return.
+///
+/// D3D11 has no way of swizzling a resource view, so the backend has to remap the channels
+/// itself. The device passes the swizzle of each stage's texture to the backend when binding it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Swizzle(pub [Channel; 4]);
 
-                _ => panic!("Unknown D3D9 format: {}", fmt),
+impl Swizzle {
+    /// Swizzle which leaves all channels unchanged.
+    pub const IDENTITY: Swizzle = Swizzle([Channel::R, Channel::G, Channel::B, Channel::A]);
+
+    /// Swizzle for formats whose host alpha channel contains garbage.
+    pub const OPAQUE: Swizzle = Swizzle([Channel::R, Channel::G, Channel::B, Channel::One]);
+
+    /// Swizzle for luminance formats, which replicate the luminance into all color channels.
+    pub const LUMINANCE: Swizzle = Swizzle([Channel::R, Channel::R, Channel::R, Channel::One]);
+
+    /// Swizzle for luminance formats with alpha, which is stored in the second channel.
+    pub const LUMINANCE_ALPHA: Swizzle =
+        Swizzle([Channel::R, Channel::R, Channel::R, Channel::G]);
+
+    /// Swizzle for two channel formats, whose missing channels read as 1 in D3D9.
+    pub const RG: Swizzle = Swizzle([Channel::R, Channel::G, Channel::One, Channel::One]);
+
+    /// Swizzle for single channel formats, whose missing channels read as 1 in D3D9.
+    pub const R: Swizzle = Swizzle([Channel::R, Channel::One, Channel::One, Channel::One]);
+
+    /// Checks if this swizzle leaves all channels unchanged.
+    pub fn is_identity(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+
+    /// Expresses this swizzle as a matrix and an offset.
+    ///
+    /// Each row of the matrix selects the source channel of an output channel,
+    /// so that the remapped color is `matrix * color + offset`.
+    pub fn to_matrix(&self) -> ([[f32; 4]; 4], [f32; 4]) {
+        let mut matrix = [[0.0; 4]; 4];
+        let mut offset = [0.0; 4];
+
+        for (i, &ch) in self.0.iter().enumerate() {
+            match ch {
+                Channel::Zero => (),
+                Channel::One => offset[i] = 1.0,
+                ch => matrix[i][ch as usize] = 1.0,
             }
         }
 
-        /// Converts a DXGI format back into a Direct3D format.
-        pub fn dxgi_format_to_d3d(fmt: DXGI_FORMAT) -> D3DFORMAT {
-            // Some DXGI formats map to multiple D3D9 formats.
-            #[allow(unreachable_patterns)]
-            match fmt {
-                $($b => $a,)*
-                _ => panic!("Unknown DXGI format: {}", fmt),
+        (matrix, offset)
+    }
+
+    /// Packs this swizzle into an integer, 3 bits per channel.
+    pub fn bits(&self) -> u16 {
+        self.0
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, &ch)| bits | ((ch as u16) << (i * 3)))
+    }
+}
+
+impl Default for Swizzle {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swizzle(fmt: D3DFORMAT) -> Swizzle {
+        format_info(fmt).unwrap().swizzle
+    }
+
+    #[test]
+    fn x_channel_formats_are_opaque() {
+        for info in FORMATS.iter().filter(|info| info.x_channel) {
+            assert_eq!(info.swizzle.0[3], Channel::One, "format {}", info.format);
+        }
+    }
+
+    #[test]
+    fn luminance_swizzles() {
+        assert_eq!(swizzle(D3DFMT_L8), Swizzle::LUMINANCE);
+        assert_eq!(swizzle(D3DFMT_L16), Swizzle::LUMINANCE);
+        assert_eq!(swizzle(D3DFMT_A8L8), Swizzle::LUMINANCE_ALPHA);
+        assert_eq!(swizzle(D3DFMT_A4L4), Swizzle::LUMINANCE_ALPHA);
+
+        // Every luminance format must replicate its first channel.
+        let luminance = FORMATS
+            .iter()
+            .filter(|info| info.class == FormatClass::Luminance);
+
+        for info in luminance {
+            let sw = info.swizzle.0;
+            assert_eq!(&sw[..3], &[Channel::R; 3], "format {}", info.format);
+        }
+    }
+
+    #[test]
+    fn bump_swizzles() {
+        assert_eq!(swizzle(D3DFMT_V8U8), Swizzle::RG);
+        assert_eq!(swizzle(D3DFMT_V16U16), Swizzle::RG);
+
+        // Four channel formats need no remapping.
+        assert_eq!(swizzle(D3DFMT_Q8W8V8U8), Swizzle::IDENTITY);
+        assert_eq!(swizzle(D3DFMT_Q16W16V16U16), Swizzle::IDENTITY);
+
+        // Every bump format must return U and V in the first two channels.
+        let bump = FORMATS
+            .iter()
+            .filter(|info| info.class == FormatClass::Bump && info.is_supported());
+
+        for info in bump {
+            let sw = info.swizzle.0;
+            assert_eq!(&sw[..2], &[Channel::R, Channel::G], "format {}", info.format);
+        }
+    }
+
+    #[test]
+    fn swizzle_bits_are_unique() {
+        let swizzles = [
+            Swizzle::IDENTITY,
+            Swizzle::OPAQUE,
+            Swizzle::LUMINANCE,
+            Swizzle::LUMINANCE_ALPHA,
+            Swizzle::RG,
+            Swizzle::R,
+        ];
+
+        for (i, a) in swizzles.iter().enumerate() {
+            for b in &swizzles[i + 1..] {
+                assert_ne!(a.bits(), b.bits());
             }
         }
     }
-}
 
-// Based upon the following reference:
-// https://docs.microsoft.com/en-us/windows/desktop/direct3d10/d3d10-graphics-programming-guide-resources-legacy-formats
-format_conv! {
-    // 8 bit formats
-    D3DFMT_A8 => DXGI_FORMAT_A8_UNORM,
-    D3DFMT_L8 => DXGI_FORMAT_R8_UNORM,
+    /// Remaps a color the way the backend does, using the swizzle's matrix.
+    fn remap(sw: Swizzle, color: [f32; 4]) -> [f32; 4] {
+        let (matrix, offset) = sw.to_matrix();
+        let mut out = offset;
 
-    // 16 bit formats
-    D3DFMT_R5G6B5 => DXGI_FORMAT_B5G6R5_UNORM,
-    D3DFMT_X4R4G4B4 => DXGI_FORMAT_B4G4R4A4_UNORM,
-    D3DFMT_A4R4G4B4 => DXGI_FORMAT_B4G4R4A4_UNORM,
-    D3DFMT_X1R5G5B5 => DXGI_FORMAT_B5G5R5A1_UNORM,
-    D3DFMT_A1R5G5B5 => DXGI_FORMAT_B5G5R5A1_UNORM,
-    D3DFMT_A8L8 => DXGI_FORMAT_R8G8_UNORM,
-    D3DFMT_L16 => DXGI_FORMAT_R16_UNORM,
+        for (o, row) in out.iter_mut().zip(&matrix) {
+            *o += row.iter().zip(&color).map(|(m, c)| m * c).sum::<f32>();
+        }
 
-    // 32 bit formats
-    D3DFMT_X8B8G8R8 => DXGI_FORMAT_R8G8B8A8_UNORM,
-    D3DFMT_X8R8G8B8 => DXGI_FORMAT_B8G8R8X8_UNORM,
-    D3DFMT_A8R8G8B8 => DXGI_FORMAT_B8G8R8A8_UNORM,
-    D3DFMT_A8B8G8R8 => DXGI_FORMAT_R8G8B8A8_UNORM,
-    D3DFMT_G16R16 => DXGI_FORMAT_R16G16_UNORM,
+        out
+    }
 
-    // HDR formats
-    D3DFMT_A2R10G10B10 => DXGI_FORMAT_R10G10B10A2_UNORM,
-    D3DFMT_A2B10G10R10 => DXGI_FORMAT_R10G10B10A2_UNORM,
+    #[test]
+    fn swizzle_matrices() {
+        let color = [0.25, 0.5, 0.75, 0.125];
 
-    // Depth / stencil formats
-    D3DFMT_D16_LOCKABLE => DXGI_FORMAT_D16_UNORM,
-    D3DFMT_D16 => DXGI_FORMAT_D16_UNORM,
-    D3DFMT_D24S8 => DXGI_FORMAT_D24_UNORM_S8_UINT,
-    D3DFMT_D24X8 => DXGI_FORMAT_D24_UNORM_S8_UINT,
-    D3DFMT_D32 => DXGI_FORMAT_D32_FLOAT,
-    D3DFMT_D32F_LOCKABLE => DXGI_FORMAT_D32_FLOAT,
+        assert_eq!(remap(Swizzle::IDENTITY, color), color);
+        assert_eq!(remap(Swizzle::OPAQUE, color), [0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(remap(Swizzle::R, color), [0.25, 1.0, 1.0, 1.0]);
 
-    // Compressed formats
-    D3DFMT_DXT1 => DXGI_FORMAT_BC1_UNORM,
-    D3DFMT_DXT2 => DXGI_FORMAT_BC2_UNORM,
-    D3DFMT_DXT3 => DXGI_FORMAT_BC3_UNORM,
-    D3DFMT_DXT4 => DXGI_FORMAT_BC4_UNORM,
-    D3DFMT_DXT5 => DXGI_FORMAT_BC5_UNORM,
+        let zero = Swizzle([Channel::A, Channel::Zero, Channel::B, Channel::G]);
+        assert_eq!(remap(zero, color), [0.125, 0.0, 0.75, 0.5]);
+    }
 
-    // Special formats: mostly used for hardware video.
-    D3DFMT_R8G8_B8G8 => DXGI_FORMAT_G8R8_G8B8_UNORM,
-    D3DFMT_G8R8_G8B8 => DXGI_FORMAT_R8G8_B8G8_UNORM,
+    /// Returns the color D3D11 samples from a texel stored with a DXGI format,
+    /// which fills the channels the format lacks with 0, and alpha with 1.
+    fn host_sample(fmt: DXGI_FORMAT, texel: [f32; 4]) -> [f32; 4] {
+        let channels = match fmt {
+            DXGI_FORMAT_R8_UNORM | DXGI_FORMAT_R16_UNORM => 1,
+            DXGI_FORMAT_R8G8_UNORM | DXGI_FORMAT_R8G8_SNORM | DXGI_FORMAT_R16G16_SNORM => 2,
+            _ => 4,
+        };
 
-    // Signed formats
-    D3DFMT_V8U8 => DXGI_FORMAT_R8G8_SNORM,
-    D3DFMT_Q8W8V8U8 => DXGI_FORMAT_R8G8B8A8_SNORM,
-    D3DFMT_V16U16 => DXGI_FORMAT_R16G16_SNORM,
+        let mut color = [0.0, 0.0, 0.0, 1.0];
+        color[..channels].copy_from_slice(&texel[..channels]);
+        color
+    }
 
-    // Buffer formats
-    D3DFMT_R16F => DXGI_FORMAT_R16_FLOAT,
-    D3DFMT_G16R16F => DXGI_FORMAT_R16G16_FLOAT,
-    D3DFMT_A16B16G16R16 => DXGI_FORMAT_R16G16B16A16_UNORM,
-    D3DFMT_A16B16G16R16F => DXGI_FORMAT_R16G16B16A16_FLOAT,
-    D3DFMT_R32F => DXGI_FORMAT_R32_FLOAT,
-    D3DFMT_G32R32F => DXGI_FORMAT_R32G32_FLOAT,
-    D3DFMT_A32B32G32R32F => DXGI_FORMAT_R32G32B32A32_FLOAT,
+    #[test]
+    fn luminance_and_bump_formats_read_like_d3d9() {
+        let (l, a) = (0.25, 0.75);
+        let (u, v, w, q) = (-0.5, 0.5, 0.125, -0.125);
 
-    // Unknown format
-    D3DFMT_UNKNOWN => DXGI_FORMAT_UNKNOWN,
+        // The texels are given in the order of the host format's channels.
+        let cases = [
+            (D3DFMT_L8, [l, 0.0, 0.0, 0.0], [l, l, l, 1.0]),
+            (D3DFMT_L16, [l, 0.0, 0.0, 0.0], [l, l, l, 1.0]),
+            (D3DFMT_A8L8, [l, a, 0.0, 0.0], [l, l, l, a]),
+            (D3DFMT_V8U8, [u, v, 0.0, 0.0], [u, v, 1.0, 1.0]),
+            (D3DFMT_V16U16, [u, v, 0.0, 0.0], [u, v, 1.0, 1.0]),
+            (D3DFMT_Q8W8V8U8, [u, v, w, q], [u, v, w, q]),
+            (D3DFMT_Q16W16V16U16, [u, v, w, q], [u, v, w, q]),
+        ];
 
-    // Unsupported formats
-    // TODO: some formats have no support in modern DXGI.
-    // We might still be able to approximate them with some other formats though.
-    D3DFMT_P8 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_A8P8 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_A4L4 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_R3G3B2 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_A8R3G3B2 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_R8G8B8 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_D15S1 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_D24FS8 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_D24X4S4 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_S8_LOCKABLE => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_CxV8U8 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_L6V5U5 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_X8L8V8U8 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_A2W10V10U10 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_Q16W16V16U16 => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_UYVY => DXGI_FORMAT_UNKNOWN,
-    D3DFMT_YUY2 => DXGI_FORMAT_UNKNOWN,
+        for &(fmt, texel, expected) in &cases {
+            let info = format_info(fmt).unwrap();
+            let color = remap(info.swizzle, host_sample(info.dxgi, texel));
+            assert_eq!(color, expected, "format {}", fmt);
+        }
+
+        // Make sure no supported format of these classes was left out.
+        let tested = FORMATS.iter().filter(|info| {
+            (info.class == FormatClass::Luminance || info.class == FormatClass::Bump)
+                && info.is_supported()
+        });
+
+        for info in tested {
+            let covered = cases.iter().any(|case| case.0 == info.format);
+            assert!(covered, "format {}", info.format);
+        }
+    }
+
+    /// Returns the average number of bits per pixel of a DXGI format.
+    fn dxgi_bits_per_pixel(fmt: DXGI_FORMAT) -> u32 {
+        match fmt {
+            DXGI_FORMAT_BC1_UNORM => 4,
+            DXGI_FORMAT_A8_UNORM | DXGI_FORMAT_R8_UNORM | DXGI_FORMAT_BC2_UNORM
+            | DXGI_FORMAT_BC3_UNORM => 8,
+            DXGI_FORMAT_B5G6R5_UNORM
+            | DXGI_FORMAT_B4G4R4A4_UNORM
+            | DXGI_FORMAT_B5G5R5A1_UNORM
+            | DXGI_FORMAT_R8G8_UNORM
+            | DXGI_FORMAT_R8G8_SNORM
+            | DXGI_FORMAT_R16_UNORM
+            | DXGI_FORMAT_R16_FLOAT
+            | DXGI_FORMAT_D16_UNORM
+            | DXGI_FORMAT_R16_UINT
+            | DXGI_FORMAT_G8R8_G8B8_UNORM
+            | DXGI_FORMAT_R8G8_B8G8_UNORM => 16,
+            DXGI_FORMAT_B8G8R8A8_UNORM
+            | DXGI_FORMAT_R8G8B8A8_UNORM
+            | DXGI_FORMAT_R8G8B8A8_SNORM
+            | DXGI_FORMAT_R10G10B10A2_UNORM
+            | DXGI_FORMAT_R16G16_UNORM
+            | DXGI_FORMAT_R16G16_SNORM
+            | DXGI_FORMAT_R16G16_FLOAT
+            | DXGI_FORMAT_R32_FLOAT
+            | DXGI_FORMAT_D24_UNORM_S8_UINT
+            | DXGI_FORMAT_D32_FLOAT
+            | DXGI_FORMAT_R32_UINT => 32,
+            DXGI_FORMAT_R16G16B16A16_UNORM
+            | DXGI_FORMAT_R16G16B16A16_SNORM
+            | DXGI_FORMAT_R16G16B16A16_FLOAT
+            | DXGI_FORMAT_R32G32_FLOAT
+            | DXGI_FORMAT_D32_FLOAT_S8X24_UINT => 64,
+            DXGI_FORMAT_R32G32B32A32_FLOAT => 128,
+            fmt => panic!("unknown DXGI format {}", fmt),
+        }
+    }
+
+    #[test]
+    fn sizes_match_host_formats() {
+        // Locked data is copied to and from the host textures as is.
+        for info in FORMATS.iter().filter(|info| info.is_supported()) {
+            let bits = dxgi_bits_per_pixel(info.dxgi);
+            assert_eq!(info.bits_per_pixel, bits, "format {}", info.format);
+        }
+    }
+
+    #[test]
+    fn block_sizes_and_pitches() {
+        let info = |fmt| format_info(fmt).unwrap();
+
+        // (format, block size, pitch of 5 pixels, rows of 5 pixels)
+        let cases = [
+            (D3DFMT_L8, 1, 5, 5),
+            (D3DFMT_R5G6B5, 2, 10, 5),
+            (D3DFMT_A8R8G8B8, 4, 20, 5),
+            (D3DFMT_X8R8G8B8, 4, 20, 5),
+            (D3DFMT_D24FS8, 8, 40, 5),
+            (D3DFMT_A16B16G16R16F, 8, 40, 5),
+            (D3DFMT_A32B32G32R32F, 16, 80, 5),
+            (D3DFMT_R8G8_B8G8, 4, 12, 5),
+            (D3DFMT_DXT1, 8, 16, 2),
+            (D3DFMT_DXT3, 16, 32, 2),
+            (D3DFMT_DXT5, 16, 32, 2),
+        ];
+
+        for &(fmt, block_size, pitch, rows) in &cases {
+            let info = info(fmt);
+            assert_eq!(info.block_size(), block_size, "format {}", fmt);
+            assert_eq!(info.row_pitch(5), pitch, "format {}", fmt);
+            assert_eq!(info.row_count(5), rows, "format {}", fmt);
+            assert_eq!(info.image_size(5, 5), pitch * rows, "format {}", fmt);
+        }
+    }
+
+    #[test]
+    fn a2r10g10b10_is_only_a_display_format() {
+        // Its red and blue channels are swapped compared to the only 10-bit DXGI format.
+        assert!(d3d_format_to_dxgi(D3DFMT_A2R10G10B10).is_err());
+        assert_eq!(
+            d3d_display_format_to_dxgi(D3DFMT_A2R10G10B10).ok(),
+            Some(DXGI_FORMAT_R10G10B10A2_UNORM)
+        );
+    }
+
+    #[test]
+    fn srgb_formats_have_typeless_storage() {
+        // Both views must be creatable from the same texture.
+        for info in FORMATS.iter().filter(|info| info.is_srgb_capable()) {
+            assert_ne!(info.typeless, DXGI_FORMAT_UNKNOWN, "format {}", info.format);
+        }
+    }
 }
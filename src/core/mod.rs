@@ -8,8 +8,12 @@ pub use self::enums::*;
 
 pub mod fmt;
 
+pub mod caps;
+
 pub mod msample;
 
+pub mod sampler;
+
 pub mod str;
 
 mod adapter;
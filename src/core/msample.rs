@@ -1,7 +1,10 @@
 //! Implements multisampling-related conversion functions.
 
 use std::cmp;
-use winapi::shared::{d3d9types::D3DMULTISAMPLE_TYPE, dxgitype::DXGI_SAMPLE_DESC};
+use winapi::shared::{
+    d3d9types::{D3DMULTISAMPLE_NONE, D3DMULTISAMPLE_TYPE},
+    dxgitype::DXGI_SAMPLE_DESC,
+};
 
 /// Converts a D3D9 multisample type / quality pair to a DXGI_SAMPLE_DESC structure.
 pub fn d3d9_to_dxgi_samples(ty: D3DMULTISAMPLE_TYPE, qlt: u32) -> DXGI_SAMPLE_DESC {
@@ -17,5 +20,12 @@ pub fn d3d9_to_dxgi_samples(ty: D3DMULTISAMPLE_TYPE, qlt: u32) -> DXGI_SAMPLE_DE
 
 /// Converts a DXGI_SAMPLE_DESC structure to a pair of MS type and quality.
 pub fn dxgi_samples_to_d3d9(d: DXGI_SAMPLE_DESC) -> (D3DMULTISAMPLE_TYPE, u32) {
-    (d.Count, d.Quality)
+    // A single sample means no multisampling, not the non-maskable type.
+    let ty = if d.Count > 1 {
+        d.Count
+    } else {
+        D3DMULTISAMPLE_NONE
+    };
+
+    (ty, d.Quality)
 }
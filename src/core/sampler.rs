@@ -0,0 +1,179 @@
+//! Translates D3D9 sampler states to D3D11 sampler objects.
+
+use std::cmp;
+
+use winapi::shared::d3d9types::*;
+use winapi::um::d3d11::*;
+
+/// The D3D9 sampler states which determine a D3D11 sampler object.
+///
+/// This is used as the key when caching the created samplers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SamplerKey {
+    pub address_u: u32,
+    pub address_v: u32,
+    pub address_w: u32,
+    pub border_color: u32,
+    pub mag_filter: u32,
+    pub min_filter: u32,
+    pub mip_filter: u32,
+    pub mip_map_lod_bias: u32,
+    pub max_mip_level: u32,
+    pub max_anisotropy: u32,
+}
+
+impl SamplerKey {
+    /// Gathers the sampler states, using a function which returns the value of each state.
+    pub fn new<F>(state: F) -> Self
+    where
+        F: Fn(D3DSAMPLERSTATETYPE) -> u32,
+    {
+        Self {
+            address_u: state(D3DSAMP_ADDRESSU),
+            address_v: state(D3DSAMP_ADDRESSV),
+            address_w: state(D3DSAMP_ADDRESSW),
+            border_color: state(D3DSAMP_BORDERCOLOR),
+            mag_filter: state(D3DSAMP_MAGFILTER),
+            min_filter: state(D3DSAMP_MINFILTER),
+            mip_filter: state(D3DSAMP_MIPFILTER),
+            mip_map_lod_bias: state(D3DSAMP_MIPMAPLODBIAS),
+            max_mip_level: state(D3DSAMP_MAXMIPLEVEL),
+            max_anisotropy: state(D3DSAMP_MAXANISOTROPY),
+        }
+    }
+
+    /// Builds the description of the equivalent D3D11 sampler.
+    ///
+    /// If `forced_anisotropy` is set, samplers which use linear or anisotropic
+    /// minification are upgraded to anisotropic filtering with that level.
+    pub fn to_d3d11(&self, forced_anisotropy: Option<u32>) -> D3D11_SAMPLER_DESC {
+        // Anisotropic and the various Gaussian filters are all at least linear.
+        let is_linear = |filter| filter >= D3DTEXF_LINEAR;
+
+        let anisotropy = match forced_anisotropy {
+            Some(level) if is_linear(self.min_filter) => Some(level),
+            _ if self.min_filter == D3DTEXF_ANISOTROPIC
+                || self.mag_filter == D3DTEXF_ANISOTROPIC =>
+            {
+                Some(self.max_anisotropy)
+            }
+            _ => None,
+        };
+
+        let filter = match anisotropy {
+            Some(_) => D3D11_FILTER_ANISOTROPIC,
+            None => {
+                // D3D11 encodes the filter type of each stage in a separate bit.
+                let flag = |filter, bit| if is_linear(filter) { bit } else { 0 };
+
+                let min = flag(self.min_filter, 0x10);
+                let mag = flag(self.mag_filter, 0x04);
+                let mip = flag(self.mip_filter, 0x01);
+
+                min | mag | mip
+            }
+        };
+
+        // D3D9's maximum mip level is the index of the most detailed level which is sampled.
+        let min_lod = self.max_mip_level as f32;
+
+        // Without mip-mapping, only the most detailed level is sampled.
+        let max_lod = if self.mip_filter == D3DTEXF_NONE {
+            min_lod
+        } else {
+            D3D11_FLOAT32_MAX
+        };
+
+        // The border color is stored as ARGB.
+        let channel = |shift: u32| ((self.border_color >> shift) & 0xFF) as f32 / 255.0;
+        let border_color = [channel(16), channel(8), channel(0), channel(24)];
+
+        D3D11_SAMPLER_DESC {
+            Filter: filter,
+            AddressU: address_mode(self.address_u),
+            AddressV: address_mode(self.address_v),
+            AddressW: address_mode(self.address_w),
+            MipLODBias: f32::from_bits(self.mip_map_lod_bias),
+            MaxAnisotropy: cmp::min(cmp::max(anisotropy.unwrap_or(1), 1), 16),
+            ComparisonFunc: D3D11_COMPARISON_NEVER,
+            BorderColor: border_color,
+            MinLOD: min_lod,
+            MaxLOD: max_lod,
+        }
+    }
+}
+
+/// Converts a D3D9 texture address mode to the equivalent D3D11 mode.
+fn address_mode(mode: D3DTEXTUREADDRESS) -> D3D11_TEXTURE_ADDRESS_MODE {
+    // The modes have the same values in both APIs.
+    match mode {
+        D3DTADDRESS_WRAP..=D3DTADDRESS_MIRRORONCE => mode,
+        _ => D3D11_TEXTURE_ADDRESS_WRAP,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(min_filter: u32, mag_filter: u32, mip_filter: u32) -> SamplerKey {
+        SamplerKey {
+            address_u: D3DTADDRESS_WRAP,
+            address_v: D3DTADDRESS_CLAMP,
+            address_w: D3DTADDRESS_BORDER,
+            border_color: 0xFF00_80FF,
+            mag_filter,
+            min_filter,
+            mip_filter,
+            mip_map_lod_bias: 0,
+            max_mip_level: 0,
+            max_anisotropy: 1,
+        }
+    }
+
+    #[test]
+    fn filters() {
+        let desc = key(D3DTEXF_POINT, D3DTEXF_POINT, D3DTEXF_NONE).to_d3d11(None);
+        assert_eq!(desc.Filter, D3D11_FILTER_MIN_MAG_MIP_POINT);
+        assert_eq!(desc.MaxLOD, 0.0);
+
+        let desc = key(D3DTEXF_LINEAR, D3DTEXF_LINEAR, D3DTEXF_LINEAR).to_d3d11(None);
+        assert_eq!(desc.Filter, D3D11_FILTER_MIN_MAG_MIP_LINEAR);
+        assert_eq!(desc.MaxLOD, D3D11_FLOAT32_MAX);
+
+        let desc = key(D3DTEXF_LINEAR, D3DTEXF_POINT, D3DTEXF_POINT).to_d3d11(None);
+        assert_eq!(desc.Filter, D3D11_FILTER_MIN_LINEAR_MAG_MIP_POINT);
+    }
+
+    #[test]
+    fn address_modes_and_border() {
+        let desc = key(D3DTEXF_POINT, D3DTEXF_POINT, D3DTEXF_NONE).to_d3d11(None);
+        assert_eq!(desc.AddressU, D3D11_TEXTURE_ADDRESS_WRAP);
+        assert_eq!(desc.AddressV, D3D11_TEXTURE_ADDRESS_CLAMP);
+        assert_eq!(desc.AddressW, D3D11_TEXTURE_ADDRESS_BORDER);
+        assert_eq!(desc.BorderColor, [0.0, 128.0 / 255.0, 1.0, 1.0]);
+        assert_eq!(address_mode(0), D3D11_TEXTURE_ADDRESS_WRAP);
+    }
+
+    #[test]
+    fn forced_anisotropy() {
+        let mut aniso = key(D3DTEXF_ANISOTROPIC, D3DTEXF_LINEAR, D3DTEXF_LINEAR);
+        aniso.max_anisotropy = 4;
+
+        let desc = aniso.to_d3d11(None);
+        assert_eq!(desc.Filter, D3D11_FILTER_ANISOTROPIC);
+        assert_eq!(desc.MaxAnisotropy, 4);
+
+        let desc = aniso.to_d3d11(Some(16));
+        assert_eq!(desc.MaxAnisotropy, 16);
+
+        let desc = key(D3DTEXF_LINEAR, D3DTEXF_LINEAR, D3DTEXF_LINEAR).to_d3d11(Some(8));
+        assert_eq!(desc.Filter, D3D11_FILTER_ANISOTROPIC);
+        assert_eq!(desc.MaxAnisotropy, 8);
+
+        // Point sampling is usually used on purpose, and is left alone.
+        let desc = key(D3DTEXF_POINT, D3DTEXF_POINT, D3DTEXF_POINT).to_d3d11(Some(16));
+        assert_eq!(desc.Filter, D3D11_FILTER_MIN_MAG_MIP_POINT);
+        assert_eq!(desc.MaxAnisotropy, 1);
+    }
+}
@@ -0,0 +1,998 @@
+use std::{
+    cell::{Cell, Ref, RefCell},
+    cmp,
+    collections::HashMap,
+    mem, ptr,
+};
+
+use winapi::shared::{dxgi::*, dxgiformat::*, dxgitype::DXGI_MODE_DESC, winerror};
+use winapi::um::{d3d11::*, d3dcommon::*, unknwnbase::IUnknown, winnt::HANDLE};
+use winapi::Interface;
+
+use comptr::ComPtr;
+
+use crate::backend::*;
+use crate::core::{fmt::Swizzle, *};
+use crate::hud::Quad;
+use crate::{Error, Result};
+
+use super::{Blitter, Buffer as D3D11Buffer, Device, DeviceContext, Overlay, Texture2D};
+
+/// A copy of a texture with its channels remapped, which is sampled instead of the texture.
+///
+/// Views cannot swizzle, so this is how formats which D3D9 reads differently are emulated.
+///
+/// The views keep the copy's texture alive.
+struct Shadow {
+    view: ComPtr<ID3D11ShaderResourceView>,
+    // Views of each subresource of the original and of the copy, used for updating the latter.
+    subresources: Vec<(
+        ComPtr<ID3D11ShaderResourceView>,
+        ComPtr<ID3D11RenderTargetView>,
+        D3D11_VIEWPORT,
+    )>,
+    // The version of the original texture this copy was last updated from.
+    version: Cell<u64>,
+}
+
+/// A texture, together with the views which were created for it so far.
+///
+/// D3D9 has no concept of views, so they are created the first time a texture is bound
+/// with a certain format, and reused afterwards.
+struct TextureObject {
+    texture: Texture2D,
+    rt_views: RefCell<HashMap<DXGI_FORMAT, ComPtr<ID3D11RenderTargetView>>>,
+    sr_views: RefCell<HashMap<DXGI_FORMAT, ComPtr<ID3D11ShaderResourceView>>>,
+    ds_view: RefCell<Option<ComPtr<ID3D11DepthStencilView>>>,
+    // Incremented every time the texture's contents could have changed.
+    version: Cell<u64>,
+    shadows: RefCell<HashMap<(DXGI_FORMAT, Swizzle), Shadow>>,
+}
+
+impl TextureObject {
+    fn new(texture: Texture2D) -> Texture {
+        Texture::new(Self {
+            texture,
+            rt_views: RefCell::new(HashMap::new()),
+            sr_views: RefCell::new(HashMap::new()),
+            ds_view: RefCell::new(None),
+            version: Cell::new(1),
+            shadows: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Marks the contents of this texture as modified.
+    fn modified(&self) {
+        self.version.set(self.version.get() + 1);
+    }
+
+    fn rt_view(&self, device: &Device, fmt: DXGI_FORMAT) -> Result<ComPtr<ID3D11RenderTargetView>> {
+        if let Some(view) = self.rt_views.borrow().get(&fmt) {
+            return Ok(view.clone());
+        }
+
+        let view = self.texture.create_rt_view(device, fmt)?;
+        self.rt_views.borrow_mut().insert(fmt, view.clone());
+        Ok(view)
+    }
+
+    fn sr_view(
+        &self,
+        device: &Device,
+        fmt: DXGI_FORMAT,
+    ) -> Result<ComPtr<ID3D11ShaderResourceView>> {
+        if let Some(view) = self.sr_views.borrow().get(&fmt) {
+            return Ok(view.clone());
+        }
+
+        let view = self.texture.create_sr_view(device, fmt)?;
+        self.sr_views.borrow_mut().insert(fmt, view.clone());
+        Ok(view)
+    }
+
+    fn ds_view(&self, device: &Device) -> Result<ComPtr<ID3D11DepthStencilView>> {
+        if let Some(view) = self.ds_view.borrow().as_ref() {
+            return Ok(view.clone());
+        }
+
+        let view = self.texture.create_ds_view(device)?;
+        *self.ds_view.borrow_mut() = Some(view.clone());
+        Ok(view)
+    }
+
+    /// Retrieves a view of a copy of this texture, whose channels are remapped by a swizzle.
+    ///
+    /// The copy is updated if this texture was modified since it was last made.
+    fn swizzled_view(
+        &self,
+        device: &Device,
+        ctx: &DeviceContext,
+        blitter: &Blitter,
+        fmt: DXGI_FORMAT,
+        swizzle: Swizzle,
+    ) -> Result<ComPtr<ID3D11ShaderResourceView>> {
+        let key = (fmt, swizzle);
+
+        if !self.shadows.borrow().contains_key(&key) {
+            let shadow = self.create_shadow(device, fmt)?;
+            self.shadows.borrow_mut().insert(key, shadow);
+        }
+
+        let shadows = self.shadows.borrow();
+        let shadow = &shadows[&key];
+
+        if shadow.version.get() != self.version.get() {
+            for (src, dest, viewport) in &shadow.subresources {
+                blitter.swizzle(ctx, src, dest, *viewport, swizzle)?;
+            }
+            shadow.version.set(self.version.get());
+        }
+
+        Ok(shadow.view.clone())
+    }
+
+    fn create_shadow(&self, device: &Device, fmt: DXGI_FORMAT) -> Result<Shadow> {
+        let desc = self.texture.desc();
+        let shadow_fmt = swizzled_format(fmt);
+
+        let shadow_desc = D3D11_TEXTURE2D_DESC {
+            Format: shadow_fmt,
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET,
+            CPUAccessFlags: 0,
+            MiscFlags: desc.MiscFlags & D3D11_RESOURCE_MISC_TEXTURECUBE,
+            ..desc
+        };
+
+        let texture = Texture2D::from_desc(device, &shadow_desc)?;
+        let view = texture.create_sr_view(device, shadow_fmt)?;
+
+        let mut subresources = Vec::new();
+
+        for slice in 0..desc.ArraySize {
+            for level in 0..desc.MipLevels {
+                let src = self
+                    .texture
+                    .create_subresource_sr_view(device, fmt, (level, slice))?;
+                let dest =
+                    texture.create_subresource_rt_view(device, shadow_fmt, (level, slice))?;
+
+                let viewport = D3D11_VIEWPORT {
+                    TopLeftX: 0.0,
+                    TopLeftY: 0.0,
+                    Width: cmp::max(desc.Width >> level, 1) as f32,
+                    Height: cmp::max(desc.Height >> level, 1) as f32,
+                    MinDepth: 0.0,
+                    MaxDepth: 1.0,
+                };
+
+                subresources.push((src, dest, viewport));
+            }
+        }
+
+        Ok(Shadow {
+            view,
+            subresources,
+            version: Cell::new(0),
+        })
+    }
+}
+
+/// Chooses the format of a texture's swizzled copy.
+///
+/// It has to be renderable, and store the channels with at least the original's precision.
+fn swizzled_format(fmt: DXGI_FORMAT) -> DXGI_FORMAT {
+    match fmt {
+        DXGI_FORMAT_B8G8R8A8_UNORM_SRGB | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => {
+            DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+        }
+        DXGI_FORMAT_A8_UNORM
+        | DXGI_FORMAT_R8_UNORM
+        | DXGI_FORMAT_R8G8_UNORM
+        | DXGI_FORMAT_B5G6R5_UNORM
+        | DXGI_FORMAT_B5G5R5A1_UNORM
+        | DXGI_FORMAT_B4G4R4A4_UNORM
+        | DXGI_FORMAT_B8G8R8A8_UNORM
+        | DXGI_FORMAT_R8G8B8A8_UNORM => DXGI_FORMAT_R8G8B8A8_UNORM,
+        DXGI_FORMAT_R8G8_SNORM | DXGI_FORMAT_R8G8B8A8_SNORM => DXGI_FORMAT_R8G8B8A8_SNORM,
+        DXGI_FORMAT_R16_UNORM | DXGI_FORMAT_R16G16_UNORM => DXGI_FORMAT_R16G16B16A16_UNORM,
+        DXGI_FORMAT_R16G16_SNORM => DXGI_FORMAT_R16G16B16A16_SNORM,
+        DXGI_FORMAT_R16_FLOAT | DXGI_FORMAT_R16G16_FLOAT => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        _ => DXGI_FORMAT_R32G32B32A32_FLOAT,
+    }
+}
+
+/// A texture bound to a shader through its swizzled copy.
+struct SwizzledBinding {
+    stage: ShaderStage,
+    slot: u32,
+    texture: Texture,
+    fmt: DXGI_FORMAT,
+    swizzle: Swizzle,
+}
+
+/// A DXGI swap chain, with the handles of the buffers retrieved so far.
+struct SwapChainObject {
+    swap_chain: ComPtr<IDXGISwapChain>,
+    buffers: RefCell<HashMap<u32, Texture>>,
+}
+
+fn tex(texture: &Texture) -> &TextureObject {
+    texture.downcast()
+}
+
+fn buf(buffer: &Buffer) -> &D3D11Buffer {
+    buffer.downcast()
+}
+
+fn sc(swap_chain: &SwapChain) -> &IDXGISwapChain {
+    &swap_chain.downcast::<SwapChainObject>().swap_chain
+}
+
+/// Backend which executes commands on a D3D11 device.
+pub struct D3D11Backend {
+    device: Device,
+    ctx: DeviceContext,
+    // The factory which creates the swap chains.
+    factory: ComPtr<IDXGIFactory>,
+    feature_level: D3D_FEATURE_LEVEL,
+    // Created the first time something is blitted.
+    blitter: RefCell<Option<Blitter>>,
+    // Created the first time an overlay is drawn.
+    overlay: RefCell<Option<Overlay>>,
+    // Textures whose swizzled copies have to be kept up to date while they are bound.
+    swizzled: RefCell<Vec<SwizzledBinding>>,
+    // The bound render targets, whose contents are modified by draws.
+    render_targets: RefCell<Vec<Texture>>,
+}
+
+impl D3D11Backend {
+    /// Creates a D3D11 device on an adapter, and a backend which uses it.
+    pub fn new(adapter: &IDXGIAdapter, factory: ComPtr<IDXGIFactory>) -> Result<Self> {
+        let mut feature_level = 0;
+        let device = unsafe {
+            let mut device = ptr::null_mut();
+            let result = D3D11CreateDevice(
+                // Create a device for the adapter we own.
+                adapter as *const _ as *mut _,
+                D3D_DRIVER_TYPE_UNKNOWN,
+                ptr::null_mut(),
+                // No additional flags.
+                0,
+                // We will use whichever feature level is supported.
+                ptr::null_mut(),
+                0,
+                D3D11_SDK_VERSION,
+                &mut device,
+                &mut feature_level,
+                ptr::null_mut(),
+            );
+
+            check_hresult(result, "Failed to create D3D11 device")?;
+
+            Device::new(ComPtr::new(device))
+        };
+
+        if feature_level < D3D_FEATURE_LEVEL_11_0 {
+            warn!("Your GPU doesn't support all of D3D11's features");
+        }
+
+        Ok(Self {
+            ctx: DeviceContext::new(&device),
+            device,
+            factory,
+            feature_level,
+            blitter: RefCell::new(None),
+            overlay: RefCell::new(None),
+            swizzled: RefCell::new(Vec::new()),
+            render_targets: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Retrieves the blitter, creating it the first time it is needed.
+    fn blitter(&self) -> Result<Ref<'_, Blitter>> {
+        if self.blitter.borrow().is_none() {
+            *self.blitter.borrow_mut() = Some(Blitter::new(&self.device)?);
+        }
+
+        Ok(Ref::map(self.blitter.borrow(), |blitter| {
+            blitter.as_ref().unwrap()
+        }))
+    }
+
+    fn set_shader_resource(
+        &self,
+        stage: ShaderStage,
+        slot: u32,
+        view: Option<&ComPtr<ID3D11ShaderResourceView>>,
+    ) {
+        let view = view.map_or(ptr::null_mut(), |view| view.as_mut());
+
+        unsafe {
+            match stage {
+                ShaderStage::Vertex => self.ctx.VSSetShaderResources(slot, 1, &view),
+                ShaderStage::Pixel => self.ctx.PSSetShaderResources(slot, 1, &view),
+            }
+        }
+    }
+
+    /// Brings the swizzled copies of the bound textures up to date, before a draw samples them.
+    fn update_swizzled_textures(&self) {
+        let bindings = self.swizzled.borrow();
+
+        if bindings.is_empty() {
+            return;
+        }
+
+        let result = self.blitter().and_then(|blitter| {
+            for b in bindings.iter() {
+                let texture = tex(&b.texture);
+                let view =
+                    texture.swizzled_view(&self.device, &self.ctx, &blitter, b.fmt, b.swizzle)?;
+
+                // Updating a copy unbinds it, since it is used as a render target.
+                self.set_shader_resource(b.stage, b.slot, Some(&view));
+            }
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            error!("Failed to update swizzled textures: {:?}", err);
+        }
+    }
+
+    /// Marks the contents of the bound render targets as modified by a draw.
+    fn render_targets_modified(&self) {
+        for target in self.render_targets.borrow().iter() {
+            tex(target).modified();
+        }
+    }
+
+    /// Maps a resource, translating the errors D3D11 returns.
+    fn map(
+        &self,
+        resource: *mut ID3D11Resource,
+        subresource: u32,
+        map_type: D3D11_MAP,
+        flags: u32,
+    ) -> Result<D3D11_MAPPED_SUBRESOURCE> {
+        unsafe {
+            let mut mapped = mem::zeroed();
+            let result = self
+                .ctx
+                .Map(resource, subresource, map_type, flags, &mut mapped);
+
+            match result {
+                0 => Ok(mapped),
+                winerror::DXGI_ERROR_WAS_STILL_DRAWING => Err(Error::WasStillDrawing),
+                hr => Err(check_hresult(hr, "Failed to map resource")),
+            }
+        }
+    }
+}
+
+/// Creates a D3D11 state object from its description.
+macro_rules! create_state {
+    ($device:expr, $method:ident, $desc:expr, $msg:expr) => {
+        unsafe {
+            let mut ptr = ptr::null_mut();
+
+            let result = $device.$method($desc, &mut ptr);
+            check_hresult(result, $msg)?;
+
+            ComPtr::new(ptr)
+        }
+    };
+}
+
+impl Backend for D3D11Backend {
+    fn adapter_name(&self) -> String {
+        let desc = self.device.dxgi_device().and_then(|dxgi| unsafe {
+            let mut adapter = ptr::null_mut();
+            let result = dxgi.GetAdapter(&mut adapter);
+            check_hresult(result, "Failed to retrieve DXGI adapter")?;
+
+            let adapter = ComPtr::new(adapter);
+
+            let mut desc = mem::zeroed();
+            let result = adapter.GetDesc(&mut desc);
+            check_hresult(result, "Failed to get adapter description")?;
+
+            Ok(desc)
+        });
+
+        match desc {
+            Ok(desc) => crate::core::str::wstr_to_string(&desc.Description),
+            Err(_) => "D3D11 device".into(),
+        }
+    }
+
+    fn feature_level(&self) -> D3D_FEATURE_LEVEL {
+        self.feature_level
+    }
+
+    fn format_support(&self, fmt: DXGI_FORMAT) -> u32 {
+        unsafe {
+            let mut support = 0;
+            if self.device.CheckFormatSupport(fmt, &mut support) != 0 {
+                return 0;
+            }
+            support
+        }
+    }
+
+    fn multisample_quality_levels(&self, fmt: DXGI_FORMAT, samples: u32) -> u32 {
+        let mut quality = 0;
+        unsafe {
+            // Even if this fails, quality is initialized to 0.
+            self.device
+                .CheckMultisampleQualityLevels(fmt, samples, &mut quality);
+        }
+        quality
+    }
+
+    fn maximum_frame_latency(&self) -> Result<u32> {
+        let dxgi = self.device.dxgi_device()?;
+
+        let mut frames = 0;
+        let result = unsafe { dxgi.GetMaximumFrameLatency(&mut frames) };
+        check_hresult(result, "Failed to get maximum frame latency")?;
+
+        Ok(frames)
+    }
+
+    fn set_maximum_frame_latency(&self, frames: u32) -> Result<()> {
+        let dxgi = self.device.dxgi_device()?;
+
+        let result = unsafe { dxgi.SetMaximumFrameLatency(frames) };
+        check_hresult(result, "Failed to set maximum frame latency")?;
+
+        Ok(())
+    }
+
+    fn gpu_thread_priority(&self) -> Result<i32> {
+        let dxgi = self.device.dxgi_device()?;
+
+        let mut priority = 0;
+        let result = unsafe { dxgi.GetGPUThreadPriority(&mut priority) };
+        check_hresult(result, "Failed to get GPU thread priority")?;
+
+        Ok(priority)
+    }
+
+    fn set_gpu_thread_priority(&self, priority: i32) -> Result<()> {
+        let dxgi = self.device.dxgi_device()?;
+
+        let result = unsafe { dxgi.SetGPUThreadPriority(priority) };
+        check_hresult(result, "Failed to set GPU thread priority")?;
+
+        Ok(())
+    }
+
+    fn create_texture_2d(&self, desc: &D3D11_TEXTURE2D_DESC) -> Result<Texture> {
+        Texture2D::from_desc(&self.device, desc).map(TextureObject::new)
+    }
+
+    fn open_shared_texture(&self, handle: HANDLE) -> Result<Texture> {
+        Texture2D::open_shared(&self.device, handle).map(TextureObject::new)
+    }
+
+    fn shared_handle(&self, texture: &Texture) -> Result<HANDLE> {
+        tex(texture).texture.shared_handle()
+    }
+
+    fn create_buffer(&self, desc: &D3D11_BUFFER_DESC) -> Result<Buffer> {
+        D3D11Buffer::from_desc(&self.device, desc).map(Buffer::new)
+    }
+
+    fn texture_desc(&self, texture: &Texture) -> D3D11_TEXTURE2D_DESC {
+        tex(texture).texture.desc()
+    }
+
+    fn buffer_desc(&self, buffer: &Buffer) -> D3D11_BUFFER_DESC {
+        buf(buffer).desc()
+    }
+
+    fn create_sampler(&self, desc: &D3D11_SAMPLER_DESC) -> Result<Sampler> {
+        let sampler: ComPtr<ID3D11SamplerState> = create_state!(
+            self.device,
+            CreateSamplerState,
+            desc,
+            "Failed to create sampler state"
+        );
+        Ok(Sampler::new(sampler))
+    }
+
+    fn create_blend_state(&self, desc: &D3D11_BLEND_DESC) -> Result<BlendState> {
+        let state: ComPtr<ID3D11BlendState> = create_state!(
+            self.device,
+            CreateBlendState,
+            desc,
+            "Failed to create blend state"
+        );
+        Ok(BlendState::new(state))
+    }
+
+    fn create_depth_stencil_state(
+        &self,
+        desc: &D3D11_DEPTH_STENCIL_DESC,
+    ) -> Result<DepthStencilState> {
+        let state: ComPtr<ID3D11DepthStencilState> = create_state!(
+            self.device,
+            CreateDepthStencilState,
+            desc,
+            "Failed to create depth / stencil state"
+        );
+        Ok(DepthStencilState::new(state))
+    }
+
+    fn create_rasterizer_state(&self, desc: &D3D11_RASTERIZER_DESC) -> Result<RasterizerState> {
+        let state: ComPtr<ID3D11RasterizerState> = create_state!(
+            self.device,
+            CreateRasterizerState,
+            desc,
+            "Failed to create rasterizer state"
+        );
+        Ok(RasterizerState::new(state))
+    }
+
+    fn create_query(&self, ty: D3D11_QUERY) -> Result<Query> {
+        let desc = D3D11_QUERY_DESC {
+            Query: ty,
+            MiscFlags: 0,
+        };
+
+        let query: ComPtr<ID3D11Query> =
+            create_state!(self.device, CreateQuery, &desc, "Failed to create query");
+        Ok(Query::new(query))
+    }
+
+    fn begin_query(&self, query: &Query) {
+        let query: &ComPtr<ID3D11Query> = query.downcast();
+        unsafe {
+            self.ctx.Begin(query.upcast().as_mut());
+        }
+    }
+
+    fn end_query(&self, query: &Query) {
+        let query: &ComPtr<ID3D11Query> = query.downcast();
+        unsafe {
+            self.ctx.End(query.upcast().as_mut());
+        }
+    }
+
+    fn query_data(&self, query: &Query, data: &mut [u8], flush: bool) -> Result<bool> {
+        let query: &ComPtr<ID3D11Query> = query.downcast();
+        let flags = if flush {
+            0
+        } else {
+            D3D11_ASYNC_GETDATA_DONOTFLUSH
+        };
+
+        let result = unsafe {
+            self.ctx.GetData(
+                query.upcast().as_mut(),
+                data.as_mut_ptr() as *mut _,
+                data.len() as u32,
+                flags,
+            )
+        };
+
+        match result {
+            0 => Ok(true),
+            winerror::S_FALSE => Ok(false),
+            hr => Err(check_hresult(hr, "Failed to retrieve query data")),
+        }
+    }
+
+    fn map_texture(
+        &self,
+        texture: &Texture,
+        subresource: u32,
+        map_type: D3D11_MAP,
+        flags: u32,
+    ) -> Result<D3D11_MAPPED_SUBRESOURCE> {
+        let texture = tex(texture);
+
+        if map_type != D3D11_MAP_READ {
+            texture.modified();
+        }
+
+        self.map(texture.texture.as_resource(), subresource, map_type, flags)
+    }
+
+    fn unmap_texture(&self, texture: &Texture, subresource: u32) {
+        unsafe {
+            self.ctx
+                .Unmap(tex(texture).texture.as_resource(), subresource);
+        }
+    }
+
+    fn map_buffer(
+        &self,
+        buffer: &Buffer,
+        map_type: D3D11_MAP,
+        flags: u32,
+    ) -> Result<D3D11_MAPPED_SUBRESOURCE> {
+        self.map(buf(buffer).as_resource(), 0, map_type, flags)
+    }
+
+    fn unmap_buffer(&self, buffer: &Buffer) {
+        unsafe {
+            self.ctx.Unmap(buf(buffer).as_resource(), 0);
+        }
+    }
+
+    fn copy_texture(&self, dest: &Texture, src: &Texture) {
+        tex(dest).modified();
+
+        unsafe {
+            self.ctx.CopyResource(
+                tex(dest).texture.as_resource(),
+                tex(src).texture.as_resource(),
+            );
+        }
+    }
+
+    fn copy_texture_region(
+        &self,
+        dest: &Texture,
+        dest_subresource: u32,
+        (x, y): (u32, u32),
+        src: &Texture,
+        src_subresource: u32,
+        src_box: Option<&D3D11_BOX>,
+    ) {
+        let src_box = src_box.map(|b| b as *const _).unwrap_or(ptr::null());
+
+        tex(dest).modified();
+
+        unsafe {
+            self.ctx.CopySubresourceRegion(
+                tex(dest).texture.as_resource(),
+                dest_subresource,
+                x,
+                y,
+                0,
+                tex(src).texture.as_resource(),
+                src_subresource,
+                src_box,
+            );
+        }
+    }
+
+    fn resolve_texture(&self, dest: &Texture, src: &Texture, fmt: DXGI_FORMAT) {
+        tex(dest).modified();
+
+        unsafe {
+            self.ctx.ResolveSubresource(
+                tex(dest).texture.as_resource(),
+                0,
+                tex(src).texture.as_resource(),
+                0,
+                fmt,
+            );
+        }
+    }
+
+    fn blit(
+        &self,
+        (src, src_fmt): (&Texture, DXGI_FORMAT),
+        src_rect: [f32; 4],
+        (dest, dest_fmt): (&Texture, DXGI_FORMAT),
+        dest_rect: &D3D11_VIEWPORT,
+        mode: BlitMode,
+        linear_filter: bool,
+    ) -> Result<()> {
+        let src_view = tex(src).sr_view(&self.device, src_fmt)?;
+        let dest_view = tex(dest).rt_view(&self.device, dest_fmt)?;
+
+        tex(dest).modified();
+
+        self.blitter()?.blit(
+            &self.ctx,
+            &src_view,
+            src_rect,
+            &dest_view,
+            *dest_rect,
+            mode,
+            linear_filter,
+        )
+    }
+
+    fn set_render_targets(
+        &self,
+        targets: &[Option<(&Texture, DXGI_FORMAT)>],
+        depth_stencil: Option<&Texture>,
+    ) -> Result<()> {
+        let views = targets
+            .iter()
+            .map(|target| match target {
+                Some((texture, fmt)) => tex(texture).rt_view(&self.device, *fmt).map(Some),
+                None => Ok(None),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let views: Vec<_> = views
+            .iter()
+            .map(|view| view.as_ref().map_or(ptr::null_mut(), |view| view.as_mut()))
+            .collect();
+
+        let ds_view = match depth_stencil {
+            Some(texture) => Some(tex(texture).ds_view(&self.device)?),
+            None => None,
+        };
+        let ds_view = ds_view
+            .as_ref()
+            .map_or(ptr::null_mut(), |view| view.as_mut());
+
+        unsafe {
+            self.ctx
+                .OMSetRenderTargets(views.len() as u32, views.as_ptr(), ds_view);
+        }
+
+        *self.render_targets.borrow_mut() = targets
+            .iter()
+            .filter_map(|target| target.map(|(texture, _)| texture.clone()))
+            .collect();
+
+        Ok(())
+    }
+
+    fn clear_render_target(
+        &self,
+        target: &Texture,
+        fmt: DXGI_FORMAT,
+        color: [f32; 4],
+    ) -> Result<()> {
+        let view = tex(target).rt_view(&self.device, fmt)?;
+
+        tex(target).modified();
+
+        unsafe {
+            self.ctx.ClearRenderTargetView(view.as_mut(), &color);
+        }
+
+        Ok(())
+    }
+
+    fn bind_texture(
+        &self,
+        stage: ShaderStage,
+        slot: u32,
+        texture: Option<(&Texture, DXGI_FORMAT, fmt::Swizzle)>,
+    ) -> Result<()> {
+        self.swizzled
+            .borrow_mut()
+            .retain(|b| (b.stage, b.slot) != (stage, slot));
+
+        let view = match texture {
+            // Multisampled textures cannot be sampled, so they need no copies.
+            Some((texture, fmt, swizzle))
+                if !swizzle.is_identity() && tex(texture).texture.desc().SampleDesc.Count <= 1 =>
+            {
+                let view = {
+                    let blitter = self.blitter()?;
+                    tex(texture).swizzled_view(&self.device, &self.ctx, &blitter, fmt, swizzle)?
+                };
+
+                self.swizzled.borrow_mut().push(SwizzledBinding {
+                    stage,
+                    slot,
+                    texture: texture.clone(),
+                    fmt,
+                    swizzle,
+                });
+
+                Some(view)
+            }
+            Some((texture, fmt, _)) => Some(tex(texture).sr_view(&self.device, fmt)?),
+            None => None,
+        };
+
+        self.set_shader_resource(stage, slot, view.as_ref());
+
+        Ok(())
+    }
+
+    fn set_viewport(&self, viewport: &D3D11_VIEWPORT) {
+        unsafe {
+            self.ctx.RSSetViewports(1, viewport);
+        }
+    }
+
+    fn set_vertex_buffer(&self, slot: u32, buffer: Option<&Buffer>, stride: u32, offset: u32) {
+        let buffer = buffer.map_or(ptr::null_mut(), |b| buf(b).as_buffer());
+
+        unsafe {
+            self.ctx
+                .IASetVertexBuffers(slot, 1, &buffer, &stride, &offset);
+        }
+    }
+
+    fn set_index_buffer(&self, buffer: Option<&Buffer>, fmt: DXGI_FORMAT, offset: u32) {
+        let buffer = buffer.map_or(ptr::null_mut(), |b| buf(b).as_buffer());
+
+        unsafe {
+            self.ctx.IASetIndexBuffer(buffer, fmt, offset);
+        }
+    }
+
+    fn set_primitive_topology(&self, topology: D3D11_PRIMITIVE_TOPOLOGY) {
+        unsafe {
+            self.ctx.IASetPrimitiveTopology(topology);
+        }
+    }
+
+    fn bind_sampler(&self, stage: ShaderStage, slot: u32, sampler: &Sampler) {
+        let sampler = sampler.downcast::<ComPtr<ID3D11SamplerState>>().as_mut() as *mut _;
+
+        unsafe {
+            match stage {
+                ShaderStage::Vertex => self.ctx.VSSetSamplers(slot, 1, &sampler),
+                ShaderStage::Pixel => self.ctx.PSSetSamplers(slot, 1, &sampler),
+            }
+        }
+    }
+
+    fn bind_blend_state(&self, state: &BlendState, blend_factor: [f32; 4]) {
+        let state: &ComPtr<ID3D11BlendState> = state.downcast();
+        unsafe {
+            self.ctx.OMSetBlendState(state.as_mut(), &blend_factor, !0);
+        }
+    }
+
+    fn bind_depth_stencil_state(&self, state: &DepthStencilState, stencil_ref: u32) {
+        let state: &ComPtr<ID3D11DepthStencilState> = state.downcast();
+        unsafe {
+            self.ctx.OMSetDepthStencilState(state.as_mut(), stencil_ref);
+        }
+    }
+
+    fn bind_rasterizer_state(&self, state: &RasterizerState) {
+        let state: &ComPtr<ID3D11RasterizerState> = state.downcast();
+        unsafe {
+            self.ctx.RSSetState(state.as_mut());
+        }
+    }
+
+    fn clear_state(&self) {
+        unsafe {
+            self.ctx.ClearState();
+        }
+
+        self.swizzled.borrow_mut().clear();
+        self.render_targets.borrow_mut().clear();
+    }
+
+    fn draw(&self, vertex_count: u32, start_vertex: u32) {
+        self.update_swizzled_textures();
+
+        unsafe {
+            self.ctx.Draw(vertex_count, start_vertex);
+        }
+
+        self.render_targets_modified();
+    }
+
+    fn draw_indexed(&self, index_count: u32, start_index: u32, base_vertex: i32) {
+        self.update_swizzled_textures();
+
+        unsafe {
+            self.ctx.DrawIndexed(index_count, start_index, base_vertex);
+        }
+
+        self.render_targets_modified();
+    }
+
+    fn draw_overlay(&self, target: &Texture, fmt: DXGI_FORMAT, quads: &[Quad]) -> Result<()> {
+        let mut overlay = self.overlay.borrow_mut();
+
+        if overlay.is_none() {
+            *overlay = Some(Overlay::new(&self.device)?);
+        }
+
+        let target = tex(target);
+        let desc = target.texture.desc();
+        let view = target.rt_view(&self.device, fmt)?;
+
+        target.modified();
+
+        overlay
+            .as_ref()
+            .unwrap()
+            .draw(&self.ctx, &view, (desc.Width, desc.Height), quads)
+    }
+
+    fn create_swap_chain(&self, desc: &DXGI_SWAP_CHAIN_DESC) -> Result<SwapChain> {
+        let mut desc = *desc;
+
+        let swap_chain = unsafe {
+            let mut ptr = ptr::null_mut();
+
+            let device: &ID3D11Device = &self.device;
+            let result = self.factory.CreateSwapChain(
+                device as *const _ as *mut IUnknown,
+                &mut desc,
+                &mut ptr,
+            );
+            check_hresult(result, "Failed to create swap chain")?;
+
+            ComPtr::new(ptr)
+        };
+
+        Ok(SwapChain::new(SwapChainObject {
+            swap_chain,
+            buffers: RefCell::new(HashMap::new()),
+        }))
+    }
+
+    fn swap_chain_buffer(&self, swap_chain: &SwapChain, index: u32) -> Result<Texture> {
+        let object: &SwapChainObject = swap_chain.downcast();
+
+        if let Some(buffer) = object.buffers.borrow().get(&index) {
+            return Ok(buffer.clone());
+        }
+
+        let mut ptr: *mut ID3D11Texture2D = ptr::null_mut();
+        let uuid = ID3D11Texture2D::uuidof();
+
+        let ret = &mut ptr as *mut _ as *mut *mut _;
+
+        let result = unsafe { object.swap_chain.GetBuffer(index, &uuid, ret) };
+        check_hresult(result, "Failed to retrieve swap chain buffer")?;
+
+        let buffer = TextureObject::new(ComPtr::new(ptr).into());
+        object.buffers.borrow_mut().insert(index, buffer.clone());
+
+        Ok(buffer)
+    }
+
+    fn present(&self, swap_chain: &SwapChain, sync_interval: u32, flags: u32) -> Result<()> {
+        let result = unsafe { sc(swap_chain).Present(sync_interval, flags) };
+
+        match result {
+            0 => Ok(()),
+            winerror::DXGI_ERROR_WAS_STILL_DRAWING => Err(Error::WasStillDrawing),
+            hr => Err(check_hresult(hr, "Failed to present to screen")),
+        }
+    }
+
+    fn fullscreen_state(&self, swap_chain: &SwapChain) -> Result<bool> {
+        let mut fullscreen = 0;
+
+        let result = unsafe { sc(swap_chain).GetFullscreenState(&mut fullscreen, ptr::null_mut()) };
+        check_hresult(result, "Failed to get fullscreen state")?;
+
+        Ok(fullscreen != 0)
+    }
+
+    fn set_fullscreen_state(&self, swap_chain: &SwapChain, fullscreen: bool) -> Result<()> {
+        let result =
+            unsafe { sc(swap_chain).SetFullscreenState(fullscreen as i32, ptr::null_mut()) };
+        check_hresult(result, "Failed to set fullscreen state")?;
+
+        Ok(())
+    }
+
+    fn resize_target(&self, swap_chain: &SwapChain, mode: &DXGI_MODE_DESC) -> Result<()> {
+        let result = unsafe { sc(swap_chain).ResizeTarget(mode) };
+        check_hresult(result, "Failed to resize swap chain target")?;
+
+        Ok(())
+    }
+
+    fn wait_for_vblank(&self, swap_chain: &SwapChain) -> Result<()> {
+        let output = unsafe {
+            let mut ptr = ptr::null_mut();
+            let result = sc(swap_chain).GetContainingOutput(&mut ptr);
+            check_hresult(result, "Failed to get swap chain's output")?;
+            ComPtr::new(ptr)
+        };
+
+        let result = unsafe { output.WaitForVBlank() };
+        check_hresult(result, "Failed to wait for vertical blank")?;
+
+        Ok(())
+    }
+}
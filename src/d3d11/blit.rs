@@ -0,0 +1,416 @@
+use std::{mem, ptr};
+
+use winapi::um::d3d11::*;
+use winapi::um::d3dcommon::D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST;
+use winapi::um::unknwnbase::IUnknown;
+
+use comptr::ComPtr;
+
+use crate::backend::BlitMode;
+use crate::core::{fmt::Swizzle, *};
+use crate::Result;
+
+use super::shader::{create_pixel_shader, create_vertex_shader};
+
+/// Shaders used for drawing a textured rectangle.
+///
+/// The vertex shader generates a triangle covering the whole viewport,
+/// so no vertex buffers need to be bound.
+const BLIT_SHADER: &str = r#"
+cbuffer Blit : register(b0) {
+    // Offset and size of the source rectangle, in texture coordinates.
+    float4 src_rect;
+};
+
+Texture2D src : register(t0);
+SamplerState smp : register(s0);
+
+struct VsOut {
+    float4 pos : SV_Position;
+    float2 uv : TEXCOORD0;
+};
+
+VsOut vs_main(uint id : SV_VertexID) {
+    float2 t = float2((id << 1) & 2, id & 2);
+
+    VsOut o;
+    o.pos = float4(t * float2(2.0, -2.0) + float2(-1.0, 1.0), 0.0, 1.0);
+    o.uv = src_rect.xy + t * src_rect.zw;
+    return o;
+}
+
+float4 ps_copy(VsOut i) : SV_Target {
+    return src.Sample(smp, i.uv);
+}
+
+float3 linear_to_srgb(float3 c) {
+    float3 lo = c * 12.92;
+    float3 hi = 1.055 * pow(c, 1.0 / 2.4) - 0.055;
+    return c <= 0.0031308 ? lo : hi;
+}
+
+float4 ps_linear_to_srgb(VsOut i) : SV_Target {
+    float4 c = src.Sample(smp, i.uv);
+    return float4(linear_to_srgb(saturate(c.rgb)), c.a);
+}
+
+cbuffer Swizzle : register(b1) {
+    // Each row selects the source channel of an output channel.
+    row_major float4x4 channels;
+    // Added to the result, for channels which read as a constant.
+    float4 constants;
+};
+
+// Swizzles read a single subresource, which could be a face of a cube map.
+Texture2DArray layer : register(t0);
+
+float4 ps_swizzle(VsOut i) : SV_Target {
+    float4 c = layer.Sample(smp, float3(i.uv, 0.0));
+    return mul(channels, c) + constants;
+}
+"#;
+
+/// Layout of the `Swizzle` constant buffer.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SwizzleConstants {
+    channels: [[f32; 4]; 4],
+    constants: [f32; 4],
+}
+
+/// Helper for drawing (parts of) a texture into a render target.
+///
+/// Used to implement functionality which cannot be expressed as a plain
+/// resource copy, such as format conversions or stretching.
+pub struct Blitter {
+    vs: ComPtr<ID3D11VertexShader>,
+    ps_copy: ComPtr<ID3D11PixelShader>,
+    ps_linear_to_srgb: ComPtr<ID3D11PixelShader>,
+    ps_swizzle: ComPtr<ID3D11PixelShader>,
+    point_sampler: ComPtr<ID3D11SamplerState>,
+    linear_sampler: ComPtr<ID3D11SamplerState>,
+    // Constant buffer storing the source rectangle.
+    rect_buffer: ComPtr<ID3D11Buffer>,
+    // Constant buffer storing the swizzle's matrix and constants.
+    swizzle_buffer: ComPtr<ID3D11Buffer>,
+}
+
+impl Blitter {
+    /// Creates the shaders and state objects required for blitting.
+    pub fn new(device: &ID3D11Device) -> Result<Self> {
+        let vs = create_vertex_shader(device, BLIT_SHADER, "vs_main")?;
+        let ps_copy = create_pixel_shader(device, BLIT_SHADER, "ps_copy")?;
+        let ps_linear_to_srgb = create_pixel_shader(device, BLIT_SHADER, "ps_linear_to_srgb")?;
+        let ps_swizzle = create_pixel_shader(device, BLIT_SHADER, "ps_swizzle")?;
+
+        let point_sampler = Self::create_sampler(device, D3D11_FILTER_MIN_MAG_MIP_POINT)?;
+        let linear_sampler = Self::create_sampler(device, D3D11_FILTER_MIN_MAG_MIP_LINEAR)?;
+
+        let rect_buffer = Self::create_constant_buffer::<[f32; 4]>(device)?;
+        let swizzle_buffer = Self::create_constant_buffer::<SwizzleConstants>(device)?;
+
+        Ok(Self {
+            vs,
+            ps_copy,
+            ps_linear_to_srgb,
+            ps_swizzle,
+            point_sampler,
+            linear_sampler,
+            rect_buffer,
+            swizzle_buffer,
+        })
+    }
+
+    fn create_constant_buffer<T>(device: &ID3D11Device) -> Result<ComPtr<ID3D11Buffer>> {
+        let buffer = unsafe {
+            let desc = D3D11_BUFFER_DESC {
+                ByteWidth: mem::size_of::<T>() as u32,
+                Usage: D3D11_USAGE_DYNAMIC,
+                BindFlags: D3D11_BIND_CONSTANT_BUFFER,
+                CPUAccessFlags: D3D11_CPU_ACCESS_WRITE,
+                MiscFlags: 0,
+                StructureByteStride: 0,
+            };
+
+            let mut ptr = ptr::null_mut();
+
+            let result = device.CreateBuffer(&desc, ptr::null(), &mut ptr);
+            check_hresult(result, "Failed to create blit constant buffer")?;
+
+            ComPtr::new(ptr)
+        };
+
+        Ok(buffer)
+    }
+
+    /// Overwrites the contents of a constant buffer.
+    fn write_constants<T: Copy>(
+        ctx: &ID3D11DeviceContext,
+        buffer: &ComPtr<ID3D11Buffer>,
+        data: T,
+    ) -> Result<()> {
+        unsafe {
+            let mut mapped = mem::uninitialized();
+            let resource = buffer.upcast().as_mut();
+
+            let result = ctx.Map(resource, 0, D3D11_MAP_WRITE_DISCARD, 0, &mut mapped);
+            check_hresult(result, "Failed to map blit constant buffer")?;
+
+            *(mapped.pData as *mut T) = data;
+
+            ctx.Unmap(resource, 0);
+        }
+
+        Ok(())
+    }
+
+    fn create_sampler(
+        device: &ID3D11Device,
+        filter: D3D11_FILTER,
+    ) -> Result<ComPtr<ID3D11SamplerState>> {
+        let desc = D3D11_SAMPLER_DESC {
+            Filter: filter,
+            AddressU: D3D11_TEXTURE_ADDRESS_CLAMP,
+            AddressV: D3D11_TEXTURE_ADDRESS_CLAMP,
+            AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+            MipLODBias: 0.0,
+            MaxAnisotropy: 1,
+            ComparisonFunc: D3D11_COMPARISON_NEVER,
+            BorderColor: [0.0; 4],
+            MinLOD: 0.0,
+            MaxLOD: D3D11_FLOAT32_MAX,
+        };
+
+        let sampler = unsafe {
+            let mut ptr = ptr::null_mut();
+
+            let result = device.CreateSamplerState(&desc, &mut ptr);
+            check_hresult(result, "Failed to create blit sampler")?;
+
+            ComPtr::new(ptr)
+        };
+
+        Ok(sampler)
+    }
+
+    /// Draws a rectangle of the source texture into a rectangle of the destination.
+    ///
+    /// The source rectangle is given as `[left, top, width, height]`, in texture coordinates.
+    /// The pipeline state modified by this function is restored before it returns.
+    pub fn blit(
+        &self,
+        ctx: &ID3D11DeviceContext,
+        src: &ID3D11ShaderResourceView,
+        src_rect: [f32; 4],
+        dest: &ID3D11RenderTargetView,
+        dest_rect: D3D11_VIEWPORT,
+        mode: BlitMode,
+        linear_filter: bool,
+    ) -> Result<()> {
+        let _state = StateGuard::save(ctx);
+
+        Self::write_constants(ctx, &self.rect_buffer, src_rect)?;
+
+        let ps = match mode {
+            BlitMode::Copy => &self.ps_copy,
+            BlitMode::LinearToSrgb => &self.ps_linear_to_srgb,
+        };
+
+        let sampler = if linear_filter {
+            &self.linear_sampler
+        } else {
+            &self.point_sampler
+        };
+
+        self.draw(ctx, src, ps, sampler, dest, dest_rect);
+
+        Ok(())
+    }
+
+    /// Copies a subresource into another one with the same size, remapping its channels.
+    ///
+    /// Both views must refer to a single subresource, through array views.
+    /// The pipeline state modified by this function is restored before it returns.
+    pub fn swizzle(
+        &self,
+        ctx: &ID3D11DeviceContext,
+        src: &ID3D11ShaderResourceView,
+        dest: &ID3D11RenderTargetView,
+        dest_rect: D3D11_VIEWPORT,
+        swizzle: Swizzle,
+    ) -> Result<()> {
+        let _state = StateGuard::save(ctx);
+
+        Self::write_constants(ctx, &self.rect_buffer, [0.0, 0.0, 1.0, 1.0])?;
+        let (channels, constants) = swizzle.to_matrix();
+        let data = SwizzleConstants {
+            channels,
+            constants,
+        };
+        Self::write_constants(ctx, &self.swizzle_buffer, data)?;
+
+        unsafe {
+            ctx.PSSetConstantBuffers(1, 1, &(self.swizzle_buffer.as_mut() as *mut _));
+        }
+
+        let (ps, sampler) = (&self.ps_swizzle, &self.point_sampler);
+        self.draw(ctx, src, ps, sampler, dest, dest_rect);
+
+        Ok(())
+    }
+
+    /// Draws the full screen triangle, with the given pixel shader.
+    fn draw(
+        &self,
+        ctx: &ID3D11DeviceContext,
+        src: &ID3D11ShaderResourceView,
+        ps: &ComPtr<ID3D11PixelShader>,
+        sampler: &ComPtr<ID3D11SamplerState>,
+        dest: &ID3D11RenderTargetView,
+        dest_rect: D3D11_VIEWPORT,
+    ) {
+        unsafe {
+            ctx.IASetInputLayout(ptr::null_mut());
+            ctx.IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+
+            ctx.VSSetShader(self.vs.as_mut(), ptr::null(), 0);
+            ctx.PSSetShader(ps.as_mut(), ptr::null(), 0);
+
+            ctx.PSSetShaderResources(0, 1, &(src as *const _ as *mut _));
+            ctx.PSSetSamplers(0, 1, &(sampler.as_mut() as *mut _));
+            ctx.VSSetConstantBuffers(0, 1, &(self.rect_buffer.as_mut() as *mut _));
+
+            // The default states have blending disabled, and no culling of this triangle.
+            ctx.RSSetState(ptr::null_mut());
+            ctx.RSSetViewports(1, &dest_rect);
+            ctx.OMSetBlendState(ptr::null_mut(), &[1.0; 4], !0);
+            ctx.OMSetDepthStencilState(ptr::null_mut(), 0);
+            ctx.OMSetRenderTargets(1, &(dest as *const _ as *mut _), ptr::null_mut());
+
+            ctx.Draw(3, 0);
+        }
+    }
+}
+
+/// Releases a reference to a COM object returned by a context's getters.
+unsafe fn release<T>(ptr: *mut T) {
+    if let Some(unknwn) = (ptr as *mut IUnknown).as_ref() {
+        unknwn.Release();
+    }
+}
+
+/// Saves the pipeline state modified by a blit or an overlay, and restores it when dropped.
+///
+/// This ensures internal draws never interfere with the app's state.
+pub(super) struct StateGuard<'a> {
+    ctx: &'a ID3D11DeviceContext,
+    input_layout: *mut ID3D11InputLayout,
+    topology: D3D11_PRIMITIVE_TOPOLOGY,
+    vs: *mut ID3D11VertexShader,
+    ps: *mut ID3D11PixelShader,
+    vs_srv: *mut ID3D11ShaderResourceView,
+    srv: *mut ID3D11ShaderResourceView,
+    sampler: *mut ID3D11SamplerState,
+    cbuffer: *mut ID3D11Buffer,
+    ps_cbuffer: *mut ID3D11Buffer,
+    rasterizer: *mut ID3D11RasterizerState,
+    num_viewports: u32,
+    viewports: [D3D11_VIEWPORT; 16],
+    blend: *mut ID3D11BlendState,
+    blend_factor: [f32; 4],
+    sample_mask: u32,
+    depth_stencil: *mut ID3D11DepthStencilState,
+    stencil_ref: u32,
+    rtvs: [*mut ID3D11RenderTargetView; 8],
+    dsv: *mut ID3D11DepthStencilView,
+}
+
+impl<'a> StateGuard<'a> {
+    pub(super) fn save(ctx: &'a ID3D11DeviceContext) -> Self {
+        unsafe {
+            let mut state = Self {
+                ctx,
+                input_layout: ptr::null_mut(),
+                topology: 0,
+                vs: ptr::null_mut(),
+                ps: ptr::null_mut(),
+                vs_srv: ptr::null_mut(),
+                srv: ptr::null_mut(),
+                sampler: ptr::null_mut(),
+                cbuffer: ptr::null_mut(),
+                ps_cbuffer: ptr::null_mut(),
+                rasterizer: ptr::null_mut(),
+                num_viewports: 16,
+                viewports: mem::zeroed(),
+                blend: ptr::null_mut(),
+                blend_factor: [0.0; 4],
+                sample_mask: 0,
+                depth_stencil: ptr::null_mut(),
+                stencil_ref: 0,
+                rtvs: [ptr::null_mut(); 8],
+                dsv: ptr::null_mut(),
+            };
+
+            ctx.IAGetInputLayout(&mut state.input_layout);
+            ctx.IAGetPrimitiveTopology(&mut state.topology);
+            ctx.VSGetShader(&mut state.vs, ptr::null_mut(), ptr::null_mut());
+            ctx.PSGetShader(&mut state.ps, ptr::null_mut(), ptr::null_mut());
+            ctx.VSGetShaderResources(0, 1, &mut state.vs_srv);
+            ctx.PSGetShaderResources(0, 1, &mut state.srv);
+            ctx.PSGetSamplers(0, 1, &mut state.sampler);
+            ctx.VSGetConstantBuffers(0, 1, &mut state.cbuffer);
+            ctx.PSGetConstantBuffers(1, 1, &mut state.ps_cbuffer);
+            ctx.RSGetState(&mut state.rasterizer);
+            ctx.RSGetViewports(&mut state.num_viewports, state.viewports.as_mut_ptr());
+            ctx.OMGetBlendState(
+                &mut state.blend,
+                &mut state.blend_factor,
+                &mut state.sample_mask,
+            );
+            ctx.OMGetDepthStencilState(&mut state.depth_stencil, &mut state.stencil_ref);
+            ctx.OMGetRenderTargets(8, state.rtvs.as_mut_ptr(), &mut state.dsv);
+
+            state
+        }
+    }
+}
+
+impl<'a> Drop for StateGuard<'a> {
+    fn drop(&mut self) {
+        let ctx = self.ctx;
+
+        unsafe {
+            ctx.IASetInputLayout(self.input_layout);
+            ctx.IASetPrimitiveTopology(self.topology);
+            ctx.VSSetShader(self.vs, ptr::null(), 0);
+            ctx.PSSetShader(self.ps, ptr::null(), 0);
+            ctx.VSSetShaderResources(0, 1, &self.vs_srv);
+            ctx.PSSetShaderResources(0, 1, &self.srv);
+            ctx.PSSetSamplers(0, 1, &self.sampler);
+            ctx.VSSetConstantBuffers(0, 1, &self.cbuffer);
+            ctx.PSSetConstantBuffers(1, 1, &self.ps_cbuffer);
+            ctx.RSSetState(self.rasterizer);
+            ctx.RSSetViewports(self.num_viewports, self.viewports.as_ptr());
+            ctx.OMSetBlendState(self.blend, &self.blend_factor, self.sample_mask);
+            ctx.OMSetDepthStencilState(self.depth_stencil, self.stencil_ref);
+            ctx.OMSetRenderTargets(8, self.rtvs.as_ptr(), self.dsv);
+
+            // The getters above have incremented the reference counts.
+            release(self.input_layout);
+            release(self.vs);
+            release(self.ps);
+            release(self.vs_srv);
+            release(self.srv);
+            release(self.sampler);
+            release(self.cbuffer);
+            release(self.ps_cbuffer);
+            release(self.rasterizer);
+            release(self.blend);
+            release(self.depth_stencil);
+            for &rtv in &self.rtvs {
+                release(rtv);
+            }
+            release(self.dsv);
+        }
+    }
+}
@@ -7,8 +7,6 @@ use comptr::ComPtr;
 use crate::core::*;
 use crate::Result;
 
-use super::util::d3d_usage_to_d3d11;
-
 /// Wrapper for a vertex/index buffer.
 #[derive(Clone)]
 pub struct Buffer {
@@ -16,29 +14,12 @@ pub struct Buffer {
 }
 
 impl Buffer {
-    /// Creates a vertex/index buffer.
-    pub fn new(
-        device: &ID3D11Device,
-        len: u32,
-        usage: UsageFlags,
-        pool: MemoryPool,
-        bind_flags: u32,
-    ) -> Result<Self> {
-        let (usage, _, cpu_flags) = d3d_usage_to_d3d11(usage, pool)?;
-
-        let desc = D3D11_BUFFER_DESC {
-            ByteWidth: len,
-            Usage: usage,
-            BindFlags: bind_flags,
-            CPUAccessFlags: cpu_flags,
-            MiscFlags: 0,
-            StructureByteStride: 0,
-        };
-
+    /// Creates a new buffer from a complete description.
+    pub fn from_desc(device: &ID3D11Device, desc: &D3D11_BUFFER_DESC) -> Result<Self> {
         let buffer = unsafe {
             let mut ptr = ptr::null_mut();
 
-            let result = device.CreateBuffer(&desc, ptr::null(), &mut ptr);
+            let result = device.CreateBuffer(desc, ptr::null(), &mut ptr);
             check_hresult(result, "Failed to create buffer")?;
 
             ComPtr::new(ptr)
@@ -52,6 +33,11 @@ impl Buffer {
         self.buffer.upcast().as_mut()
     }
 
+    /// Retrieves a pointer to the underlying buffer object.
+    pub fn as_buffer(&self) -> *mut ID3D11Buffer {
+        self.buffer.as_mut()
+    }
+
     /// Retrieves the description of this buffer.
     pub fn desc(&self) -> D3D11_BUFFER_DESC {
         unsafe {
@@ -1,9 +1,14 @@
-use std::ops;
+use std::{ops, ptr};
 
+use winapi::shared::dxgi::IDXGIDevice1;
 use winapi::um::d3d11::ID3D11Device;
+use winapi::Interface;
 
 use comptr::ComPtr;
 
+use crate::core::*;
+use crate::Result;
+
 /// Wraps a D3D11 device.
 #[derive(Clone)]
 pub struct Device {
@@ -15,6 +20,19 @@ impl Device {
     pub fn new(device: ComPtr<ID3D11Device>) -> Self {
         Self { device }
     }
+
+    /// Retrieves the DXGI interface of this device.
+    pub fn dxgi_device(&self) -> Result<ComPtr<IDXGIDevice1>> {
+        let mut ptr: *mut IDXGIDevice1 = ptr::null_mut();
+        let uuid = IDXGIDevice1::uuidof();
+
+        let ret = &mut ptr as *mut _ as *mut *mut _;
+
+        let result = unsafe { self.device.QueryInterface(&uuid, ret) };
+        check_hresult(result, "Failed to retrieve DXGI device")?;
+
+        Ok(ComPtr::new(ptr))
+    }
 }
 
 impl ops::Deref for Device {
@@ -1,7 +1,5 @@
 //! Helper module wrapping D3D11's interfaces.
 
-mod util;
-
 mod device;
 pub use self::device::Device;
 
@@ -13,3 +11,14 @@ pub use self::buffer::Buffer;
 
 mod texture;
 pub use self::texture::Texture2D;
+
+mod shader;
+
+mod blit;
+pub use self::blit::Blitter;
+
+mod overlay;
+pub use self::overlay::Overlay;
+
+mod backend;
+pub use self::backend::D3D11Backend;
@@ -0,0 +1,313 @@
+use std::{mem, ptr};
+
+use winapi::shared::dxgiformat::{DXGI_FORMAT_R32G32B32A32_FLOAT, DXGI_FORMAT_R8_UNORM};
+use winapi::shared::dxgitype::DXGI_SAMPLE_DESC;
+use winapi::um::d3d11::*;
+use winapi::um::d3dcommon::{D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST, D3D11_SRV_DIMENSION_BUFFER};
+
+use comptr::ComPtr;
+
+use crate::core::*;
+use crate::hud::{font, Quad};
+use crate::Result;
+
+use super::blit::StateGuard;
+use super::shader::{create_pixel_shader, create_vertex_shader};
+
+/// Vertex shader which expands each quad into two triangles.
+///
+/// The quads are read from a buffer, so no input layout is required.
+const OVERLAY_VS: &str = r#"
+// Each quad is made up of its rectangle, its texture coordinates and its color.
+Buffer<float4> quads : register(t0);
+
+struct VsOut {
+    float4 pos : SV_Position;
+    float2 uv : TEXCOORD0;
+    float4 color : COLOR0;
+};
+
+static const float2 corners[6] = {
+    float2(0.0, 0.0), float2(1.0, 0.0), float2(0.0, 1.0),
+    float2(0.0, 1.0), float2(1.0, 0.0), float2(1.0, 1.0),
+};
+
+VsOut vs_main(uint id : SV_VertexID) {
+    uint quad = (id / 6) * 3;
+    float2 t = corners[id % 6];
+
+    float4 rect = quads.Load(quad);
+    float4 uv = quads.Load(quad + 1);
+
+    VsOut o;
+    o.pos = float4(lerp(rect.xy, rect.zw, t), 0.0, 1.0);
+    o.uv = lerp(uv.xy, uv.zw, t);
+    o.color = quads.Load(quad + 2);
+    return o;
+}
+"#;
+
+/// Pixel shader which either fills a quad, or draws a glyph of the font.
+const OVERLAY_PS: &str = r#"
+Texture2D<float> font : register(t0);
+SamplerState smp : register(s0);
+
+struct VsOut {
+    float4 pos : SV_Position;
+    float2 uv : TEXCOORD0;
+    float4 color : COLOR0;
+};
+
+float4 ps_main(VsOut i) : SV_Target {
+    // Filled quads have negative texture coordinates.
+    float coverage = i.uv.x < 0.0 ? 1.0 : font.Sample(smp, i.uv);
+    return float4(i.color.rgb, i.color.a * coverage);
+}
+"#;
+
+/// Maximum number of quads drawn by the overlay each frame.
+const MAX_QUADS: usize = 4096;
+
+/// Each quad is stored as three `float4`s.
+type QuadData = [[f32; 4]; 3];
+
+/// Draws the HUD's quads on top of a render target.
+pub struct Overlay {
+    vs: ComPtr<ID3D11VertexShader>,
+    ps: ComPtr<ID3D11PixelShader>,
+    // Texture containing the glyphs of the HUD's font.
+    font_view: ComPtr<ID3D11ShaderResourceView>,
+    // Buffer storing the quads of the current frame.
+    quad_buffer: ComPtr<ID3D11Buffer>,
+    quad_view: ComPtr<ID3D11ShaderResourceView>,
+    sampler: ComPtr<ID3D11SamplerState>,
+    // Blends the overlay with the render target's contents.
+    blend: ComPtr<ID3D11BlendState>,
+}
+
+impl Overlay {
+    /// Creates the shaders and resources required for drawing the overlay.
+    pub fn new(device: &ID3D11Device) -> Result<Self> {
+        let vs = create_vertex_shader(device, OVERLAY_VS, "vs_main")?;
+        let ps = create_pixel_shader(device, OVERLAY_PS, "ps_main")?;
+
+        let font_view = Self::create_font(device)?;
+        let (quad_buffer, quad_view) = Self::create_quad_buffer(device)?;
+
+        let sampler = unsafe {
+            let desc = D3D11_SAMPLER_DESC {
+                Filter: D3D11_FILTER_MIN_MAG_MIP_POINT,
+                AddressU: D3D11_TEXTURE_ADDRESS_CLAMP,
+                AddressV: D3D11_TEXTURE_ADDRESS_CLAMP,
+                AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+                MipLODBias: 0.0,
+                MaxAnisotropy: 1,
+                ComparisonFunc: D3D11_COMPARISON_NEVER,
+                BorderColor: [0.0; 4],
+                MinLOD: 0.0,
+                MaxLOD: D3D11_FLOAT32_MAX,
+            };
+
+            let mut ptr = ptr::null_mut();
+
+            let result = device.CreateSamplerState(&desc, &mut ptr);
+            check_hresult(result, "Failed to create overlay sampler")?;
+
+            ComPtr::new(ptr)
+        };
+
+        let blend = unsafe {
+            let mut desc: D3D11_BLEND_DESC = mem::zeroed();
+
+            desc.RenderTarget[0] = D3D11_RENDER_TARGET_BLEND_DESC {
+                BlendEnable: 1,
+                SrcBlend: D3D11_BLEND_SRC_ALPHA,
+                DestBlend: D3D11_BLEND_INV_SRC_ALPHA,
+                BlendOp: D3D11_BLEND_OP_ADD,
+                SrcBlendAlpha: D3D11_BLEND_ZERO,
+                DestBlendAlpha: D3D11_BLEND_ONE,
+                BlendOpAlpha: D3D11_BLEND_OP_ADD,
+                // The back buffer's alpha channel is left untouched.
+                RenderTargetWriteMask: (D3D11_COLOR_WRITE_ENABLE_RED
+                    | D3D11_COLOR_WRITE_ENABLE_GREEN
+                    | D3D11_COLOR_WRITE_ENABLE_BLUE) as u8,
+            };
+
+            let mut ptr = ptr::null_mut();
+
+            let result = device.CreateBlendState(&desc, &mut ptr);
+            check_hresult(result, "Failed to create overlay blend state")?;
+
+            ComPtr::new(ptr)
+        };
+
+        Ok(Self {
+            vs,
+            ps,
+            font_view,
+            quad_buffer,
+            quad_view,
+            sampler,
+            blend,
+        })
+    }
+
+    /// Uploads the font's glyphs to a texture.
+    fn create_font(device: &ID3D11Device) -> Result<ComPtr<ID3D11ShaderResourceView>> {
+        let texels = font::atlas();
+
+        let desc = D3D11_TEXTURE2D_DESC {
+            Width: font::ATLAS_WIDTH,
+            Height: font::ATLAS_HEIGHT,
+            MipLevels: 1,
+            ArraySize: 1,
+            Format: DXGI_FORMAT_R8_UNORM,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Usage: D3D11_USAGE_IMMUTABLE,
+            BindFlags: D3D11_BIND_SHADER_RESOURCE,
+            CPUAccessFlags: 0,
+            MiscFlags: 0,
+        };
+
+        let data = D3D11_SUBRESOURCE_DATA {
+            pSysMem: texels.as_ptr() as *const _,
+            SysMemPitch: font::ATLAS_WIDTH,
+            SysMemSlicePitch: 0,
+        };
+
+        unsafe {
+            let mut ptr = ptr::null_mut();
+
+            let result = device.CreateTexture2D(&desc, &data, &mut ptr);
+            check_hresult(result, "Failed to create overlay font texture")?;
+
+            let texture = ComPtr::new(ptr);
+
+            let mut ptr = ptr::null_mut();
+
+            let result =
+                device.CreateShaderResourceView(texture.upcast().as_mut(), ptr::null(), &mut ptr);
+            check_hresult(result, "Failed to create overlay font view")?;
+
+            Ok(ComPtr::new(ptr))
+        }
+    }
+
+    /// Creates the buffer the quads are written to, and a view for reading it in the shader.
+    fn create_quad_buffer(
+        device: &ID3D11Device,
+    ) -> Result<(ComPtr<ID3D11Buffer>, ComPtr<ID3D11ShaderResourceView>)> {
+        let elements = MAX_QUADS * mem::size_of::<QuadData>() / mem::size_of::<[f32; 4]>();
+
+        unsafe {
+            let desc = D3D11_BUFFER_DESC {
+                ByteWidth: (MAX_QUADS * mem::size_of::<QuadData>()) as u32,
+                Usage: D3D11_USAGE_DYNAMIC,
+                BindFlags: D3D11_BIND_SHADER_RESOURCE,
+                CPUAccessFlags: D3D11_CPU_ACCESS_WRITE,
+                MiscFlags: 0,
+                StructureByteStride: 0,
+            };
+
+            let mut ptr = ptr::null_mut();
+
+            let result = device.CreateBuffer(&desc, ptr::null(), &mut ptr);
+            check_hresult(result, "Failed to create overlay quad buffer")?;
+
+            let buffer: ComPtr<ID3D11Buffer> = ComPtr::new(ptr);
+
+            let mut view_desc: D3D11_SHADER_RESOURCE_VIEW_DESC = mem::zeroed();
+            view_desc.Format = DXGI_FORMAT_R32G32B32A32_FLOAT;
+            view_desc.ViewDimension = D3D11_SRV_DIMENSION_BUFFER;
+            *view_desc.u.Buffer_mut().u2.NumElements_mut() = elements as u32;
+
+            let mut ptr = ptr::null_mut();
+
+            let result =
+                device.CreateShaderResourceView(buffer.upcast().as_mut(), &view_desc, &mut ptr);
+            check_hresult(result, "Failed to create overlay quad view")?;
+
+            Ok((buffer, ComPtr::new(ptr)))
+        }
+    }
+
+    /// Draws quads on top of a render target of a certain size.
+    ///
+    /// The pipeline state modified by this function is restored before it returns.
+    pub fn draw(
+        &self,
+        ctx: &ID3D11DeviceContext,
+        target: &ID3D11RenderTargetView,
+        (width, height): (u32, u32),
+        quads: &[Quad],
+    ) -> Result<()> {
+        let quads = &quads[..quads.len().min(MAX_QUADS)];
+
+        if quads.is_empty() {
+            return Ok(());
+        }
+
+        let _state = StateGuard::save(ctx);
+
+        // Converts a position in pixels to normalized device coordinates.
+        let (width, height) = (width as f32, height as f32);
+        let x = |x: f32| x / width * 2.0 - 1.0;
+        let y = |y: f32| 1.0 - y / height * 2.0;
+
+        unsafe {
+            let mut mapped = mem::uninitialized();
+            let resource = self.quad_buffer.upcast().as_mut();
+
+            let result = ctx.Map(resource, 0, D3D11_MAP_WRITE_DISCARD, 0, &mut mapped);
+            check_hresult(result, "Failed to map overlay quad buffer")?;
+
+            let data = mapped.pData as *mut QuadData;
+
+            for (i, quad) in quads.iter().enumerate() {
+                let [left, top, right, bottom] = quad.rect;
+
+                *data.add(i) = [
+                    [x(left), y(top), x(right), y(bottom)],
+                    quad.uv.unwrap_or([-1.0; 4]),
+                    quad.color,
+                ];
+            }
+
+            ctx.Unmap(resource, 0);
+        }
+
+        let viewport = D3D11_VIEWPORT {
+            TopLeftX: 0.0,
+            TopLeftY: 0.0,
+            Width: width,
+            Height: height,
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        };
+
+        unsafe {
+            ctx.IASetInputLayout(ptr::null_mut());
+            ctx.IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+
+            ctx.VSSetShader(self.vs.as_mut(), ptr::null(), 0);
+            ctx.PSSetShader(self.ps.as_mut(), ptr::null(), 0);
+
+            ctx.VSSetShaderResources(0, 1, &(self.quad_view.as_mut() as *mut _));
+            ctx.PSSetShaderResources(0, 1, &(self.font_view.as_mut() as *mut _));
+            ctx.PSSetSamplers(0, 1, &(self.sampler.as_mut() as *mut _));
+
+            ctx.RSSetState(ptr::null_mut());
+            ctx.RSSetViewports(1, &viewport);
+            ctx.OMSetBlendState(self.blend.as_mut(), &[0.0; 4], !0);
+            ctx.OMSetDepthStencilState(ptr::null_mut(), 0);
+            ctx.OMSetRenderTargets(1, &(target as *const _ as *mut _), ptr::null_mut());
+
+            ctx.Draw(quads.len() as u32 * 6, 0);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use std::{ffi::CString, ptr, slice};
+
+use winapi::um::d3d11::*;
+use winapi::um::d3dcommon::ID3DBlob;
+use winapi::um::d3dcompiler::*;
+
+use comptr::ComPtr;
+
+use crate::core::*;
+use crate::Result;
+
+/// Compiles a HLSL shader's source code into bytecode.
+///
+/// This is only used for the shaders we need internally,
+/// the app's shaders are translated from D3D9 bytecode.
+fn compile(src: &str, entry: &str, target: &str) -> Result<ComPtr<ID3DBlob>> {
+    let entry = CString::new(entry).unwrap();
+    let target = CString::new(target).unwrap();
+
+    let mut code = ptr::null_mut();
+    let mut errors: *mut ID3DBlob = ptr::null_mut();
+
+    let result = unsafe {
+        D3DCompile(
+            src.as_ptr() as *const _,
+            src.len(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null_mut(),
+            entry.as_ptr(),
+            target.as_ptr(),
+            D3DCOMPILE_OPTIMIZATION_LEVEL3,
+            0,
+            &mut code,
+            &mut errors,
+        )
+    };
+
+    if let Some(errors) = unsafe { errors.as_ref() } {
+        let msg = unsafe { blob_bytes(errors) };
+        error!("Shader compiler output:\n{}", String::from_utf8_lossy(msg));
+
+        unsafe {
+            errors.Release();
+        }
+    }
+
+    check_hresult(result, "Failed to compile internal shader")?;
+
+    Ok(ComPtr::new(code))
+}
+
+/// Retrieves the contents of a blob.
+unsafe fn blob_bytes(blob: &ID3DBlob) -> &[u8] {
+    let ptr = blob.GetBufferPointer() as *const u8;
+    slice::from_raw_parts(ptr, blob.GetBufferSize())
+}
+
+/// Compiles and creates a vertex shader.
+pub fn create_vertex_shader(
+    device: &ID3D11Device,
+    src: &str,
+    entry: &str,
+) -> Result<ComPtr<ID3D11VertexShader>> {
+    let code = compile(src, entry, "vs_4_0")?;
+
+    let shader = unsafe {
+        let code = blob_bytes(&code);
+        let mut ptr = ptr::null_mut();
+
+        let result = device.CreateVertexShader(
+            code.as_ptr() as *const _,
+            code.len(),
+            ptr::null_mut(),
+            &mut ptr,
+        );
+        check_hresult(result, "Failed to create vertex shader")?;
+
+        ComPtr::new(ptr)
+    };
+
+    Ok(shader)
+}
+
+/// Compiles and creates a pixel shader.
+pub fn create_pixel_shader(
+    device: &ID3D11Device,
+    src: &str,
+    entry: &str,
+) -> Result<ComPtr<ID3D11PixelShader>> {
+    let code = compile(src, entry, "ps_4_0")?;
+
+    let shader = unsafe {
+        let code = blob_bytes(&code);
+        let mut ptr = ptr::null_mut();
+
+        let result = device.CreatePixelShader(
+            code.as_ptr() as *const _,
+            code.len(),
+            ptr::null_mut(),
+            &mut ptr,
+        );
+        check_hresult(result, "Failed to create pixel shader")?;
+
+        ComPtr::new(ptr)
+    };
+
+    Ok(shader)
+}
@@ -1,15 +1,14 @@
 use std::{mem, ptr};
 
-use winapi::shared::d3d9types::*;
-use winapi::um::d3d11::*;
+use winapi::shared::{dxgi::IDXGIResource, dxgiformat::*};
+use winapi::um::{d3d11::*, d3dcommon::*, winnt::HANDLE};
+use winapi::Interface;
 
 use comptr::ComPtr;
 
-use crate::core::{fmt::d3d_format_to_dxgi, msample::d3d9_to_dxgi_samples, *};
+use crate::core::*;
 use crate::Result;
 
-use super::util::d3d_usage_to_d3d11;
-
 /// Wrapper for a D3D11 2D texture.
 #[derive(Clone)]
 pub struct Texture2D {
@@ -17,36 +16,12 @@ pub struct Texture2D {
 }
 
 impl Texture2D {
-    /// Creates a new texture.
-    pub fn new(
-        device: &ID3D11Device,
-        (width, height): (u32, u32),
-        levels: u32,
-        uflags: UsageFlags,
-        fmt: D3DFORMAT,
-        pool: MemoryPool,
-    ) -> Result<Self> {
-        let (usage, bind_flags, cpu_flags) = d3d_usage_to_d3d11(uflags, pool)?;
-
-        let fmt = d3d_format_to_dxgi(fmt);
-
-        let desc = D3D11_TEXTURE2D_DESC {
-            Width: width,
-            Height: height,
-            MipLevels: levels,
-            ArraySize: 1,
-            Format: fmt,
-            SampleDesc: d3d9_to_dxgi_samples(0, 0),
-            Usage: usage,
-            BindFlags: bind_flags,
-            CPUAccessFlags: cpu_flags,
-            MiscFlags: 0,
-        };
-
+    /// Creates a new texture from a complete description.
+    pub fn from_desc(device: &ID3D11Device, desc: &D3D11_TEXTURE2D_DESC) -> Result<Self> {
         let texture = unsafe {
             let mut ptr = ptr::null_mut();
 
-            let result = device.CreateTexture2D(&desc, ptr::null(), &mut ptr);
+            let result = device.CreateTexture2D(desc, ptr::null(), &mut ptr);
             check_hresult(result, "Failed to create 2D texture")?;
 
             ComPtr::new(ptr)
@@ -55,86 +30,144 @@ impl Texture2D {
         Ok(Self { texture })
     }
 
-    /// Creates a new cube map texture.
-    pub fn new_cube_texture(
-        device: &ID3D11Device,
-        dimension: u32,
-        levels: u32,
-        uflags: UsageFlags,
-        fmt: D3DFORMAT,
-        pool: MemoryPool,
-    ) -> Result<Self> {
-        let (usage, bind_flags, cpu_flags) = d3d_usage_to_d3d11(uflags, pool)?;
-        let fmt = d3d_format_to_dxgi(fmt);
-
-        let desc = D3D11_TEXTURE2D_DESC {
-            Width: dimension,
-            Height: dimension,
-            MipLevels: levels,
-            ArraySize: 6,
-            Format: fmt,
-            SampleDesc: d3d9_to_dxgi_samples(0, 0),
-            Usage: usage,
-            BindFlags: bind_flags,
-            CPUAccessFlags: cpu_flags,
-            MiscFlags: 0,
+    /// Opens a texture created by another device, through its shared handle.
+    pub fn open_shared(device: &ID3D11Device, handle: HANDLE) -> Result<Self> {
+        let mut ptr: *mut ID3D11Texture2D = ptr::null_mut();
+        let uuid = ID3D11Texture2D::uuidof();
+
+        let ret = &mut ptr as *mut _ as *mut *mut _;
+
+        let result = unsafe { device.OpenSharedResource(handle, &uuid, ret) };
+        check_hresult(result, "Failed to open shared texture")?;
+
+        Ok(Self {
+            texture: ComPtr::new(ptr),
+        })
+    }
+
+    /// Retrieves a handle through which other devices can open this texture.
+    ///
+    /// The texture must have been created as shared.
+    pub fn shared_handle(&self) -> Result<HANDLE> {
+        let mut ptr: *mut IDXGIResource = ptr::null_mut();
+        let uuid = IDXGIResource::uuidof();
+
+        let ret = &mut ptr as *mut _ as *mut *mut _;
+
+        let result = unsafe { self.texture.QueryInterface(&uuid, ret) };
+        check_hresult(result, "Failed to retrieve DXGI resource")?;
+
+        let resource = ComPtr::new(ptr);
+
+        let handle = unsafe {
+            let mut handle = ptr::null_mut();
+
+            let result = resource.GetSharedHandle(&mut handle);
+            check_hresult(result, "Failed to get shared handle")?;
+
+            handle
         };
 
-        let texture = unsafe {
+        Ok(handle)
+    }
+
+    /// Creates a render target view from this texture.
+    ///
+    /// The view will interpret the texture's data as having the given format.
+    pub fn create_rt_view(
+        &self,
+        device: &ID3D11Device,
+        fmt: DXGI_FORMAT,
+    ) -> Result<ComPtr<ID3D11RenderTargetView>> {
+        let resource = self.as_resource();
+        let multisampled = self.desc().SampleDesc.Count > 1;
+
+        let view = unsafe {
+            let mut desc: D3D11_RENDER_TARGET_VIEW_DESC = mem::zeroed();
+            desc.Format = fmt;
+
+            if multisampled {
+                desc.ViewDimension = D3D11_RTV_DIMENSION_TEXTURE2DMS;
+            } else {
+                desc.ViewDimension = D3D11_RTV_DIMENSION_TEXTURE2D;
+                desc.u.Texture2D_mut().MipSlice = 0;
+            }
+
             let mut ptr = ptr::null_mut();
 
-            let result = device.CreateTexture2D(&desc, ptr::null(), &mut ptr);
-            check_hresult(result, "Failed to create cube texture")?;
+            let result = device.CreateRenderTargetView(resource, &desc, &mut ptr);
+            check_hresult(result, "Failed to create render target view")?;
 
             ComPtr::new(ptr)
         };
 
-        Ok(Self { texture })
+        Ok(view)
     }
 
-    /// Creates a new render target.
-    pub fn new_rt(
+    /// Creates a shader resource view of all the mip levels of this texture.
+    ///
+    /// The view will interpret the texture's data as having the given format.
+    pub fn create_sr_view(
+        &self,
         device: &ID3D11Device,
-        (width, height): (u32, u32),
-        fmt: D3DFORMAT,
-        ms_ty: D3DMULTISAMPLE_TYPE,
-        ms_qlt: u32,
-    ) -> Result<Self> {
-        let fmt = d3d_format_to_dxgi(fmt);
-
-        let desc = D3D11_TEXTURE2D_DESC {
-            Width: width,
-            Height: height,
-            MipLevels: 1,
-            ArraySize: 1,
-            Format: fmt,
-            SampleDesc: d3d9_to_dxgi_samples(ms_ty, ms_qlt),
-            Usage: D3D11_USAGE_DEFAULT,
-            BindFlags: D3D11_BIND_RENDER_TARGET,
-            CPUAccessFlags: 0,
-            MiscFlags: 0,
-        };
+        fmt: DXGI_FORMAT,
+    ) -> Result<ComPtr<ID3D11ShaderResourceView>> {
+        let resource = self.as_resource();
+        let tex_desc = self.desc();
+
+        let view = unsafe {
+            let mut desc: D3D11_SHADER_RESOURCE_VIEW_DESC = mem::zeroed();
+            desc.Format = fmt;
+
+            if tex_desc.SampleDesc.Count > 1 {
+                desc.ViewDimension = D3D11_SRV_DIMENSION_TEXTURE2DMS;
+            } else if tex_desc.MiscFlags & D3D11_RESOURCE_MISC_TEXTURECUBE != 0 {
+                desc.ViewDimension = D3D11_SRV_DIMENSION_TEXTURECUBE;
+                let cube = desc.u.TextureCube_mut();
+                cube.MostDetailedMip = 0;
+                cube.MipLevels = tex_desc.MipLevels;
+            } else {
+                desc.ViewDimension = D3D11_SRV_DIMENSION_TEXTURE2D;
+                let tex = desc.u.Texture2D_mut();
+                tex.MostDetailedMip = 0;
+                tex.MipLevels = tex_desc.MipLevels;
+            }
 
-        let texture = unsafe {
             let mut ptr = ptr::null_mut();
 
-            let result = device.CreateTexture2D(&desc, ptr::null(), &mut ptr);
-            check_hresult(result, "Failed to create render target texture")?;
+            let result = device.CreateShaderResourceView(resource, &desc, &mut ptr);
+            check_hresult(result, "Failed to create shader resource view")?;
 
             ComPtr::new(ptr)
         };
 
-        Ok(Self { texture })
+        Ok(view)
     }
 
-    /// Creates a render target view from this texture.
-    pub fn create_rt_view(&self, device: &ID3D11Device) -> Result<ComPtr<ID3D11RenderTargetView>> {
+    /// Creates a render target view of a single subresource of this texture.
+    ///
+    /// The view is an array view, so that it can also refer to the faces of cube maps.
+    pub fn create_subresource_rt_view(
+        &self,
+        device: &ID3D11Device,
+        fmt: DXGI_FORMAT,
+        (level, slice): (u32, u32),
+    ) -> Result<ComPtr<ID3D11RenderTargetView>> {
         let resource = self.as_resource();
 
         let view = unsafe {
+            let mut desc: D3D11_RENDER_TARGET_VIEW_DESC = mem::zeroed();
+            desc.Format = fmt;
+            desc.ViewDimension = D3D11_RTV_DIMENSION_TEXTURE2DARRAY;
+
+            let array = desc.u.Texture2DArray_mut();
+            array.MipSlice = level;
+            array.FirstArraySlice = slice;
+            array.ArraySize = 1;
+
             let mut ptr = ptr::null_mut();
 
-            let result = device.CreateRenderTargetView(resource, ptr::null(), &mut ptr);
+            let result = device.CreateRenderTargetView(resource, &desc, &mut ptr);
             check_hresult(result, "Failed to create render target view")?;
 
             ComPtr::new(ptr)
@@ -143,37 +176,37 @@ impl Texture2D {
         Ok(view)
     }
 
-    /// Creates a new depth/stencil buffer.
-    pub fn new_ds(
+    /// Creates a shader resource view of a single subresource of this texture.
+    ///
+    /// The view is an array view, so that it can also refer to the faces of cube maps.
+    pub fn create_subresource_sr_view(
+        &self,
         device: &ID3D11Device,
-        (width, height): (u32, u32),
-        fmt: D3DFORMAT,
-    ) -> Result<Self> {
-        let fmt = d3d_format_to_dxgi(fmt);
-
-        let desc = D3D11_TEXTURE2D_DESC {
-            Width: width,
-            Height: height,
-            MipLevels: 1,
-            ArraySize: 1,
-            Format: fmt,
-            SampleDesc: d3d9_to_dxgi_samples(0, 0),
-            Usage: D3D11_USAGE_DEFAULT,
-            BindFlags: D3D11_BIND_DEPTH_STENCIL,
-            CPUAccessFlags: 0,
-            MiscFlags: 0,
-        };
+        fmt: DXGI_FORMAT,
+        (level, slice): (u32, u32),
+    ) -> Result<ComPtr<ID3D11ShaderResourceView>> {
+        let resource = self.as_resource();
+
+        let view = unsafe {
+            let mut desc: D3D11_SHADER_RESOURCE_VIEW_DESC = mem::zeroed();
+            desc.Format = fmt;
+            desc.ViewDimension = D3D11_SRV_DIMENSION_TEXTURE2DARRAY;
+
+            let array = desc.u.Texture2DArray_mut();
+            array.MostDetailedMip = level;
+            array.MipLevels = 1;
+            array.FirstArraySlice = slice;
+            array.ArraySize = 1;
 
-        let texture = unsafe {
             let mut ptr = ptr::null_mut();
 
-            let result = device.CreateTexture2D(&desc, ptr::null(), &mut ptr);
-            check_hresult(result, "Failed to create depth/stencil texture")?;
+            let result = device.CreateShaderResourceView(resource, &desc, &mut ptr);
+            check_hresult(result, "Failed to create shader resource view")?;
 
             ComPtr::new(ptr)
         };
 
-        Ok(Self { texture })
+        Ok(view)
     }
 
     /// Creates a depth / stencil view from this texture.
@@ -197,11 +230,6 @@ impl Texture2D {
         self.texture.upcast().as_mut()
     }
 
-    /// Calculates the index of a subresource.
-    pub fn calc_subresource(&self, levels: u32, array_slice: u32, num_levels: u32) -> u32 {
-        D3D11CalcSubresource(levels, array_slice, num_levels)
-    }
-
     /// Retrieves the description of this texture.
     pub fn desc(&self) -> D3D11_TEXTURE2D_DESC {
         unsafe {
@@ -6,8 +6,8 @@ use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
 use com_impl::{implementation, interface, ComInterface};
 use comptr::ComPtr;
 
+use crate::backend::Buffer;
 use crate::core::*;
-use crate::d3d11;
 use crate::Error;
 
 use super::{Device, Resource};
@@ -18,7 +18,7 @@ pub struct VertexBuffer {
     resource: Resource,
     refs: AtomicU32,
     fvf: u32,
-    buffer: d3d11::Buffer,
+    buffer: Buffer,
 }
 
 impl VertexBuffer {
@@ -27,7 +27,7 @@ impl VertexBuffer {
         device: &Device,
         pool: MemoryPool,
         fvf: u32,
-        buffer: d3d11::Buffer,
+        buffer: Buffer,
         usage: UsageFlags,
     ) -> ComPtr<Self> {
         let vb = Self {
@@ -40,6 +40,11 @@ impl VertexBuffer {
 
         unsafe { new_com_interface(vb) }
     }
+
+    /// Retrieves the backend's buffer storing the vertices.
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
 }
 
 impl std::ops::Deref for VertexBuffer {
@@ -62,42 +67,53 @@ impl ComInterface<IDirect3DResource9Vtbl> for VertexBuffer {
 #[implementation(IDirect3DVertexBuffer9)]
 impl VertexBuffer {
     fn get_desc(&self, ret: *mut D3DVERTEXBUFFER_DESC) -> Error {
-        let ret = check_mut_ref(ret)?;
+        guarded!("IDirect3DVertexBuffer9::GetDesc", {
+            let ret = check_mut_ref(ret)?;
 
-        let desc = self.buffer.desc();
+            let desc = self.device().backend().buffer_desc(&self.buffer);
 
-        ret.Type = ResourceType::VertexBuffer as u32;
-        ret.Size = desc.ByteWidth;
-        ret.Format = D3DFMT_R32F;
-        ret.FVF = self.fvf;
-        ret.Pool = self.pool() as u32;
-        ret.Usage = self.usage().bits();
+            ret.Type = ResourceType::VertexBuffer as u32;
+            ret.Size = desc.ByteWidth;
+            ret.Format = D3DFMT_R32F;
+            ret.FVF = self.fvf;
+            ret.Pool = self.pool() as u32;
+            ret.Usage = self.usage().bits();
 
-        Error::Success
+            Error::Success
+        })
     }
 
     fn lock(&self, offset: u32, _size: u32, ret: *mut *mut u8, flags: LockFlags) -> Error {
-        let ret = check_mut_ref(ret)?;
+        traced!(
+            self,
+            "IDirect3DVertexBuffer9::Lock",
+            [offset, _size, flags.bits()],
+            {
+                let ret = check_mut_ref(ret)?;
 
-        let resource = self.buffer.as_resource();
-        let ctx = self.device_context();
-        let mapped = ctx.map(resource, 0, flags, self.usage())?;
+                // TODO: allow buffers to be mapped multiple times.
+                self.validate_lock("IDirect3DVertexBuffer9::Lock", None, 0)?;
 
-        // TODO: allow buffers to be mapped multiple times.
-        info!("Mapped vertex buffer");
-        *ret = unsafe {
-            let addr = mapped.pBits as *mut u8;
-            addr.offset(offset as isize)
-        };
+                let mapped = self.map_buffer(&self.buffer, flags)?;
 
-        Error::Success
+                info!("Mapped vertex buffer");
+                *ret = unsafe { mapped.offset(offset as isize) };
+
+                Error::Success
+            }
+        )
     }
 
     fn unlock(&self) -> Error {
-        let resource = self.buffer.as_resource();
-        let ctx = self.device_context();
-        ctx.unmap(resource, 0);
-        Error::Success
+        guarded!("IDirect3DVertexBuffer9::Unlock", {
+            self.validate_unlock("IDirect3DVertexBuffer9::Unlock", None, 0)?;
+            let (_, contents) = self.locked_contents(0);
+
+            traced!(self, "IDirect3DVertexBuffer9::Unlock", [contents], {
+                self.unmap_buffer(&self.buffer);
+                Error::Success
+            })
+        })
     }
 }
 
@@ -107,7 +123,7 @@ pub struct IndexBuffer {
     resource: Resource,
     refs: AtomicU32,
     fmt: D3DFORMAT,
-    buffer: d3d11::Buffer,
+    buffer: Buffer,
 }
 
 impl IndexBuffer {
@@ -116,7 +132,7 @@ impl IndexBuffer {
         device: &Device,
         fmt: D3DFORMAT,
         pool: MemoryPool,
-        buffer: d3d11::Buffer,
+        buffer: Buffer,
         usage: UsageFlags,
     ) -> ComPtr<Self> {
         let vb = Self {
@@ -129,6 +145,16 @@ impl IndexBuffer {
 
         unsafe { new_com_interface(vb) }
     }
+
+    /// Retrieves the backend's buffer storing the indices.
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// Retrieves the format of the indices, which are either 16 or 32 bits wide.
+    pub fn format(&self) -> D3DFORMAT {
+        self.fmt
+    }
 }
 
 impl std::ops::Deref for IndexBuffer {
@@ -151,40 +177,51 @@ impl ComInterface<IDirect3DResource9Vtbl> for IndexBuffer {
 #[implementation(IDirect3DIndexBuffer9)]
 impl IndexBuffer {
     fn get_desc(&self, ret: *mut D3DINDEXBUFFER_DESC) -> Error {
-        let ret = check_mut_ref(ret)?;
+        guarded!("IDirect3DIndexBuffer9::GetDesc", {
+            let ret = check_mut_ref(ret)?;
 
-        let desc = self.buffer.desc();
+            let desc = self.device().backend().buffer_desc(&self.buffer);
 
-        ret.Type = ResourceType::IndexBuffer as u32;
-        ret.Size = desc.ByteWidth;
-        ret.Format = self.fmt;
-        ret.Pool = self.pool() as u32;
-        ret.Usage = self.usage().bits();
+            ret.Type = ResourceType::IndexBuffer as u32;
+            ret.Size = desc.ByteWidth;
+            ret.Format = self.fmt;
+            ret.Pool = self.pool() as u32;
+            ret.Usage = self.usage().bits();
 
-        Error::Success
+            Error::Success
+        })
     }
 
     fn lock(&self, offset: u32, _size: u32, ret: *mut *mut u8, flags: LockFlags) -> Error {
-        let ret = check_mut_ref(ret)?;
+        traced!(
+            self,
+            "IDirect3DIndexBuffer9::Lock",
+            [offset, _size, flags.bits()],
+            {
+                let ret = check_mut_ref(ret)?;
 
-        let resource = self.buffer.as_resource();
-        let ctx = self.device_context();
-        let mapped = ctx.map(resource, 0, flags, self.usage())?;
+                // TODO: allow buffers to be mapped multiple times.
+                self.validate_lock("IDirect3DIndexBuffer9::Lock", None, 0)?;
 
-        // TODO: allow buffers to be mapped multiple times.
-        info!("Mapped index buffer");
-        *ret = unsafe {
-            let addr = mapped.pBits as *mut u8;
-            addr.offset(offset as isize)
-        };
+                let mapped = self.map_buffer(&self.buffer, flags)?;
+
+                info!("Mapped index buffer");
+                *ret = unsafe { mapped.offset(offset as isize) };
 
-        Error::Success
+                Error::Success
+            }
+        )
     }
 
     fn unlock(&self) -> Error {
-        let resource = self.buffer.as_resource();
-        let ctx = self.device_context();
-        ctx.unmap(resource, 0);
-        Error::Success
+        guarded!("IDirect3DIndexBuffer9::Unlock", {
+            self.validate_unlock("IDirect3DIndexBuffer9::Unlock", None, 0)?;
+            let (_, contents) = self.locked_contents(0);
+
+            traced!(self, "IDirect3DIndexBuffer9::Unlock", [contents], {
+                self.unmap_buffer(&self.buffer);
+                Error::Success
+            })
+        })
     }
 }
@@ -0,0 +1,239 @@
+use std::{cell::Cell, mem, ptr, slice};
+
+use winapi::shared::{d3d9types::*, windef::HCURSOR};
+use winapi::um::{d3d11::*, wingdi, winuser};
+
+use crate::backend::desc;
+use crate::core::*;
+use crate::{Error, Result};
+
+use super::{Device, Surface};
+
+/// State of the device's cursor.
+///
+/// D3D9's hardware cursor is implemented using the OS cursor,
+/// which is created from the image the app provides.
+pub struct Cursor {
+    // Cursor created from the app's image, if any.
+    handle: Cell<HCURSOR>,
+    // Whether the app wants the cursor to be shown.
+    visible: Cell<bool>,
+    // Last position set by the app, in desktop coordinates.
+    position: Cell<(i32, i32)>,
+}
+
+impl Cursor {
+    /// Creates a new, hidden cursor with no image.
+    pub fn new() -> Self {
+        Self {
+            handle: Cell::new(ptr::null_mut()),
+            visible: Cell::new(false),
+            position: Cell::new((0, 0)),
+        }
+    }
+
+    /// Replaces the cursor's image with the contents of a surface.
+    pub fn set_image(&self, device: &Device, surface: &Surface, hotspot: (u32, u32)) -> Result<()> {
+        let mut desc = unsafe { mem::zeroed() };
+        surface.get_desc(&mut desc)?;
+
+        let (width, height) = (desc.Width, desc.Height);
+
+        if desc.Format != D3DFMT_A8R8G8B8 {
+            error!("Cursor images must be A8R8G8B8");
+            return Err(Error::InvalidCall);
+        }
+
+        if !width.is_power_of_two() || !height.is_power_of_two() {
+            error!("Cursor image dimensions must be powers of 2");
+            return Err(Error::InvalidCall);
+        }
+
+        if hotspot.0 >= width || hotspot.1 >= height {
+            error!("Cursor hotspot is outside the image");
+            return Err(Error::InvalidCall);
+        }
+
+        let pixels = Self::read_pixels(device, surface, (width, height))?;
+
+        let handle = unsafe { Self::create_cursor((width, height), &pixels, hotspot)? };
+
+        let old = self.handle.replace(handle);
+        if !old.is_null() {
+            unsafe {
+                winuser::DestroyCursor(old);
+            }
+        }
+
+        self.update();
+
+        Ok(())
+    }
+
+    /// Moves the cursor to a position in desktop coordinates.
+    pub fn set_position(&self, x: i32, y: i32) {
+        unsafe {
+            winuser::SetCursorPos(x, y);
+        }
+
+        self.position.set((x, y));
+    }
+
+    /// Retrieves the last position set by the app.
+    pub fn position(&self) -> (i32, i32) {
+        self.position.get()
+    }
+
+    /// Shows or hides the cursor, returning the previous visibility.
+    pub fn show(&self, visible: bool) -> bool {
+        let old = self.visible.replace(visible);
+        self.update();
+        old
+    }
+
+    /// Updates the OS cursor to match our state.
+    fn update(&self) {
+        let handle = self.handle.get();
+
+        // The app might not have set an image yet,
+        // in which case we leave the OS cursor alone.
+        if handle.is_null() {
+            return;
+        }
+
+        let cursor = if self.visible.get() {
+            handle
+        } else {
+            ptr::null_mut()
+        };
+
+        unsafe {
+            winuser::SetCursor(cursor);
+        }
+    }
+
+    /// Copies a surface's contents into CPU memory, as 32-bit BGRA pixels.
+    fn read_pixels(
+        device: &Device,
+        surface: &Surface,
+        (width, height): (u32, u32),
+    ) -> Result<Vec<u32>> {
+        // We need a staging copy of the surface in order to read it.
+        let staging_desc = desc::texture(
+            (width, height),
+            1,
+            UsageFlags::empty(),
+            D3DFMT_A8R8G8B8,
+            MemoryPool::SystemMem,
+            false,
+        )?;
+
+        let backend = device.backend();
+        let staging = backend.create_texture_2d(&staging_desc)?;
+
+        let subresource = surface.subresource();
+        backend.copy_texture_region(&staging, 0, (0, 0), surface.texture(), subresource, None);
+
+        let mapped = backend.map_texture(&staging, 0, D3D11_MAP_READ, 0)?;
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+
+        unsafe {
+            for y in 0..height {
+                let row = (mapped.pData as *const u8).offset((y * mapped.RowPitch) as isize);
+                let row = slice::from_raw_parts(row as *const u32, width as usize);
+                pixels.extend_from_slice(row);
+            }
+        }
+
+        backend.unmap_texture(&staging, 0);
+
+        Ok(pixels)
+    }
+
+    /// Creates an OS cursor from an image.
+    unsafe fn create_cursor(
+        (width, height): (u32, u32),
+        pixels: &[u32],
+        (x_hot, y_hot): (u32, u32),
+    ) -> Result<HCURSOR> {
+        // The color bitmap is a DIB section, whose layout is given explicitly:
+        // top-down rows of 32-bit BGRA pixels, with alpha.
+        let mut info: wingdi::BITMAPINFO = mem::zeroed();
+        info.bmiHeader = wingdi::BITMAPINFOHEADER {
+            biSize: mem::size_of::<wingdi::BITMAPINFOHEADER>() as u32,
+            biWidth: width as i32,
+            // A negative height means the rows start from the top.
+            biHeight: -(height as i32),
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: wingdi::BI_RGB,
+            biSizeImage: 0,
+            biXPelsPerMeter: 0,
+            biYPelsPerMeter: 0,
+            biClrUsed: 0,
+            biClrImportant: 0,
+        };
+
+        let mut bits = ptr::null_mut();
+        let color = wingdi::CreateDIBSection(
+            ptr::null_mut(),
+            &info,
+            wingdi::DIB_RGB_COLORS,
+            &mut bits,
+            ptr::null_mut(),
+            0,
+        );
+
+        if color.is_null() || bits.is_null() {
+            error!("Failed to create cursor bitmap");
+            return Err(Error::InvalidCall);
+        }
+
+        ptr::copy_nonoverlapping(pixels.as_ptr(), bits as *mut u32, pixels.len());
+
+        // The mask is all zeroes, so that the color bitmap's alpha channel is used.
+        // Monochrome bitmap rows are aligned to 16 bits.
+        let mask_pitch = ((width + 15) / 16) * 2;
+        let mask_bits = vec![0u8; (mask_pitch * height) as usize];
+        let mask = wingdi::CreateBitmap(
+            width as i32,
+            height as i32,
+            1,
+            1,
+            mask_bits.as_ptr() as *const _,
+        );
+
+        let mut info = winuser::ICONINFO {
+            fIcon: 0,
+            xHotspot: x_hot,
+            yHotspot: y_hot,
+            hbmMask: mask,
+            hbmColor: color,
+        };
+
+        let cursor = winuser::CreateIconIndirect(&mut info);
+
+        // The cursor keeps its own copy of the bitmaps.
+        wingdi::DeleteObject(color as *mut _);
+        wingdi::DeleteObject(mask as *mut _);
+
+        if cursor.is_null() {
+            error!("Failed to create cursor");
+            Err(Error::InvalidCall)
+        } else {
+            Ok(cursor)
+        }
+    }
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        let handle = self.handle.get();
+        if !handle.is_null() {
+            unsafe {
+                winuser::DestroyCursor(handle);
+            }
+        }
+    }
+}
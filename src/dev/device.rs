@@ -1,10 +1,21 @@
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::{cmp, mem, ptr};
+use std::cell::{Cell, RefCell};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
+};
+use std::{cmp, mem, ptr, rc::Rc, slice};
 
-use winapi::shared::{d3d9::*, d3d9caps::D3DCAPS9, d3d9types::*, dxgi::IDXGIFactory, windef::*};
+use winapi::ctypes::c_void;
+use winapi::shared::{
+    d3d9::*, d3d9caps::D3DCAPS9, d3d9types::*, dxgiformat::*, minwindef::BOOL, windef::*,
+};
 use winapi::um::{
     d3d11::*,
+    d3dcommon::*,
     unknwnbase::{IUnknown, IUnknownVtbl},
+    wingdi::RGNDATAHEADER,
+    winnt::HANDLE,
+    winuser,
 };
 
 use com_impl::{implementation, interface, ComInterface};
@@ -13,12 +24,15 @@ use comptr::ComPtr;
 use super::state::{DeviceState, StateBlock};
 use super::*;
 
-use crate::core::*;
-use crate::d3d11;
-use crate::{Error, Result};
+use crate::backend::{self, desc, Backend, SamplerCache, ShaderStage};
+use crate::core::sampler::SamplerKey;
+use crate::core::{msample::*, *};
+use crate::{config::Config, trace, Error, Result};
 
 /// Structure representing a logical graphics device.
-#[interface(IDirect3DDevice9)]
+///
+/// This implements both the original and the D3D9Ex interfaces.
+#[interface(IDirect3DDevice9Ex)]
 pub struct Device {
     refs: AtomicU32,
     // Interface which created this device.
@@ -28,15 +42,12 @@ pub struct Device {
     // Since D3D11 is thread-safe, we allow multiple logical devices
     // to share the same adapter.
     adapter: *const Adapter,
-    // The equivalent interface from D3D11.
-    device: d3d11::Device,
-    // The context in which commands are run.
-    ctx: d3d11::DeviceContext,
+    // The backend through which translated commands are executed.
+    backend: Rc<dyn Backend>,
+    // Sampler objects created from the D3D9 sampler states.
+    samplers: SamplerCache,
     // Store the creation params, since the app might request them later.
     creation_params: D3DDEVICE_CREATION_PARAMETERS,
-    // The DXGI factory which was used to create this device.
-    // Required when creating new swap chains.
-    factory: ComPtr<IDXGIFactory>,
     // The window associated with this device.
     window: HWND,
     // The implicit swap chain for the back buffer.
@@ -46,6 +57,33 @@ pub struct Device {
     render_targets: Vec<Option<ComPtr<Surface>>>,
     // The device's current depth / stencil buffer.
     depth_stencil: Option<ComPtr<Surface>>,
+    // Number of resources in the default memory pool the app has not yet released.
+    // The device cannot be reset while any of these exist.
+    default_resources: Arc<AtomicU32>,
+    // Set when the device is lost, and cleared when it is reset.
+    lost: AtomicBool,
+    // Whether this device was created through the D3D9Ex interface,
+    // which has different rules for device loss and resetting.
+    ex: bool,
+    // Whether the app is between calls to `BeginScene` and `EndScene`.
+    in_scene: bool,
+
+    // Statistics of the frame the app is currently submitting.
+    stats: RefCell<FrameStats>,
+    // Statistics of the last frame which was presented.
+    last_stats: Cell<FrameStats>,
+
+    // Caps the frame rate, if the user set a limit.
+    frame_limiter: RefCell<Option<FrameLimiter>>,
+    // Waits for the GPU after presenting, if the user set a maximum frame latency.
+    latency_limiter: RefCell<Option<LatencyLimiter>>,
+
+    // State of the hardware cursor.
+    cursor: Cursor,
+
+    // Dynamic buffers into which the vertices and indices passed to the `*UP` draws are copied.
+    up_vertices: RefCell<Option<backend::Buffer>>,
+    up_indices: RefCell<Option<backend::Buffer>>,
 
     // The current internal state of this device,
     // as it was last set by calling state functions.
@@ -59,11 +97,8 @@ impl Device {
         adapter: &Adapter,
         cp: D3DDEVICE_CREATION_PARAMETERS,
         pp: &mut D3DPRESENT_PARAMETERS,
-        factory: ComPtr<IDXGIFactory>,
+        ex: bool,
     ) -> Result<ComPtr<Device>> {
-        let device = d3d11::Device::new(adapter.device());
-        let ctx = d3d11::DeviceContext::new(&device);
-
         // Determine which window to render to.
         // TODO: track the focus window and use it to disable rendering
         // when the app loses focus. It is currently ignored.
@@ -78,62 +113,49 @@ impl Device {
 
         let istate = DeviceState::default();
 
+        let backend = adapter.backend();
+
+        let config = adapter.config();
+        let frame_limiter = config.fps_limit.map(FrameLimiter::new);
+        let latency_limiter = match config.max_frame_latency {
+            Some(frames) => Some(LatencyLimiter::new(&*backend, frames)?),
+            None => None,
+        };
+
         let device = Self {
             __vtable: Box::new(Self::create_vtable()),
             refs: AtomicU32::new(1),
             parent,
             adapter,
-            device,
-            ctx,
+            backend,
+            samplers: SamplerCache::new(),
             creation_params: cp,
-            factory,
             window,
             swap_chains: Vec::new(),
             render_targets: Vec::new(),
             depth_stencil: None,
+            default_resources: Arc::new(AtomicU32::new(0)),
+            lost: AtomicBool::new(false),
+            ex,
+            in_scene: false,
+            stats: RefCell::new(FrameStats::default()),
+            last_stats: Cell::new(FrameStats::default()),
+            frame_limiter: RefCell::new(frame_limiter),
+            latency_limiter: RefCell::new(latency_limiter),
+            cursor: Cursor::new(),
+            up_vertices: RefCell::new(None),
+            up_indices: RefCell::new(None),
             istate,
         };
 
         let mut device: ComPtr<Device> = unsafe { new_com_interface(device) };
 
-        // Create the default swap chain for the adapter.
-        device.create_default_swap_chain(pp)?;
-
-        // Create the default render target for the swap chain.
-        device.create_default_render_target()?;
-
-        // If the application requested it, we can automatically create
-        // a depth/stencil buffer for it.
-        if pp.EnableAutoDepthStencil != 0 {
-            device.depth_stencil = {
-                let width = pp.BackBufferWidth;
-                let height = pp.BackBufferHeight;
-                let fmt = pp.AutoDepthStencilFormat;
-                let discard = pp.Flags & D3DPRESENTFLAG_DISCARD_DEPTHSTENCIL;
-                let ms_ty = 0;
-                let ms_qlt = 0;
-                let shared_handle = 0;
-
-                let mut ptr = ptr::null_mut();
-
-                device.create_depth_stencil_surface(
-                    width,
-                    height,
-                    fmt,
-                    ms_ty,
-                    ms_qlt,
-                    discard,
-                    &mut ptr,
-                    shared_handle,
-                )?;
+        device.create_implicit_objects(pp)?;
 
-                Some(ComPtr::new(ptr))
-            };
+        if let Some(latency) = device.config().max_frame_latency {
+            device.backend.set_maximum_frame_latency(latency)?;
         }
 
-        // Now that we have an initial RT / DS buffer, we need to set D3D11's state.
-        device.update_render_targets();
-
         Ok(device)
     }
 
@@ -142,9 +164,162 @@ impl Device {
         unsafe { &*self.adapter }
     }
 
-    /// Retrieves a reference to the immediate device context.
-    pub fn device_context(&self) -> &d3d11::DeviceContext {
-        &self.ctx
+    /// Returns the user's configuration.
+    pub fn config(&self) -> &Config {
+        self.adapter().config()
+    }
+
+    /// Checks if this device was created through the D3D9Ex interface.
+    pub fn is_ex(&self) -> bool {
+        self.ex
+    }
+
+    /// Checks a call against the rules of the API.
+    ///
+    /// The check only runs if the user enabled strict validation,
+    /// in which case the call fails if it returns a diagnostic.
+    pub fn validate<F>(&self, check: F) -> Result<()>
+    where
+        F: FnOnce() -> Option<Diagnostic>,
+    {
+        if !self.config().strict_validation {
+            return Ok(());
+        }
+
+        match check() {
+            Some(diag) => {
+                diag.report();
+                Err(Error::InvalidCall)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Rejects a call which is always invalid.
+    ///
+    /// The reason is only logged if the user enabled strict validation.
+    pub fn reject(&self, diag: Diagnostic) -> Error {
+        if self.config().strict_validation {
+            diag.report();
+        }
+
+        Error::InvalidCall
+    }
+
+    /// Checks that the current state allows drawing.
+    pub fn validate_draw(&self, method: &'static str) -> Result<()> {
+        self.validate(|| {
+            if !self.in_scene {
+                let message = "not called between BeginScene and EndScene";
+                return Some(Diagnostic::state(method, message));
+            }
+
+            let vs = unsafe { self.istate.get_vertex_shader().as_ref() };
+            let decl = unsafe { self.istate.get_vertex_declaration().as_ref() };
+
+            if let (Some(vs), Some(decl)) = (vs, decl) {
+                let diag = check_vertex_inputs(method, decl.elements(), vs.code());
+                if diag.is_some() {
+                    return diag;
+                }
+            }
+
+            let stages = (0..16).chain(D3DVERTEXTEXTURESAMPLER0..=D3DVERTEXTEXTURESAMPLER3);
+
+            stages
+                .filter_map(|stage| BaseTexture::from_interface(self.istate.get_texture(stage)))
+                .find(|texture| self.is_render_target(texture))
+                .map(|_| {
+                    let message = "a texture is bound both as a render target and to a sampler";
+                    Diagnostic::state(method, message)
+                })
+        })
+    }
+
+    /// Checks if a texture is currently bound as a render target.
+    fn is_render_target(&self, texture: &BaseTexture) -> bool {
+        self.render_targets
+            .iter()
+            .filter_map(|rt| rt.as_ref())
+            .any(|rt| rt.texture().same(texture.texture()))
+    }
+
+    /// Counts a draw call in the statistics of the current frame.
+    fn count_draw(&self, primitives: u32) {
+        self.count(|stats| {
+            stats.draws += 1;
+            stats.primitives += u64::from(primitives);
+        });
+    }
+
+    /// Updates the statistics of the frame the app is currently submitting.
+    pub fn count<F>(&self, update: F)
+    where
+        F: FnOnce(&mut FrameStats),
+    {
+        update(&mut self.stats.borrow_mut());
+    }
+
+    /// Retrieves the statistics of the last frame which was presented.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.last_stats.get()
+    }
+
+    /// Finishes the statistics of the current frame, and starts counting the next one.
+    ///
+    /// Called each time a swap chain of this device presents.
+    pub fn end_frame(&self) {
+        let mut stats = self.stats.replace(FrameStats::default());
+
+        let (hits, misses) = self.samplers.take_counts();
+        stats.cache_hits += hits;
+        stats.cache_misses += misses;
+
+        debug!("Frame statistics: {}", stats);
+
+        self.last_stats.set(stats);
+    }
+
+    /// Waits until the next frame can be presented, if the user capped the frame rate.
+    pub fn limit_frame_rate(&self) {
+        if let Some(limiter) = self.frame_limiter.borrow_mut().as_mut() {
+            limiter.wait();
+        }
+    }
+
+    /// Waits until the GPU is no more than the maximum frame latency behind,
+    /// after a frame was presented.
+    ///
+    /// DXGI's own limit only applies to the frames it queues up,
+    /// so the work the app submits for the next frames could still pile up.
+    pub fn limit_frame_latency(&self) -> Result<()> {
+        match self.latency_limiter.borrow_mut().as_mut() {
+            Some(limiter) => limiter.end_frame(&*self.backend),
+            None => Ok(()),
+        }
+    }
+
+    /// Retrieves the backend which executes this device's commands.
+    pub fn backend(&self) -> &dyn Backend {
+        &*self.backend
+    }
+
+    /// Creates the objects which the device owns, and binds them.
+    fn create_implicit_objects(&mut self, pp: &mut D3DPRESENT_PARAMETERS) -> Result<()> {
+        // Create the default swap chain for the adapter.
+        self.create_default_swap_chain(pp)?;
+
+        // Create the default render target for the swap chain.
+        self.create_default_render_target()?;
+
+        // Create the depth / stencil buffer, if the app wants one.
+        self.create_default_depth_stencil(pp)?;
+
+        // Now that we have an initial RT / DS buffer, we need to set D3D11's state.
+        self.update_render_targets();
+        self.bind_samplers();
+
+        Ok(())
     }
 
     /// Creates the default swap chain for this device.
@@ -176,34 +351,185 @@ impl Device {
     }
 
     /// Helper function for creating render targets.
-    fn create_render_target_helper(&self, texture: d3d11::Texture2D) -> Result<ComPtr<Surface>> {
-        // Create a render target view into the texture.
-        let rt_view = texture.create_rt_view(&self.device)?;
-
-        let data = SurfaceData::RenderTarget(rt_view);
+    fn create_render_target_helper(
+        &self,
+        texture: backend::Texture,
+        fmt: D3DFORMAT,
+    ) -> Result<ComPtr<Surface>> {
+        let data = self.render_target_data(&texture, fmt);
         let surface = Surface::new(
             self,
             texture,
             UsageFlags::RENDER_TARGET,
             MemoryPool::Default,
+            fmt,
             data,
         );
 
         Ok(surface)
     }
 
+    /// Determines the formats a texture is bound with as a render target.
+    fn render_target_data(&self, texture: &backend::Texture, fmt: D3DFORMAT) -> SurfaceData {
+        // If possible, the surface can also be bound with a format which writes sRGB values.
+        let desc = self.backend.texture_desc(texture);
+        let srgb_fmt = desc::srgb_view_format(&desc, fmt);
+
+        SurfaceData::RenderTarget(desc::linear_view_format(&desc, fmt), srgb_fmt)
+    }
+
     /// Creates the default render target for this device.
     fn create_default_render_target(&mut self) -> Result<()> {
         let sc = &self.swap_chains[0];
         let bbuf = sc.buffer(0)?;
+        let fmt = sc.back_buffer_format();
+
+        // Once the app enables sRGB writes, the swap chain replaces this buffer with
+        // a typeless one, which the render target is then pointed at.
+        let rt = self.create_render_target_helper(bbuf, fmt)?;
+        rt.as_mut().make_implicit();
 
-        let rt = self.create_render_target_helper(bbuf)?;
+        if sc.lockable_back_buffer() {
+            rt.as_mut().make_lockable();
+        }
 
         self.render_targets.push(Some(rt));
 
         Ok(())
     }
 
+    /// Creates the implicit depth / stencil buffer.
+    fn create_default_depth_stencil(&mut self, pp: &D3DPRESENT_PARAMETERS) -> Result<()> {
+        // If the application requested it, we can automatically create
+        // a depth/stencil buffer for it.
+        if pp.EnableAutoDepthStencil == 0 {
+            return Ok(());
+        }
+
+        let width = pp.BackBufferWidth;
+        let height = pp.BackBufferHeight;
+        let fmt = pp.AutoDepthStencilFormat;
+        let discard = pp.Flags & D3DPRESENTFLAG_DISCARD_DEPTHSTENCIL;
+
+        // The depth buffer must have the same number of samples as the back buffer,
+        // which might have been upgraded by the user.
+        let back_buffer = self.swap_chains[0].buffer(0)?;
+        let samples = self.backend.texture_desc(&back_buffer).SampleDesc;
+        let (ms_ty, ms_qlt) = dxgi_samples_to_d3d9(samples);
+        let shared_handle = ptr::null_mut();
+
+        let mut ptr = ptr::null_mut();
+
+        self.create_depth_stencil_surface(
+            width,
+            height,
+            fmt,
+            ms_ty,
+            ms_qlt,
+            discard,
+            &mut ptr,
+            shared_handle,
+        )?;
+
+        let ds = ComPtr::new(ptr);
+        ds.as_mut().make_implicit();
+
+        self.depth_stencil = Some(ds);
+
+        Ok(())
+    }
+
+    /// Creates a texture which could be shared with other devices.
+    ///
+    /// If `handle` points to an existing shared handle, the texture it refers to is opened.
+    /// Otherwise, a new texture is created by calling `create` with whether it has to be
+    /// shareable, and if `handle` is not null the new texture's shared handle is written to it.
+    fn create_shared_texture<F>(
+        &self,
+        handle: *mut HANDLE,
+        pool: MemoryPool,
+        create: F,
+    ) -> Result<backend::Texture>
+    where
+        F: FnOnce(bool) -> Result<backend::Texture>,
+    {
+        let handle = match unsafe { handle.as_mut() } {
+            Some(handle) => handle,
+            None => return create(false),
+        };
+
+        if !self.ex {
+            error!("Only D3D9Ex devices can share resources");
+            return Err(Error::InvalidCall);
+        }
+
+        if pool != MemoryPool::Default {
+            error!("Only resources in the default pool can be shared");
+            return Err(Error::InvalidCall);
+        }
+
+        if handle.is_null() {
+            let texture = create(true)?;
+            *handle = self.backend.shared_handle(&texture)?;
+            Ok(texture)
+        } else {
+            self.backend.open_shared_texture(*handle)
+        }
+    }
+
+    /// Registers a newly created resource from the default memory pool.
+    ///
+    /// Returns the counter which the resource has to decrement once it is released.
+    /// Resources hold on to it, since the app could release them after the device.
+    pub fn track_resource(&self) -> Arc<AtomicU32> {
+        self.default_resources.fetch_add(1, Ordering::SeqCst);
+        self.default_resources.clone()
+    }
+
+    /// Retrieves the window which is used to determine if the app is in the foreground.
+    fn focus_window(&self) -> HWND {
+        let focus = self.creation_params.hFocusWindow;
+
+        if focus.is_null() {
+            self.window
+        } else {
+            focus
+        }
+    }
+
+    /// Checks if the device has been lost, and whether it can be reset.
+    ///
+    /// Returns `DeviceLost` if the device is lost and cannot be reset yet,
+    /// or `DeviceNotReset` if the app has to reset it before continuing.
+    pub fn check_lost(&self) -> Result<()> {
+        if !self.lost.load(Ordering::SeqCst) {
+            // The device is lost when the app loses exclusive full-screen mode.
+            // D3D9Ex devices are only lost if they fail to reset.
+            let lost = !self.ex
+                && self
+                    .swap_chains
+                    .first()
+                    .map(|sc| sc.lost_fullscreen())
+                    .unwrap_or(false);
+
+            if !lost {
+                return Ok(());
+            }
+
+            info!("Device lost due to leaving full-screen mode");
+            self.lost.store(true, Ordering::SeqCst);
+        }
+
+        // The device can only be reset once the app is back in the foreground.
+        let foreground = unsafe { winuser::GetForegroundWindow() };
+
+        if foreground == self.focus_window() {
+            Err(Error::DeviceNotReset)
+        } else {
+            Err(Error::DeviceLost)
+        }
+    }
+
     /// Retrieves a handle to a render target.
     fn check_render_target(&self, i: u32) -> Result<&ComPtr<Surface>> {
         if let Some(rt) = self.render_targets.get(i as usize) {
@@ -217,30 +543,58 @@ impl Device {
         }
     }
 
-    /// Synchronises D3D9's render target views and depth / stencil view with D3D11.
-    fn update_render_targets(&mut self) {
-        let num = self.render_targets.len() as u32;
+    /// Points the bound render targets which are DXGI's back buffers
+    /// at the buffers which replaced them.
+    ///
+    /// Swap chains only replace their buffers once the app writes sRGB values into them.
+    fn retarget_back_buffers(&self) -> Result<()> {
+        let srgb = self.istate.get_render_state(D3DRS_SRGBWRITEENABLE) != 0;
 
-        let mut rt_views = [ptr::null_mut(); 8];
-        for (i, rt) in self.render_targets.iter().enumerate() {
-            if let Some(rt) = rt {
-                rt_views[i] = rt.render_target_view().unwrap() as *mut _;
+        for rt in self.render_targets.iter().filter_map(Option::as_ref) {
+            for sc in &self.swap_chains {
+                if let Some(buffer) = sc.retained_buffer(rt.texture(), srgb)? {
+                    let data = self.render_target_data(&buffer, rt.format());
+                    rt.as_mut().retarget(buffer, data);
+                }
             }
         }
 
-        let ds_view = self
-            .depth_stencil
-            .as_ref()
-            .map(|ds| ds.depth_stencil_view().unwrap() as *mut _)
-            .unwrap_or(ptr::null_mut());
+        Ok(())
+    }
 
-        unsafe {
-            self.ctx.OMSetRenderTargets(num, rt_views.as_ptr(), ds_view);
+    /// Synchronises D3D9's render target views and depth / stencil view with D3D11.
+    fn bind_render_targets(&self) {
+        // The render targets' formats depend on whether sRGB writes are enabled.
+        let srgb = self.istate.get_render_state(D3DRS_SRGBWRITEENABLE) != 0;
+
+        let targets: Vec<_> = self
+            .render_targets
+            .iter()
+            .map(|rt| {
+                rt.as_ref()
+                    .map(|rt| (rt.texture(), rt.render_target_format(srgb).unwrap()))
+            })
+            .collect();
+
+        let depth_stencil = self.depth_stencil.as_ref().map(|ds| ds.texture());
+
+        if let Err(err) = self.backend.set_render_targets(&targets, depth_stencil) {
+            error!("Failed to bind render targets: {:?}", err);
         }
+    }
+
+    /// Binds the current render targets, and resets the viewport to cover the first one.
+    fn update_render_targets(&mut self) {
+        self.bind_render_targets();
 
         // We also need to update the viewport.
+        // A device which failed to reset has no render target, until it is reset again.
+        let rt = match self.render_targets.first() {
+            Some(Some(rt)) => rt,
+            _ => return,
+        };
+
         let (width, height) = unsafe {
-            let rt = self.render_targets[0].as_ref().unwrap();
             let mut desc = mem::uninitialized();
             rt.get_desc(&mut desc);
             (desc.Width, desc.Height)
@@ -257,57 +611,415 @@ impl Device {
 
         self.set_viewport(&vp);
     }
+
+    /// Retrieves a texture subresource from which a surface's contents can be copied.
+    ///
+    /// Multisampled surfaces, such as a back buffer on which the user forced
+    /// multisampling, have to be resolved first.
+    fn resolved_surface(&self, surface: &Surface) -> Result<(backend::Texture, u32)> {
+        let texture = surface.texture();
+        let desc = self.backend.texture_desc(texture);
+
+        if desc.SampleDesc.Count <= 1 {
+            return Ok((texture.clone(), surface.subresource()));
+        }
+
+        let resolved_desc = D3D11_TEXTURE2D_DESC {
+            SampleDesc: d3d9_to_dxgi_samples(0, 0),
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: 0,
+            CPUAccessFlags: 0,
+            MiscFlags: 0,
+            ..desc
+        };
+
+        let resolved = self.backend.create_texture_2d(&resolved_desc)?;
+
+        // The texture might be typeless, so we have to specify how to interpret the samples.
+        let fmt = desc::linear_view_format(&desc, surface.format());
+        self.backend.resolve_texture(&resolved, texture, fmt);
+
+        Ok((resolved, 0))
+    }
+
+    /// Draws a rectangle of a surface scaled into a rectangle of a render target.
+    fn stretch_surface(
+        &self,
+        src: &Surface,
+        sr: &RECT,
+        dest: &Surface,
+        dr: &RECT,
+        filter: D3DTEXTUREFILTERTYPE,
+    ) -> Result<()> {
+        // Only the first level of a render target can be drawn into.
+        let dest_fmt = match dest.render_target_format(false) {
+            Some(fmt) if dest.subresource() == 0 => fmt,
+            _ => {
+                error!("Stretching into surfaces other than render targets is not supported");
+                return Err(Error::InvalidCall);
+            }
+        };
+
+        let (src_texture, src_subres) = self.resolved_surface(src)?;
+
+        let (width, height) = ((sr.right - sr.left) as u32, (sr.bottom - sr.top) as u32);
+
+        // The rectangle is copied into a texture which can be sampled,
+        // since the surface might not be the first level of a shader resource.
+        let desc = self.backend.texture_desc(&src_texture);
+        let src_fmt = desc::linear_view_format(&desc, src.format());
+        let copy_desc = D3D11_TEXTURE2D_DESC {
+            Width: width,
+            Height: height,
+            MipLevels: 1,
+            ArraySize: 1,
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_SHADER_RESOURCE,
+            CPUAccessFlags: 0,
+            MiscFlags: 0,
+            ..desc
+        };
+
+        let copy = self.backend.create_texture_2d(&copy_desc)?;
+
+        let src_box = D3D11_BOX {
+            left: sr.left as u32,
+            top: sr.top as u32,
+            front: 0,
+            right: sr.right as u32,
+            bottom: sr.bottom as u32,
+            back: 1,
+        };
+
+        self.backend.copy_texture_region(
+            &copy,
+            0,
+            (0, 0),
+            &src_texture,
+            src_subres,
+            Some(&src_box),
+        );
+
+        let viewport = D3D11_VIEWPORT {
+            TopLeftX: dr.left as f32,
+            TopLeftY: dr.top as f32,
+            Width: (dr.right - dr.left) as f32,
+            Height: (dr.bottom - dr.top) as f32,
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        };
+
+        self.backend.blit(
+            (&copy, src_fmt),
+            [0.0, 0.0, 1.0, 1.0],
+            (dest.texture(), dest_fmt),
+            &viewport,
+            backend::BlitMode::Copy,
+            filter == D3DTEXF_LINEAR,
+        )
+    }
+
+    /// Binds the texture of a sampler stage to the corresponding shader stage.
+    fn bind_texture(&self, stage: u32) {
+        let texture = self.istate.get_texture(stage);
+
+        // The texture's view format depends on whether sRGB reads are enabled.
+        let srgb = self.istate.get_sampler_state(stage, D3DSAMP_SRGBTEXTURE) != 0;
+
+        // Formats which are emulated with another one need their channels remapped.
+        let swizzle = self.istate.get_texture_swizzle(stage);
+
+        let view = BaseTexture::from_interface(texture).and_then(|texture| {
+            texture
+                .view_format(srgb)
+                .map(|fmt| (texture.texture(), fmt, swizzle))
+        });
+
+        let result = if D3DVERTEXTEXTURESAMPLER0 <= stage && stage <= D3DVERTEXTEXTURESAMPLER3 {
+            let slot = stage - D3DVERTEXTEXTURESAMPLER0;
+            self.backend.bind_texture(ShaderStage::Vertex, slot, view)
+        } else if stage < 16 {
+            self.backend.bind_texture(ShaderStage::Pixel, stage, view)
+        } else {
+            Ok(())
+        };
+
+        if let Err(err) = result {
+            error!("Failed to bind texture: {:?}", err);
+        }
+    }
+
+    /// Translates the sampler states of a stage, and binds the resulting sampler.
+    fn bind_sampler(&self, stage: u32) {
+        let key = SamplerKey::new(|ty| self.istate.get_sampler_state(stage, ty));
+
+        // The user might want to force anisotropic filtering.
+        let anisotropy = self.config().max_anisotropy;
+
+        let sampler = match self.samplers.get(&*self.backend, key, anisotropy) {
+            Ok(sampler) => sampler,
+            Err(_) => return,
+        };
+
+        if D3DVERTEXTEXTURESAMPLER0 <= stage && stage <= D3DVERTEXTEXTURESAMPLER3 {
+            let slot = stage - D3DVERTEXTEXTURESAMPLER0;
+            self.backend
+                .bind_sampler(ShaderStage::Vertex, slot, &sampler);
+        } else if stage < 16 {
+            self.backend
+                .bind_sampler(ShaderStage::Pixel, stage, &sampler);
+        }
+    }
+
+    /// Binds the samplers of all the stages.
+    fn bind_samplers(&self) {
+        let vertex_samplers = D3DVERTEXTEXTURESAMPLER0..=D3DVERTEXTEXTURESAMPLER3;
+
+        for stage in (0..16).chain(vertex_samplers) {
+            self.bind_sampler(stage);
+        }
+    }
+
+    /// Converts a position on the back buffer of a full-screen swap chain to desktop coordinates.
+    ///
+    /// The back buffer is stretched over the whole display, which might have a different size.
+    fn back_buffer_to_desktop(&self, sc: &SwapChain, (x, y): (i32, i32)) -> (i32, i32) {
+        let display = unsafe {
+            let mut mi: winuser::MONITORINFO = mem::zeroed();
+            mi.cbSize = mem::size_of_val(&mi) as u32;
+
+            if winuser::GetMonitorInfoW(self.adapter().monitor(), &mut mi) == 0 {
+                return (x, y);
+            }
+
+            mi.rcMonitor
+        };
+
+        let (width, height) = sc.back_buffer_size();
+
+        let scale = |pos: i32, display: i32, buffer: u32| {
+            (i64::from(pos) * i64::from(display) / i64::from(cmp::max(buffer, 1))) as i32
+        };
+
+        (
+            display.left + scale(x, display.right - display.left, width),
+            display.top + scale(y, display.bottom - display.top, height),
+        )
+    }
+
+    /// Binds the vertex buffer of a stream to the input slot with the same number.
+    fn bind_stream(&self, stream: u32) {
+        let (buffer, offset, stride) = self.istate.get_stream_source(stream);
+        let buffer = unsafe { buffer.as_ref() }.map(|buffer| buffer.buffer());
+
+        self.backend
+            .set_vertex_buffer(stream, buffer, stride, offset);
+    }
+
+    /// Binds the index buffer set by the app.
+    fn bind_indices(&self) {
+        let indices = unsafe { self.istate.get_indices().as_ref() };
+
+        match indices {
+            Some(indices) => {
+                let fmt = index_format(indices.format());
+                self.backend
+                    .set_index_buffer(Some(indices.buffer()), fmt, 0)
+            }
+            None => self.backend.set_index_buffer(None, DXGI_FORMAT_R16_UINT, 0),
+        }
+    }
+
+    /// Sets the topology for drawing primitives of a certain type,
+    /// and returns how many vertices `count` of them are made of.
+    fn set_topology(&self, method: &'static str, ty: D3DPRIMITIVETYPE, count: u32) -> Result<u32> {
+        match primitive_topology(ty, count) {
+            Some((topology, vertices)) => {
+                self.backend.set_primitive_topology(topology);
+                Ok(vertices)
+            }
+            None => {
+                let diag = Diagnostic::new(method, "PrimitiveType", "unknown primitive type");
+                Err(self.reject(diag))
+            }
+        }
+    }
+
+    /// Copies data the app passed to a draw call into one of the dynamic buffers.
+    ///
+    /// The buffer is reused by later draws, and only recreated when it is too small.
+    fn upload(
+        &self,
+        cache: &RefCell<Option<backend::Buffer>>,
+        bind_flags: u32,
+        data: &[u8],
+    ) -> Result<backend::Buffer> {
+        let cached = cache
+            .borrow()
+            .clone()
+            .filter(|buffer| self.backend.buffer_desc(buffer).ByteWidth as usize >= data.len());
+
+        let buffer = match cached {
+            Some(buffer) => buffer,
+            None => {
+                let len = (data.len() as u32).next_power_of_two();
+                let usage = UsageFlags::DYNAMIC | UsageFlags::WRITE_ONLY;
+                let desc = desc::buffer(len, usage, MemoryPool::Default, bind_flags)?;
+
+                let buffer = self.backend.create_buffer(&desc)?;
+                cache.replace(Some(buffer.clone()));
+                buffer
+            }
+        };
+
+        let mapped = self
+            .backend
+            .map_buffer(&buffer, D3D11_MAP_WRITE_DISCARD, 0)?;
+
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), mapped.pData as *mut u8, data.len());
+        }
+
+        self.backend.unmap_buffer(&buffer);
+
+        self.count(|stats| stats.bytes_uploaded += data.len() as u64);
+
+        Ok(buffer)
+    }
+
+    /// Draws a triangle fan, given the indices of its vertices.
+    ///
+    /// D3D11 cannot draw fans, so they are converted to triangle lists,
+    /// whose indices are drawn from one of the dynamic buffers.
+    fn draw_fan(&self, fan: &[u32], base_vertex: i32) -> Result<()> {
+        let list = fan_to_list(fan);
+        let data = unsafe {
+            slice::from_raw_parts(
+                list.as_ptr() as *const u8,
+                list.len() * mem::size_of::<u32>(),
+            )
+        };
+
+        let buffer = self.upload(&self.up_indices, D3D11_BIND_INDEX_BUFFER, data)?;
+
+        self.backend
+            .set_index_buffer(Some(&buffer), DXGI_FORMAT_R32_UINT, 0);
+        self.backend.draw_indexed(list.len() as u32, 0, base_vertex);
+
+        // The app's index buffer has to be bound again for its next draws.
+        self.bind_indices();
+
+        Ok(())
+    }
 }
 
-impl_iunknown!(struct Device: IUnknown, IDirect3DDevice9);
+impl_iunknown!(struct Device: IUnknown, IDirect3DDevice9; ex: IDirect3DDevice9Ex);
 
 #[implementation(IDirect3DDevice9)]
 impl Device {
     // -- Device status functions --
 
     /// Resets the device, recreating all its state.
-    fn reset(&mut self) {
-        unimplemented!()
+    fn reset(&mut self, pp: *mut D3DPRESENT_PARAMETERS) -> Error {
+        traced!(self, "IDirect3DDevice9::Reset", [trace::data(pp)], {
+            let pp = check_mut_ref(pp)?;
+
+            // A lost device can only be reset once the app is back in the foreground.
+            if let Err(Error::DeviceLost) = self.check_lost() {
+                return Error::DeviceLost;
+            }
+
+            // The app has to release all the resources which depend on the device's old state.
+            // D3D9Ex relaxed this requirement.
+            let outstanding = self.default_resources.load(Ordering::SeqCst);
+            if outstanding != 0 && !self.ex {
+                error!(
+                    "Cannot reset device: {} default pool resources were not released",
+                    outstanding
+                );
+                return Error::InvalidCall;
+            }
+
+            // Unbind and release the implicit resources.
+            self.backend.clear_state();
+
+            self.render_targets.clear();
+            self.depth_stencil = None;
+            self.swap_chains.clear();
+
+            self.istate = DeviceState::default();
+            self.in_scene = false;
+
+            // If the new objects cannot be created, the device is left without any,
+            // so it is lost until the app manages to reset it.
+            if let Err(err) = self.create_implicit_objects(pp) {
+                error!("Failed to reset device: {:?}", err);
+
+                self.render_targets.clear();
+                self.depth_stencil = None;
+                self.swap_chains.clear();
+
+                self.lost.store(true, Ordering::SeqCst);
+
+                return err;
+            }
+
+            self.lost.store(false, Ordering::SeqCst);
+
+            Error::Success
+        })
     }
 
     /// Checks that the device has not yet been lost / reset.
     fn test_cooperative_level(&self) -> Error {
-        // Even if the device were lost, we wouldn't be able to do much.
-        Error::Success
+        guarded!("IDirect3DDevice9::TestCooperativeLevel", {
+            self.check_lost()?;
+            Error::Success
+        })
     }
 
     /// Determines how much graphics memory is available.
     fn get_available_texture_mem(&self) -> u32 {
-        self.adapter().available_memory()
+        guarded!("IDirect3DDevice9::GetAvailableTextureMem", {
+            self.adapter().available_memory()
+        })
     }
 
     /// Asks the driver to evict all managed resources from VRAM.
     fn evict_managed_resources(&self) -> Error {
-        // Do nothing. The D3D11 driver handles everything.
-        Error::Success
+        traced!(self, "IDirect3DDevice9::EvictManagedResources", [], {
+            // Do nothing. The D3D11 driver handles everything.
+            Error::Success
+        })
     }
 
     // -- Creation parameters functions --
 
     /// Returns a reference to the parent interface.
     fn get_direct_3_d(&self, ptr: *mut *mut Context) -> Error {
-        let ptr = check_mut_ref(ptr)?;
-        *ptr = com_ref(self.parent);
-        Error::Success
+        guarded!("IDirect3DDevice9::GetDirect3D", {
+            let ptr = check_mut_ref(ptr)?;
+            *ptr = com_ref(self.parent);
+            Error::Success
+        })
     }
 
     /// Returns the caps of this device.
     fn get_device_caps(&self, caps: *mut D3DCAPS9) -> Error {
-        let caps = check_mut_ref(caps)?;
-        *caps = self.adapter().caps();
-        Error::Success
+        guarded!("IDirect3DDevice9::GetDeviceCaps", {
+            let caps = check_mut_ref(caps)?;
+            *caps = self.adapter().caps(self.ex);
+            Error::Success
+        })
     }
 
     /// Returns the creation parameters of this device.
     fn get_creation_parameters(&self, params: *mut D3DDEVICE_CREATION_PARAMETERS) -> Error {
-        let params = check_mut_ref(params)?;
-        *params = self.creation_params;
-        Error::Success
+        guarded!("IDirect3DDevice9::GetCreationParameters", {
+            let params = check_mut_ref(params)?;
+            *params = self.creation_params;
+            Error::Success
+        })
     }
 
     // -- Swap chain functions --
@@ -318,25 +1030,38 @@ impl Device {
         pp: *mut D3DPRESENT_PARAMETERS,
         ret: *mut *mut SwapChain,
     ) -> Error {
-        let factory = self.factory.as_mut();
-        let pp = check_mut_ref(pp)?;
-        let window = self.window;
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateAdditionalSwapChain",
+            [trace::data(pp), trace::returned(ret)],
+            {
+                let pp = check_mut_ref(pp)?;
+                let window = self.window;
 
-        let ret = check_mut_ref(ret)?;
+                let ret = check_mut_ref(ret)?;
 
-        *ret = SwapChain::new(self, &self.device, factory, pp, window)?.into();
+                *ret = SwapChain::new(self, pp, window)?.into();
 
-        Error::Success
+                Error::Success
+            }
+        )
     }
 
     /// Returns an implicit swap chain.
     fn get_swap_chain(&self, sc: u32, ret: *mut *mut SwapChain) -> Error {
-        let sc = self.check_swap_chain(sc)?;
-        let ret = check_mut_ref(ret)?;
+        traced!(
+            self,
+            "IDirect3DDevice9::GetSwapChain",
+            [sc, trace::returned(ret)],
+            {
+                let sc = self.check_swap_chain(sc)?;
+                let ret = check_mut_ref(ret)?;
 
-        *ret = sc.clone().into();
+                *ret = sc.clone().into();
 
-        Error::Success
+                Error::Success
+            }
+        )
     }
 
     /// Returns the number of implicit swap chains.
@@ -348,14 +1073,28 @@ impl Device {
     // The functions below all operate on the implicit swap chains.
 
     fn present(&self, src: usize, dest: usize, wnd: HWND, dirty: usize) -> Error {
-        for sc in &self.swap_chains {
-            sc.present(src, dest, wnd, dirty, 0)?;
-        }
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::Present",
+            [
+                trace::data(src as *const RECT),
+                trace::data(dest as *const RECT),
+                trace::object(wnd),
+                trace::data(dirty as *const RGNDATAHEADER)
+            ],
+            {
+                for sc in &self.swap_chains {
+                    sc.present(src, dest, wnd, dirty, 0)?;
+                }
+                Error::Success
+            }
+        )
     }
 
     fn get_front_buffer_data(&self, sc: u32, fb: *mut Surface) -> Error {
-        self.check_swap_chain(sc)?.get_front_buffer_data(fb)
+        guarded!("IDirect3DDevice9::GetFrontBufferData", {
+            self.check_swap_chain(sc)?.get_front_buffer_data(fb)
+        })
     }
 
     fn get_back_buffer(
@@ -365,31 +1104,49 @@ impl Device {
         ty: D3DBACKBUFFER_TYPE,
         ret: *mut *mut Surface,
     ) -> Error {
-        self.check_swap_chain(sc)?.get_back_buffer(bi, ty, ret)
+        traced!(
+            self,
+            "IDirect3DDevice9::GetBackBuffer",
+            [sc, bi, ty, trace::returned(ret)],
+            { self.check_swap_chain(sc)?.get_back_buffer(bi, ty, ret) }
+        )
     }
 
     fn get_raster_status(&self, sc: u32, rs: *mut D3DRASTER_STATUS) -> Error {
-        self.check_swap_chain(sc)?.get_raster_status(rs)
+        guarded!("IDirect3DDevice9::GetRasterStatus", {
+            self.check_swap_chain(sc)?.get_raster_status(rs)
+        })
     }
 
     fn get_display_mode(&self, sc: u32, dm: *mut D3DDISPLAYMODE) -> Error {
-        self.check_swap_chain(sc)?.get_display_mode(dm)
+        guarded!("IDirect3DDevice9::GetDisplayMode", {
+            self.check_swap_chain(sc)?.get_display_mode(dm)
+        })
     }
 
     // -- Gamma control functions --
 
     /// Sets the current gamma ramp.
     fn set_gamma_ramp(&mut self, sc: u32, flags: u32, ramp: *const D3DGAMMARAMP) {
-        self.check_swap_chain(sc)
-            .and_then(|sc| check_ref(ramp).and_then(|ramp| sc.set_gamma_ramp(flags, ramp)))
-            .unwrap_or_else(|_| error!("Failed to set gamma ramp"));
+        traced!(
+            self,
+            "IDirect3DDevice9::SetGammaRamp",
+            [sc, flags, trace::data(ramp)],
+            {
+                self.check_swap_chain(sc)
+                    .and_then(|sc| check_ref(ramp).and_then(|ramp| sc.set_gamma_ramp(flags, ramp)))
+                    .unwrap_or_else(|_| error!("Failed to set gamma ramp"));
+            }
+        )
     }
 
     /// Retrieves the monitor's gamma ramp.
     fn get_gamma_ramp(&self, sc: u32, ret: *mut D3DGAMMARAMP) {
-        self.check_swap_chain(sc)
-            .and_then(|sc| check_mut_ref(ret).and_then(|ret| sc.get_gamma_ramp(ret)))
-            .unwrap_or_else(|_| error!("Failed to retrieve gamma ramp"));
+        guarded!("IDirect3DDevice9::GetGammaRamp", {
+            self.check_swap_chain(sc)
+                .and_then(|sc| check_mut_ref(ret).and_then(|ret| sc.get_gamma_ramp(ret)))
+                .unwrap_or_else(|_| error!("Failed to retrieve gamma ramp"));
+        })
     }
 
     // -- Render target functions --
@@ -404,72 +1161,135 @@ impl Device {
         ms_qlt: u32,
         lockable: u32,
         ret: *mut *mut Surface,
-        shared_handle: usize,
+        shared_handle: *mut HANDLE,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
-
-        if lockable != 0 {
-            error!("Lockable render targets are not supported");
-        }
-
-        if shared_handle != 0 {
-            error!("Shared resources are not supported");
-            return Error::InvalidCall;
-        }
-
-        // First we need to create a texture we will render to.
-        let texture = d3d11::Texture2D::new_rt(&self.device, (width, height), fmt, ms_ty, ms_qlt)?;
-
-        *ret = self.create_render_target_helper(texture)?.into();
-
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateRenderTarget",
+            [
+                width,
+                height,
+                fmt,
+                ms_ty,
+                ms_qlt,
+                lockable,
+                trace::returned(ret),
+                trace::data(shared_handle)
+            ],
+            {
+                let ret = check_mut_ref(ret)?;
+
+                if lockable != 0 {
+                    error!("Lockable render targets are not supported");
+                }
+
+                let ms_ty = self.config().multisample_type(ms_ty);
+
+                // First we need to create a texture we will render to.
+                let texture =
+                    self.create_shared_texture(shared_handle, MemoryPool::Default, |shared| {
+                        let desc =
+                            desc::render_target((width, height), fmt, ms_ty, ms_qlt, shared)?;
+                        self.backend.create_texture_2d(&desc)
+                    })?;
+
+                let surface = self.create_render_target_helper(texture, fmt)?;
+
+                if lockable != 0 {
+                    surface.as_mut().make_lockable();
+                }
+
+                *ret = surface.into();
+
+                Error::Success
+            }
+        )
     }
 
     /// Sets a new render target on this device.
     fn set_render_target(&mut self, i: u32, rt: *mut Surface) -> Error {
-        if i >= D3D11_SIMULTANEOUS_RENDER_TARGET_COUNT {
-            return Error::InvalidCall;
-        }
-
-        // The default render target is never allowed to be null.
-        if i == 0 && rt.is_null() {
-            return Error::InvalidCall;
-        }
-
-        let i = i as usize;
-
-        // Ensure the RT vector contains at least as many entries as we need.
-        self.render_targets.resize(i + 1, None);
-
-        self.render_targets[i] = if let Some(rt) = unsafe { rt.as_mut() } {
-            // Ensure this surface is indeed a render target.
-            if rt.render_target_view().is_none() {
-                return Error::InvalidCall;
+        traced!(
+            self,
+            "IDirect3DDevice9::SetRenderTarget",
+            [i, trace::object(rt)],
+            {
+                if i >= D3D11_SIMULTANEOUS_RENDER_TARGET_COUNT {
+                    return Error::InvalidCall;
+                }
+
+                // The default render target is never allowed to be null.
+                if i == 0 && rt.is_null() {
+                    return Error::InvalidCall;
+                }
+
+                let i = i as usize;
+
+                // Ensure the RT vector contains at least as many entries as we need.
+                self.render_targets.resize(i + 1, None);
+
+                self.render_targets[i] = if let Some(rt) = unsafe { rt.as_mut() } {
+                    // Ensure this surface is indeed a render target.
+                    if rt.render_target_format(false).is_none() {
+                        return Error::InvalidCall;
+                    }
+
+                    Some(ComPtr::new(rt))
+                } else {
+                    None
+                };
+
+                self.retarget_back_buffers()?;
+                self.update_render_targets();
+
+                Error::Success
             }
-
-            Some(ComPtr::new(rt))
-        } else {
-            None
-        };
-
-        self.update_render_targets();
-
-        Error::Success
+        )
     }
 
     /// Retrieves a reference to a bound render target.
     fn get_render_target(&self, i: u32, ret: *mut *mut Surface) -> Error {
-        let rt = self.check_render_target(i)?;
-        let ret = check_mut_ref(ret)?;
+        traced!(
+            self,
+            "IDirect3DDevice9::GetRenderTarget",
+            [i, trace::returned(ret)],
+            {
+                let rt = self.check_render_target(i)?;
+                let ret = check_mut_ref(ret)?;
 
-        *ret = rt.clone().into();
+                *ret = rt.clone().into();
 
-        Error::Success
+                Error::Success
+            }
+        )
     }
 
-    /// Copies a render target's data into a surface.
-    fn get_render_target_data(&self, _rt: *mut Surface, _dest: *mut Surface) {
-        unimplemented!()
+    /// Copies a render target's data into a surface in system memory.
+    fn get_render_target_data(&self, rt: *mut Surface, dest: *mut Surface) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9::GetRenderTargetData",
+            [trace::object(rt), trace::object(dest)],
+            {
+                let rt = check_mut_ref(rt)?;
+                let dest = check_mut_ref(dest)?;
+
+                if dest.pool() != MemoryPool::SystemMem {
+                    return Error::InvalidCall;
+                }
+
+                let (src, src_subres) = self.resolved_surface(rt)?;
+                self.backend.copy_texture_region(
+                    dest.texture(),
+                    dest.subresource(),
+                    (0, 0),
+                    &src,
+                    src_subres,
+                    None,
+                );
+
+                Error::Success
+            }
+        )
     }
 
     // -- Depth / stencil buffer functions --
@@ -480,66 +1300,114 @@ impl Device {
         width: u32,
         height: u32,
         fmt: D3DFORMAT,
-        _ms_ty: D3DMULTISAMPLE_TYPE,
-        _ms_qlt: u32,
+        ms_ty: D3DMULTISAMPLE_TYPE,
+        ms_qlt: u32,
         discard: u32,
         ret: *mut *mut Surface,
-        shared_handle: usize,
+        shared_handle: *mut HANDLE,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
-
-        if shared_handle != 0 {
-            error!("Shared resources are not supported");
-            return Error::InvalidCall;
-        }
-
-        if discard != 0 {
-            error!("Discarding depth/stencil buffer not supported");
-        }
-
-        let texture = d3d11::Texture2D::new_ds(&self.device, (width, height), fmt)?;
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateDepthStencilSurface",
+            [
+                width,
+                height,
+                fmt,
+                ms_ty,
+                ms_qlt,
+                discard,
+                trace::returned(ret),
+                trace::data(shared_handle)
+            ],
+            {
+                let ret = check_mut_ref(ret)?;
+
+                if discard != 0 {
+                    error!("Discarding depth/stencil buffer not supported");
+                }
+
+                // Depth buffers for the back buffers must match their multisampling,
+                // which the user might have changed.
+                let (ms_ty, ms_qlt) = self
+                    .swap_chains
+                    .first()
+                    .and_then(|sc| sc.depth_stencil_multisampling((width, height), ms_ty))
+                    .unwrap_or_else(|| (self.config().multisample_type(ms_ty), ms_qlt));
+
+                let texture =
+                    self.create_shared_texture(shared_handle, MemoryPool::Default, |shared| {
+                        let desc =
+                            desc::depth_stencil((width, height), fmt, ms_ty, ms_qlt, shared)?;
+                        self.backend.create_texture_2d(&desc)
+                    })?;
+
+                let data = SurfaceData::DepthStencil;
+
+                let surface = Surface::new(
+                    self,
+                    texture,
+                    UsageFlags::DEPTH_STENCIL,
+                    MemoryPool::Default,
+                    fmt,
+                    data,
+                );
 
-        let ds_view = texture.create_ds_view(&self.device)?;
+                match fmt {
+                    D3DFMT_D16_LOCKABLE | D3DFMT_D32F_LOCKABLE | D3DFMT_S8_LOCKABLE => {
+                        surface.as_mut().make_lockable()
+                    }
+                    _ => (),
+                }
 
-        let data = SurfaceData::DepthStencil(ds_view);
+                *ret = surface.into();
 
-        *ret = Surface::new(
-            self,
-            texture,
-            UsageFlags::DEPTH_STENCIL,
-            MemoryPool::Default,
-            data,
-        ).into();
-
-        Error::Success
+                Error::Success
+            }
+        )
     }
 
     /// Sets the current depth / stencil buffer.
     fn set_depth_stencil_surface(&mut self, ds: *mut Surface) -> Error {
-        self.depth_stencil = if let Some(ds) = unsafe { ds.as_mut() } {
-            if ds.depth_stencil_view().is_none() {
-                return Error::InvalidCall;
+        traced!(
+            self,
+            "IDirect3DDevice9::SetDepthStencilSurface",
+            [trace::object(ds)],
+            {
+                self.depth_stencil = if let Some(ds) = unsafe { ds.as_mut() } {
+                    if !ds.is_depth_stencil() {
+                        return Error::InvalidCall;
+                    }
+
+                    Some(ComPtr::new(ds))
+                } else {
+                    None
+                };
+
+                self.bind_render_targets();
+
+                Error::Success
             }
-
-            Some(ComPtr::new(ds))
-        } else {
-            None
-        };
-
-        Error::Success
+        )
     }
 
     /// Retrieves the bound depth / stencil buffer.
     fn get_depth_stencil_surface(&self, ret: *mut *mut Surface) -> Error {
-        let ret = check_mut_ref(ret)?;
-
-        *ret = self
-            .depth_stencil
-            .as_ref()
-            .map(|ds| ds.clone().into())
-            .unwrap_or(ptr::null_mut());
-
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::GetDepthStencilSurface",
+            [trace::returned(ret)],
+            {
+                let ret = check_mut_ref(ret)?;
+
+                *ret = self
+                    .depth_stencil
+                    .as_ref()
+                    .map(|ds| ds.clone().into())
+                    .unwrap_or(ptr::null_mut());
+
+                Error::Success
+            }
+        )
     }
 
     /// Creates an off-screen surface.
@@ -550,32 +1418,50 @@ impl Device {
         fmt: D3DFORMAT,
         pool: MemoryPool,
         ret: *mut *mut Surface,
-        shared_handle: usize,
+        shared_handle: *mut HANDLE,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
-
-        if shared_handle != 0 {
-            error!("Shared resources are not supported");
-            return Error::InvalidCall;
-        }
-
-        let texture = d3d11::Texture2D::new(
-            &self.device,
-            (width, height),
-            1,
-            UsageFlags::empty(),
-            fmt,
-            // We ignore the pool, we need this surface to always be CPU-readable
-            // (i.e. D3D11_USAGE_STAGING), since that's its intended use.
-            MemoryPool::SystemMem,
-        )?;
-
-        let data = SurfaceData::None;
-
-        // We pass in the correct pool here, for storage purposes.
-        *ret = Surface::new(self, texture, UsageFlags::empty(), pool, data).into();
-
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateOffscreenPlainSurface",
+            [
+                width,
+                height,
+                fmt,
+                pool as u32,
+                trace::returned(ret),
+                trace::data(shared_handle)
+            ],
+            {
+                let ret = check_mut_ref(ret)?;
+
+                let texture = self.create_shared_texture(shared_handle, pool, |shared| {
+                    // We ignore the pool, we need this surface to always be CPU-readable
+                    // (i.e. D3D11_USAGE_STAGING), since that's its intended use.
+                    // Shared surfaces are the exception, since staging resources cannot be shared.
+                    let pool = if shared {
+                        MemoryPool::Default
+                    } else {
+                        MemoryPool::SystemMem
+                    };
+
+                    let desc =
+                        desc::texture((width, height), 1, UsageFlags::empty(), fmt, pool, shared)?;
+                    self.backend.create_texture_2d(&desc)
+                })?;
+
+                let data = SurfaceData::None;
+
+                // We pass in the correct pool here, for storage purposes.
+                let surface = Surface::new(self, texture, UsageFlags::empty(), pool, fmt, data);
+
+                // Unlike textures, off-screen surfaces are always lockable.
+                surface.as_mut().make_lockable();
+
+                *ret = surface.into();
+
+                Error::Success
+            }
+        )
     }
 
     // -- Surface manipulation functions --
@@ -588,52 +1474,140 @@ impl Device {
         dest: *mut Surface,
         dp: *const POINT,
     ) -> Error {
-        let src = check_mut_ref(src)?;
-        let dest = check_mut_ref(dest)?;
-        let dp = check_ref(dp)?;
-
-        if src.pool() != MemoryPool::SystemMem || dest.pool() != MemoryPool::Default {
-            return Error::InvalidCall;
-        }
-
-        let (src_res, src_subres) = src.subresource();
-        let (dest_res, dest_subres) = dest.subresource();
-
-        unsafe {
-            let src_box = sr.as_ref().map(|sr| D3D11_BOX {
-                left: sr.left as u32,
-                top: sr.top as u32,
-                front: 0,
-                right: sr.right as u32,
-                bottom: sr.bottom as u32,
-                back: 1,
-            });
-
-            let src_box = src_box.map(|b| &b as *const _).unwrap_or(ptr::null());
-
-            self.ctx.CopySubresourceRegion(
-                dest_res,
-                dest_subres,
-                dp.x as u32,
-                dp.y as u32,
-                0,
-                src_res,
-                src_subres,
-                src_box,
-            );
-        }
-
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::UpdateSurface",
+            [
+                trace::object(src),
+                trace::data(sr),
+                trace::object(dest),
+                trace::data(dp)
+            ],
+            {
+                let src = check_mut_ref(src)?;
+                let dest = check_mut_ref(dest)?;
+                let dp = check_ref(dp)?;
+
+                if src.pool() != MemoryPool::SystemMem || dest.pool() != MemoryPool::Default {
+                    return Error::InvalidCall;
+                }
+
+                let src_subres = src.subresource();
+                let dest_subres = dest.subresource();
+
+                let src_box = unsafe { sr.as_ref() }.map(|sr| D3D11_BOX {
+                    left: sr.left as u32,
+                    top: sr.top as u32,
+                    front: 0,
+                    right: sr.right as u32,
+                    bottom: sr.bottom as u32,
+                    back: 1,
+                });
+
+                self.backend.copy_texture_region(
+                    dest.texture(),
+                    dest_subres,
+                    (dp.x as u32, dp.y as u32),
+                    src.texture(),
+                    src_subres,
+                    src_box.as_ref(),
+                );
+
+                Error::Success
+            }
+        )
     }
 
-    fn update_texture() {
-        unimplemented!()
+    fn update_texture() -> Error {
+        unimplemented_method!("IDirect3DDevice9::UpdateTexture")
     }
-    fn stretch_rect() {
-        unimplemented!()
+
+    /// Copies a rectangle of a surface into a rectangle of another surface.
+    ///
+    /// Multisampled sources are resolved first. Stretching and format conversions
+    /// are not yet supported.
+    fn stretch_rect(
+        &self,
+        src: *mut Surface,
+        sr: *const RECT,
+        dest: *mut Surface,
+        dr: *const RECT,
+        filter: D3DTEXTUREFILTERTYPE,
+    ) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9::StretchRect",
+            [
+                trace::object(src),
+                trace::data(sr),
+                trace::object(dest),
+                trace::data(dr),
+                filter
+            ],
+            {
+                let src = check_mut_ref(src)?;
+                let dest = check_mut_ref(dest)?;
+
+                // A null rectangle refers to the whole surface.
+                let rect = |r: *const RECT, surface: &Surface| {
+                    check_ref(r).map(|r| *r).unwrap_or_else(|_| {
+                        let desc = self.backend.texture_desc(surface.texture());
+                        RECT {
+                            left: 0,
+                            top: 0,
+                            right: desc.Width as i32,
+                            bottom: desc.Height as i32,
+                        }
+                    })
+                };
+
+                let sr = rect(sr, src);
+                let dr = rect(dr, dest);
+
+                if (sr.right - sr.left, sr.bottom - sr.top)
+                    != (dr.right - dr.left, dr.bottom - dr.top)
+                {
+                    self.stretch_surface(src, &sr, dest, &dr, filter)?;
+                    return Error::Success;
+                }
+
+                if src.format() != dest.format() {
+                    error!("Converting between surface formats is not yet supported");
+                    return Error::InvalidCall;
+                }
+
+                if self.backend.texture_desc(dest.texture()).SampleDesc.Count > 1 {
+                    error!("Copying into multisampled surfaces is not yet supported");
+                    return Error::InvalidCall;
+                }
+
+                let (src_texture, src_subres) = self.resolved_surface(src)?;
+                let dest_subres = dest.subresource();
+
+                let src_box = D3D11_BOX {
+                    left: sr.left as u32,
+                    top: sr.top as u32,
+                    front: 0,
+                    right: sr.right as u32,
+                    bottom: sr.bottom as u32,
+                    back: 1,
+                };
+
+                self.backend.copy_texture_region(
+                    dest.texture(),
+                    dest_subres,
+                    (dr.left as u32, dr.top as u32),
+                    &src_texture,
+                    src_subres,
+                    Some(&src_box),
+                );
+
+                Error::Success
+            }
+        )
     }
-    fn color_fill() {
-        unimplemented!()
+    fn color_fill() -> Error {
+        unimplemented_method!("IDirect3DDevice9::ColorFill")
     }
 
     // -- Texture creation functions --
@@ -643,143 +1617,412 @@ impl Device {
         &self,
         width: u32,
         height: u32,
-        mut levels: u32,
+        levels: u32,
         usage: UsageFlags,
         fmt: D3DFORMAT,
         pool: MemoryPool,
         ret: *mut *mut Texture,
-        shared_handle: usize,
+        shared_handle: *mut HANDLE,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
-
-        if shared_handle != 0 {
-            error!("Shared resources are not supported");
-            return Error::InvalidCall;
-        }
-
-        if levels == 0 {
-            levels = 32 - cmp::max(width, height).leading_zeros();
-        }
-
-        if usage.intersects(UsageFlags::AUTO_GEN_MIP_MAP) {
-            warn!("Autom mip-map generation not yet supported");
-        }
-
-        let texture =
-            d3d11::Texture2D::new(&self.device, (width, height), levels, usage, fmt, pool)?;
-
-        *ret = Texture::new(self, pool, texture, levels, usage).into();
-
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateTexture",
+            [
+                width,
+                height,
+                levels,
+                usage.bits(),
+                fmt,
+                pool as u32,
+                trace::returned(ret),
+                trace::data(shared_handle)
+            ],
+            {
+                let ret = check_mut_ref(ret)?;
+
+                let levels = match levels {
+                    0 => 32 - cmp::max(width, height).leading_zeros(),
+                    levels => levels,
+                };
+
+                if usage.intersects(UsageFlags::AUTO_GEN_MIP_MAP) {
+                    warn!("Autom mip-map generation not yet supported");
+                }
+
+                let texture = self.create_shared_texture(shared_handle, pool, |shared| {
+                    let desc = desc::texture((width, height), levels, usage, fmt, pool, shared)?;
+                    self.backend.create_texture_2d(&desc)
+                })?;
+
+                // Opened textures could have a different number of levels.
+                let desc = self.backend.texture_desc(&texture);
+                let levels = desc.MipLevels;
+
+                let views = TextureViews::new(&desc, fmt)?;
+
+                *ret = Texture::new(self, pool, texture, levels, usage, fmt, views).into();
+
+                Error::Success
+            }
+        )
     }
 
     /// Creates a new cube map texture.
     fn create_cube_texture(
         &self,
         edge_len: u32,
-        mut levels: u32,
+        levels: u32,
         usage: UsageFlags,
         fmt: D3DFORMAT,
         pool: MemoryPool,
         ret: *mut *mut CubeTexture,
-        shared_handle: usize,
+        shared_handle: *mut HANDLE,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateCubeTexture",
+            [
+                edge_len,
+                levels,
+                usage.bits(),
+                fmt,
+                pool as u32,
+                trace::returned(ret),
+                trace::data(shared_handle)
+            ],
+            {
+                let ret = check_mut_ref(ret)?;
+
+                let levels = match levels {
+                    0 => 32 - edge_len.leading_zeros(),
+                    levels => levels,
+                };
+
+                if usage.intersects(UsageFlags::AUTO_GEN_MIP_MAP) {
+                    warn!("Autom mip-map generation not yet supported");
+                }
+
+                let texture = self.create_shared_texture(shared_handle, pool, |shared| {
+                    let desc = desc::cube_texture(edge_len, levels, usage, fmt, pool, shared)?;
+                    self.backend.create_texture_2d(&desc)
+                })?;
+
+                // Opened textures could have a different number of levels.
+                let desc = self.backend.texture_desc(&texture);
+                let levels = desc.MipLevels;
+
+                let views = TextureViews::new(&desc, fmt)?;
+
+                *ret = CubeTexture::new(self, texture, levels, usage, pool, fmt, views).into();
+
+                Error::Success
+            }
+        )
+    }
 
-        if shared_handle != 0 {
-            error!("Shared resources are not supported");
-            return Error::InvalidCall;
-        }
+    fn create_volume_texture() -> Error {
+        unimplemented_method!("IDirect3DDevice9::CreateVolumeTexture")
+    }
 
-        if levels == 0 {
-            levels = 32 - edge_len.leading_zeros();
-        }
+    // -- Drawing functions --
 
-        if usage.intersects(UsageFlags::AUTO_GEN_MIP_MAP) {
-            warn!("Autom mip-map generation not yet supported");
-        }
+    fn clear() -> Error {
+        unimplemented_method!("IDirect3DDevice9::Clear")
+    }
 
-        let texture =
-            d3d11::Texture2D::new_cube_texture(&self.device, edge_len, levels, usage, fmt, pool)?;
+    /// Starts a scene, which must contain all the draw calls.
+    fn begin_scene(&mut self) -> Error {
+        traced!(self, "IDirect3DDevice9::BeginScene", [], {
+            if self.in_scene {
+                let diag = Diagnostic::state("IDirect3DDevice9::BeginScene", "already in a scene");
+                return self.reject(diag);
+            }
 
-        *ret = CubeTexture::new(self, texture, levels, usage, pool).into();
+            self.in_scene = true;
 
-        Error::Success
+            Error::Success
+        })
     }
 
-    fn create_volume_texture() {
-        unimplemented!()
-    }
+    /// Ends the scene started by `BeginScene`.
+    fn end_scene(&mut self) -> Error {
+        traced!(self, "IDirect3DDevice9::EndScene", [], {
+            if !self.in_scene {
+                let diag = Diagnostic::state("IDirect3DDevice9::EndScene", "not in a scene");
+                return self.reject(diag);
+            }
 
-    // -- Drawing functions --
+            self.in_scene = false;
 
-    fn clear() {
-        unimplemented!()
+            Error::Success
+        })
     }
 
-    fn begin_scene() {
-        unimplemented!()
-    }
-    fn end_scene() {
-        unimplemented!()
+    fn draw_indexed_primitive(
+        &self,
+        ty: D3DPRIMITIVETYPE,
+        base_vertex: i32,
+        _min_index: u32,
+        _num_vertices: u32,
+        start_index: u32,
+        count: u32,
+    ) -> Error {
+        let method = "IDirect3DDevice9::DrawIndexedPrimitive";
+        traced!(
+            self,
+            method,
+            [
+                ty,
+                base_vertex,
+                _min_index,
+                _num_vertices,
+                start_index,
+                count
+            ],
+            {
+                self.validate_draw(method)?;
+                self.count_draw(count);
+
+                if self.istate.get_indices().is_null() {
+                    let diag = Diagnostic::state(method, "no index buffer is set");
+                    return self.reject(diag);
+                }
+
+                // The indices are in the index buffer, where they cannot be converted to a list.
+                if ty == D3DPT_TRIANGLEFAN {
+                    return unimplemented_method!(
+                        "IDirect3DDevice9::DrawIndexedPrimitive with triangle fans"
+                    );
+                }
+
+                let indices = self.set_topology(method, ty, count)?;
+                self.backend.draw_indexed(indices, start_index, base_vertex);
+
+                Error::Success
+            }
+        )
     }
 
-    fn draw_indexed_primitive() {
-        unimplemented!()
-    }
-    fn draw_indexed_primitive_u_p() {
-        unimplemented!()
+    /// Draws indexed primitives whose vertices and indices are in the app's memory.
+    ///
+    /// Like on D3D9, stream 0 and the index buffer are unbound afterwards.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    fn draw_indexed_primitive_u_p(
+        &mut self,
+        ty: D3DPRIMITIVETYPE,
+        min_index: u32,
+        num_vertices: u32,
+        count: u32,
+        indices: *const c_void,
+        index_fmt: D3DFORMAT,
+        vertices: *const c_void,
+        stride: u32,
+    ) -> Error {
+        let method = "IDirect3DDevice9::DrawIndexedPrimitiveUP";
+
+        let index_count = primitive_topology(ty, count).map_or(0, |(_, count)| count);
+        let index_size = if index_fmt == D3DFMT_INDEX32 { 4 } else { 2 };
+        let index_data = unsafe { user_data(indices, index_count as usize * index_size) };
+
+        let vertex_count = min_index as usize + num_vertices as usize;
+        let vertex_data = unsafe { user_data(vertices, vertex_count * stride as usize) };
+
+        traced!(
+            self,
+            method,
+            [
+                ty,
+                min_index,
+                num_vertices,
+                count,
+                trace::array(index_data),
+                index_fmt,
+                trace::array(vertex_data),
+                stride
+            ],
+            {
+                self.validate_draw(method)?;
+                self.count_draw(count);
+
+                if index_fmt != D3DFMT_INDEX16 && index_fmt != D3DFMT_INDEX32 {
+                    let message = "must be D3DFMT_INDEX16 or D3DFMT_INDEX32";
+                    return self.reject(Diagnostic::new(method, "IndexDataFormat", message));
+                }
+
+                if indices.is_null() || vertices.is_null() {
+                    return Error::InvalidCall;
+                }
+
+                let index_count = self.set_topology(method, ty, count)?;
+
+                let buffer =
+                    self.upload(&self.up_vertices, D3D11_BIND_VERTEX_BUFFER, vertex_data)?;
+                self.backend.set_vertex_buffer(0, Some(&buffer), stride, 0);
+
+                if ty == D3DPT_TRIANGLEFAN {
+                    self.draw_fan(&read_indices(index_data, index_fmt), 0)?;
+                } else {
+                    let buffer =
+                        self.upload(&self.up_indices, D3D11_BIND_INDEX_BUFFER, index_data)?;
+                    let fmt = index_format(index_fmt);
+
+                    self.backend.set_index_buffer(Some(&buffer), fmt, 0);
+                    self.backend.draw_indexed(index_count, 0, 0);
+                }
+
+                self.istate.set_stream_source(0, ptr::null_mut(), 0, 0);
+                self.istate.set_indices(ptr::null_mut());
+                self.bind_stream(0);
+                self.bind_indices();
+
+                Error::Success
+            }
+        )
     }
-    fn draw_primitive() {
-        unimplemented!()
+
+    fn draw_primitive(&self, ty: D3DPRIMITIVETYPE, start_vertex: u32, count: u32) -> Error {
+        let method = "IDirect3DDevice9::DrawPrimitive";
+        traced!(self, method, [ty, start_vertex, count], {
+            self.validate_draw(method)?;
+            self.count_draw(count);
+
+            let vertices = self.set_topology(method, ty, count)?;
+
+            if ty == D3DPT_TRIANGLEFAN {
+                let fan: Vec<u32> = (0..vertices).collect();
+                self.draw_fan(&fan, start_vertex as i32)?;
+            } else {
+                self.backend.draw(vertices, start_vertex);
+            }
+
+            Error::Success
+        })
     }
-    fn draw_primitive_u_p() {
-        unimplemented!()
+
+    /// Draws primitives whose vertices are in the app's memory.
+    ///
+    /// Like on D3D9, stream 0 is unbound afterwards.
+    fn draw_primitive_u_p(
+        &mut self,
+        ty: D3DPRIMITIVETYPE,
+        count: u32,
+        vertices: *const c_void,
+        stride: u32,
+    ) -> Error {
+        let method = "IDirect3DDevice9::DrawPrimitiveUP";
+
+        let vertex_count = primitive_topology(ty, count).map_or(0, |(_, count)| count);
+        let data = unsafe { user_data(vertices, vertex_count as usize * stride as usize) };
+
+        traced!(self, method, [ty, count, trace::array(data), stride], {
+            self.validate_draw(method)?;
+            self.count_draw(count);
+
+            if vertices.is_null() {
+                return Error::InvalidCall;
+            }
+
+            let vertex_count = self.set_topology(method, ty, count)?;
+
+            let buffer = self.upload(&self.up_vertices, D3D11_BIND_VERTEX_BUFFER, data)?;
+            self.backend.set_vertex_buffer(0, Some(&buffer), stride, 0);
+
+            if ty == D3DPT_TRIANGLEFAN {
+                let fan: Vec<u32> = (0..vertex_count).collect();
+                self.draw_fan(&fan, 0)?;
+            } else {
+                self.backend.draw(vertex_count, 0);
+            }
+
+            self.istate.set_stream_source(0, ptr::null_mut(), 0, 0);
+            self.bind_stream(0);
+
+            Error::Success
+        })
     }
 
     // -- State block functions --
 
     /// Creates a new state block which can capture commands.
     fn create_state_block(&mut self, ty: D3DSTATEBLOCKTYPE, ret: *mut *mut StateBlock) -> Error {
-        let ret = check_mut_ref(ret)?;
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateStateBlock",
+            [ty, trace::returned(ret)],
+            {
+                let ret = check_mut_ref(ret)?;
 
-        *ret = StateBlock::new(self, ty)?.into();
+                *ret = StateBlock::new(self, ty)?.into();
 
-        Error::Success
+                Error::Success
+            }
+        )
     }
 
     /// Begins recording a new state block.
     fn begin_state_block(&mut self) -> Error {
-        unimplemented!()
+        unimplemented_method!("IDirect3DDevice9::BeginStateBlock")
     }
 
     /// Ends recording a state block, and returns a pointer to it.
     fn end_state_block(&mut self, ret: *mut *mut StateBlock) -> Error {
         let _ret = check_mut_ref(ret)?;
-        unimplemented!()
+        unimplemented_method!("IDirect3DDevice9::EndStateBlock")
     }
 
     /// Validates the current state of the device, or the state of the
     /// currently recording state block, if any.
     fn validate_device(&self, passes: *mut u32) -> Error {
-        let passes = check_mut_ref(passes)?;
+        guarded!("IDirect3DDevice9::ValidateDevice", {
+            let passes = check_mut_ref(passes)?;
 
-        // We do not emulate anything using multiple passes.
-        *passes = 1;
+            // We do not emulate anything using multiple passes.
+            *passes = 1;
 
-        Error::Success
+            Error::Success
+        })
     }
 
     // -- Hardware cursor functions --
 
-    fn set_cursor_position() {
-        unimplemented!()
+    /// Sets the position of the cursor.
+    fn set_cursor_position(&self, x: i32, y: i32, _flags: u32) {
+        traced!(
+            self,
+            "IDirect3DDevice9::SetCursorPosition",
+            [x, y, _flags],
+            {
+                // Windowed apps give the position in desktop coordinates,
+                // while full-screen apps give it on the back buffer.
+                let (x, y) = match self.swap_chains.first() {
+                    Some(sc) if !sc.is_windowed() => self.back_buffer_to_desktop(sc, (x, y)),
+                    _ => (x, y),
+                };
+
+                self.cursor.set_position(x, y);
+            }
+        )
     }
-    fn set_cursor_properties() {
-        unimplemented!()
+
+    /// Sets the image used for the cursor, and its hotspot.
+    fn set_cursor_properties(&self, x_hot: u32, y_hot: u32, surface: *mut Surface) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9::SetCursorProperties",
+            [x_hot, y_hot, trace::object(surface)],
+            {
+                let surface = check_ref(surface)?;
+                self.cursor.set_image(self, surface, (x_hot, y_hot))?;
+                Error::Success
+            }
+        )
     }
-    fn show_cursor() {
-        unimplemented!()
+
+    /// Shows or hides the cursor, returning whether it was previously visible.
+    fn show_cursor(&self, show: BOOL) -> BOOL {
+        traced!(self, "IDirect3DDevice9::ShowCursor", [show], {
+            self.cursor.show(show != 0) as BOOL
+        })
     }
 
     // -- Pipeline state functions --
@@ -789,17 +2032,29 @@ impl Device {
 
     /// Sets the render state.
     fn set_render_state(&mut self, state: D3DRENDERSTATETYPE, value: u32) -> Error {
-        self.istate.set_render_state(state, value);
-        Error::Success
+        traced!(self, "IDirect3DDevice9::SetRenderState", [state, value], {
+            self.istate.set_render_state(state, value);
+            self.count(|stats| stats.state_changes += 1);
+
+            // Switch between the linear and the sRGB views of the render targets.
+            if state == D3DRS_SRGBWRITEENABLE {
+                self.retarget_back_buffers()?;
+                self.bind_render_targets();
+            }
+
+            Error::Success
+        })
     }
 
     /// Retrieves the value of the current render state.
     fn get_render_state(&self, state: D3DRENDERSTATETYPE, ret: *mut u32) -> Error {
-        let ret = check_mut_ref(ret)?;
+        guarded!("IDirect3DDevice9::GetRenderState", {
+            let ret = check_mut_ref(ret)?;
 
-        *ret = self.istate.get_render_state(state);
+            *ret = self.istate.get_render_state(state);
 
-        Error::Success
+            Error::Success
+        })
     }
 
     // -- Vertex shader functions --
@@ -810,63 +2065,103 @@ impl Device {
         elems: *const D3DVERTEXELEMENT9,
         ret: *mut *mut VertexDeclaration,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateVertexDeclaration",
+            [
+                trace::array(declaration_elements(elems)),
+                trace::returned(ret)
+            ],
+            {
+                let ret = check_mut_ref(ret)?;
 
-        *ret = VertexDeclaration::new(self, elems).into();
+                *ret = VertexDeclaration::new(self, elems).into();
 
-        Error::Success
+                Error::Success
+            }
+        )
     }
 
     /// Sets the current vertex declaration.
     fn set_vertex_declaration(&mut self, decl: *const VertexDeclaration) -> Error {
-        self.istate.set_vertex_declaration(decl);
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::SetVertexDeclaration",
+            [trace::object(decl)],
+            {
+                self.istate.set_vertex_declaration(decl);
+                self.count(|stats| stats.state_changes += 1);
+                Error::Success
+            }
+        )
     }
 
     /// Gets the current vertex declaration.
     fn get_vertex_declaration(&self, ret: *mut *const VertexDeclaration) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = com_ref(self.istate.get_vertex_declaration());
-        Error::Success
+        guarded!("IDirect3DDevice9::GetVertexDeclaration", {
+            let ret = check_mut_ref(ret)?;
+            *ret = com_ref(self.istate.get_vertex_declaration());
+            Error::Success
+        })
     }
 
     /// Creates a vertex shader from its bytecode.
     fn create_vertex_shader(&self, func: *const u32, ret: *mut *mut VertexShader) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = VertexShader::new(self, func)?.into();
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateVertexShader",
+            [trace::array(shader_tokens(func)), trace::returned(ret)],
+            {
+                let ret = check_mut_ref(ret)?;
+                *ret = VertexShader::new(self, func)?.into();
+                Error::Success
+            }
+        )
     }
 
     /// Sets the current vertex shader.
     fn set_vertex_shader(&mut self, vs: *const VertexShader) -> Error {
-        self.istate.set_vertex_shader(vs);
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::SetVertexShader",
+            [trace::object(vs)],
+            {
+                if self.istate.get_vertex_shader() != vs {
+                    self.count(|stats| stats.shader_switches += 1);
+                }
+
+                self.istate.set_vertex_shader(vs);
+                Error::Success
+            }
+        )
     }
 
     /// Retrieves the current vertex shader;
     fn get_vertex_shader(&self, ret: *mut *const VertexShader) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = self.istate.get_vertex_shader();
-        Error::Success
+        guarded!("IDirect3DDevice9::GetVertexShader", {
+            let ret = check_mut_ref(ret)?;
+            *ret = self.istate.get_vertex_shader();
+            Error::Success
+        })
     }
 
-    fn set_vertex_shader_constant_b() {
-        unimplemented!()
+    fn set_vertex_shader_constant_b() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetVertexShaderConstantB")
     }
-    fn get_vertex_shader_constant_b() {
-        unimplemented!()
+    fn get_vertex_shader_constant_b() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetVertexShaderConstantB")
     }
-    fn set_vertex_shader_constant_f() {
-        unimplemented!()
+    fn set_vertex_shader_constant_f() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetVertexShaderConstantF")
     }
-    fn get_vertex_shader_constant_f() {
-        unimplemented!()
+    fn get_vertex_shader_constant_f() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetVertexShaderConstantF")
     }
-    fn set_vertex_shader_constant_i() {
-        unimplemented!()
+    fn set_vertex_shader_constant_i() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetVertexShaderConstantI")
     }
-    fn get_vertex_shader_constant_i() {
-        unimplemented!()
+    fn get_vertex_shader_constant_i() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetVertexShaderConstantI")
     }
 
     /// Creates a new vertex buffer.
@@ -877,20 +2172,35 @@ impl Device {
         fvf: u32,
         pool: MemoryPool,
         ret: *mut *mut VertexBuffer,
-        shared_handle: usize,
+        shared_handle: *mut HANDLE,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
-
-        if shared_handle != 0 {
-            error!("Shared resources are not supported");
-            return Error::InvalidCall;
-        }
-
-        let buffer = d3d11::Buffer::new(&self.device, len, usage, pool, D3D11_BIND_VERTEX_BUFFER)?;
-
-        *ret = VertexBuffer::new(self, pool, fvf, buffer, usage).into();
-
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateVertexBuffer",
+            [
+                len,
+                usage.bits(),
+                fvf,
+                pool as u32,
+                trace::returned(ret),
+                trace::data(shared_handle)
+            ],
+            {
+                let ret = check_mut_ref(ret)?;
+
+                if !shared_handle.is_null() {
+                    error!("Shared buffers are not supported");
+                    return Error::InvalidCall;
+                }
+
+                let desc = desc::buffer(len, usage, pool, D3D11_BIND_VERTEX_BUFFER)?;
+                let buffer = self.backend.create_buffer(&desc)?;
+
+                *ret = VertexBuffer::new(self, pool, fvf, buffer, usage).into();
+
+                Error::Success
+            }
+        )
     }
 
     /// Creates a new index buffer.
@@ -901,104 +2211,251 @@ impl Device {
         fmt: D3DFORMAT,
         pool: MemoryPool,
         ret: *mut *mut IndexBuffer,
-        shared_handle: usize,
+        shared_handle: *mut HANDLE,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateIndexBuffer",
+            [
+                len,
+                usage.bits(),
+                fmt,
+                pool as u32,
+                trace::returned(ret),
+                trace::data(shared_handle)
+            ],
+            {
+                let ret = check_mut_ref(ret)?;
+
+                if !shared_handle.is_null() {
+                    error!("Shared buffers are not supported");
+                    return Error::InvalidCall;
+                }
+
+                let desc = desc::buffer(len, usage, pool, D3D11_BIND_INDEX_BUFFER)?;
+                let buffer = self.backend.create_buffer(&desc)?;
+
+                *ret = IndexBuffer::new(self, fmt, pool, buffer, usage).into();
+
+                Error::Success
+            }
+        )
+    }
 
-        if shared_handle != 0 {
-            error!("Shared resources are not supported");
-            return Error::InvalidCall;
-        }
+    /// Binds a vertex buffer to a stream, from which vertices are read when drawing.
+    fn set_stream_source(
+        &mut self,
+        stream: u32,
+        buffer: *mut VertexBuffer,
+        offset: u32,
+        stride: u32,
+    ) -> Error {
+        let method = "IDirect3DDevice9::SetStreamSource";
+        traced!(
+            self,
+            method,
+            [stream, trace::object(buffer), offset, stride],
+            {
+                if stream >= 16 {
+                    let diag = Diagnostic::new(method, "StreamNumber", "there are only 16 streams");
+                    return self.reject(diag);
+                }
+
+                self.istate
+                    .set_stream_source(stream, buffer, offset, stride);
+                self.count(|stats| stats.state_changes += 1);
+                self.bind_stream(stream);
+
+                Error::Success
+            }
+        )
+    }
+
+    /// Retrieves the vertex buffer bound to a stream.
+    fn get_stream_source(
+        &self,
+        stream: u32,
+        ret: *mut *mut VertexBuffer,
+        offset: *mut u32,
+        stride: *mut u32,
+    ) -> Error {
+        guarded!("IDirect3DDevice9::GetStreamSource", {
+            let ret = check_mut_ref(ret)?;
+            let offset = check_mut_ref(offset)?;
+            let stride = check_mut_ref(stride)?;
+
+            if stream >= 16 {
+                return Error::InvalidCall;
+            }
 
-        let buffer = d3d11::Buffer::new(&self.device, len, usage, pool, D3D11_BIND_INDEX_BUFFER)?;
+            let (buffer, buffer_offset, buffer_stride) = self.istate.get_stream_source(stream);
 
-        *ret = IndexBuffer::new(self, fmt, pool, buffer, usage).into();
+            *ret = if buffer.is_null() {
+                buffer
+            } else {
+                com_ref(buffer)
+            };
+            *offset = buffer_offset;
+            *stride = buffer_stride;
 
-        Error::Success
+            Error::Success
+        })
     }
 
-    fn set_stream_source() {
-        unimplemented!()
+    /// Sets the index buffer used by indexed draws.
+    fn set_indices(&mut self, indices: *mut IndexBuffer) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9::SetIndices",
+            [trace::object(indices)],
+            {
+                self.istate.set_indices(indices);
+                self.count(|stats| stats.state_changes += 1);
+                self.bind_indices();
+                Error::Success
+            }
+        )
     }
-    fn get_stream_source() {
-        unimplemented!()
+
+    /// Retrieves the current index buffer.
+    fn get_indices(&self, ret: *mut *mut IndexBuffer) -> Error {
+        guarded!("IDirect3DDevice9::GetIndices", {
+            let ret = check_mut_ref(ret)?;
+            let indices = self.istate.get_indices();
+
+            *ret = if indices.is_null() {
+                indices
+            } else {
+                com_ref(indices)
+            };
+
+            Error::Success
+        })
     }
 
-    fn set_stream_source_freq() {
-        unimplemented!()
+    fn set_stream_source_freq() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetStreamSourceFreq")
     }
-    fn get_stream_source_freq() {
-        unimplemented!()
+    fn get_stream_source_freq() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetStreamSourceFreq")
     }
 
     // -- Pixel shader functions --
 
     /// Sets the state of a texture sampler.
     fn set_sampler_state(&mut self, sampler: u32, ty: D3DSAMPLERSTATETYPE, value: u32) -> Error {
-        self.istate.set_sampler_state(sampler, ty, value);
-
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::SetSamplerState",
+            [sampler, ty, value],
+            {
+                self.istate.set_sampler_state(sampler, ty, value);
+                self.count(|stats| stats.state_changes += 1);
+
+                // Switch between the linear and the sRGB views of the bound texture.
+                if ty == D3DSAMP_SRGBTEXTURE {
+                    self.bind_texture(sampler);
+                } else {
+                    self.bind_sampler(sampler);
+                }
+
+                Error::Success
+            }
+        )
     }
 
     /// Gets the state of a texture sampler.
     fn get_sampler_state(&self, sampler: u32, ty: D3DSAMPLERSTATETYPE, ret: *mut u32) -> Error {
-        let ret = check_mut_ref(ret)?;
+        guarded!("IDirect3DDevice9::GetSamplerState", {
+            let ret = check_mut_ref(ret)?;
 
-        *ret = self.istate.get_sampler_state(sampler, ty);
+            *ret = self.istate.get_sampler_state(sampler, ty);
 
-        Error::Success
+            Error::Success
+        })
     }
 
     /// Create a pixel shader from its bytecode.
     fn create_pixel_shader(&self, func: *const u32, ret: *mut *mut PixelShader) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = PixelShader::new(self, func)?.into();
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::CreatePixelShader",
+            [trace::array(shader_tokens(func)), trace::returned(ret)],
+            {
+                let ret = check_mut_ref(ret)?;
+                *ret = PixelShader::new(self, func)?.into();
+                Error::Success
+            }
+        )
     }
 
     /// Sets the current pixel shader.
     fn set_pixel_shader(&mut self, ps: *const PixelShader) -> Error {
-        self.istate.set_pixel_shader(ps);
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::SetPixelShader",
+            [trace::object(ps)],
+            {
+                if self.istate.get_pixel_shader() != ps {
+                    self.count(|stats| stats.shader_switches += 1);
+                }
+
+                self.istate.set_pixel_shader(ps);
+                Error::Success
+            }
+        )
     }
 
     /// Gets the current pixel shader.
     fn get_pixel_shader(&self, ret: *mut *const PixelShader) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = self.istate.get_pixel_shader();
-        Error::Success
+        guarded!("IDirect3DDevice9::GetPixelShader", {
+            let ret = check_mut_ref(ret)?;
+            *ret = self.istate.get_pixel_shader();
+            Error::Success
+        })
     }
 
-    fn set_pixel_shader_constant_b() {
-        unimplemented!()
+    fn set_pixel_shader_constant_b() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetPixelShaderConstantB")
     }
-    fn get_pixel_shader_constant_b() {
-        unimplemented!()
+    fn get_pixel_shader_constant_b() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetPixelShaderConstantB")
     }
-    fn set_pixel_shader_constant_f() {
-        unimplemented!()
+    fn set_pixel_shader_constant_f() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetPixelShaderConstantF")
     }
-    fn get_pixel_shader_constant_f() {
-        unimplemented!()
+    fn get_pixel_shader_constant_f() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetPixelShaderConstantF")
     }
-    fn set_pixel_shader_constant_i() {
-        unimplemented!()
+    fn set_pixel_shader_constant_i() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetPixelShaderConstantI")
     }
-    fn get_pixel_shader_constant_i() {
-        unimplemented!()
+    fn get_pixel_shader_constant_i() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetPixelShaderConstantI")
     }
 
     /// Binds a texture to a stage.
     fn set_texture(&mut self, stage: u32, texture: *mut BaseTexture) -> Error {
-        self.istate.set_texture(stage, texture);
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::SetTexture",
+            [stage, trace::object(texture)],
+            {
+                self.istate.set_texture(stage, texture);
+                self.count(|stats| stats.state_changes += 1);
+                self.bind_texture(stage);
+                Error::Success
+            }
+        )
     }
 
     /// Retrieves the bound texture of a certain stage.
     fn get_texture(&self, stage: u32, ret: *mut *mut BaseTexture) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = self.istate.get_texture(stage);
-        Error::Success
+        guarded!("IDirect3DDevice9::GetTexture", {
+            let ret = check_mut_ref(ret)?;
+            *ret = self.istate.get_texture(stage);
+            Error::Success
+        })
     }
 
     /// Set a state for the texture bound to a certain stage.
@@ -1008,8 +2465,16 @@ impl Device {
         ty: D3DTEXTURESTAGESTATETYPE,
         value: u32,
     ) -> Error {
-        self.istate.set_texture_stage_state(stage, ty, value);
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DDevice9::SetTextureStageState",
+            [stage, ty, value],
+            {
+                self.istate.set_texture_stage_state(stage, ty, value);
+                self.count(|stats| stats.state_changes += 1);
+                Error::Success
+            }
+        )
     }
 
     /// Retrieves the state of a certain texture stage.
@@ -1019,161 +2484,613 @@ impl Device {
         ty: D3DTEXTURESTAGESTATETYPE,
         ret: *mut u32,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = self.istate.get_texture_stage_state(stage, ty);
-        Error::Success
+        guarded!("IDirect3DDevice9::GetTextureStageState", {
+            let ret = check_mut_ref(ret)?;
+            *ret = self.istate.get_texture_stage_state(stage, ty);
+            Error::Success
+        })
     }
 
     // -- Output Merger state --
 
     /// Sets a device's viewport.
     fn set_viewport(&mut self, vp: *const D3DVIEWPORT9) -> Error {
-        let vp = check_ref(vp)?;
-        self.istate.set_viewport(vp);
-        Error::Success
+        traced!(self, "IDirect3DDevice9::SetViewport", [trace::data(vp)], {
+            let vp = check_ref(vp)?;
+            self.istate.set_viewport(vp);
+            self.count(|stats| stats.state_changes += 1);
+            Error::Success
+        })
     }
 
     /// Retrieves the currently set viewport.
     fn get_viewport(&self, ret: *mut D3DVIEWPORT9) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = self.istate.get_viewport();
-        Error::Success
+        guarded!("IDirect3DDevice9::GetViewport", {
+            let ret = check_mut_ref(ret)?;
+            *ret = self.istate.get_viewport();
+            Error::Success
+        })
     }
 
-    fn set_scissor_rect() {
-        unimplemented!()
+    fn set_scissor_rect() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetScissorRect")
     }
-    fn get_scissor_rect() {
-        unimplemented!()
+    fn get_scissor_rect() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetScissorRect")
     }
 
     // -- Query creation --
 
-    fn create_query() {
-        unimplemented!()
+    /// Creates a new asynchronous query.
+    ///
+    /// If `ret` is null, this only checks if the query type is supported.
+    fn create_query(&self, ty: D3DQUERYTYPE, ret: *mut *mut Query) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9::CreateQuery",
+            [ty, trace::returned(ret)],
+            {
+                if !Query::is_supported(ty) {
+                    return Error::NotAvailable;
+                }
+
+                let ret = match unsafe { ret.as_mut() } {
+                    Some(ret) => ret,
+                    None => return Error::Success,
+                };
+
+                *ret = Query::new(self, ty)?.into();
+
+                Error::Success
+            }
+        )
     }
 
     // -- Fixed function pipeline --
 
     /// Sets the current material.
     fn set_material(&mut self, mat: *const D3DMATERIAL9) -> Error {
-        let mat = check_ref(mat)?;
-        self.istate.set_material(mat);
-        Error::Success
+        traced!(self, "IDirect3DDevice9::SetMaterial", [trace::data(mat)], {
+            let mat = check_ref(mat)?;
+            self.istate.set_material(mat);
+            self.count(|stats| stats.state_changes += 1);
+            Error::Success
+        })
     }
 
     /// Retrieves the currently set material.
     fn get_material(&self, ret: *mut D3DMATERIAL9) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = self.istate.get_material();
-        Error::Success
+        guarded!("IDirect3DDevice9::GetMaterial", {
+            let ret = check_mut_ref(ret)?;
+            *ret = self.istate.get_material();
+            Error::Success
+        })
     }
 
     /// Sets a transformation matrix to a value.
     fn set_transform(&mut self, ty: D3DTRANSFORMSTATETYPE, mat: *const D3DMATRIX) -> Error {
-        if (D3DTS_VIEW <= ty && ty <= D3DTS_PROJECTION)
-            || (D3DTS_TEXTURE0 <= ty && ty <= D3DTS_TEXTURE7)
-            || (256 <= ty && ty <= 512)
-        {
-            let mat = check_ref(mat)?;
-            self.istate
-                .set_transform(ty, unsafe { mem::transmute(*mat) });
-            Error::Success
-        } else {
-            Error::InvalidCall
-        }
+        traced!(
+            self,
+            "IDirect3DDevice9::SetTransform",
+            [ty, trace::data(mat)],
+            {
+                if (D3DTS_VIEW <= ty && ty <= D3DTS_PROJECTION)
+                    || (D3DTS_TEXTURE0 <= ty && ty <= D3DTS_TEXTURE7)
+                    || (256 <= ty && ty <= 512)
+                {
+                    let mat = check_ref(mat)?;
+                    self.istate
+                        .set_transform(ty, unsafe { mem::transmute(*mat) });
+                    self.count(|stats| stats.state_changes += 1);
+                    Error::Success
+                } else {
+                    Error::InvalidCall
+                }
+            }
+        )
     }
 
     /// Retrieves a transformation matrix.
     fn get_transform(&self, ty: D3DTRANSFORMSTATETYPE, ret: *mut D3DMATRIX) -> Error {
-        if (D3DTS_VIEW <= ty && ty <= D3DTS_PROJECTION)
-            || (D3DTS_TEXTURE0 <= ty && ty <= D3DTS_TEXTURE7)
-            || (256 <= ty && ty <= 512)
-        {
-            let ret = check_mut_ref(ret)?;
-            *ret = unsafe { mem::transmute(self.istate.get_transform(ty)) };
-            Error::Success
-        } else {
-            Error::InvalidCall
-        }
+        guarded!("IDirect3DDevice9::GetTransform", {
+            if (D3DTS_VIEW <= ty && ty <= D3DTS_PROJECTION)
+                || (D3DTS_TEXTURE0 <= ty && ty <= D3DTS_TEXTURE7)
+                || (256 <= ty && ty <= 512)
+            {
+                let ret = check_mut_ref(ret)?;
+                *ret = unsafe { mem::transmute(self.istate.get_transform(ty)) };
+                Error::Success
+            } else {
+                Error::InvalidCall
+            }
+        })
     }
 
-    fn delete_patch() {
-        unimplemented!()
+    fn delete_patch() -> Error {
+        unimplemented_method!("IDirect3DDevice9::DeletePatch")
+    }
+    fn draw_rect_patch() -> Error {
+        unimplemented_method!("IDirect3DDevice9::DrawRectPatch")
     }
-    fn draw_rect_patch() {
-        unimplemented!()
+    fn draw_tri_patch() -> Error {
+        unimplemented_method!("IDirect3DDevice9::DrawTriPatch")
     }
-    fn draw_tri_patch() {
-        unimplemented!()
+    fn get_clip_plane() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetClipPlane")
     }
-    fn get_clip_plane() {
-        unimplemented!()
+    fn get_clip_status() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetClipStatus")
     }
-    fn get_clip_status() {
-        unimplemented!()
+    fn get_current_texture_palette() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetCurrentTexturePalette")
     }
-    fn get_current_texture_palette() {
-        unimplemented!()
+    fn get_f_v_f() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetFVF")
     }
-    fn get_f_v_f() {
-        unimplemented!()
+    fn get_light() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetLight")
     }
-    fn get_indices() {
-        unimplemented!()
+    fn get_light_enable() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetLightEnable")
     }
-    fn get_light() {
-        unimplemented!()
+    fn get_n_patch_mode() -> f32 {
+        unimplemented_method!("IDirect3DDevice9::GetNPatchMode")
+    }
+    fn get_palette_entries() -> Error {
+        unimplemented_method!("IDirect3DDevice9::GetPaletteEntries")
+    }
+    fn get_software_vertex_processing() -> BOOL {
+        unimplemented_method!("IDirect3DDevice9::GetSoftwareVertexProcessing")
+    }
+    fn light_enable() -> Error {
+        unimplemented_method!("IDirect3DDevice9::LightEnable")
+    }
+    fn multiply_transform() -> Error {
+        unimplemented_method!("IDirect3DDevice9::MultiplyTransform")
+    }
+    fn process_vertices() -> Error {
+        unimplemented_method!("IDirect3DDevice9::ProcessVertices")
+    }
+    fn set_clip_plane() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetClipPlane")
+    }
+    fn set_clip_status() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetClipStatus")
+    }
+    fn set_current_texture_palette() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetCurrentTexturePalette")
+    }
+    fn set_dialog_box_mode() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetDialogBoxMode")
+    }
+    fn set_f_v_f() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetFVF")
+    }
+    fn set_light() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetLight")
+    }
+    fn set_n_patch_mode() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetNPatchMode")
+    }
+    fn set_palette_entries() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetPaletteEntries")
+    }
+    fn set_software_vertex_processing() -> Error {
+        unimplemented_method!("IDirect3DDevice9::SetSoftwareVertexProcessing")
+    }
+}
+
+#[implementation(IDirect3DDevice9Ex)]
+impl Device {
+    // -- Composition functions --
+
+    /// Sets the kernel used by the convolution composition operation.
+    fn set_convolution_mono_kernel(
+        &mut self,
+        _width: u32,
+        _height: u32,
+        _rows: *mut f32,
+        _columns: *mut f32,
+    ) -> Error {
+        guarded!("IDirect3DDevice9Ex::SetConvolutionMonoKernel", {
+            run_once!(|| warn!("Convolution kernels are not supported"));
+            Error::Success
+        })
+    }
+
+    /// Composes rectangles from a surface onto another surface.
+    fn compose_rects(
+        &mut self,
+        _src: *mut Surface,
+        _dest: *mut Surface,
+        _src_descs: *mut VertexBuffer,
+        _num_rects: u32,
+        _dest_descs: *mut VertexBuffer,
+        _op: D3DCOMPOSERECTSOP,
+        _x: i32,
+        _y: i32,
+    ) -> Error {
+        guarded!("IDirect3DDevice9Ex::ComposeRects", {
+            run_once!(|| error!("Composing rectangles is not supported"));
+            Error::NotAvailable
+        })
     }
-    fn get_light_enable() {
-        unimplemented!()
+
+    // -- Presentation functions --
+
+    /// Presents the back buffers of the implicit swap chains, with some extra flags.
+    fn present_ex(&self, src: usize, dest: usize, wnd: HWND, dirty: usize, flags: u32) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9Ex::PresentEx",
+            [
+                trace::data(src as *const RECT),
+                trace::data(dest as *const RECT),
+                trace::object(wnd),
+                trace::data(dirty as *const RGNDATAHEADER),
+                flags
+            ],
+            {
+                for sc in &self.swap_chains {
+                    sc.present(src, dest, wnd, dirty, flags)?;
+                }
+                Error::Success
+            }
+        )
     }
-    fn get_n_patch_mode() {
-        unimplemented!()
+
+    /// Retrieves the priority of the GPU thread.
+    fn get_g_p_u_thread_priority(&self, ret: *mut i32) -> Error {
+        guarded!("IDirect3DDevice9Ex::GetGPUThreadPriority", {
+            let ret = check_mut_ref(ret)?;
+
+            *ret = self.backend.gpu_thread_priority()?;
+            Error::Success
+        })
     }
-    fn get_palette_entries() {
-        unimplemented!()
+
+    /// Sets the priority of the GPU thread.
+    fn set_g_p_u_thread_priority(&mut self, priority: i32) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9Ex::SetGPUThreadPriority",
+            [priority],
+            {
+                // The range of valid priorities is the same as in DXGI.
+                if priority < -7 || priority > 7 {
+                    return Error::InvalidCall;
+                }
+
+                self.backend.set_gpu_thread_priority(priority)?;
+                Error::Success
+            }
+        )
     }
-    fn get_software_vertex_processing() {
-        unimplemented!()
+
+    /// Waits until the next vertical blank of a swap chain's display.
+    fn wait_for_v_blank(&self, sc: u32) -> Error {
+        guarded!("IDirect3DDevice9Ex::WaitForVBlank", {
+            self.check_swap_chain(sc)?.wait_for_vblank()?;
+            Error::Success
+        })
     }
-    fn light_enable() {
-        unimplemented!()
+
+    /// Checks if a list of resources is resident in video memory.
+    fn check_resource_residency(&self, _resources: *mut *mut Resource, _num: u32) -> Error {
+        // The driver manages residency for us, so we assume everything is in VRAM.
+        Error::Success
     }
-    fn multiply_transform() {
-        unimplemented!()
+
+    /// Sets the number of frames the CPU is allowed to queue up for presenting.
+    fn set_maximum_frame_latency(&mut self, latency: u32) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9Ex::SetMaximumFrameLatency",
+            [latency],
+            {
+                // D3D9Ex defines a limit of 20 frames, and 0 means the default of 3.
+                if latency > 20 {
+                    return Error::InvalidCall;
+                }
+
+                // The user's choice takes precedence.
+                let latency = self.config().max_frame_latency.unwrap_or(latency);
+
+                self.backend.set_maximum_frame_latency(latency)?;
+                Error::Success
+            }
+        )
     }
-    fn process_vertices() {
-        unimplemented!()
+
+    /// Retrieves the number of frames the CPU is allowed to queue up for presenting.
+    fn get_maximum_frame_latency(&self, ret: *mut u32) -> Error {
+        guarded!("IDirect3DDevice9Ex::GetMaximumFrameLatency", {
+            let ret = check_mut_ref(ret)?;
+
+            *ret = self.backend.maximum_frame_latency()?;
+            Error::Success
+        })
     }
-    fn set_clip_plane() {
-        unimplemented!()
+
+    /// Checks if the app's window can still be presented to.
+    fn check_device_state(&self, wnd: HWND) -> Error {
+        guarded!("IDirect3DDevice9Ex::CheckDeviceState", {
+            let wnd = if wnd.is_null() { self.window } else { wnd };
+
+            // Minimized windows do not need to be presented to.
+            if unsafe { winuser::IsIconic(wnd) } != 0 {
+                return Error::PresentOccluded;
+            }
+
+            let lost = self
+                .swap_chains
+                .first()
+                .map(|sc| sc.lost_fullscreen())
+                .unwrap_or(false);
+
+            // If the app left full-screen mode, it should reset the device to windowed.
+            if lost {
+                Error::PresentModeChanged
+            } else {
+                Error::Success
+            }
+        })
     }
-    fn set_clip_status() {
-        unimplemented!()
+
+    // -- Resource creation functions --
+
+    /// Creates a new render target with some extra usage flags.
+    fn create_render_target_ex(
+        &mut self,
+        width: u32,
+        height: u32,
+        fmt: D3DFORMAT,
+        ms_ty: D3DMULTISAMPLE_TYPE,
+        ms_qlt: u32,
+        lockable: u32,
+        ret: *mut *mut Surface,
+        shared_handle: *mut HANDLE,
+        _usage: u32,
+    ) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9Ex::CreateRenderTargetEx",
+            [
+                width,
+                height,
+                fmt,
+                ms_ty,
+                ms_qlt,
+                lockable,
+                trace::returned(ret),
+                trace::data(shared_handle),
+                _usage
+            ],
+            {
+                // The only valid usage flags are related to content protection, which we ignore.
+                self.create_render_target(
+                    width,
+                    height,
+                    fmt,
+                    ms_ty,
+                    ms_qlt,
+                    lockable,
+                    ret,
+                    shared_handle,
+                )
+            }
+        )
     }
-    fn set_current_texture_palette() {
-        unimplemented!()
+
+    /// Creates an off-screen surface with some extra usage flags.
+    fn create_offscreen_plain_surface_ex(
+        &self,
+        width: u32,
+        height: u32,
+        fmt: D3DFORMAT,
+        pool: MemoryPool,
+        ret: *mut *mut Surface,
+        shared_handle: *mut HANDLE,
+        _usage: u32,
+    ) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9Ex::CreateOffscreenPlainSurfaceEx",
+            [
+                width,
+                height,
+                fmt,
+                pool as u32,
+                trace::returned(ret),
+                trace::data(shared_handle),
+                _usage
+            ],
+            { self.create_offscreen_plain_surface(width, height, fmt, pool, ret, shared_handle) }
+        )
+    }
+
+    /// Creates a depth / stencil buffer with some extra usage flags.
+    fn create_depth_stencil_surface_ex(
+        &self,
+        width: u32,
+        height: u32,
+        fmt: D3DFORMAT,
+        ms_ty: D3DMULTISAMPLE_TYPE,
+        ms_qlt: u32,
+        discard: u32,
+        ret: *mut *mut Surface,
+        shared_handle: *mut HANDLE,
+        _usage: u32,
+    ) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9Ex::CreateDepthStencilSurfaceEx",
+            [
+                width,
+                height,
+                fmt,
+                ms_ty,
+                ms_qlt,
+                discard,
+                trace::returned(ret),
+                trace::data(shared_handle),
+                _usage
+            ],
+            {
+                self.create_depth_stencil_surface(
+                    width,
+                    height,
+                    fmt,
+                    ms_ty,
+                    ms_qlt,
+                    discard,
+                    ret,
+                    shared_handle,
+                )
+            }
+        )
     }
-    fn set_dialog_box_mode() {
-        unimplemented!()
+
+    // -- Device status functions --
+
+    /// Resets the device, possibly changing the full-screen display mode.
+    fn reset_ex(
+        &mut self,
+        pp: *mut D3DPRESENT_PARAMETERS,
+        fs_mode: *mut D3DDISPLAYMODEEX,
+    ) -> Error {
+        traced!(
+            self,
+            "IDirect3DDevice9Ex::ResetEx",
+            [trace::data(pp), trace::data(fs_mode)],
+            {
+                let mode = {
+                    let pp = check_mut_ref(pp)?;
+
+                    // The display mode must be given if and only if the device is full-screen.
+                    if (pp.Windowed != 0) != fs_mode.is_null() {
+                        return Error::InvalidCall;
+                    }
+
+                    match unsafe { fs_mode.as_ref() } {
+                        Some(mode) => {
+                            // The mode has to describe the new back buffer.
+                            if (mode.Width, mode.Height, mode.Format)
+                                != (pp.BackBufferWidth, pp.BackBufferHeight, pp.BackBufferFormat)
+                            {
+                                error!("Full-screen display mode does not match the back buffer");
+                                return Error::InvalidCall;
+                            }
+
+                            pp.FullScreen_RefreshRateInHz = mode.RefreshRate;
+                            Some(*mode)
+                        }
+                        None => None,
+                    }
+                };
+
+                self.reset(pp)?;
+
+                if let Some(mode) = mode {
+                    self.swap_chains[0].set_display_mode(&mode)?;
+                }
+
+                Error::Success
+            }
+        )
     }
-    fn set_f_v_f() {
-        unimplemented!()
+
+    /// Retrieves a swap chain's display mode and the display's rotation.
+    fn get_display_mode_ex(
+        &self,
+        sc: u32,
+        mode: *mut D3DDISPLAYMODEEX,
+        rotation: *mut D3DDISPLAYROTATION,
+    ) -> Error {
+        guarded!("IDirect3DDevice9Ex::GetDisplayModeEx", {
+            let mode = check_mut_ref(mode)?;
+
+            let mut dm = unsafe { mem::zeroed() };
+            self.check_swap_chain(sc)?.get_display_mode(&mut dm)?;
+
+            *mode = D3DDISPLAYMODEEX {
+                Size: mem::size_of::<D3DDISPLAYMODEEX>() as u32,
+                Width: dm.Width,
+                Height: dm.Height,
+                RefreshRate: dm.RefreshRate,
+                Format: dm.Format,
+                ScanLineOrdering: D3DSCANLINEORDERING_PROGRESSIVE,
+            };
+
+            // We do not support rotated displays.
+            if let Ok(rotation) = check_mut_ref(rotation) {
+                *rotation = D3DDISPLAYROTATION_IDENTITY;
+            }
+
+            Error::Success
+        })
     }
-    fn set_indices() {
-        unimplemented!()
+}
+
+/// Translates a primitive type, and returns how many vertices `count` primitives are made of.
+///
+/// Triangle fans have no equivalent, so they are drawn as triangle lists.
+fn primitive_topology(ty: D3DPRIMITIVETYPE, count: u32) -> Option<(D3D11_PRIMITIVE_TOPOLOGY, u32)> {
+    match ty {
+        D3DPT_POINTLIST => Some((D3D11_PRIMITIVE_TOPOLOGY_POINTLIST, count)),
+        D3DPT_LINELIST => Some((D3D11_PRIMITIVE_TOPOLOGY_LINELIST, count * 2)),
+        D3DPT_LINESTRIP => Some((D3D11_PRIMITIVE_TOPOLOGY_LINESTRIP, count + 1)),
+        D3DPT_TRIANGLELIST => Some((D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST, count * 3)),
+        D3DPT_TRIANGLESTRIP => Some((D3D11_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP, count + 2)),
+        D3DPT_TRIANGLEFAN => Some((D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST, count + 2)),
+        _ => None,
     }
-    fn set_light() {
-        unimplemented!()
+}
+
+/// Returns the DXGI format of an index buffer's D3D9 format.
+fn index_format(fmt: D3DFORMAT) -> DXGI_FORMAT {
+    if fmt == D3DFMT_INDEX32 {
+        DXGI_FORMAT_R32_UINT
+    } else {
+        DXGI_FORMAT_R16_UINT
     }
-    fn set_n_patch_mode() {
-        unimplemented!()
+}
+
+/// Converts the indices of a triangle fan to the ones of the equivalent triangle list.
+fn fan_to_list(fan: &[u32]) -> Vec<u32> {
+    let mut list = Vec::with_capacity(fan.len().saturating_sub(2) * 3);
+
+    if let Some((&center, rest)) = fan.split_first() {
+        for edge in rest.windows(2) {
+            list.extend_from_slice(&[center, edge[0], edge[1]]);
+        }
     }
-    fn set_palette_entries() {
-        unimplemented!()
+
+    list
+}
+
+/// Reads indices the app passed in one of the index formats.
+fn read_indices(data: &[u8], fmt: D3DFORMAT) -> Vec<u32> {
+    if fmt == D3DFMT_INDEX32 {
+        data.chunks(4)
+            .filter(|index| index.len() == 4)
+            .map(|index| unsafe { ptr::read_unaligned(index.as_ptr() as *const u32) })
+            .collect()
+    } else {
+        data.chunks(2)
+            .filter(|index| index.len() == 2)
+            .map(|index| unsafe { u32::from(ptr::read_unaligned(index.as_ptr() as *const u16)) })
+            .collect()
     }
-    fn set_software_vertex_processing() {
-        unimplemented!()
+}
+
+/// Views memory the app passed to a draw call as bytes.
+///
+/// A null pointer is viewed as no data.
+unsafe fn user_data<'a>(data: *const c_void, len: usize) -> &'a [u8] {
+    if data.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(data as *const u8, len)
     }
 }
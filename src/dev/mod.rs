@@ -23,3 +23,18 @@ pub use self::shader::*;
 
 mod buffer;
 pub use self::buffer::*;
+
+mod query;
+pub use self::query::*;
+
+mod cursor;
+pub use self::cursor::*;
+
+mod validation;
+pub use self::validation::*;
+
+mod stats;
+pub use self::stats::*;
+
+mod pacing;
+pub use self::pacing::*;
@@ -0,0 +1,201 @@
+//! Control over the rate at which frames are presented.
+//!
+//! Both limits are set by the user, and apply regardless of the presentation interval
+//! the app requested.
+
+use std::time::{Duration, Instant};
+use std::{mem, thread};
+
+use winapi::shared::minwindef::BOOL;
+use winapi::um::d3d11::D3D11_QUERY_EVENT;
+
+use crate::backend::{Backend, Query};
+use crate::Result;
+
+/// How long before a deadline we stop sleeping, and start spinning.
+///
+/// Sleeping is only as precise as the OS scheduler's timer, which is often a millisecond or worse.
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+/// Caps the frame rate, by waiting before each frame is presented.
+///
+/// Some older games tie their physics to the frame rate, and break when it is too high.
+pub struct FrameLimiter {
+    // Time between two frames.
+    interval: Duration,
+    // When the previous frame was allowed to be presented.
+    last: Option<Instant>,
+}
+
+impl FrameLimiter {
+    /// Creates a limiter for a certain number of frames per second.
+    pub fn new(fps: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / fps,
+            last: None,
+        }
+    }
+
+    /// Waits until the next frame can be presented.
+    pub fn wait(&mut self) {
+        let deadline = self.next_deadline(Instant::now());
+
+        loop {
+            let now = Instant::now();
+
+            if now >= deadline {
+                break;
+            }
+
+            let remaining = deadline - now;
+
+            if remaining > SPIN_THRESHOLD {
+                thread::sleep(remaining - SPIN_THRESHOLD);
+            } else {
+                thread::yield_now();
+            }
+        }
+
+        self.last = Some(deadline);
+    }
+
+    /// Determines when the next frame can be presented.
+    fn next_deadline(&self, now: Instant) -> Instant {
+        let next = match self.last {
+            Some(last) => last + self.interval,
+            None => return now,
+        };
+
+        // If the game could not keep up, we start counting from the current frame,
+        // instead of letting it present the next frames quicker to catch up.
+        if now > next + self.interval {
+            now
+        } else {
+            next
+        }
+    }
+}
+
+/// Prevents the CPU from getting too many frames ahead of the GPU.
+///
+/// An event query is issued after each frame is presented. Once there are as many queries
+/// in flight as the maximum latency, we wait for the oldest one to complete.
+pub struct LatencyLimiter {
+    queries: Vec<Query>,
+    // Index of the query which is issued after the next frame.
+    next: usize,
+    // Number of queries which were issued at least once.
+    issued: usize,
+}
+
+impl LatencyLimiter {
+    /// Creates a limiter allowing up to `frames` frames to be queued.
+    pub fn new(backend: &dyn Backend, frames: u32) -> Result<Self> {
+        let queries = (0..frames)
+            .map(|_| backend.create_query(D3D11_QUERY_EVENT))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            queries,
+            next: 0,
+            issued: 0,
+        })
+    }
+
+    /// Marks the end of a frame, and waits until the GPU is close enough to catching up.
+    pub fn end_frame(&mut self, backend: &dyn Backend) -> Result<()> {
+        let count = self.queries.len();
+
+        backend.end_query(&self.queries[self.next]);
+
+        self.next = (self.next + 1) % count;
+        self.issued = (self.issued + 1).min(count);
+
+        // The next query to be reused is the oldest one in flight.
+        if self.issued == count {
+            self.wait(backend, &self.queries[self.next])?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits until the GPU has reached a query.
+    fn wait(&self, backend: &dyn Backend, query: &Query) -> Result<()> {
+        // The first poll flushes the commands, in case they were not submitted yet.
+        let mut flush = true;
+        let mut done = [0; mem::size_of::<BOOL>()];
+
+        while !backend.query_data(query, &mut done, flush)? {
+            thread::yield_now();
+            flush = false;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{Call, MockBackend};
+
+    #[test]
+    fn deadlines() {
+        let mut limiter = FrameLimiter::new(50);
+        let interval = Duration::from_millis(20);
+        let start = Instant::now();
+
+        // The first frame never waits.
+        assert_eq!(limiter.next_deadline(start), start);
+        limiter.last = Some(start);
+
+        // Frames which are quicker than the limit wait for the rest of the interval.
+        let now = start + Duration::from_millis(5);
+        assert_eq!(limiter.next_deadline(now), start + interval);
+
+        // Frames which are a bit slower than the limit are not delayed further.
+        let now = start + Duration::from_millis(30);
+        assert_eq!(limiter.next_deadline(now), start + interval);
+
+        // Frames which are much slower start a new schedule.
+        let now = start + Duration::from_millis(100);
+        assert_eq!(limiter.next_deadline(now), now);
+    }
+
+    #[test]
+    fn waiting() {
+        let mut limiter = FrameLimiter::new(200);
+        let start = Instant::now();
+
+        for _ in 0..5 {
+            limiter.wait();
+        }
+
+        // The first frame is immediate, the other four are 5 ms apart.
+        assert!(Instant::now() - start >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn latency_queries() {
+        let backend = MockBackend::new();
+        let mut limiter = LatencyLimiter::new(&backend, 2).unwrap();
+
+        for _ in 0..3 {
+            limiter.end_frame(&backend).unwrap();
+        }
+
+        // The queries are issued in turns, and reused once the GPU reached them.
+        let ends: Vec<_> = backend
+            .take_calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::EndQuery { id } => Some(id),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(ends.len(), 3);
+        assert_ne!(ends[0], ends[1]);
+        assert_eq!(ends[0], ends[2]);
+    }
+}
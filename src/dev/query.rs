@@ -0,0 +1,249 @@
+use std::{
+    mem, ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use winapi::ctypes::c_void;
+use winapi::shared::{d3d9::*, d3d9types::*, minwindef::BOOL};
+use winapi::um::d3d11::*;
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+
+use com_impl::{implementation, interface, ComInterface};
+use comptr::ComPtr;
+
+use crate::backend;
+use crate::core::*;
+use crate::{Error, Result};
+
+use super::{Device, Diagnostic};
+
+/// Vertex cache information we report to the app.
+///
+/// Modern GPUs don't have a fixed size post-transform cache,
+/// so we return the values other drivers commonly report.
+const VCACHE_INFO: D3DDEVINFO_VCACHE = D3DDEVINFO_VCACHE {
+    Pattern: 0x4843_4143, // 'CACH'
+    OptMethod: 1,
+    CacheSize: 24,
+    MagicNumber: 20,
+};
+
+/// Asynchronous query, used to retrieve information from the GPU
+/// without stalling the pipeline.
+#[interface(IDirect3DQuery9)]
+pub struct Query {
+    refs: AtomicU32,
+    // Parent device of this interface.
+    device: *const Device,
+    // The D3D9 type of this query.
+    ty: D3DQUERYTYPE,
+    // The underlying backend query, if this type needs one.
+    query: Option<backend::Query>,
+}
+
+impl Query {
+    /// Creates a new query of a certain type.
+    pub fn new(device: &Device, ty: D3DQUERYTYPE) -> Result<ComPtr<Self>> {
+        let query = match Self::d3d11_query_type(ty)? {
+            Some(query_ty) => Some(device.backend().create_query(query_ty)?),
+            None => None,
+        };
+
+        let query = Self {
+            __vtable: Box::new(Self::create_vtable()),
+            refs: AtomicU32::new(1),
+            device,
+            ty,
+            query,
+        };
+
+        Ok(unsafe { new_com_interface(query) })
+    }
+
+    /// Checks if a certain type of query is supported.
+    pub fn is_supported(ty: D3DQUERYTYPE) -> bool {
+        Self::d3d11_query_type(ty).is_ok()
+    }
+
+    /// Determines which D3D11 query is used to implement a D3D9 query type.
+    ///
+    /// Returns `None` for queries which we can answer without the GPU.
+    fn d3d11_query_type(ty: D3DQUERYTYPE) -> Result<Option<D3D11_QUERY>> {
+        Ok(Some(match ty {
+            D3DQUERYTYPE_EVENT => D3D11_QUERY_EVENT,
+            D3DQUERYTYPE_OCCLUSION => D3D11_QUERY_OCCLUSION,
+            D3DQUERYTYPE_TIMESTAMP => D3D11_QUERY_TIMESTAMP,
+            // Both of these are answered by the same D3D11 query.
+            D3DQUERYTYPE_TIMESTAMPDISJOINT | D3DQUERYTYPE_TIMESTAMPFREQ => {
+                D3D11_QUERY_TIMESTAMP_DISJOINT
+            }
+            D3DQUERYTYPE_VCACHE => return Ok(None),
+            _ => return Err(Error::NotAvailable),
+        }))
+    }
+
+    /// Returns the size of the data returned by this query.
+    fn data_size(&self) -> u32 {
+        let size = match self.ty {
+            D3DQUERYTYPE_EVENT => mem::size_of::<BOOL>(),
+            D3DQUERYTYPE_OCCLUSION => mem::size_of::<u32>(),
+            D3DQUERYTYPE_TIMESTAMP => mem::size_of::<u64>(),
+            D3DQUERYTYPE_TIMESTAMPDISJOINT => mem::size_of::<BOOL>(),
+            D3DQUERYTYPE_TIMESTAMPFREQ => mem::size_of::<u64>(),
+            D3DQUERYTYPE_VCACHE => mem::size_of::<D3DDEVINFO_VCACHE>(),
+            _ => unreachable!(),
+        };
+
+        size as u32
+    }
+
+    /// Determines if this type of query is issued in pairs of begin / end calls.
+    fn has_begin(&self) -> bool {
+        match self.ty {
+            D3DQUERYTYPE_OCCLUSION | D3DQUERYTYPE_TIMESTAMPDISJOINT => true,
+            _ => false,
+        }
+    }
+
+    /// Reads the query's data, in the format D3D11 returns it.
+    ///
+    /// Returns `Error::False` if the data is not yet available.
+    fn read<T>(&self, query: &backend::Query, flush: bool) -> Result<T> {
+        let backend = unsafe { &*self.device }.backend();
+
+        unsafe {
+            let mut data: T = mem::zeroed();
+            let bytes =
+                std::slice::from_raw_parts_mut(&mut data as *mut T as *mut u8, mem::size_of::<T>());
+
+            if backend.query_data(query, bytes, flush)? {
+                Ok(data)
+            } else {
+                Err(Error::False)
+            }
+        }
+    }
+}
+
+impl_iunknown!(struct Query: IUnknown, IDirect3DQuery9);
+
+#[implementation(IDirect3DQuery9)]
+impl Query {
+    /// Retrieves the device which created this query.
+    fn get_device(&self, ret: *mut *mut Device) -> Error {
+        guarded!("IDirect3DQuery9::GetDevice", {
+            let ret = check_mut_ref(ret)?;
+            *ret = com_ref(self.device);
+            Error::Success
+        })
+    }
+
+    /// Returns the type of this query.
+    fn get_type(&self) -> D3DQUERYTYPE {
+        self.ty
+    }
+
+    /// Returns the size of the data this query returns.
+    fn get_data_size(&self) -> u32 {
+        self.data_size()
+    }
+
+    /// Marks the beginning or the end of the commands this query applies to.
+    fn issue(&self, flags: u32) -> Error {
+        traced!(self, "IDirect3DQuery9::Issue", [flags], {
+            let device = unsafe { &*self.device };
+
+            // Queries such as events and timestamps only have an end.
+            if flags & D3DISSUE_BEGIN != 0 && !self.has_begin() {
+                let message = format!(
+                    "must not contain D3DISSUE_BEGIN, this query type ({}) only has an end",
+                    self.ty
+                );
+                let diag = Diagnostic::new("IDirect3DQuery9::Issue", "dwIssueFlags", message);
+                return device.reject(diag);
+            }
+
+            let query = match self.query {
+                Some(ref query) => query,
+                None => return Error::Success,
+            };
+
+            let backend = device.backend();
+
+            if flags & D3DISSUE_BEGIN != 0 {
+                backend.begin_query(query);
+            } else if flags & D3DISSUE_END != 0 {
+                // The frequency query needs a begin in D3D11, but not in D3D9.
+                if self.ty == D3DQUERYTYPE_TIMESTAMPFREQ {
+                    backend.begin_query(query);
+                }
+
+                backend.end_query(query);
+            } else {
+                return Error::InvalidCall;
+            }
+
+            Error::Success
+        })
+    }
+
+    /// Retrieves the results of this query.
+    fn get_data(&self, data: *mut c_void, size: u32, flags: u32) -> Error {
+        guarded!("IDirect3DQuery9::GetData", {
+            if !data.is_null() && size < self.data_size() {
+                return Error::InvalidCall;
+            }
+
+            let flush = flags & D3DGETDATA_FLUSH != 0;
+
+            // Avoid writing anything if the app only wants to poll the query.
+            let write = |value: &[u8]| unsafe {
+                if !data.is_null() && size != 0 {
+                    ptr::copy_nonoverlapping(value.as_ptr(), data as *mut u8, value.len());
+                }
+            };
+
+            let query = match self.query {
+                Some(ref query) => query,
+                None => {
+                    let info = VCACHE_INFO;
+                    write(as_bytes(&info));
+                    return Error::Success;
+                }
+            };
+
+            match self.ty {
+                D3DQUERYTYPE_EVENT => {
+                    let done: BOOL = self.read(query, flush)?;
+                    write(as_bytes(&done));
+                }
+                D3DQUERYTYPE_OCCLUSION => {
+                    // D3D9 only has 32 bits for the number of samples.
+                    let samples: u64 = self.read(query, flush)?;
+                    let samples = samples as u32;
+                    write(as_bytes(&samples));
+                }
+                D3DQUERYTYPE_TIMESTAMP => {
+                    let timestamp: u64 = self.read(query, flush)?;
+                    write(as_bytes(&timestamp));
+                }
+                D3DQUERYTYPE_TIMESTAMPDISJOINT => {
+                    let result: D3D11_QUERY_DATA_TIMESTAMP_DISJOINT = self.read(query, flush)?;
+                    write(as_bytes(&result.Disjoint));
+                }
+                D3DQUERYTYPE_TIMESTAMPFREQ => {
+                    let result: D3D11_QUERY_DATA_TIMESTAMP_DISJOINT = self.read(query, flush)?;
+                    write(as_bytes(&result.Frequency));
+                }
+                _ => unreachable!(),
+            }
+
+            Error::Success
+        })
+    }
+}
+
+/// Reinterprets a value as a slice of bytes.
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+}
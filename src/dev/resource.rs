@@ -1,13 +1,22 @@
-use winapi::shared::d3d9::*;
-use winapi::um::unknwnbase::IUnknownVtbl;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+use std::{cell::RefCell, cmp, slice};
+
+use winapi::shared::{d3d9::*, d3d9types::D3DLOCKED_RECT};
+use winapi::um::{
+    d3d11::{D3D11_MAP, D3D11_MAP_READ},
+    unknwnbase::IUnknownVtbl,
+};
 
 use com_impl::{implementation, ComInterface};
 
-use crate::core::*;
-use crate::d3d11;
-use crate::Error;
+use crate::backend::{self, Buffer, Texture};
+use crate::core::{fmt::dxgi_format_info, *};
+use crate::{trace, Error, Result};
 
-use super::Device;
+use super::{Device, Diagnostic};
 
 /// Structure used as the base for all the D3D9 device resources.
 /// Use the `impl_resource` macro to implement its functions in inherited classes.
@@ -23,6 +32,23 @@ pub struct Resource {
     /// Priority of this resource.
     /// Higher value indicates this resource should be evicted last from VRAM.
     priority: u32,
+    /// If this resource is counted by the device as an outstanding default pool resource,
+    /// which must be released before the device is reset, the device's counter.
+    tracker: Option<Arc<AtomicU32>>,
+    /// Whether the app is allowed to lock this resource.
+    lockable: bool,
+    /// The subresources which are currently locked.
+    /// Only kept while tracing, to record their contents when they are unlocked,
+    /// or while validating calls, to catch overlapping locks.
+    locks: RefCell<Vec<Lock>>,
+}
+
+/// Memory of a locked subresource.
+struct Lock {
+    subresource: u32,
+    data: *const u8,
+    row_pitch: u32,
+    size: usize,
 }
 
 impl Resource {
@@ -33,25 +59,55 @@ impl Resource {
         pool: MemoryPool,
         ty: ResourceType,
     ) -> Self {
+
+        // Textures and surfaces in video memory can only be locked if they are dynamic,
+        // or if they were explicitly created as lockable.
+        let lockable = match ty {
+            ResourceType::VertexBuffer | ResourceType::IndexBuffer => true,
+            _ => pool != MemoryPool::Default || usage.intersects(UsageFlags::DYNAMIC),
+        };
+
+        let tracker = if pool == MemoryPool::Default {
+            Some(unsafe { &*device }.track_resource())
+        } else {
+            None
+        };
+
         Self {
             device,
             usage,
             pool,
             ty,
             priority: 0,
+            tracker,
+            lockable,
+            locks: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Marks this resource as being owned by the device,
+    /// meaning the app does not have to release it before resetting the device.
+    ///
+    /// Used for the implicit render target, depth / stencil buffer and back buffers.
+    pub fn make_implicit(&mut self) {
+        if let Some(tracker) = self.tracker.take() {
+            tracker.fetch_sub(1, Ordering::SeqCst);
         }
     }
 
+    /// Allows the app to lock this resource, even though it is in video memory.
+    ///
+    /// Used for off-screen plain surfaces, lockable render targets and back buffers,
+    /// and depth / stencil buffers with lockable formats.
+    pub fn make_lockable(&mut self) {
+        self.lockable = true;
+    }
+
     /// Returns the parent device of this resource.
     pub fn device(&self) -> &Device {
         unsafe { &*self.device }
     }
 
-    /// Retrieves the immediate device context of the parent device.
-    pub fn device_context(&self) -> &d3d11::DeviceContext {
-        self.device().device_context()
-    }
-
     /// Retrieves the usage flags of this resource.
     pub fn usage(&self) -> UsageFlags {
         self.usage
@@ -61,6 +117,206 @@ impl Resource {
     pub fn pool(&self) -> MemoryPool {
         self.pool
     }
+
+    /// Adjusts the flags of a lock according to the workarounds enabled for the app.
+    fn lock_map_type(&self, mut flags: LockFlags) -> (D3D11_MAP, u32) {
+        let workarounds = &self.device().config().workarounds;
+
+        if workarounds.ignore_do_not_wait {
+            flags.remove(LockFlags::DO_NOT_WAIT);
+        }
+
+        if workarounds.force_discard_locks && self.usage.intersects(UsageFlags::DYNAMIC) {
+            let keep = LockFlags::READ_ONLY | LockFlags::NO_OVERWRITE | LockFlags::DISCARD;
+            if !flags.intersects(keep) {
+                flags.insert(LockFlags::DISCARD);
+            }
+        }
+
+        backend::lock_to_map(flags, self.usage)
+    }
+
+    /// Maps a subresource of a texture owned by this resource to memory.
+    pub fn map_texture(
+        &self,
+        texture: &Texture,
+        subres: u32,
+        flags: LockFlags,
+    ) -> Result<D3DLOCKED_RECT> {
+        let (map_type, map_flags) = self.lock_map_type(flags);
+
+        let mapped = self
+            .device()
+            .backend()
+            .map_texture(texture, subres, map_type, map_flags)?;
+
+        // TODO: we need special handling for pitch with DXT texture formats.
+
+        let desc = self.device().backend().texture_desc(texture);
+        let level = subres % desc.MipLevels;
+        let height = cmp::max(desc.Height >> level, 1);
+        let rows = dxgi_format_info(desc.Format).map_or(height, |info| info.row_count(height));
+
+        self.count_lock(map_type, mapped.RowPitch * rows);
+
+        if self.keep_locks() {
+            self.add_lock(subres, mapped.pData as *const u8, mapped.RowPitch, rows);
+        }
+
+        Ok(D3DLOCKED_RECT {
+            Pitch: mapped.RowPitch as i32,
+            pBits: mapped.pData,
+        })
+    }
+
+    /// Unmaps a subresource of a texture owned by this resource.
+    pub fn unmap_texture(&self, texture: &Texture, subres: u32) {
+        self.device().backend().unmap_texture(texture, subres);
+    }
+
+    /// Maps a buffer owned by this resource to memory.
+    pub fn map_buffer(&self, buffer: &Buffer, flags: LockFlags) -> Result<*mut u8> {
+        let (map_type, map_flags) = self.lock_map_type(flags);
+
+        let mapped = self
+            .device()
+            .backend()
+            .map_buffer(buffer, map_type, map_flags)?;
+
+        let size = self.device().backend().buffer_desc(buffer).ByteWidth;
+
+        self.count_lock(map_type, size);
+
+        if self.keep_locks() {
+            self.add_lock(0, mapped.pData as *const u8, size, 1);
+        }
+
+        Ok(mapped.pData as *mut u8)
+    }
+
+    /// Unmaps a buffer owned by this resource.
+    pub fn unmap_buffer(&self, buffer: &Buffer) {
+        self.device().backend().unmap_buffer(buffer);
+    }
+
+    /// Adds a lock of `size` bytes to the statistics of the current frame.
+    fn count_lock(&self, map_type: D3D11_MAP, size: u32) {
+        self.device().count(|stats| {
+            stats.locks += 1;
+
+            if map_type != D3D11_MAP_READ {
+                stats.bytes_uploaded += u64::from(size);
+            }
+        });
+    }
+
+    /// Checks if the locked subresources have to be remembered.
+    fn keep_locks(&self) -> bool {
+        trace::tracer().is_some() || self.device().config().strict_validation
+    }
+
+    /// Checks if the app is allowed to lock a subresource.
+    ///
+    /// `argument` is the name of the method's argument which selects the subresource, if any.
+    pub fn validate_lock(
+        &self,
+        method: &'static str,
+        argument: Option<&'static str>,
+        subresource: u32,
+    ) -> Result<()> {
+        self.device().validate(|| {
+            let message = if !self.lockable {
+                format!(
+                    "{:?} resources in the default pool are not lockable, unless they are dynamic",
+                    self.ty
+                )
+            } else if self.is_locked(subresource) {
+                format!("subresource {} is already locked", subresource)
+            } else {
+                return None;
+            };
+
+            Some(Diagnostic {
+                method,
+                argument,
+                message,
+            })
+        })
+    }
+
+    /// Checks if the app is allowed to unlock a subresource.
+    ///
+    /// Must be called before the lock's contents are retrieved.
+    pub fn validate_unlock(
+        &self,
+        method: &'static str,
+        argument: Option<&'static str>,
+        subresource: u32,
+    ) -> Result<()> {
+        self.device().validate(|| {
+            if self.is_locked(subresource) {
+                return None;
+            }
+
+            Some(Diagnostic {
+                method,
+                argument,
+                message: format!("subresource {} is not locked", subresource),
+            })
+        })
+    }
+
+    /// Checks if a subresource is currently locked.
+    fn is_locked(&self, subresource: u32) -> bool {
+        self.locks
+            .borrow()
+            .iter()
+            .any(|lock| lock.subresource == subresource)
+    }
+
+    /// Remembers the memory of a locked subresource.
+    fn add_lock(&self, subresource: u32, data: *const u8, row_pitch: u32, rows: u32) {
+        self.locks.borrow_mut().push(Lock {
+            subresource,
+            data,
+            row_pitch,
+            size: (row_pitch * rows) as usize,
+        });
+    }
+
+    /// Copies the contents of a locked subresource, before it is unlocked.
+    ///
+    /// Returns the row pitch and the data, or no data if calls are not being traced.
+    pub fn locked_contents(&self, subresource: u32) -> (u32, Vec<u8>) {
+        let tracing = trace::tracer().is_some();
+
+        let mut locks = self.locks.borrow_mut();
+        let index = locks
+            .iter()
+            .position(|lock| lock.subresource == subresource);
+
+        match index {
+            Some(index) if tracing => {
+                let lock = locks.remove(index);
+                let data = unsafe { slice::from_raw_parts(lock.data, lock.size) };
+                (lock.row_pitch, data.to_vec())
+            }
+            Some(index) => {
+                locks.remove(index);
+                (0, Vec::new())
+            }
+            None => (0, Vec::new()),
+        }
+    }
+}
+
+impl Drop for Resource {
+    fn drop(&mut self) {
+        // The device might have been released already, so it must not be accessed here.
+        if let Some(tracker) = self.tracker.take() {
+            tracker.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
 }
 
 impl ComInterface<IUnknownVtbl> for Resource {
@@ -98,21 +354,23 @@ impl Resource {
 
     /// Returns the parent device.
     fn get_device(self: &Thunk, ret: *mut *mut Device) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = com_ref(self.device);
-        Error::Success
+        guarded!("IDirect3DResource9::GetDevice", {
+            let ret = check_mut_ref(ret)?;
+            *ret = com_ref(self.device);
+            Error::Success
+        })
     }
 
-    fn set_private_data(self: &Thunk) {
-        unimplemented!()
+    fn set_private_data(self: &Thunk) -> Error {
+        unimplemented_method!("IDirect3DResource9::SetPrivateData")
     }
 
-    fn get_private_data(self: &Thunk) {
-        unimplemented!()
+    fn get_private_data(self: &Thunk) -> Error {
+        unimplemented_method!("IDirect3DResource9::GetPrivateData")
     }
 
-    fn free_private_data(self: &Thunk) {
-        unimplemented!()
+    fn free_private_data(self: &Thunk) -> Error {
+        unimplemented_method!("IDirect3DResource9::FreePrivateData")
     }
 
     // TODO: the functions below could be used to improve performance.
@@ -131,6 +389,8 @@ impl Resource {
 
     /// Pre loads resource to VRAM.
     fn pre_load(self: &Thunk) {
-        info!("Resource pre-loading is not yet implemented");
+        guarded!("IDirect3DResource9::PreLoad", {
+            info!("Resource pre-loading is not yet implemented");
+        })
     }
 }
@@ -12,9 +12,13 @@ use crate::{core::*, Error, Result};
 use super::Device;
 
 /// Given a pointer to an array of tokens (forming up a shader),
-/// returns a box containing the tokens.
-fn tokens_to_box(tokens: *const u32) -> Box<[u32]> {
-    let tokens = unsafe {
+/// returns a slice containing all the tokens.
+pub fn shader_tokens<'a>(tokens: *const u32) -> &'a [u32] {
+    if tokens.is_null() {
+        return &[];
+    }
+
+    unsafe {
         // We don't know how long the shader will be.
         let mut len = 0;
 
@@ -29,10 +33,8 @@ fn tokens_to_box(tokens: *const u32) -> Box<[u32]> {
         // Ensure the end token is included.
         len += 1;
 
-        std::slice::from_raw_parts(tokens, len as usize)
-    };
-
-    tokens.into()
+        slice::from_raw_parts(tokens, len as usize)
+    }
 }
 
 macro_rules! impl_shader {
@@ -41,26 +43,30 @@ macro_rules! impl_shader {
         impl $name {
             /// Retrieves the device which created this shader.
             fn get_device(&self, ret: *mut *mut Device) -> Error {
-                let ret = check_mut_ref(ret)?;
-                *ret = com_ref(self.device);
-                Error::Success
+                guarded!(concat!(stringify!($iface), "::GetDevice"), {
+                    let ret = check_mut_ref(ret)?;
+                    *ret = com_ref(self.device);
+                    Error::Success
+                })
             }
 
             /// Retrieve the shader's byte code.
             fn get_function(&self, ret: *mut u32, num: *mut u32) -> Error {
-                if ret.is_null() {
-                    let num = check_mut_ref(num)?;
-
-                    *num = self.code.len() as u32;
-                } else {
-                    let code = unsafe {
-                        let ret = check_mut_ref(ret)?;
-                        slice::from_raw_parts_mut(ret, self.code.len())
-                    };
-
-                    code.copy_from_slice(&self.code);
-                }
-                Error::Success
+                guarded!(concat!(stringify!($iface), "::GetFunction"), {
+                    if ret.is_null() {
+                        let num = check_mut_ref(num)?;
+
+                        *num = self.code.len() as u32;
+                    } else {
+                        let code = unsafe {
+                            let ret = check_mut_ref(ret)?;
+                            slice::from_raw_parts_mut(ret, self.code.len())
+                        };
+
+                        code.copy_from_slice(&self.code);
+                    }
+                    Error::Success
+                })
             }
         }
     };
@@ -77,7 +83,7 @@ pub struct VertexShader {
 impl VertexShader {
     /// Create a new vertex shader.
     pub fn new(device: &Device, func: *const u32) -> Result<ComPtr<Self>> {
-        let code = tokens_to_box(func);
+        let code = shader_tokens(func).into();
 
         let vs = Self {
             __vtable: Box::new(Self::create_vtable()),
@@ -88,6 +94,11 @@ impl VertexShader {
 
         Ok(unsafe { new_com_interface(vs) })
     }
+
+    /// Retrieves the shader's byte code.
+    pub fn code(&self) -> &[u32] {
+        &self.code
+    }
 }
 
 impl_iunknown!(struct VertexShader: IUnknown, IDirect3DVertexShader9);
@@ -105,7 +116,7 @@ pub struct PixelShader {
 impl PixelShader {
     /// Create a new pixel shader.
     pub fn new(device: &Device, func: *const u32) -> Result<ComPtr<Self>> {
-        let code = tokens_to_box(func);
+        let code = shader_tokens(func).into();
 
         let ps = Self {
             __vtable: Box::new(Self::create_vtable()),
@@ -122,6 +133,43 @@ impl_iunknown!(struct PixelShader: IUnknown, IDirect3DPixelShader9);
 
 impl_shader!(PixelShader, IDirect3DPixelShader9);
 
+/// Given a pointer to an array of vertex elements terminated by `D3DDECL_END`,
+/// returns a slice containing the elements before the terminator.
+pub fn declaration_elements<'a>(elems: *const D3DVERTEXELEMENT9) -> &'a [D3DVERTEXELEMENT9] {
+    if elems.is_null() {
+        return &[];
+    }
+
+    unsafe {
+        let mut count = 0;
+        let mut ptr = elems;
+
+        fn is_end(ve: D3DVERTEXELEMENT9) -> bool {
+            let end = D3DDECL_END;
+            ve.Stream == end.Stream
+                && ve.Offset == end.Offset
+                && ve.Type == end.Type
+                && ve.Method == end.Method
+                && ve.Usage == end.Usage
+                && ve.UsageIndex == end.UsageIndex
+        }
+
+        // Input is a variable-length array terminated by END.
+        while !is_end(*ptr) {
+            ptr = ptr.offset(1);
+            count += 1;
+
+            // It's possible some apps forgot the terminator, in which case
+            // we try to avoid looping forever.
+            if count == 64 {
+                error!("Maximum vertex elements reached, but no terminator found.")
+            }
+        }
+
+        slice::from_raw_parts(elems, count)
+    }
+}
+
 /// Declaration of a vertex shader's inputs.
 #[interface(IDirect3DVertexDeclaration9)]
 pub struct VertexDeclaration {
@@ -133,36 +181,7 @@ pub struct VertexDeclaration {
 impl VertexDeclaration {
     /// Creates a new vertex declaration.
     pub fn new(device: &Device, elems: *const D3DVERTEXELEMENT9) -> ComPtr<Self> {
-        let elems = unsafe {
-            let mut count = 0;
-            let mut ptr = elems;
-
-            fn is_end(ve: D3DVERTEXELEMENT9) -> bool {
-                let end = D3DDECL_END;
-                ve.Stream == end.Stream
-                    && ve.Offset == end.Offset
-                    && ve.Type == end.Type
-                    && ve.Method == end.Method
-                    && ve.Usage == end.Usage
-                    && ve.UsageIndex == end.UsageIndex
-            }
-
-            // Input is a variable-length array terminated by END.
-            while !is_end(*ptr) {
-                ptr = ptr.offset(1);
-                count += 1;
-
-                // It's possible some apps forgot the terminator, in which case
-                // we try to avoid looping forever.
-                if count == 64 {
-                    error!("Maximum vertex elements reached, but no terminator found.")
-                }
-            }
-
-            let elems = slice::from_raw_parts(elems, count);
-
-            Box::from(elems)
-        };
+        let elems = declaration_elements(elems).into();
 
         let vd = Self {
             __vtable: Box::new(Self::create_vtable()),
@@ -173,6 +192,11 @@ impl VertexDeclaration {
 
         unsafe { new_com_interface(vd) }
     }
+
+    /// Retrieves the elements which make up this declaration.
+    pub fn elements(&self) -> &[D3DVERTEXELEMENT9] {
+        &self.elems
+    }
 }
 
 impl_iunknown!(struct VertexDeclaration: IUnknown, IDirect3DVertexDeclaration9);
@@ -181,26 +205,30 @@ impl_iunknown!(struct VertexDeclaration: IUnknown, IDirect3DVertexDeclaration9);
 impl VertexDeclaration {
     /// Retrieves the device which owns this vertex declaration.
     fn get_device(&self, ret: *mut *mut Device) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = com_ref(self.device);
-        Error::Success
+        guarded!("IDirect3DVertexDeclaration9::GetDevice", {
+            let ret = check_mut_ref(ret)?;
+            *ret = com_ref(self.device);
+            Error::Success
+        })
     }
 
     /// Retrieves the elements which make up this declaration.
     fn get_declaration(&self, elems: *mut D3DVERTEXELEMENT9, num: *mut u32) -> Error {
-        if elems.is_null() {
-            let num = check_mut_ref(num)?;
-
-            *num = self.elems.len() as u32;
-        } else {
-            let elems = unsafe {
-                let elems = check_mut_ref(elems)?;
-                slice::from_raw_parts_mut(elems, self.elems.len())
-            };
-
-            elems.copy_from_slice(&self.elems);
-        }
+        guarded!("IDirect3DVertexDeclaration9::GetDeclaration", {
+            if elems.is_null() {
+                let num = check_mut_ref(num)?;
+
+                *num = self.elems.len() as u32;
+            } else {
+                let elems = unsafe {
+                    let elems = check_mut_ref(elems)?;
+                    slice::from_raw_parts_mut(elems, self.elems.len())
+                };
+
+                elems.copy_from_slice(&self.elems);
+            }
 
-        Error::Success
+            Error::Success
+        })
     }
 }
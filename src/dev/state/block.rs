@@ -25,7 +25,7 @@ impl StateBlock {
             device,
         };
 
-        unimplemented!();
+        unimplemented_method!("IDirect3DDevice9::CreateStateBlock")
     }
 }
 
@@ -35,18 +35,24 @@ impl_iunknown!(struct StateBlock: IUnknown, IDirect3DStateBlock9);
 impl StateBlock {
     /// Retrieves the device which owns this tate block.
     fn get_device(&self, ret: *mut *mut Device) -> Error {
-        let ret = check_mut_ref(ret)?;
-        *ret = com_ref(self.device);
-        Error::Success
+        guarded!("IDirect3DStateBlock9::GetDevice", {
+            let ret = check_mut_ref(ret)?;
+            *ret = com_ref(self.device);
+            Error::Success
+        })
     }
 
     /// Captures the current values for the state which is already in this block.
     fn capture(&mut self) -> Error {
-        unimplemented!()
+        traced!(self, "IDirect3DStateBlock9::Capture", [], {
+            unimplemented_method!("IDirect3DStateBlock9::Capture")
+        })
     }
 
     /// Applies the contained state to the parent device.
     fn apply(&self) -> Error {
-        unimplemented!()
+        traced!(self, "IDirect3DStateBlock9::Apply", [], {
+            unimplemented_method!("IDirect3DStateBlock9::Apply")
+        })
     }
 }
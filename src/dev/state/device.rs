@@ -5,6 +5,7 @@ use winapi::shared::d3d9types::*;
 
 use nalgebra::{self as na, Matrix4};
 
+use crate::core::fmt::{format_info, Swizzle};
 use crate::dev::shader::VertexDeclaration;
 use crate::dev::*;
 
@@ -19,6 +20,11 @@ pub struct DeviceState {
     vertex: VertexState,
     pixel: PixelState,
     textures: [*mut BaseTexture; 20],
+    // The swizzle which has to be applied when sampling each bound texture.
+    swizzles: [Swizzle; 20],
+    // The vertex buffer bound to each stream, with the offset and stride of its vertices.
+    streams: [(*mut VertexBuffer, u32, u32); 16],
+    indices: *mut IndexBuffer,
     viewport: D3DVIEWPORT9,
     transforms: HashMap<D3DTRANSFORMSTATETYPE, Matrix4<f32>>,
     material: D3DMATERIAL9,
@@ -88,6 +94,16 @@ impl DeviceState {
         if let Some(tx) = self.textures.get_mut(stage as usize) {
             *tx = texture;
         }
+
+        // Some formats have to be emulated by remapping the channels returned by the sampler.
+        let swizzle = BaseTexture::from_interface(texture)
+            .and_then(|texture| format_info(texture.format()).ok())
+            .map(|info| info.swizzle)
+            .unwrap_or_default();
+
+        if let Some(sw) = self.swizzles.get_mut(stage as usize) {
+            *sw = swizzle;
+        }
     }
 
     pub fn get_texture(&self, mut stage: u32) -> *mut BaseTexture {
@@ -101,6 +117,20 @@ impl DeviceState {
             .unwrap_or(ptr::null_mut())
     }
 
+    /// Retrieves the swizzle which has to be applied to values read from a texture stage.
+    ///
+    /// It is passed to the backend together with the stage's texture.
+    pub fn get_texture_swizzle(&self, mut stage: u32) -> Swizzle {
+        if D3DVERTEXTEXTURESAMPLER0 <= stage && stage <= D3DVERTEXTEXTURESAMPLER3 {
+            stage = 16 + stage - D3DVERTEXTEXTURESAMPLER0;
+        }
+
+        self.swizzles
+            .get(stage as usize)
+            .cloned()
+            .unwrap_or_default()
+    }
+
     pub fn set_vertex_shader(&mut self, shader: *const VertexShader) {
         self.vertex.vertex_shader = shader;
     }
@@ -125,6 +155,34 @@ impl DeviceState {
         self.vertex.vertex_decl
     }
 
+    pub fn set_stream_source(
+        &mut self,
+        stream: u32,
+        buffer: *mut VertexBuffer,
+        offset: u32,
+        stride: u32,
+    ) {
+        if let Some(source) = self.streams.get_mut(stream as usize) {
+            *source = (buffer, offset, stride);
+        }
+    }
+
+    /// Retrieves the vertex buffer bound to a stream, and the offset and stride of its vertices.
+    pub fn get_stream_source(&self, stream: u32) -> (*mut VertexBuffer, u32, u32) {
+        self.streams
+            .get(stream as usize)
+            .cloned()
+            .unwrap_or((ptr::null_mut(), 0, 0))
+    }
+
+    pub fn set_indices(&mut self, indices: *mut IndexBuffer) {
+        self.indices = indices;
+    }
+
+    pub fn get_indices(&self) -> *mut IndexBuffer {
+        self.indices
+    }
+
     pub fn set_viewport(&mut self, vp: &D3DVIEWPORT9) {
         self.viewport = *vp;
     }
@@ -156,6 +214,9 @@ impl Default for DeviceState {
             vertex: VertexState::default(),
             pixel: PixelState::default(),
             textures: [ptr::null_mut(); 20],
+            swizzles: [Swizzle::IDENTITY; 20],
+            streams: [(ptr::null_mut(), 0, 0); 16],
+            indices: ptr::null_mut(),
             // The default viewport depends on the default render target's size.
             viewport: unsafe { mem::zeroed() },
             transforms: HashMap::with_capacity(4),
@@ -0,0 +1,70 @@
+//! Counters of the work the app submits each frame.
+
+use std::fmt;
+
+/// Statistics about the calls the app made during a frame.
+///
+/// A frame ends each time the app presents.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct FrameStats {
+    /// Number of draw calls.
+    pub draws: u64,
+    /// Number of primitives drawn.
+    pub primitives: u64,
+    /// Number of changes to the pipeline's state, excluding shaders.
+    pub state_changes: u64,
+    /// Number of times a different vertex or pixel shader was set.
+    pub shader_switches: u64,
+    /// Number of resources locked.
+    pub locks: u64,
+    /// Size of the memory the app could write to through its locks.
+    pub bytes_uploaded: u64,
+    /// Number of state objects which were found in a cache.
+    pub cache_hits: u64,
+    /// Number of state objects which had to be created.
+    pub cache_misses: u64,
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} draws ({} primitives), {} state changes, {} shader switches, ",
+            self.draws, self.primitives, self.state_changes, self.shader_switches
+        )?;
+
+        write!(
+            f,
+            "{} locks ({} KiB uploaded), state object cache: {} hits / {} misses",
+            self.locks,
+            self.bytes_uploaded / 1024,
+            self.cache_hits,
+            self.cache_misses
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let stats = FrameStats {
+            draws: 12,
+            primitives: 3400,
+            state_changes: 120,
+            shader_switches: 8,
+            locks: 4,
+            bytes_uploaded: 64 * 1024,
+            cache_hits: 30,
+            cache_misses: 2,
+        };
+
+        assert_eq!(
+            stats.to_string(),
+            "12 draws (3400 primitives), 120 state changes, 8 shader switches, \
+             4 locks (64 KiB uploaded), state object cache: 30 hits / 2 misses"
+        );
+    }
+}
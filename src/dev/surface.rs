@@ -1,15 +1,14 @@
 use std::sync::atomic::{AtomicU32, Ordering};
 
-use winapi::shared::{d3d9::*, d3d9types::*, guiddef::GUID, windef::RECT};
-use winapi::um::d3d11::*;
+use winapi::shared::{d3d9::*, d3d9types::*, dxgiformat::DXGI_FORMAT, guiddef::GUID, windef::RECT};
 use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
 
 use com_impl::{implementation, interface, ComInterface};
 use comptr::ComPtr;
 
-use crate::core::{fmt::dxgi_format_to_d3d, msample::dxgi_samples_to_d3d9, *};
-use crate::d3d11;
-use crate::Error;
+use crate::backend::Texture;
+use crate::core::{msample::dxgi_samples_to_d3d9, *};
+use crate::{trace, Error};
 
 use super::{Device, Resource};
 
@@ -19,7 +18,10 @@ pub struct Surface {
     resource: Resource,
     refs: AtomicU32,
     // Reference to the texture we own, or our parent texture.
-    texture: d3d11::Texture2D,
+    texture: Texture,
+    // The format of this surface.
+    // We need to store it since multiple D3D9 formats share the same DXGI format.
+    fmt: D3DFORMAT,
     // Extra data required for this surface type.
     data: SurfaceData,
 }
@@ -32,9 +34,12 @@ pub enum SurfaceData {
     /// This is an ordinary surface.
     None,
     /// This surface is owning a render target.
-    RenderTarget(ComPtr<ID3D11RenderTargetView>),
+    ///
+    /// Stores the format with which it is bound, and the one used when sRGB writes are enabled,
+    /// which is missing if the surface's format has no sRGB variant.
+    RenderTarget(DXGI_FORMAT, Option<DXGI_FORMAT>),
     /// This surface is owning a depth / stencil buffer.
-    DepthStencil(ComPtr<ID3D11DepthStencilView>),
+    DepthStencil,
     /// This surface is part of a bigger texture.
     SubResource(u32),
 }
@@ -43,9 +48,10 @@ impl Surface {
     /// Creates a new surface from a D3D11 2D texture, and possibly some extra data.
     pub fn new(
         device: *const Device,
-        texture: d3d11::Texture2D,
+        texture: Texture,
         usage: UsageFlags,
         pool: MemoryPool,
+        fmt: D3DFORMAT,
         data: SurfaceData,
     ) -> ComPtr<Self> {
         let surface = Self {
@@ -53,39 +59,64 @@ impl Surface {
             resource: Resource::new(device, usage, pool, ResourceType::Surface),
             refs: AtomicU32::new(1),
             texture,
+            fmt,
             data,
         };
 
         unsafe { new_com_interface(surface) }
     }
 
-    /// Retrieves a reference to the subresource this surface represents.
-    pub fn subresource(&self) -> (*mut ID3D11Resource, u32) {
-        let resource = self.texture.as_resource();
-        let subresource = if let SurfaceData::SubResource(sr) = self.data {
+    /// Retrieves the index of the subresource this surface represents in its texture.
+    pub fn subresource(&self) -> u32 {
+        if let SurfaceData::SubResource(sr) = self.data {
             sr
         } else {
             0
-        };
+        }
+    }
 
-        (resource, subresource)
+    /// Retrieves the format of this surface.
+    pub fn format(&self) -> D3DFORMAT {
+        self.fmt
     }
 
-    /// If this surface is a render target, retrieves the associated RT view.
-    pub fn render_target_view(&self) -> Option<&mut ID3D11RenderTargetView> {
-        if let SurfaceData::RenderTarget(ref view) = self.data {
-            Some(view.as_mut())
+    /// Retrieves the texture which contains this surface.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Points this surface at another texture, which has replaced the one it represented.
+    pub fn retarget(&mut self, texture: Texture, data: SurfaceData) {
+        self.texture = texture;
+        self.data = data;
+    }
+
+    /// If this surface is a render target, retrieves the format it is bound with.
+    ///
+    /// If `srgb` is true, the format which converts the written values to sRGB is returned.
+    /// Falls back to the linear format if the surface does not support sRGB writes.
+    pub fn render_target_format(&self, srgb: bool) -> Option<DXGI_FORMAT> {
+        if let SurfaceData::RenderTarget(linear, srgb_fmt) = self.data {
+            match srgb_fmt {
+                Some(fmt) if srgb => Some(fmt),
+                _ => {
+                    if srgb {
+                        run_once!(|| warn!("sRGB writes are not supported on this render target"));
+                    }
+                    Some(linear)
+                }
+            }
         } else {
             None
         }
     }
 
-    /// If this surface is a depth / stencil buffer, retrieves the associated DS view.
-    pub fn depth_stencil_view(&self) -> Option<&mut ID3D11DepthStencilView> {
-        if let SurfaceData::DepthStencil(ref view) = self.data {
-            Some(view.as_mut())
+    /// Checks if this surface is a depth / stencil buffer.
+    pub fn is_depth_stencil(&self) -> bool {
+        if let SurfaceData::DepthStencil = self.data {
+            true
         } else {
-            None
+            false
         }
     }
 }
@@ -97,6 +128,12 @@ impl std::ops::Deref for Surface {
     }
 }
 
+impl std::ops::DerefMut for Surface {
+    fn deref_mut(&mut self) -> &mut Resource {
+        &mut self.resource
+    }
+}
+
 impl_iunknown!(struct Surface: IUnknown, IDirect3DResource9, IDirect3DSurface9);
 
 impl ComInterface<IDirect3DResource9Vtbl> for Surface {
@@ -111,57 +148,73 @@ impl ComInterface<IDirect3DResource9Vtbl> for Surface {
 impl Surface {
     /// Gets the container of this resource.
     fn get_container(&self, _riid: &GUID, _ret: *mut usize) -> Error {
-        unimplemented!()
+        unimplemented_method!("IDirect3DSurface9::GetContainer")
     }
 
     /// Retrieves a description of this surface.
     pub fn get_desc(&self, ret: *mut D3DSURFACE_DESC) -> Error {
-        let ret = check_mut_ref(ret)?;
+        guarded!("IDirect3DSurface9::GetDesc", {
+            let ret = check_mut_ref(ret)?;
 
-        let desc = self.texture.desc();
+            let desc = self.device().backend().texture_desc(&self.texture);
 
-        ret.Width = desc.Width;
-        ret.Height = desc.Height;
+            ret.Width = desc.Width;
+            ret.Height = desc.Height;
 
-        ret.Format = dxgi_format_to_d3d(desc.Format);
-        ret.Type = D3DRTYPE_SURFACE;
+            ret.Format = self.fmt;
+            ret.Type = D3DRTYPE_SURFACE;
 
-        ret.Usage = self.usage().bits();
-        ret.Pool = self.pool() as u32;
+            ret.Usage = self.usage().bits();
+            ret.Pool = self.pool() as u32;
 
-        let (ms_ty, ms_qlt) = dxgi_samples_to_d3d9(desc.SampleDesc);
-        ret.MultiSampleType = ms_ty;
-        ret.MultiSampleQuality = ms_qlt;
+            let (ms_ty, ms_qlt) = dxgi_samples_to_d3d9(desc.SampleDesc);
+            ret.MultiSampleType = ms_ty;
+            ret.MultiSampleQuality = ms_qlt;
 
-        Error::Success
+            Error::Success
+        })
     }
 
     // -- Memory mapping functions --
 
-    fn lock_rect(&mut self, ret: *mut D3DLOCKED_RECT, _r: *const RECT, flags: LockFlags) -> Error {
-        let ret = check_mut_ref(ret)?;
-        let (res, subres) = self.subresource();
-        *ret = self
-            .device_context()
-            .map(res, subres, flags, self.usage())?;
-        Error::Success
+    fn lock_rect(&mut self, ret: *mut D3DLOCKED_RECT, r: *const RECT, flags: LockFlags) -> Error {
+        traced!(
+            self,
+            "IDirect3DSurface9::LockRect",
+            [trace::data(r), flags.bits()],
+            {
+                let ret = check_mut_ref(ret)?;
+                let subres = self.subresource();
+                self.validate_lock("IDirect3DSurface9::LockRect", None, subres)?;
+                *ret = self.map_texture(&self.texture, subres, flags)?;
+                Error::Success
+            }
+        )
     }
 
     fn unlock_rect(&self) -> Error {
-        let (res, subres) = self.subresource();
-        self.device_context().unmap(res, subres);
-        Error::Success
+        // The contents are recorded before unmapping, since they are not available afterwards.
+        guarded!("IDirect3DSurface9::UnlockRect", {
+            let subres = self.subresource();
+            self.validate_unlock("IDirect3DSurface9::UnlockRect", None, subres)?;
+            let (pitch, contents) = self.locked_contents(subres);
+
+            traced!(self, "IDirect3DSurface9::UnlockRect", [pitch, contents], {
+                self.unmap_texture(&self.texture, subres);
+                Error::Success
+            })
+        })
     }
 
     // -- GDI interop functions --
 
     /// Retrieves the device context associated with this surface.
-    fn get_d_c() {
-        unimplemented!()
+    fn get_d_c() -> Error {
+        unimplemented_method!("IDirect3DSurface9::GetDC")
     }
 
     /// Releases a device context associated with this surface.
-    fn release_d_c() {
-        unimplemented!()
+    fn release_d_c() -> Error {
+        unimplemented_method!("IDirect3DSurface9::ReleaseDC")
     }
 }
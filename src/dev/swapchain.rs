@@ -1,22 +1,40 @@
 use std::{
-    cmp, mem, ptr,
+    cell::RefCell,
+    cmp, mem,
+    rc::Rc,
+    slice,
     sync::atomic::{AtomicU32, Ordering},
+    time::Instant,
 };
 
-use winapi::shared::{d3d9::*, d3d9types::*, dxgi::*, dxgitype::*, windef::HWND, winerror};
+use winapi::shared::{
+    d3d9::*,
+    d3d9types::*,
+    dxgi::*,
+    dxgiformat::{DXGI_FORMAT, DXGI_FORMAT_UNKNOWN},
+    dxgitype::*,
+    windef::{HWND, RECT},
+};
 use winapi::um::d3d11::*;
 use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
-use winapi::um::winuser;
-use winapi::Interface;
+use winapi::um::{
+    wingdi::{RGNDATA, RGNDATAHEADER},
+    winuser,
+};
 
 use com_impl::{implementation, interface, ComInterface};
 use comptr::ComPtr;
 
-use crate::core::{fmt::d3d_display_format_to_dxgi, msample::d3d9_to_dxgi_samples, *};
-use crate::d3d11;
-use crate::{Error, Result};
+use crate::backend::{self, Backend, BlitMode, Texture};
+use crate::core::{
+    fmt::{d3d_display_format_to_dxgi, format_info},
+    msample::{d3d9_to_dxgi_samples, dxgi_samples_to_d3d9},
+    *,
+};
+use crate::hud::{Hud, HudInfo};
+use crate::{trace, Error, Result};
 
-use super::{Device, Surface, SurfaceData};
+use super::{Device, Diagnostic, Surface, SurfaceData};
 
 /// Represents a swap chain, which is a queue of buffers
 /// on which the app can draw.
@@ -29,27 +47,77 @@ pub struct SwapChain {
     refs: AtomicU32,
     // Parent device of this interface.
     parent: *const Device,
-    // The equivalent DXGI interface.
-    swap_chain: ComPtr<IDXGISwapChain>,
+    // The backend which created the swap chain.
+    // Kept here, since the swap chain might outlive its device.
+    backend: Rc<dyn Backend>,
+    // The equivalent swap chain in the backend.
+    swap_chain: backend::SwapChain,
+    // The window this swap chain presents into.
+    window: HWND,
     // Store these for retrieving them later.
     pp: D3DPRESENT_PARAMETERS,
     // Determines how many vblanks to wait before presenting:
     // 0 -> no vsync
     // 1 through 4 -> vsync, with `refresh rate = (monitor Hz / sync_interval)`.
     sync_interval: u32,
+    // Created the first time the app presents linear content.
+    linear_content: RefCell<Option<LinearContent>>,
+    // Created the first time the HUD is drawn, if the user enabled it.
+    hud: RefCell<Option<HudOverlay>>,
+    // The buffers the app draws into, if their contents must be kept after presenting,
+    // or if the app writes sRGB values into them.
+    retained: RefCell<Option<RetainedBuffers>>,
+    // Created the first time the app presents into another window.
+    other_windows: RefCell<Vec<OtherWindow>>,
+}
+
+/// Back buffers which the app draws into instead of DXGI's buffer,
+/// and which are copied into it when presenting.
+///
+/// They implement the COPY and FLIP swap effects, since DXGI's DISCARD swap effect
+/// leaves its buffer undefined after presenting. With DISCARD, they are only created
+/// once the app enables sRGB writes, because swap chain buffers cannot be typeless,
+/// and so cannot have an sRGB view for the app to write sRGB values through.
+struct RetainedBuffers {
+    // The buffers the app draws into. COPY always has a single one.
+    back_buffers: Vec<Texture>,
+    // Copy of the image currently shown in the window.
+    // Only kept if the back buffers must keep their contents.
+    front: Option<Texture>,
+    // The format the buffers are viewed with, which is the format of DXGI's buffer.
+    format: DXGI_FORMAT,
+}
+
+/// A swap chain for presenting into a window other than the swap chain's own,
+/// which the app can request every time it presents.
+struct OtherWindow {
+    window: HWND,
+    swap_chain: backend::SwapChain,
+    // Copy of the image currently shown in the window, like the retained front buffer.
+    front: Option<Texture>,
+}
+
+/// Resources used for encoding a linear back buffer to sRGB before presenting it.
+struct LinearContent {
+    // Copy of the back buffer, which is read while the back buffer is written.
+    copy: Texture,
+}
+
+/// State of the HUD drawn on top of the back buffer.
+struct HudOverlay {
+    hud: Hud,
+    info: HudInfo,
 }
 
 impl SwapChain {
     /// Creates a new swap chain with the given parameters, which presents into a window.
     pub fn new(
         parent: &Device,
-        device: &ID3D11Device,
-        factory: &IDXGIFactory,
         pp: &mut D3DPRESENT_PARAMETERS,
         window: HWND,
     ) -> Result<ComPtr<Self>> {
         // First we need to set up the description of this swap chain.
-        let mut sc_desc = {
+        let sc_desc = {
             // Fill in the description of the back buffer.
             let buffer_desc = {
                 // Determine the dimensions of the back buffer.
@@ -99,40 +167,49 @@ impl SwapChain {
                     Width: width,
                     Height: height,
                     RefreshRate: refresh_rate,
-                    Format: d3d_display_format_to_dxgi(*fmt),
+                    Format: d3d_display_format_to_dxgi(*fmt)?,
                     ScanlineOrdering: DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED,
                     Scaling: DXGI_MODE_SCALING_UNSPECIFIED,
                 }
             };
 
             let sample_desc = {
-                let count = if pp.SwapEffect != D3DSWAPEFFECT_DISCARD {
+                let (count, quality) = if pp.SwapEffect != D3DSWAPEFFECT_DISCARD {
                     error!("Multisampling is only supported with the DISCARD swap effect");
                     error!("Disabling multisample antialiasing");
-                    1
+                    (1, 0)
                 } else {
-                    pp.MultiSampleType
+                    Self::multisampling(parent, pp)
                 };
 
-                d3d9_to_dxgi_samples(count, pp.MultiSampleQuality)
+                d3d9_to_dxgi_samples(count, quality)
             };
 
             let buffer_usage = DXGI_USAGE_BACK_BUFFER | DXGI_USAGE_RENDER_TARGET_OUTPUT;
 
-            let buffer_count = {
-                if pp.SwapEffect == D3DSWAPEFFECT_COPY {
-                    error!("Application requested multiple back buffers, even though swap effect is COPY");
-                    error!("Limiting to one back buffer");
-                    pp.BackBufferCount = 1;
-                }
+            if pp.SwapEffect == D3DSWAPEFFECT_COPY && pp.BackBufferCount > 1 {
+                error!(
+                    "Application requested multiple back buffers, even though swap effect is COPY"
+                );
+                error!("Limiting to one back buffer");
+                pp.BackBufferCount = 1;
+            }
 
-                // We have to clamp this to be between 1 and 3.
-                // TODO: with D3D9Ex the limit goes up to 30.
-                std::cmp::min(std::cmp::max(pp.BackBufferCount, 1), 3)
+            // We have to clamp this to be between 1 and 3.
+            // TODO: with D3D9Ex the limit goes up to 30.
+            pp.BackBufferCount = std::cmp::min(std::cmp::max(pp.BackBufferCount, 1), 3);
+
+            // With COPY and FLIP, the app draws into our own buffers,
+            // so DXGI only needs a single one to present them.
+            let buffer_count = match pp.SwapEffect {
+                D3DSWAPEFFECT_COPY | D3DSWAPEFFECT_FLIP => 1,
+                _ => pp.BackBufferCount,
             };
 
             let swap_effect = match pp.SwapEffect {
-                D3DSWAPEFFECT_DISCARD => DXGI_SWAP_EFFECT_DISCARD,
+                D3DSWAPEFFECT_DISCARD | D3DSWAPEFFECT_COPY | D3DSWAPEFFECT_FLIP => {
+                    DXGI_SWAP_EFFECT_DISCARD
+                }
                 se => {
                     error!("Unsupported swap effect: {}", se);
                     error!("Falling back to DISCARD");
@@ -158,60 +235,506 @@ impl SwapChain {
             }
         };
 
-        let swap_chain = unsafe {
-            let mut ptr = ptr::null_mut();
-
-            let result = factory.CreateSwapChain(
-                device as *const _ as *mut IUnknown,
-                &mut sc_desc,
-                &mut ptr,
-            );
-            check_hresult(result, "Failed to create swap chain")?;
-
-            ComPtr::new(ptr)
-        };
+        let backend = parent.adapter().backend();
+        let swap_chain = backend.create_swap_chain(&sc_desc)?;
 
         let pp = *pp;
 
         // Clamp this to 4.
         let sync_interval = cmp::min(pp.PresentationInterval, 4);
 
+        // The user might want to force vsync on or off.
+        let sync_interval = match parent.config().vsync {
+            Some(true) => cmp::max(sync_interval, 1),
+            Some(false) => 0,
+            None => sync_interval,
+        };
+
+        // With DISCARD, the app draws straight into DXGI's buffers,
+        // until it needs buffers which can be viewed as sRGB.
+        let retained = match pp.SwapEffect {
+            D3DSWAPEFFECT_COPY | D3DSWAPEFFECT_FLIP => {
+                let desc = backend.texture_desc(&backend.swap_chain_buffer(&swap_chain, 0)?);
+                let count = pp.BackBufferCount;
+                let typeless = typeless_format(pp.BackBufferFormat);
+                let buffers = RetainedBuffers::new(&*backend, desc, count, typeless, true)?;
+                Some(buffers)
+            }
+            _ => None,
+        };
+
         let swap_chain = Self {
             __vtable: Box::new(Self::create_vtable()),
             refs: AtomicU32::new(1),
             parent,
+            backend,
             swap_chain,
+            window,
             pp,
             sync_interval,
+            linear_content: RefCell::new(None),
+            hud: RefCell::new(None),
+            retained: RefCell::new(retained),
+            other_windows: RefCell::new(Vec::new()),
         };
 
         Ok(unsafe { new_com_interface(swap_chain) })
     }
 
-    /// Retrieves a buffer in this swap chain.
-    pub fn buffer(&self, id: u32) -> Result<d3d11::Texture2D> {
-        let mut ptr: *mut ID3D11Texture2D = ptr::null_mut();
-        let uuid = ID3D11Texture2D::uuidof();
+    /// Determines the multisample type and quality of the back buffers.
+    fn multisampling(parent: &Device, pp: &D3DPRESENT_PARAMETERS) -> (D3DMULTISAMPLE_TYPE, u32) {
+        let requested = (pp.MultiSampleType, pp.MultiSampleQuality);
 
-        let ret = &mut ptr as *mut _ as *mut *mut _;
+        let config = parent.config();
+        let ty = config.implicit_multisample_type(pp.MultiSampleType);
 
-        let result = unsafe { self.swap_chain.GetBuffer(id, &uuid, ret) };
+        if ty == pp.MultiSampleType {
+            return requested;
+        }
 
-        check_hresult(result, "Failed to retrieve swap chain buffer")?;
+        if ty <= 1 {
+            return (ty, 0);
+        }
 
-        Ok(ComPtr::new(ptr).into())
+        // Multisampled back buffers cannot be locked.
+        if pp.Flags & D3DPRESENTFLAG_LOCKABLE_BACKBUFFER != 0 {
+            warn!("Cannot force multisampling on a lockable back buffer");
+            return requested;
+        }
+
+        let max_quality = parent
+            .adapter()
+            .is_multisampling_supported(pp.BackBufferFormat, ty);
+
+        if max_quality == 0 {
+            warn!("Cannot force {}x multisampling, it is not supported", ty);
+            return requested;
+        }
+
+        info!("Forcing {}x multisampling", ty);
+        (ty, 0)
+    }
+
+    /// Retrieves a buffer in this swap chain, which the app can draw into.
+    pub fn buffer(&self, id: u32) -> Result<Texture> {
+        match &*self.retained.borrow() {
+            Some(rb) => rb
+                .back_buffers
+                .get(id as usize)
+                .cloned()
+                .ok_or(Error::InvalidCall),
+            None => self.backend.swap_chain_buffer(&self.swap_chain, id),
+        }
     }
 
-    // Retrieves this swap chain's containing output.
-    fn output(&self) -> Result<ComPtr<IDXGIOutput>> {
-        let output = unsafe {
-            let mut ptr = ptr::null_mut();
-            let result = self.swap_chain.GetContainingOutput(&mut ptr);
-            check_hresult(result, "Failed to get swap chain's output")?;
-            ComPtr::new(ptr)
+    /// Retrieves the buffer which replaced `texture`, if it is DXGI's first buffer
+    /// and the app has been switched over to retained buffers.
+    ///
+    /// If the app is about to write sRGB values into it, the switch happens now.
+    /// DXGI only gives access to its first buffer with DISCARD, so it is the only one
+    /// the app could have been drawing into.
+    pub fn retained_buffer(&self, texture: &Texture, srgb_writes: bool) -> Result<Option<Texture>> {
+        let backend = &*self.backend;
+
+        let buffer = backend.swap_chain_buffer(&self.swap_chain, 0)?;
+        if !buffer.same(texture) {
+            return Ok(None);
+        }
+
+        let mut retained = self.retained.borrow_mut();
+
+        if retained.is_none() {
+            let typeless = typeless_format(self.pp.BackBufferFormat);
+
+            if !srgb_writes || typeless == DXGI_FORMAT_UNKNOWN {
+                return Ok(None);
+            }
+
+            let desc = backend.texture_desc(texture);
+            let count = self.pp.BackBufferCount;
+            let rb = RetainedBuffers::new(backend, desc, count, typeless, false)?;
+
+            // The app might already have drawn part of this frame.
+            backend.copy_texture(&rb.back_buffers[0], &buffer);
+
+            *retained = Some(rb);
+        }
+
+        Ok(retained.as_ref().map(|rb| rb.back_buffers[0].clone()))
+    }
+
+    /// Switches the output to a full-screen display mode.
+    pub fn set_display_mode(&self, mode: &D3DDISPLAYMODEEX) -> Result<()> {
+        let desc = DXGI_MODE_DESC {
+            Width: mode.Width,
+            Height: mode.Height,
+            RefreshRate: DXGI_RATIONAL {
+                Numerator: mode.RefreshRate,
+                Denominator: 1,
+            },
+            Format: d3d_display_format_to_dxgi(mode.Format)?,
+            ScanlineOrdering: match mode.ScanLineOrdering {
+                D3DSCANLINEORDERING_PROGRESSIVE => DXGI_MODE_SCANLINE_ORDER_PROGRESSIVE,
+                D3DSCANLINEORDERING_INTERLACED => DXGI_MODE_SCANLINE_ORDER_UPPER_FIELD_FIRST,
+                _ => DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED,
+            },
+            Scaling: DXGI_MODE_SCALING_UNSPECIFIED,
         };
 
-        Ok(output)
+        self.backend.resize_target(&self.swap_chain, &desc)?;
+        self.backend.set_fullscreen_state(&self.swap_chain, true)
+    }
+
+    /// Returns the multisampling of the back buffers, if a depth / stencil buffer
+    /// the app creates is meant to be used with them.
+    ///
+    /// The user might have forced the back buffers to be multisampled, in which case
+    /// their depth buffers have to be upgraded too. These are recognized by having
+    /// the size and the multisample type the app requested for the back buffers.
+    pub fn depth_stencil_multisampling(
+        &self,
+        size: (u32, u32),
+        ms_ty: D3DMULTISAMPLE_TYPE,
+    ) -> Option<(D3DMULTISAMPLE_TYPE, u32)> {
+        let pp = &self.pp;
+
+        if size != (pp.BackBufferWidth, pp.BackBufferHeight) || ms_ty != pp.MultiSampleType {
+            return None;
+        }
+
+        let desc = self.backend.texture_desc(&self.buffer(0).ok()?);
+        Some(dxgi_samples_to_d3d9(desc.SampleDesc))
+    }
+
+    /// Checks if the swap chain presents into a window, rather than the whole screen.
+    pub fn is_windowed(&self) -> bool {
+        self.pp.Windowed != 0
+    }
+
+    /// Retrieves the size of the back buffers.
+    pub fn back_buffer_size(&self) -> (u32, u32) {
+        (self.pp.BackBufferWidth, self.pp.BackBufferHeight)
+    }
+
+    /// Retrieves the format of the back buffers.
+    pub fn back_buffer_format(&self) -> D3DFORMAT {
+        self.pp.BackBufferFormat
+    }
+
+    /// Checks if the app is allowed to lock the back buffers.
+    pub fn lockable_back_buffer(&self) -> bool {
+        self.pp.Flags & D3DPRESENTFLAG_LOCKABLE_BACKBUFFER != 0
+    }
+
+    /// Creates the resources required for presenting linear content.
+    fn create_linear_content(&self, desc: D3D11_TEXTURE2D_DESC) -> Result<LinearContent> {
+        // The copy is never multisampled, since it has to be sampled as a normal texture.
+        let copy_desc = D3D11_TEXTURE2D_DESC {
+            MipLevels: 1,
+            ArraySize: 1,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_SHADER_RESOURCE,
+            CPUAccessFlags: 0,
+            MiscFlags: 0,
+            ..desc
+        };
+
+        Ok(LinearContent {
+            copy: self.backend.create_texture_2d(&copy_desc)?,
+        })
+    }
+
+    /// Encodes the linear values in the back buffer to sRGB.
+    fn encode_linear_content(&self) -> Result<()> {
+        let back_buffer = self.backend.swap_chain_buffer(&self.swap_chain, 0)?;
+        let desc = self.backend.texture_desc(&back_buffer);
+
+        let mut linear_content = self.linear_content.borrow_mut();
+
+        if linear_content.is_none() {
+            *linear_content = Some(self.create_linear_content(desc)?);
+        }
+
+        let lc = linear_content.as_ref().unwrap();
+
+        // We cannot read from the back buffer while writing to it, so we first make a copy.
+        if desc.SampleDesc.Count > 1 {
+            self.backend
+                .resolve_texture(&lc.copy, &back_buffer, desc.Format);
+        } else {
+            self.backend.copy_texture(&lc.copy, &back_buffer);
+        }
+
+        let viewport = D3D11_VIEWPORT {
+            TopLeftX: 0.0,
+            TopLeftY: 0.0,
+            Width: desc.Width as f32,
+            Height: desc.Height as f32,
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        };
+
+        self.backend.blit(
+            (&lc.copy, desc.Format),
+            [0.0, 0.0, 1.0, 1.0],
+            (&back_buffer, desc.Format),
+            &viewport,
+            BlitMode::LinearToSrgb,
+            false,
+        )
+    }
+
+    /// Creates the resources required for drawing the HUD.
+    fn create_hud(&self, parent: &Device) -> HudOverlay {
+        let info = HudInfo {
+            device: parent.adapter().name(),
+            api: if parent.is_ex() { "D3D9Ex" } else { "D3D9" },
+        };
+
+        HudOverlay {
+            hud: Hud::new(parent.config().hud.clone()),
+            info,
+        }
+    }
+
+    /// Draws the HUD into the back buffer, if the user enabled it.
+    fn draw_hud(&self) -> Result<()> {
+        let parent = unsafe { &*self.parent };
+
+        if parent.config().hud.is_empty() {
+            return Ok(());
+        }
+
+        let mut hud = self.hud.borrow_mut();
+
+        if hud.is_none() {
+            *hud = Some(self.create_hud(parent));
+        }
+
+        let ho = hud.as_mut().unwrap();
+
+        ho.hud.present(Instant::now());
+
+        let quads = ho.hud.layout(&ho.info, &parent.frame_stats());
+
+        let back_buffer = self.backend.swap_chain_buffer(&self.swap_chain, 0)?;
+        let fmt = self.backend.texture_desc(&back_buffer).Format;
+
+        self.backend.draw_overlay(&back_buffer, fmt, &quads)
+    }
+
+    /// Updates the image shown in the window from the retained back buffers,
+    /// and copies it into DXGI's buffer.
+    fn present_retained(
+        &self,
+        rb: &RetainedBuffers,
+        src: Option<&RECT>,
+        dest: Option<&RECT>,
+        dirty: &[RECT],
+    ) -> Result<()> {
+        let backend = &*self.backend;
+
+        let output = backend.swap_chain_buffer(&self.swap_chain, 0)?;
+        let back_buffer = &rb.back_buffers[0];
+
+        let front = match rb.front {
+            Some(ref front) => front,
+            // The back buffer is undefined after presenting, so it can be copied as it is.
+            None => {
+                backend.copy_texture(&output, back_buffer);
+                return Ok(());
+            }
+        };
+
+        if self.pp.SwapEffect == D3DSWAPEFFECT_FLIP {
+            // The first back buffer becomes the front buffer, the other back buffers
+            // move forward, and the old front buffer becomes the last back buffer.
+            // DXGI's buffer holds on to the old front buffer in the meantime.
+            let last = rb.back_buffers.len() - 1;
+
+            backend.copy_texture(&output, front);
+            backend.copy_texture(front, back_buffer);
+
+            for i in 0..last {
+                backend.copy_texture(&rb.back_buffers[i], &rb.back_buffers[i + 1]);
+            }
+
+            backend.copy_texture(&rb.back_buffers[last], &output);
+        } else {
+            self.update_front(back_buffer, rb.format, front, self.window, src, dest, dirty)?;
+        }
+
+        backend.copy_texture(&output, front);
+
+        Ok(())
+    }
+
+    /// Draws the presented parts of the back buffer into a copy of the image shown in a window.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    fn update_front(
+        &self,
+        back_buffer: &Texture,
+        format: DXGI_FORMAT,
+        front: &Texture,
+        window: HWND,
+        src: Option<&RECT>,
+        dest: Option<&RECT>,
+        dirty: &[RECT],
+    ) -> Result<()> {
+        let backend = &*self.backend;
+
+        if src.is_none() && dest.is_none() && dirty.is_empty() {
+            backend.copy_texture(front, back_buffer);
+            return Ok(());
+        }
+
+        let desc = backend.texture_desc(back_buffer);
+        let size = (desc.Width, desc.Height);
+
+        let window_size = unsafe {
+            let mut r = mem::zeroed();
+            winuser::GetClientRect(window, &mut r);
+            ((r.right - r.left) as u32, (r.bottom - r.top) as u32)
+        };
+
+        for (src_rect, dest_rect, scaled) in present_rects(size, window_size, src, dest, dirty) {
+            let [left, top, width, height] = dest_rect;
+
+            let viewport = D3D11_VIEWPORT {
+                TopLeftX: left,
+                TopLeftY: top,
+                Width: width,
+                Height: height,
+                MinDepth: 0.0,
+                MaxDepth: 1.0,
+            };
+
+            backend.blit(
+                (back_buffer, format),
+                src_rect,
+                (front, format),
+                &viewport,
+                BlitMode::Copy,
+                scaled,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates a swap chain for presenting into another window,
+    /// with buffers like this swap chain's.
+    fn create_other_window(&self, window: HWND) -> Result<OtherWindow> {
+        let backend = &*self.backend;
+
+        let desc = backend.texture_desc(&backend.swap_chain_buffer(&self.swap_chain, 0)?);
+
+        // Other windows are never full-screen, so the display mode is not changed.
+        let sc_desc = DXGI_SWAP_CHAIN_DESC {
+            BufferDesc: DXGI_MODE_DESC {
+                Width: desc.Width,
+                Height: desc.Height,
+                RefreshRate: DXGI_RATIONAL {
+                    Numerator: 0,
+                    Denominator: 1,
+                },
+                Format: desc.Format,
+                ScanlineOrdering: DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED,
+                Scaling: DXGI_MODE_SCALING_UNSPECIFIED,
+            },
+            SampleDesc: desc.SampleDesc,
+            BufferUsage: DXGI_USAGE_BACK_BUFFER | DXGI_USAGE_RENDER_TARGET_OUTPUT,
+            BufferCount: 1,
+            OutputWindow: window,
+            Windowed: 1,
+            SwapEffect: DXGI_SWAP_EFFECT_DISCARD,
+            Flags: 0,
+        };
+
+        let swap_chain = backend.create_swap_chain(&sc_desc)?;
+
+        // Only the presented parts of the window are updated, like with this swap chain.
+        let front = match *self.retained.borrow() {
+            Some(RetainedBuffers { front: Some(_), .. }) => {
+                let front_desc = D3D11_TEXTURE2D_DESC {
+                    Usage: D3D11_USAGE_DEFAULT,
+                    BindFlags: D3D11_BIND_RENDER_TARGET | D3D11_BIND_SHADER_RESOURCE,
+                    CPUAccessFlags: 0,
+                    MiscFlags: 0,
+                    ..desc
+                };
+                Some(backend.create_texture_2d(&front_desc)?)
+            }
+            _ => None,
+        };
+
+        Ok(OtherWindow {
+            window,
+            swap_chain,
+            front,
+        })
+    }
+
+    /// Presents the first back buffer into another window than this swap chain's.
+    ///
+    /// The buffers of this swap chain are left as they are.
+    fn present_to_window(
+        &self,
+        window: HWND,
+        src: Option<&RECT>,
+        dest: Option<&RECT>,
+        dirty: &[RECT],
+        flags: u32,
+    ) -> Result<()> {
+        let backend = &*self.backend;
+
+        let mut other_windows = self.other_windows.borrow_mut();
+
+        let index = match other_windows.iter().position(|ow| ow.window == window) {
+            Some(index) => index,
+            None => {
+                other_windows.push(self.create_other_window(window)?);
+                other_windows.len() - 1
+            }
+        };
+
+        let ow = &other_windows[index];
+
+        let output = backend.swap_chain_buffer(&ow.swap_chain, 0)?;
+        let back_buffer = self.buffer(0)?;
+
+        match (&ow.front, &*self.retained.borrow()) {
+            (Some(front), Some(rb)) => {
+                self.update_front(&back_buffer, rb.format, front, window, src, dest, dirty)?;
+                backend.copy_texture(&output, front);
+            }
+            _ => backend.copy_texture(&output, &back_buffer),
+        }
+
+        backend.present(&ow.swap_chain, self.sync_interval, flags)
+    }
+
+    /// Checks if this swap chain was created full-screen, but is no longer.
+    ///
+    /// This happens when the user switches to another app.
+    pub fn lost_fullscreen(&self) -> bool {
+        if self.pp.Windowed != 0 {
+            return false;
+        }
+
+        match self.backend.fullscreen_state(&self.swap_chain) {
+            Ok(fullscreen) => !fullscreen,
+            Err(_) => false,
+        }
+    }
+
+    /// Waits for the next vertical blank of the associated output.
+    pub fn wait_for_vblank(&self) -> Result<()> {
+        self.backend.wait_for_vblank(&self.swap_chain)
     }
 
     /// Sets the associated output's gamma ramp.
@@ -227,8 +750,6 @@ impl SwapChain {
             warn!("Gamma calibration isn't implemented");
         }
 
-        let _output = self.output()?;
-
         error!("Setting gamma ramp is not supported");
 
         Ok(())
@@ -236,21 +757,174 @@ impl SwapChain {
 
     /// Gets the associated output's gamma ramp.
     pub fn get_gamma_ramp(&self, _ramp: &mut D3DGAMMARAMP) -> Result<()> {
-        let _output = self.output()?;
-
         error!("Getting gamma ramp is not supported");
 
         Ok(())
     }
 }
 
+impl RetainedBuffers {
+    /// Creates a number of back buffers, and the front buffer if their contents are `retain`ed,
+    /// based on the description of DXGI's buffer.
+    ///
+    /// If `typeless` is known, the buffers are created with it, so they can be viewed as sRGB.
+    fn new(
+        backend: &dyn Backend,
+        desc: D3D11_TEXTURE2D_DESC,
+        count: u32,
+        typeless: DXGI_FORMAT,
+        retain: bool,
+    ) -> Result<Self> {
+        let format = desc.Format;
+
+        let desc = D3D11_TEXTURE2D_DESC {
+            Format: if typeless != DXGI_FORMAT_UNKNOWN {
+                typeless
+            } else {
+                format
+            },
+            MipLevels: 1,
+            ArraySize: 1,
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_RENDER_TARGET | D3D11_BIND_SHADER_RESOURCE,
+            CPUAccessFlags: 0,
+            MiscFlags: 0,
+            ..desc
+        };
+
+        let back_buffers = (0..count)
+            .map(|_| backend.create_texture_2d(&desc))
+            .collect::<Result<Vec<_>>>()?;
+
+        let front = if retain {
+            Some(backend.create_texture_2d(&desc)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            back_buffers,
+            front,
+            format,
+        })
+    }
+}
+
+/// Returns the typeless variant of a back buffer format, if it has an sRGB variant.
+fn typeless_format(fmt: D3DFORMAT) -> DXGI_FORMAT {
+    format_info(fmt)
+        .map(|info| info.typeless)
+        .unwrap_or(DXGI_FORMAT_UNKNOWN)
+}
+
+/// Determines which parts of the back buffer are drawn where, when presenting part of it.
+///
+/// The source rectangle and the dirty region are in back buffer coordinates. The destination
+/// rectangle is in the coordinates of the window's client area, which DXGI stretches
+/// the back buffer to fit, so it is scaled to back buffer pixels.
+///
+/// Returns the rectangles to read from, as `[left, top, width, height]` in texture coordinates,
+/// the rectangles to write to, in pixels, and whether they have to be scaled.
+fn present_rects(
+    (width, height): (u32, u32),
+    window_size: (u32, u32),
+    src: Option<&RECT>,
+    dest: Option<&RECT>,
+    dirty: &[RECT],
+) -> Vec<([f32; 4], [f32; 4], bool)> {
+    let (width, height) = (width as i32, height as i32);
+
+    let full = RECT {
+        left: 0,
+        top: 0,
+        right: width,
+        bottom: height,
+    };
+
+    // Minimized windows have no client area, and nothing is visible in them anyway.
+    let (window_width, window_height) = match window_size {
+        (0, _) | (_, 0) => (width, height),
+        (w, h) => (w as i32, h as i32),
+    };
+
+    let src = intersect(src.unwrap_or(&full), &full);
+    let dest = match dest {
+        Some(rect) => RECT {
+            left: rect.left * width / window_width,
+            top: rect.top * height / window_height,
+            right: rect.right * width / window_width,
+            bottom: rect.bottom * height / window_height,
+        },
+        None => full,
+    };
+
+    let src_size = (src.right - src.left, src.bottom - src.top);
+    let dest_size = (dest.right - dest.left, dest.bottom - dest.top);
+
+    if src_size.0 <= 0 || src_size.1 <= 0 || dest_size.0 <= 0 || dest_size.1 <= 0 {
+        return Vec::new();
+    }
+
+    let scale_x = dest_size.0 as f32 / src_size.0 as f32;
+    let scale_y = dest_size.1 as f32 / src_size.1 as f32;
+    let scaled = src_size != dest_size;
+
+    // Without a dirty region, the whole source rectangle is presented.
+    let rects = if dirty.is_empty() {
+        vec![src]
+    } else {
+        dirty.iter().map(|rect| intersect(rect, &src)).collect()
+    };
+
+    rects
+        .into_iter()
+        .filter(|rect| rect.right > rect.left && rect.bottom > rect.top)
+        .map(|rect| {
+            let (w, h) = (rect.right - rect.left, rect.bottom - rect.top);
+
+            let src_rect = [
+                rect.left as f32 / width as f32,
+                rect.top as f32 / height as f32,
+                w as f32 / width as f32,
+                h as f32 / height as f32,
+            ];
+
+            let dest_rect = [
+                dest.left as f32 + (rect.left - src.left) as f32 * scale_x,
+                dest.top as f32 + (rect.top - src.top) as f32 * scale_y,
+                w as f32 * scale_x,
+                h as f32 * scale_y,
+            ];
+
+            (src_rect, dest_rect, scaled)
+        })
+        .collect()
+}
+
+/// Computes the intersection of two rectangles, which might be empty.
+fn intersect(a: &RECT, b: &RECT) -> RECT {
+    RECT {
+        left: a.left.max(b.left),
+        top: a.top.max(b.top),
+        right: a.right.min(b.right),
+        bottom: a.bottom.min(b.bottom),
+    }
+}
+
+/// Retrieves the rectangles of a region.
+///
+/// # Safety
+/// The region must be followed by the number of rectangles given in its header.
+unsafe fn region_rects(region: &RGNDATA) -> &[RECT] {
+    let rects = region.Buffer.as_ptr() as *const RECT;
+    slice::from_raw_parts(rects, region.rdh.nCount as usize)
+}
+
 impl Drop for SwapChain {
     fn drop(&mut self) {
-        unsafe {
-            // According to the DXGI docs, if the swap chain was created as full-screen
-            // we need to make it windowed right before destroying it.
-            self.swap_chain.SetFullscreenState(0, ptr::null_mut());
-        }
+        // According to the DXGI docs, if the swap chain was created as full-screen
+        // we need to make it windowed right before destroying it.
+        let _ = self.backend.set_fullscreen_state(&self.swap_chain, false);
     }
 }
 
@@ -260,42 +934,102 @@ impl_iunknown!(struct SwapChain: IUnknown, IDirect3DSwapChain9);
 impl SwapChain {
     /// Presents the back buffer to the screen, and moves to the next buffer in the chain.
     pub fn present(&self, src: usize, dest: usize, wnd: HWND, dirty: usize, flags: u32) -> Error {
-        if src != 0 || dest != 0 || dirty != 0 {
-            // Check if the app is even allowed to partially present.
-            if self.pp.SwapEffect != D3DSWAPEFFECT_COPY {
-                return Error::InvalidCall;
-            }
+        traced!(
+            self,
+            "IDirect3DSwapChain9::Present",
+            [
+                trace::data(src as *const RECT),
+                trace::data(dest as *const RECT),
+                trace::object(wnd),
+                trace::data(dirty as *const RGNDATAHEADER),
+                flags
+            ],
+            {
+                // Nothing is presented while the device is lost.
+                if unsafe { &*self.parent }.check_lost().is_err() {
+                    return Error::DeviceLost;
+                }
 
-            unimplemented!("Partial present is not yet supported");
-        }
+                if src != 0 || dest != 0 || dirty != 0 {
+                    // Check if the app is even allowed to partially present.
+                    if self.pp.SwapEffect != D3DSWAPEFFECT_COPY {
+                        let argument = if src != 0 {
+                            "pSourceRect"
+                        } else if dest != 0 {
+                            "pDestRect"
+                        } else {
+                            "pDirtyRegion"
+                        };
+
+                        let message = format!(
+                            "must be null, unless the swap effect is D3DSWAPEFFECT_COPY (it is {})",
+                            self.pp.SwapEffect
+                        );
+
+                        let diag =
+                            Diagnostic::new("IDirect3DSwapChain9::Present", argument, message);
+                        return unsafe { &*self.parent }.reject(diag);
+                    }
+                }
 
-        if !wnd.is_null() {
-            unimplemented!("Presenting to a different window is not supported");
-        }
+                let mut fl = 0;
+
+                // These flags are missing from `winapi`.
+                const DONOTWAIT: u32 = 1;
+                const LINEAR_CONTENT: u32 = 2;
+
+                // Retained buffers are updated before presenting,
+                // so the app must not present the same frame twice.
+                let retained = self
+                    .retained
+                    .borrow()
+                    .as_ref()
+                    .and_then(|rb| rb.front.as_ref())
+                    .is_some();
+                if flags & DONOTWAIT != 0 && !retained {
+                    fl |= DXGI_PRESENT_DO_NOT_WAIT;
+                }
 
-        let mut fl = 0;
+                let src = unsafe { (src as *const RECT).as_ref() };
+                let dest = unsafe { (dest as *const RECT).as_ref() };
+                let dirty = match unsafe { (dirty as *const RGNDATA).as_ref() } {
+                    Some(region) => unsafe { region_rects(region) },
+                    None => &[],
+                };
 
-        // These flags are missing from `winapi`.
-        const DONOTWAIT: u32 = 1;
-        const LINEAR_CONTENT: u32 = 2;
+                if wnd.is_null() || wnd == self.window {
+                    if let Some(rb) = &*self.retained.borrow() {
+                        self.present_retained(rb, src, dest, dirty)?;
+                    }
 
-        if flags & DONOTWAIT != 0 {
-            fl |= DXGI_PRESENT_DO_NOT_WAIT;
-        }
+                    // The app rendered linear values, which the display expects to be sRGB encoded.
+                    if flags & LINEAR_CONTENT != 0 {
+                        self.encode_linear_content()?;
+                    }
 
-        // TODO: determine what we have to do to support sRGB.
-        if flags & LINEAR_CONTENT != 0 {
-            warn!("sRGB / gamma correction not yet supported");
-        }
+                    self.draw_hud()?;
 
-        // Try to present.
-        let result = unsafe { self.swap_chain.Present(self.sync_interval, fl) };
+                    // Try to present.
+                    self.backend
+                        .present(&self.swap_chain, self.sync_interval, fl)?;
+                } else {
+                    // Tools present into their other windows, which do not need the HUD.
+                    self.present_to_window(wnd, src, dest, dirty, fl)?;
+                }
 
-        match result {
-            0 => Error::Success,
-            winerror::DXGI_ERROR_WAS_STILL_DRAWING => Error::WasStillDrawing,
-            hr => check_hresult(hr, "Failed to present to screen"),
-        }
+                // Apps which do not want to wait retry presenting the same frame
+                // until it succeeds, so only then has the frame ended.
+                let parent = unsafe { &*self.parent };
+
+                // The app has finished submitting this frame, so the HUD can show its statistics.
+                parent.end_frame();
+
+                parent.limit_frame_rate();
+                parent.limit_frame_latency()?;
+
+                Error::Success
+            }
+        )
     }
 
     /// Copies data from the front buffer into a surface.
@@ -303,7 +1037,7 @@ impl SwapChain {
         let _fb = check_mut_ref(fb);
         // TODO: we need to get the front buffer, then copy its data into the passed-in surface.
         // We also need to ensure the format is converted to a format D3D9 supports.
-        unimplemented!()
+        unimplemented_method!("IDirect3DSwapChain9::GetFrontBufferData")
     }
 
     /// Retrieves the the back buffer's surface.
@@ -313,67 +1047,144 @@ impl SwapChain {
         ty: D3DBACKBUFFER_TYPE,
         surf: *mut *mut Surface,
     ) -> Error {
-        let surf = check_mut_ref(surf)?;
+        traced!(
+            self,
+            "IDirect3DSwapChain9::GetBackBuffer",
+            [idx, ty, trace::returned(surf)],
+            {
+                let surf = check_mut_ref(surf)?;
+
+                // Buffer indices start from 0.
+                if idx >= self.pp.BackBufferCount {
+                    return Error::InvalidCall;
+                }
 
-        // Buffer indices start from 0.
-        if idx >= self.pp.BackBufferCount {
-            return Error::InvalidCall;
-        }
+                // The docs specify that mono is the only valid type.
+                if ty != D3DBACKBUFFER_TYPE_MONO {
+                    return Error::InvalidCall;
+                }
 
-        // The docs specify that mono is the only valid type.
-        if ty != D3DBACKBUFFER_TYPE_MONO {
-            return Error::InvalidCall;
-        }
+                // Retrieve the 2D texture representing this back buffer.
+                let buffer = self.buffer(idx)?;
+
+                // Create and return a pointer to the surface.
+                let surface = Surface::new(
+                    self.parent,
+                    buffer,
+                    UsageFlags::RENDER_TARGET,
+                    MemoryPool::Default,
+                    self.pp.BackBufferFormat,
+                    SurfaceData::None,
+                );
 
-        // Retrieve the 2D texture representing this back buffer.
-        let buffer = self.buffer(idx)?;
+                // Back buffers belong to the swap chain, and do not prevent resetting the device.
+                surface.as_mut().make_implicit();
 
-        // Create and return a pointer to the surface.
-        *surf = Surface::new(
-            self.parent,
-            buffer,
-            UsageFlags::RENDER_TARGET,
-            MemoryPool::Default,
-            SurfaceData::None,
-        ).into();
+                if self.lockable_back_buffer() {
+                    surface.as_mut().make_lockable();
+                }
 
-        Error::Success
+                *surf = surface.into();
+
+                Error::Success
+            }
+        )
     }
 
     /// Gets the status of the current scanline the rasterizer is processing.
     pub fn get_raster_status(&self, rs: *mut D3DRASTER_STATUS) -> Error {
-        check_mut_ref(rs)?;
+        guarded!("IDirect3DSwapChain9::GetRasterStatus", {
+            check_mut_ref(rs)?;
 
-        // We reported in the device caps that we don't support this.
-        Error::NotAvailable
+            // We reported in the device caps that we don't support this.
+            Error::NotAvailable
+        })
     }
 
     /// Retrieves the swap chain's display mode.
     pub fn get_display_mode(&self, dm: *mut D3DDISPLAYMODE) -> Error {
-        let dm = check_mut_ref(dm)?;
-        let pp = &self.pp;
-
-        *dm = D3DDISPLAYMODE {
-            Width: pp.BackBufferWidth,
-            Height: pp.BackBufferHeight,
-            Format: pp.BackBufferFormat,
-            RefreshRate: pp.FullScreen_RefreshRateInHz,
-        };
+        guarded!("IDirect3DSwapChain9::GetDisplayMode", {
+            let dm = check_mut_ref(dm)?;
+            let pp = &self.pp;
+
+            *dm = D3DDISPLAYMODE {
+                Width: pp.BackBufferWidth,
+                Height: pp.BackBufferHeight,
+                Format: pp.BackBufferFormat,
+                RefreshRate: pp.FullScreen_RefreshRateInHz,
+            };
 
-        Error::Success
+            Error::Success
+        })
     }
 
     /// Gets the device which created this object.
     pub fn get_device(&self, device: *mut *mut Device) -> Error {
-        let device = check_mut_ref(device)?;
-        *device = com_ref(self.parent);
-        Error::Success
+        guarded!("IDirect3DSwapChain9::GetDevice", {
+            let device = check_mut_ref(device)?;
+            *device = com_ref(self.parent);
+            Error::Success
+        })
     }
 
     /// Retrieves the presentation parameters this swap chain was created with.
     pub fn get_present_parameters(&self, pp: *mut D3DPRESENT_PARAMETERS) -> Error {
-        let pp = check_mut_ref(pp)?;
-        *pp = self.pp;
-        Error::Success
+        guarded!("IDirect3DSwapChain9::GetPresentParameters", {
+            let pp = check_mut_ref(pp)?;
+            *pp = self.pp;
+            Error::Success
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(left: i32, top: i32, right: i32, bottom: i32) -> RECT {
+        RECT {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    #[test]
+    fn partial_present() {
+        let size = (200, 100);
+
+        // Presenting everything.
+        assert_eq!(
+            present_rects(size, size, None, None, &[]),
+            [([0.0, 0.0, 1.0, 1.0], [0.0, 0.0, 200.0, 100.0], false)]
+        );
+
+        // Stretching the left half of the back buffer.
+        let src = rect(0, 0, 100, 100);
+        assert_eq!(
+            present_rects(size, size, Some(&src), None, &[]),
+            [([0.0, 0.0, 0.5, 1.0], [0.0, 0.0, 200.0, 100.0], true)]
+        );
+
+        // Moving a part of the back buffer, and only updating its dirty parts.
+        let src = rect(100, 50, 200, 100);
+        let dest = rect(0, 0, 100, 50);
+        let dirty = [rect(150, 0, 250, 60), rect(0, 0, 10, 10)];
+        assert_eq!(
+            present_rects(size, size, Some(&src), Some(&dest), &dirty),
+            [([0.75, 0.5, 0.25, 0.1], [50.0, 0.0, 50.0, 10.0], false)]
+        );
+
+        // Drawing into a window twice as large as the back buffer.
+        let dest = rect(0, 0, 200, 100);
+        assert_eq!(
+            present_rects(size, (400, 200), None, Some(&dest), &[]),
+            [([0.0, 0.0, 1.0, 1.0], [0.0, 0.0, 100.0, 50.0], true)]
+        );
+
+        // Empty rectangles are ignored.
+        let src = rect(50, 50, 50, 60);
+        assert!(present_rects(size, size, Some(&src), None, &[]).is_empty());
     }
 }
@@ -1,38 +1,104 @@
-use winapi::shared::{d3d9::*, d3d9types::*};
+use winapi::shared::{d3d9::*, d3d9types::*, dxgiformat::DXGI_FORMAT};
+use winapi::um::d3d11::*;
 
 use com_impl::{implementation, ComInterface};
 
-use crate::core::*;
+use crate::backend::{desc, Texture};
+use crate::core::{fmt::d3d_format_to_dxgi, *};
 use crate::dev::{Device, Resource};
-use crate::Error;
+use crate::{Error, Result};
+
+/// The formats with which a texture can be sampled by shaders.
+pub struct TextureViews {
+    linear: DXGI_FORMAT,
+    // Used when the sampler has sRGB reads enabled.
+    srgb: Option<DXGI_FORMAT>,
+}
+
+impl TextureViews {
+    /// Determines the view formats of a texture.
+    ///
+    /// Returns `None` if the texture cannot be bound to a shader.
+    pub fn new(desc: &D3D11_TEXTURE2D_DESC, fmt: D3DFORMAT) -> Result<Option<Self>> {
+        if desc.BindFlags & D3D11_BIND_SHADER_RESOURCE == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            linear: d3d_format_to_dxgi(fmt)?,
+            srgb: desc::srgb_view_format(desc, fmt),
+        }))
+    }
+}
 
 /// The common interface for all texture interfaces.
 #[repr(C)]
 pub struct BaseTexture {
     resource: Resource,
+    // The texture which stores all the subresources.
+    texture: Texture,
     // Number of subresource levels in this textures.
     levels: u32,
+    // The format this texture was created with.
+    fmt: D3DFORMAT,
+    // Formats used to bind this texture to the pipeline.
+    views: Option<TextureViews>,
 }
 
 impl BaseTexture {
     /// Initializes a new base texture.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn new(
         device: *const Device,
         usage: UsageFlags,
         pool: MemoryPool,
         rtype: ResourceType,
+        texture: Texture,
         levels: u32,
+        fmt: D3DFORMAT,
+        views: Option<TextureViews>,
     ) -> Self {
         Self {
             resource: Resource::new(device, usage, pool, rtype),
+            texture,
             levels,
+            fmt,
+            views,
         }
     }
 
+    /// Retrieves a base texture from a pointer to any texture interface.
+    pub fn from_interface<'a>(ptr: *mut BaseTexture) -> Option<&'a BaseTexture> {
+        unsafe { (ptr as *const Thunk).as_ref().map(|thunk| &thunk.txt) }
+    }
+
+    /// Retrieves the texture which stores this texture's subresources.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
     /// Retrieves the number of mip map levels in this texture.
     pub fn level_count(&self) -> u32 {
         self.levels
     }
+
+    /// Retrieves the format of this texture.
+    ///
+    /// Note that this could be different from the format of the D3D11 texture.
+    pub fn format(&self) -> D3DFORMAT {
+        self.fmt
+    }
+
+    /// Retrieves the format used to sample this texture, if it can be bound to a shader.
+    ///
+    /// If `srgb` is true, the format which converts the read values to linear space is returned.
+    /// Falls back to the linear format if the texture's format has no sRGB variant.
+    pub fn view_format(&self, srgb: bool) -> Option<DXGI_FORMAT> {
+        self.views.as_ref().map(|views| match views.srgb {
+            Some(fmt) if srgb => fmt,
+            _ => views.linear,
+        })
+    }
 }
 
 impl std::ops::Deref for BaseTexture {
@@ -71,10 +137,10 @@ impl std::ops::DerefMut for Thunk {
 #[implementation(IDirect3DBaseTexture9)]
 impl BaseTexture {
     fn set_l_o_d(self: &mut Thunk, _lod: u32) -> u32 {
-        unimplemented!()
+        unimplemented_method!("IDirect3DBaseTexture9::SetLOD")
     }
     fn get_l_o_d(self: &Thunk) -> u32 {
-        unimplemented!()
+        unimplemented_method!("IDirect3DBaseTexture9::GetLOD")
     }
 
     fn get_level_count(self: &Thunk) -> u32 {
@@ -82,12 +148,12 @@ impl BaseTexture {
     }
 
     fn set_auto_gen_filter_type(self: &mut Thunk, _filter: D3DTEXTUREFILTERTYPE) -> Error {
-        unimplemented!()
+        unimplemented_method!("IDirect3DBaseTexture9::SetAutoGenFilterType")
     }
     fn get_auto_gen_filter_type(self: &Thunk) -> D3DTEXTUREFILTERTYPE {
-        unimplemented!()
+        unimplemented_method!("IDirect3DBaseTexture9::GetAutoGenFilterType")
     }
     fn generate_mip_sub_levels(self: &mut Thunk) {
-        unimplemented!()
+        unimplemented_method!("IDirect3DBaseTexture9::GenerateMipSubLevels")
     }
 }
@@ -2,6 +2,7 @@ use std::ptr;
 use std::sync::atomic::{AtomicU32, Ordering};
 
 use winapi::shared::{d3d9::*, d3d9types::*, windef::RECT};
+use winapi::um::d3d11::D3D11CalcSubresource;
 use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
 
 use com_impl::{implementation, interface, ComInterface};
@@ -9,9 +10,9 @@ use comptr::ComPtr;
 
 use crate::dev::*;
 use crate::Error;
-use crate::{core::*, d3d11};
+use crate::{backend, core::*, trace};
 
-use super::BaseTexture;
+use super::{BaseTexture, TextureViews};
 
 /// Cube map texture.
 ///
@@ -20,23 +21,32 @@ use super::BaseTexture;
 pub struct CubeTexture {
     base: BaseTexture,
     refs: AtomicU32,
-    texture: d3d11::Texture2D,
 }
 
 impl CubeTexture {
     /// Creates a new cube texture.
     pub fn new(
         device: *const Device,
-        texture: d3d11::Texture2D,
+        texture: backend::Texture,
         levels: u32,
         usage: UsageFlags,
         pool: MemoryPool,
+        fmt: D3DFORMAT,
+        views: Option<TextureViews>,
     ) -> ComPtr<Self> {
         let tc = Self {
             __vtable: Box::new(Self::create_vtable()),
-            base: BaseTexture::new(device, usage, pool, ResourceType::CubeTexture, levels),
+            base: BaseTexture::new(
+                device,
+                usage,
+                pool,
+                ResourceType::CubeTexture,
+                texture,
+                levels,
+                fmt,
+                views,
+            ),
             refs: AtomicU32::new(1),
-            texture,
         };
 
         unsafe { new_com_interface(tc) }
@@ -64,39 +74,49 @@ impl ComInterface<IDirect3DBaseTexture9Vtbl> for CubeTexture {
 impl CubeTexture {
     /// Returns the description of a mip map level of a face.
     fn get_level_desc(&self, level: u32, desc: *mut D3DSURFACE_DESC) -> Error {
-        let surface = {
-            let mut ptr = ptr::null_mut();
-            // We can use any face, since they are all equal.
-            self.get_cube_map_surface(0, level, &mut ptr)?;
-            ComPtr::new(ptr)
-        };
-
-        surface.get_desc(desc)
+        guarded!("IDirect3DCubeTexture9::GetLevelDesc", {
+            let surface = {
+                let mut ptr = ptr::null_mut();
+                // We can use any face, since they are all equal.
+                self.get_cube_map_surface(0, level, &mut ptr)?;
+                ComPtr::new(ptr)
+            };
+
+            surface.get_desc(desc)
+        })
     }
 
     /// Retrieves a face of this cube map.
     fn get_cube_map_surface(&self, face: u32, level: u32, ret: *mut *mut Surface) -> Error {
-        let ret = check_mut_ref(ret)?;
-        let levels = self.level_count();
-
-        if face >= 6 {
-            return Error::InvalidCall;
-        }
-
-        if level >= levels {
-            return Error::InvalidCall;
-        }
-
-        let device = self.device();
-        let texture = self.texture.clone();
-        let usage = self.usage();
-        let pool = self.pool();
-        let subres = self.texture.calc_subresource(level, face, levels);
-        let data = SurfaceData::SubResource(subres);
-
-        *ret = Surface::new(device, texture, usage, pool, data).into();
-
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DCubeTexture9::GetCubeMapSurface",
+            [face, level, trace::returned(ret)],
+            {
+                let ret = check_mut_ref(ret)?;
+                let levels = self.level_count();
+
+                if face >= 6 {
+                    return Error::InvalidCall;
+                }
+
+                if level >= levels {
+                    return Error::InvalidCall;
+                }
+
+                let device = self.device();
+                let texture = self.texture().clone();
+                let usage = self.usage();
+                let pool = self.pool();
+                let fmt = self.format();
+                let subres = D3D11CalcSubresource(level, face, levels);
+                let data = SurfaceData::SubResource(subres);
+
+                *ret = Surface::new(device, texture, usage, pool, fmt, data).into();
+
+                Error::Success
+            }
+        )
     }
 
     /// Maps a face of this cube map to memory.
@@ -105,36 +125,57 @@ impl CubeTexture {
         face: u32,
         level: u32,
         ret: *mut D3DLOCKED_RECT,
-        _r: *const RECT,
+        r: *const RECT,
         flags: LockFlags,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
+        traced!(
+            self,
+            "IDirect3DCubeTexture9::LockRect",
+            [face, level, trace::data(r), flags.bits()],
+            {
+                let ret = check_mut_ref(ret)?;
 
-        let resource = self.texture.as_resource();
-        let levels = self.level_count();
-        let subres = self.texture.calc_subresource(level, face, levels);
-        let ctx = self.device_context();
+                let levels = self.level_count();
+                let subres = D3D11CalcSubresource(level, face, levels);
 
-        *ret = ctx.map(resource, subres, flags, self.usage())?;
+                let method = "IDirect3DCubeTexture9::LockRect";
+                self.validate_lock(method, Some("FaceType/Level"), subres)?;
 
-        Error::Success
+                *ret = self.map_texture(self.texture(), subres, flags)?;
+
+                Error::Success
+            }
+        )
     }
 
     /// Unmaps a face of this cube map.
     fn unlock_rect(&self, face: u32, level: u32) -> Error {
-        let resource = self.texture.as_resource();
-        let levels = self.level_count();
-        let subres = self.texture.calc_subresource(level, face, levels);
-        let ctx = self.device_context();
+        let method = "IDirect3DCubeTexture9::UnlockRect";
+
+        guarded!(method, {
+            let levels = self.level_count();
+            let subres = D3D11CalcSubresource(level, face, levels);
+            self.validate_unlock(method, Some("FaceType/Level"), subres)?;
+            let (pitch, contents) = self.locked_contents(subres);
 
-        ctx.unmap(resource, subres);
+            traced!(self, method, [face, level, pitch, contents], {
+                self.unmap_texture(self.texture(), subres);
 
-        Error::Success
+                Error::Success
+            })
+        })
     }
 
     fn add_dirty_rect(&mut self, _face: u32, r: *const RECT) -> Error {
-        let _r = check_ref(r)?;
-        warn!("AddDirtyRect is not implemented");
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DCubeTexture9::AddDirtyRect",
+            [_face, trace::data(r)],
+            {
+                let _r = check_ref(r)?;
+                warn!("AddDirtyRect is not implemented");
+                Error::Success
+            }
+        )
     }
 }
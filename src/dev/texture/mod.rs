@@ -3,7 +3,7 @@
 //! This means 2D textures, 3D (volume) textures, or cube maps.
 
 mod base;
-pub use self::base::BaseTexture;
+pub use self::base::{BaseTexture, TextureViews};
 
 mod texture2d;
 pub use self::texture2d::Texture;
@@ -8,9 +8,9 @@ use com_impl::{implementation, interface, ComInterface};
 use comptr::ComPtr;
 
 use crate::dev::*;
-use crate::{core::*, d3d11, Error};
+use crate::{backend, core::*, trace, Error};
 
-use super::BaseTexture;
+use super::{BaseTexture, TextureViews};
 
 /// Structure containing an image and its mip sub-levels.
 ///
@@ -19,7 +19,6 @@ use super::BaseTexture;
 pub struct Texture {
     base: BaseTexture,
     refs: AtomicU32,
-    texture: d3d11::Texture2D,
 }
 
 impl Texture {
@@ -27,15 +26,27 @@ impl Texture {
     pub fn new(
         device: *const Device,
         pool: MemoryPool,
-        texture: d3d11::Texture2D,
+        texture: backend::Texture,
         levels: u32,
         usage: UsageFlags,
+        fmt: D3DFORMAT,
+        views: Option<TextureViews>,
     ) -> ComPtr<Self> {
+        let base = BaseTexture::new(
+            device,
+            usage,
+            pool,
+            ResourceType::Texture,
+            texture,
+            levels,
+            fmt,
+            views,
+        );
+
         let texture = Self {
             __vtable: Box::new(Self::create_vtable()),
-            base: BaseTexture::new(device, usage, pool, ResourceType::Texture, levels),
+            base,
             refs: AtomicU32::new(1),
-            texture,
         };
 
         unsafe { new_com_interface(texture) }
@@ -63,32 +74,42 @@ impl ComInterface<IDirect3DBaseTexture9Vtbl> for Texture {
 impl Texture {
     /// Retrieves the description of a certain mip level.
     fn get_level_desc(&self, level: u32, desc: *mut D3DSURFACE_DESC) -> Error {
-        let surface = {
-            let mut ptr = ptr::null_mut();
-            self.get_surface_level(level, &mut ptr)?;
-            ComPtr::new(ptr)
-        };
-
-        surface.get_desc(desc)
+        guarded!("IDirect3DTexture9::GetLevelDesc", {
+            let surface = {
+                let mut ptr = ptr::null_mut();
+                self.get_surface_level(level, &mut ptr)?;
+                ComPtr::new(ptr)
+            };
+
+            surface.get_desc(desc)
+        })
     }
 
     /// Retrieves a surface representing a mip level of this texture.
     fn get_surface_level(&self, level: u32, ret: *mut *mut Surface) -> Error {
-        let ret = check_mut_ref(ret)?;
-
-        if level >= self.level_count() {
-            return Error::InvalidCall;
-        }
-
-        let device = self.device();
-        let texture = self.texture.clone();
-        let usage = self.usage();
-        let pool = self.pool();
-        let data = SurfaceData::SubResource(level);
-
-        *ret = Surface::new(device, texture, usage, pool, data).into();
-
-        Error::Success
+        traced!(
+            self,
+            "IDirect3DTexture9::GetSurfaceLevel",
+            [level, trace::returned(ret)],
+            {
+                let ret = check_mut_ref(ret)?;
+
+                if level >= self.level_count() {
+                    return Error::InvalidCall;
+                }
+
+                let device = self.device();
+                let texture = self.texture().clone();
+                let usage = self.usage();
+                let pool = self.pool();
+                let fmt = self.format();
+                let data = SurfaceData::SubResource(level);
+
+                *ret = Surface::new(device, texture, usage, pool, fmt, data).into();
+
+                Error::Success
+            }
+        )
     }
 
     /// Locks a texture and maps its memory.
@@ -97,32 +118,49 @@ impl Texture {
         level: u32,
         ret: *mut D3DLOCKED_RECT,
         // TODO: maybe track dirty regions for efficiency.
-        _r: *const RECT,
+        r: *const RECT,
         flags: LockFlags,
     ) -> Error {
-        let ret = check_mut_ref(ret)?;
+        traced!(
+            self,
+            "IDirect3DTexture9::LockRect",
+            [level, trace::data(r), flags.bits()],
+            {
+                let ret = check_mut_ref(ret)?;
 
-        let resource = self.texture.as_resource();
-        let ctx = self.device_context();
+                self.validate_lock("IDirect3DTexture9::LockRect", Some("Level"), level)?;
 
-        *ret = ctx.map(resource, level, flags, self.usage())?;
+                *ret = self.map_texture(self.texture(), level, flags)?;
 
-        Error::Success
+                Error::Success
+            }
+        )
     }
 
     /// Unlocks the locked rectangle of memory.
     pub fn unlock_rect(&self, level: u32) -> Error {
-        let resource = self.texture.as_resource();
-        let ctx = self.device_context();
-
-        ctx.unmap(resource, level);
-
-        Error::Success
+        guarded!("IDirect3DTexture9::UnlockRect", {
+            self.validate_unlock("IDirect3DTexture9::UnlockRect", Some("Level"), level)?;
+            let (pitch, contents) = self.locked_contents(level);
+
+            traced!(
+                self,
+                "IDirect3DTexture9::UnlockRect",
+                [level, pitch, contents],
+                {
+                    self.unmap_texture(self.texture(), level);
+
+                    Error::Success
+                }
+            )
+        })
     }
 
     fn add_dirty_rect(&mut self, r: *const RECT) -> Error {
-        let _r = check_ref(r)?;
-        warn!("AddDirtyRect is not implemented");
-        Error::Success
+        traced!(self, "IDirect3DTexture9::AddDirtyRect", [trace::data(r)], {
+            let _r = check_ref(r)?;
+            warn!("AddDirtyRect is not implemented");
+            Error::Success
+        })
     }
 }
@@ -0,0 +1,257 @@
+//! Validation of the app's calls against the rules of the D3D9 API.
+//!
+//! The retail D3D9 runtime silently accepts many invalid calls, and leaves the results undefined.
+//! When the user enables strict validation, each violation is reported with a diagnostic
+//! which names the method and the argument, which makes it easier to tell a game bug
+//! apart from a bug in this library.
+
+use std::fmt;
+
+use winapi::shared::d3d9types::D3DVERTEXELEMENT9;
+
+/// Description of a call which broke the rules of the API.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The method which was called, e.g. `IDirect3DSurface9::LockRect`.
+    pub method: &'static str,
+    /// The argument which was invalid, if the problem can be narrowed down to one.
+    pub argument: Option<&'static str>,
+    /// What exactly was wrong.
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic about an argument of a method.
+    pub fn new(method: &'static str, argument: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            method,
+            argument: Some(argument),
+            message: message.into(),
+        }
+    }
+
+    /// Creates a diagnostic about a call which is invalid because of the device's state.
+    pub fn state(method: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            method,
+            argument: None,
+            message: message.into(),
+        }
+    }
+
+    /// Logs this diagnostic.
+    pub fn report(&self) {
+        error!("Invalid call: {}", self);
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.argument {
+            Some(argument) => write!(f, "{}({}): {}", self.method, argument, self.message),
+            None => write!(f, "{}: {}", self.method, self.message),
+        }
+    }
+}
+
+/// Checks that a vertex declaration provides all the inputs a vertex shader reads.
+pub fn check_vertex_inputs(
+    method: &'static str,
+    elems: &[D3DVERTEXELEMENT9],
+    code: &[u32],
+) -> Option<Diagnostic> {
+    shader_inputs(code)
+        .into_iter()
+        .find(|&(usage, index)| {
+            !elems
+                .iter()
+                .any(|elem| elem.Usage == usage && elem.UsageIndex == index)
+        })
+        .map(|(usage, index)| {
+            let message = format!(
+                "the vertex shader reads {}{}, which is missing from the vertex declaration",
+                usage_name(usage),
+                index
+            );
+            Diagnostic::state(method, message)
+        })
+}
+
+/// Returns the usage and usage index of each input register a vertex shader declares.
+fn shader_inputs(code: &[u32]) -> Vec<(u8, u8)> {
+    const DCL: u32 = 31;
+    const DEF: u32 = 81;
+    const COMMENT: u32 = 0xFFFE;
+    const END: u32 = 0xFFFF;
+    // Register type of the vertex shader's inputs.
+    const INPUT: u32 = 1;
+
+    let mut inputs = Vec::new();
+
+    let major = match code.first() {
+        Some(&version) => (version >> 8) & 0xFF,
+        None => return inputs,
+    };
+
+    let mut i = 1;
+
+    while let Some(&token) = code.get(i) {
+        let opcode = token & 0xFFFF;
+
+        if opcode == END {
+            break;
+        }
+
+        let len = if opcode == COMMENT {
+            ((token >> 16) & 0x7FFF) as usize
+        } else if major >= 2 {
+            // Newer shader models encode the length of each instruction.
+            ((token >> 24) & 0xF) as usize
+        } else if opcode == DEF {
+            // The constant's value is not made up of parameter tokens.
+            5
+        } else {
+            // Otherwise, parameter tokens have the high bit set.
+            code[i + 1..]
+                .iter()
+                .take_while(|&&param| param & 0x8000_0000 != 0)
+                .count()
+        };
+
+        if opcode == DCL && len >= 2 {
+            if let (Some(&usage), Some(&reg)) = (code.get(i + 1), code.get(i + 2)) {
+                let ty = ((reg >> 28) & 0x7) | ((reg >> 8) & 0x18);
+
+                if ty == INPUT {
+                    inputs.push(((usage & 0x1F) as u8, ((usage >> 16) & 0xF) as u8));
+                }
+            }
+        }
+
+        i += 1 + len;
+    }
+
+    inputs
+}
+
+/// Returns the semantic name of a declaration usage.
+fn usage_name(usage: u8) -> &'static str {
+    const NAMES: [&str; 14] = [
+        "POSITION",
+        "BLENDWEIGHT",
+        "BLENDINDICES",
+        "NORMAL",
+        "PSIZE",
+        "TEXCOORD",
+        "TANGENT",
+        "BINORMAL",
+        "TESSFACTOR",
+        "POSITIONT",
+        "COLOR",
+        "FOG",
+        "DEPTH",
+        "SAMPLE",
+    ];
+
+    NAMES.get(usage as usize).cloned().unwrap_or("UNKNOWN")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use winapi::shared::d3d9types::*;
+
+    fn element(usage: u32, index: u8) -> D3DVERTEXELEMENT9 {
+        D3DVERTEXELEMENT9 {
+            Stream: 0,
+            Offset: 0,
+            Type: D3DDECLTYPE_FLOAT4 as u8,
+            Method: D3DDECLMETHOD_DEFAULT as u8,
+            Usage: usage as u8,
+            UsageIndex: index,
+        }
+    }
+
+    /// `vs_2_0` which declares a position and a second set of texture coordinates.
+    const VS_2_0: &[u32] = &[
+        0xFFFE_0200,
+        // A comment, which should be skipped.
+        0x0001_FFFE,
+        0x1234_5678,
+        // dcl_position v0
+        0x0200_001F,
+        0x8000_0000,
+        0x900F_0000,
+        // dcl_texcoord1 v1
+        0x0200_001F,
+        0x8001_0005,
+        0x900F_0001,
+        // mov oPos, v0
+        0x0200_0001,
+        0xC00F_0000,
+        0x90E4_0000,
+        0x0000_FFFF,
+    ];
+
+    #[test]
+    fn diagnostics() {
+        let diag = Diagnostic::new("IDirect3DSurface9::LockRect", "Flags", "invalid flags");
+        assert_eq!(
+            diag.to_string(),
+            "IDirect3DSurface9::LockRect(Flags): invalid flags"
+        );
+
+        let diag = Diagnostic::state("IDirect3DDevice9::EndScene", "not in a scene");
+        assert_eq!(
+            diag.to_string(),
+            "IDirect3DDevice9::EndScene: not in a scene"
+        );
+    }
+
+    #[test]
+    fn inputs_of_shaders() {
+        assert_eq!(shader_inputs(VS_2_0), [(0, 0), (5, 1)]);
+
+        // `vs_1_1`, which does not encode the length of its instructions.
+        let vs_1_1 = &[
+            0xFFFE_0101,
+            // def c0, 1.0, 0.0, 0.0, 1.0
+            0x0000_0051,
+            0xA00F_0000,
+            0x3F80_0000,
+            0x0000_0000,
+            0x0000_0000,
+            0x3F80_0000,
+            // dcl_normal v3
+            0x0000_001F,
+            0x8000_0003,
+            0x900F_0003,
+            0x0000_FFFF,
+        ];
+        assert_eq!(shader_inputs(vs_1_1), [(3, 0)]);
+
+        assert!(shader_inputs(&[]).is_empty());
+    }
+
+    #[test]
+    fn missing_inputs() {
+        let method = "IDirect3DDevice9::DrawPrimitive";
+
+        let elems = [
+            element(D3DDECLUSAGE_POSITION, 0),
+            element(D3DDECLUSAGE_TEXCOORD, 1),
+        ];
+        assert_eq!(check_vertex_inputs(method, &elems, VS_2_0), None);
+
+        let elems = [
+            element(D3DDECLUSAGE_POSITION, 0),
+            element(D3DDECLUSAGE_TEXCOORD, 0),
+        ];
+        let diag = check_vertex_inputs(method, &elems, VS_2_0).unwrap();
+        assert_eq!(
+            diag.message,
+            "the vertex shader reads TEXCOORD1, which is missing from the vertex declaration"
+        );
+    }
+}
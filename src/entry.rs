@@ -1,13 +1,15 @@
 use comptr::ComPtr;
-use crate::core::Context;
-use crate::Error;
+use winapi::shared::minwindef::{BOOL, DWORD, HINSTANCE, LPVOID, TRUE};
+use winapi::um::winnt::DLL_PROCESS_DETACH;
+use crate::core::{check_mut_ref, Context};
+use crate::{guard, trace, Error};
+use std::ptr;
 use std::sync::Once;
-use winapi::shared::d3d9::IDirect3D9Ex;
 
 static INIT_LOGGER: Once = Once::new();
 
-#[no_mangle]
-pub unsafe extern "system" fn Direct3DCreate9(sdk_version: u32) -> Option<ComPtr<Context>> {
+/// Initializes the library, the first time an app requests a context.
+fn init(sdk_version: u32) {
     // This function could be called multiple times during the lifetime of the DLL,
     // so we must protect the logger initializer.
     INIT_LOGGER.call_once(|| {
@@ -15,20 +17,59 @@ pub unsafe extern "system" fn Direct3DCreate9(sdk_version: u32) -> Option<ComPtr
     });
 
     // Try to identify which version of the D3D9 the app was built against.
-    // This could be used to implement compatibility workarounds if needed.
+    // The compatibility workarounds can depend on this.
     run_once!(|| match sdk_version {
         32 => info!("D3D9 version 9.0c"),
         _ => warn!("Unknown D3D9 SDK version {}", sdk_version),
     });
+}
+
+#[no_mangle]
+pub unsafe extern "system" fn Direct3DCreate9(sdk_version: u32) -> Option<ComPtr<Context>> {
+    guarded!("Direct3DCreate9", {
+        init(sdk_version);
+
+        let ctx = Context::new(sdk_version, false).ok();
+
+        if let Some(tracer) = trace::tracer() {
+            let object = ctx.as_ref().map_or(ptr::null(), |ctx| ctx.as_ref() as *const _);
+            let args = [sdk_version.into(), trace::object(object)];
+            tracer.record(0, "Direct3DCreate9", &args, 0);
+        }
 
-    Context::new().ok()
+        ctx
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "system" fn Direct3DCreate9Ex(
-    _sdk_version: u32,
-    _ptr: *mut *mut IDirect3D9Ex,
+    sdk_version: u32,
+    ptr: *mut *mut Context,
 ) -> Error {
-    error!("D3D9Ex is not yet supported");
-    Error::NotAvailable
+    guarded!("Direct3DCreate9Ex", {
+        let ptr = check_mut_ref(ptr)?;
+
+        init(sdk_version);
+
+        *ptr = Context::new(sdk_version, true)?.into();
+
+        if let Some(tracer) = trace::tracer() {
+            let args = [sdk_version.into(), trace::object(*ptr)];
+            tracer.record(0, "Direct3DCreate9Ex", &args, 0);
+        }
+
+        Error::Success
+    })
+}
+
+/// Called by Windows when the library is loaded or unloaded.
+#[no_mangle]
+pub extern "system" fn DllMain(_module: HINSTANCE, reason: DWORD, _reserved: LPVOID) -> BOOL {
+    // Tell the user which methods the app needed, but we did not implement.
+    // This also covers apps which never release their device.
+    if reason == DLL_PROCESS_DETACH {
+        guard::report();
+    }
+
+    TRUE
 }
@@ -22,6 +22,8 @@ const fn make_status(code: u32) -> u32 {
 #[repr(u32)]
 pub enum Error {
     Success = 0,
+    // Returned by operations which have not completed yet.
+    False = 1,
     NotFound = make_result(2150),
     MoreData = make_result(2151),
     NotAvailable = make_result(2154),
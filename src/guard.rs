@@ -0,0 +1,248 @@
+//! Protection of the COM entry points against panics.
+//!
+//! Unwinding out of a method called by the app through a vtable is undefined behaviour,
+//! and usually kills the game. Every method's body runs inside `call`, which catches
+//! the panic and returns an error code instead.
+//!
+//! Methods which are not implemented yet use the `unimplemented_method` macro,
+//! which reports each of them once, and counts how many times the app called them.
+//! The counts are logged when the library is unloaded, which gives us
+//! a list of what each game needs, sorted by priority.
+
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, TryLockError};
+
+use crate::{Error, Result};
+
+/// The reason a method did not run to completion.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Failure {
+    /// The method is not implemented yet.
+    Unimplemented,
+    /// The method panicked.
+    Panicked,
+}
+
+/// Values returned by COM methods which did not run to completion.
+pub trait Fallback {
+    fn fallback(failure: Failure) -> Self;
+}
+
+impl Fallback for Error {
+    fn fallback(failure: Failure) -> Self {
+        match failure {
+            Failure::Unimplemented => Error::NotAvailable,
+            Failure::Panicked => Error::DriverInternalError,
+        }
+    }
+}
+
+/// Used for `BOOL`s, which are false when the method did nothing.
+impl Fallback for i32 {
+    fn fallback(_: Failure) -> Self {
+        0
+    }
+}
+
+impl Fallback for u32 {
+    fn fallback(_: Failure) -> Self {
+        0
+    }
+}
+
+impl Fallback for f32 {
+    fn fallback(_: Failure) -> Self {
+        0.0
+    }
+}
+
+impl Fallback for () {
+    fn fallback(_: Failure) -> Self {}
+}
+
+impl<T> Fallback for Result<T> {
+    fn fallback(failure: Failure) -> Self {
+        Err(Error::fallback(failure))
+    }
+}
+
+impl<T> Fallback for Option<T> {
+    fn fallback(_: Failure) -> Self {
+        None
+    }
+}
+
+impl<T> Fallback for *mut T {
+    fn fallback(_: Failure) -> Self {
+        ptr::null_mut()
+    }
+}
+
+/// Number of failed calls to each method.
+#[derive(Default)]
+struct Counters {
+    unimplemented: HashMap<&'static str, u64>,
+    panics: HashMap<&'static str, u64>,
+}
+
+/// The counters for the whole process, created when the first call fails.
+static COUNTERS: AtomicPtr<Mutex<Counters>> = AtomicPtr::new(ptr::null_mut());
+
+fn counters() -> &'static Mutex<Counters> {
+    let mut current = COUNTERS.load(Ordering::SeqCst);
+
+    if current.is_null() {
+        let new = Box::into_raw(Box::new(Mutex::new(Counters::default())));
+
+        match COUNTERS.compare_exchange(ptr::null_mut(), new, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => current = new,
+            // Another thread got there first.
+            Err(other) => {
+                drop(unsafe { Box::from_raw(new) });
+                current = other;
+            }
+        }
+    }
+
+    unsafe { &*current }
+}
+
+/// Increments the counter of a method, returning the new count.
+fn increment(map: &mut HashMap<&'static str, u64>, method: &'static str) -> u64 {
+    let count = map.entry(method).or_insert(0);
+    *count += 1;
+    *count
+}
+
+/// Runs the body of a method, converting a panic into an error code.
+///
+/// Use the `guarded` or `traced` macros instead of calling this directly.
+pub fn call<R, F>(method: &'static str, body: F) -> R
+where
+    R: Fallback,
+    F: FnOnce() -> R,
+{
+    let payload = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(result) => return result,
+        Err(payload) => payload,
+    };
+
+    let message = payload
+        .downcast_ref::<&str>()
+        .cloned()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown error");
+
+    let mut counters = counters().lock().unwrap_or_else(|err| err.into_inner());
+
+    // Only the first panic is logged, since the method is likely to be called every frame.
+    if increment(&mut counters.panics, method) == 1 {
+        error!("{} panicked: {}", method, message);
+    }
+
+    R::fallback(Failure::Panicked)
+}
+
+/// Counts a call to a method which is not implemented, and returns an error code.
+///
+/// Use the `unimplemented_method` macro instead of calling this directly.
+pub fn unimplemented<R: Fallback>(method: &'static str) -> R {
+    let mut counters = counters().lock().unwrap_or_else(|err| err.into_inner());
+    increment(&mut counters.unimplemented, method);
+    R::fallback(Failure::Unimplemented)
+}
+
+/// Retrieves how many times the app called each method which is not implemented,
+/// starting with the most called one.
+pub fn unimplemented_calls() -> Vec<(&'static str, u64)> {
+    let counters = counters().lock().unwrap_or_else(|err| err.into_inner());
+    sorted_calls(&counters.unimplemented)
+}
+
+/// Sorts the counts of calls to each method, starting with the most called one.
+fn sorted_calls(map: &HashMap<&'static str, u64>) -> Vec<(&'static str, u64)> {
+    let mut calls: Vec<_> = map
+        .iter()
+        .map(|(&method, &count)| (method, count))
+        .collect();
+
+    calls.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    calls
+}
+
+/// Logs the failed calls the app made.
+///
+/// Called when the library is unloaded. Windows holds the loader lock then,
+/// and the other threads might have been killed while holding the counters,
+/// so this never waits for them.
+pub fn report() {
+    // Nothing failed if the counters were never created.
+    if COUNTERS.load(Ordering::SeqCst).is_null() {
+        return;
+    }
+
+    let counters = match counters().try_lock() {
+        Ok(counters) => counters,
+        Err(TryLockError::Poisoned(err)) => err.into_inner(),
+        Err(TryLockError::WouldBlock) => return,
+    };
+
+    let calls = sorted_calls(&counters.unimplemented);
+
+    if !calls.is_empty() {
+        warn!("The app called these unimplemented methods:");
+        for (method, count) in calls {
+            warn!("{:>10} {}", count, method);
+        }
+    }
+
+    for (method, count) in &counters.panics {
+        warn!("{} panicked {} times", method, count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_become_errors() {
+        let result = call("ITest::Panic", || -> Error { panic!("oops") });
+        assert_eq!(result as u32, Error::DriverInternalError as u32);
+
+        let result = call("ITest::Success", || 5u32);
+        assert_eq!(result, 5);
+    }
+
+    /// Method called by the app, which must not unwind into it.
+    extern "system" fn panicking_method() -> Error {
+        guarded!("ITest::PanickingMethod", { panic!("oops") })
+    }
+
+    #[test]
+    fn panicking_methods_return_errors() {
+        let result = panicking_method();
+        assert_eq!(result as u32, Error::DriverInternalError as u32);
+    }
+
+    #[test]
+    fn unimplemented_calls_are_counted() {
+        for _ in 0..3 {
+            let result: Error = unimplemented("ITest::Frequent");
+            assert_eq!(result as u32, Error::NotAvailable as u32);
+        }
+
+        let result: f32 = unimplemented("ITest::Rare");
+        assert_eq!(result, 0.0);
+
+        let calls: Vec<_> = unimplemented_calls()
+            .into_iter()
+            .filter(|(method, _)| method.starts_with("ITest::"))
+            .collect();
+
+        assert_eq!(calls, [("ITest::Frequent", 3), ("ITest::Rare", 1)]);
+    }
+}
@@ -0,0 +1,138 @@
+//! Built-in bitmap font used for the HUD's text.
+//!
+//! The glyphs are 5x7 pixels, and only cover upper case letters, digits and common
+//! punctuation. Lower case letters are drawn in upper case.
+
+/// Width of a glyph's cell in the atlas, including one column of spacing.
+pub const CELL_WIDTH: u32 = 6;
+/// Height of a glyph's cell in the atlas, including one row of spacing.
+pub const CELL_HEIGHT: u32 = 8;
+
+/// Number of glyphs in the font.
+const GLYPH_COUNT: usize = 59;
+
+/// Width of the atlas containing all the glyphs, in texels.
+pub const ATLAS_WIDTH: u32 = GLYPH_COUNT as u32 * CELL_WIDTH;
+/// Height of the atlas containing all the glyphs, in texels.
+pub const ATLAS_HEIGHT: u32 = CELL_HEIGHT;
+
+/// The rows of each glyph, from top to bottom. Bit 4 of each row is its leftmost pixel.
+const GLYPHS: [(char, [u8; 7]); GLYPH_COUNT] = [
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('!', [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04]),
+    ('"', [0x0A, 0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00]),
+    ('#', [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A]),
+    ('%', [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03]),
+    ('\'', [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00]),
+    ('(', [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02]),
+    (')', [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08]),
+    ('*', [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00]),
+    ('+', [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00]),
+    (',', [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08]),
+    ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+    ('/', [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00]),
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    (':', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00]),
+    (';', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08]),
+    ('<', [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02]),
+    ('=', [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00]),
+    ('>', [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08]),
+    ('?', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04]),
+    ('A', [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+    ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+    ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+    ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+    ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+    ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+    ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+    ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+    ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04]),
+    ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+    ('[', [0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E]),
+    (']', [0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F]),
+];
+
+/// Returns the position of a character's glyph in the atlas.
+///
+/// Characters which are not part of the font are drawn as a question mark.
+pub fn glyph_index(c: char) -> u32 {
+    let c = c.to_ascii_uppercase();
+
+    let index = GLYPHS
+        .iter()
+        .position(|&(glyph, _)| glyph == c)
+        .or_else(|| GLYPHS.iter().position(|&(glyph, _)| glyph == '?'))
+        .unwrap();
+
+    index as u32
+}
+
+/// Renders all the glyphs into an 8-bit coverage texture, one cell after the other.
+pub fn atlas() -> Vec<u8> {
+    let mut texels = vec![0; (ATLAS_WIDTH * ATLAS_HEIGHT) as usize];
+
+    for (index, &(_, rows)) in GLYPHS.iter().enumerate() {
+        for (y, &row) in rows.iter().enumerate() {
+            for x in 0..5 {
+                if row & (0x10 >> x) != 0 {
+                    let offset = y * ATLAS_WIDTH as usize + index * CELL_WIDTH as usize + x;
+                    texels[offset] = 0xFF;
+                }
+            }
+        }
+    }
+
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyphs() {
+        assert_eq!(glyph_index(' '), 0);
+        assert_eq!(glyph_index('a'), glyph_index('A'));
+        assert_eq!(glyph_index('\u{e9}'), glyph_index('?'));
+
+        let texels = atlas();
+        assert_eq!(texels.len(), (ATLAS_WIDTH * ATLAS_HEIGHT) as usize);
+
+        // The vertical bar of the 'T', and the space to its right.
+        let t = (glyph_index('T') * CELL_WIDTH) as usize;
+        let column = |x| -> Vec<u8> {
+            (0..CELL_HEIGHT as usize)
+                .map(|y| texels[y * ATLAS_WIDTH as usize + t + x])
+                .collect()
+        };
+        assert_eq!(column(2), [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0]);
+        assert_eq!(column(5), [0; 8]);
+    }
+}
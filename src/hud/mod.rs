@@ -0,0 +1,374 @@
+//! Heads-up display drawn on top of the game's frames.
+//!
+//! The HUD shows performance information, such as the frame rate and the number of draw calls,
+//! which makes it easier to compare the performance of a game across versions of the library.
+//!
+//! This module only lays out the HUD's contents as a list of rectangles,
+//! which the backend's overlay renderer draws into the back buffer before presenting.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::FrameStats;
+
+pub mod font;
+
+/// Information which can be shown on the HUD.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HudElement {
+    /// The number of frames presented per second.
+    Fps,
+    /// A graph of how long the last frames took.
+    FrameTimes,
+    /// The number of draw calls in the last frame.
+    DrawCalls,
+    /// The name of the GPU.
+    DevInfo,
+    /// The API the game uses.
+    Api,
+}
+
+impl HudElement {
+    /// Parses the name of an element.
+    fn parse(name: &str) -> Option<Self> {
+        let element = match name {
+            "fps" => HudElement::Fps,
+            "frametimes" => HudElement::FrameTimes,
+            "drawcalls" => HudElement::DrawCalls,
+            "devinfo" => HudElement::DevInfo,
+            "api" => HudElement::Api,
+            _ => return None,
+        };
+
+        Some(element)
+    }
+}
+
+/// Parses a comma-separated list of HUD elements, e.g. `fps,frametimes`.
+///
+/// `full` enables all the elements, while an empty list disables the HUD.
+pub fn parse_elements(s: &str) -> Option<Vec<HudElement>> {
+    let mut elements = Vec::new();
+
+    for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let name = name.to_ascii_lowercase();
+
+        if name == "full" {
+            return Some(vec![
+                HudElement::DevInfo,
+                HudElement::Api,
+                HudElement::Fps,
+                HudElement::FrameTimes,
+                HudElement::DrawCalls,
+            ]);
+        }
+
+        let element = HudElement::parse(&name)?;
+
+        if !elements.contains(&element) {
+            elements.push(element);
+        }
+    }
+
+    Some(elements)
+}
+
+/// A rectangle drawn by the overlay renderer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quad {
+    /// Left, top, right and bottom edges, in pixels.
+    pub rect: [f32; 4],
+    /// Left, top, right and bottom edges in the font atlas, in texture coordinates.
+    ///
+    /// If this is `None`, the rectangle is filled with its color.
+    pub uv: Option<[f32; 4]>,
+    /// Color in RGBA, with the alpha used for blending.
+    pub color: [f32; 4],
+}
+
+/// Information about the device, which does not change between frames.
+#[derive(Debug, Clone)]
+pub struct HudInfo {
+    /// Name of the GPU.
+    pub device: String,
+    /// Name of the API the game uses.
+    pub api: &'static str,
+}
+
+/// Number of frames shown in the frame time graph.
+const GRAPH_FRAMES: usize = 120;
+/// Width of each frame's bar in the graph, in pixels.
+const GRAPH_BAR_WIDTH: f32 = 2.0;
+/// Height of the graph, in pixels.
+const GRAPH_HEIGHT: f32 = 60.0;
+/// Frame time at the top of the graph, in milliseconds.
+const GRAPH_MAX_MS: f32 = 50.0;
+
+/// Size of each of the font's texels on the screen, in pixels.
+const TEXT_SCALE: f32 = 2.0;
+/// Space between the HUD and the edges of the screen, and between its lines.
+const MARGIN: f32 = 8.0;
+/// Interval at which the frame rate is updated.
+const FPS_INTERVAL: Duration = Duration::from_millis(500);
+
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BACKGROUND_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const GOOD_COLOR: [f32; 4] = [0.2, 0.9, 0.2, 1.0];
+const SLOW_COLOR: [f32; 4] = [0.9, 0.9, 0.2, 1.0];
+const BAD_COLOR: [f32; 4] = [0.9, 0.2, 0.2, 1.0];
+
+/// State of the HUD of a swap chain.
+pub struct Hud {
+    elements: Vec<HudElement>,
+    // When the previous frame was presented.
+    last_present: Option<Instant>,
+    // How long each of the last frames took, starting with the oldest one.
+    frame_times: VecDeque<Duration>,
+    // Frames presented since the frame rate was last updated.
+    fps_frames: u32,
+    // Time elapsed since the frame rate was last updated.
+    fps_elapsed: Duration,
+    // Frame rate shown on the HUD.
+    fps: f32,
+}
+
+impl Hud {
+    /// Creates a new HUD showing some elements, in order.
+    pub fn new(elements: Vec<HudElement>) -> Self {
+        Self {
+            elements,
+            last_present: None,
+            frame_times: VecDeque::with_capacity(GRAPH_FRAMES),
+            fps_frames: 0,
+            fps_elapsed: Duration::from_secs(0),
+            fps: 0.0,
+        }
+    }
+
+    /// Records that a frame is presented at a certain time.
+    pub fn present(&mut self, now: Instant) {
+        if let Some(last) = self.last_present {
+            self.add_frame_time(now - last);
+        }
+
+        self.last_present = Some(now);
+    }
+
+    /// Records how long a frame took.
+    fn add_frame_time(&mut self, time: Duration) {
+        if self.frame_times.len() == GRAPH_FRAMES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(time);
+
+        self.fps_frames += 1;
+        self.fps_elapsed += time;
+
+        if self.fps_elapsed >= FPS_INTERVAL {
+            self.fps = self.fps_frames as f32 / seconds(self.fps_elapsed);
+            self.fps_frames = 0;
+            self.fps_elapsed = Duration::from_secs(0);
+        }
+    }
+
+    /// Lays out the HUD's contents, using the statistics of the last frame.
+    pub fn layout(&self, info: &HudInfo, stats: &FrameStats) -> Vec<Quad> {
+        let mut layout = Layout::new();
+
+        for element in &self.elements {
+            match element {
+                HudElement::Fps => layout.text(&format!("FPS: {:.0}", self.fps)),
+                HudElement::FrameTimes => {
+                    let last = self.frame_times.back().cloned().map_or(0.0, milliseconds);
+                    layout.text(&format!("Frame time: {:.1} ms", last));
+                    layout.graph(&self.frame_times);
+                }
+                HudElement::DrawCalls => layout.text(&format!("Draw calls: {}", stats.draws)),
+                HudElement::DevInfo => layout.text(&info.device),
+                HudElement::Api => layout.text(&format!("API: {}", info.api)),
+            }
+        }
+
+        layout.finish()
+    }
+}
+
+/// Places the HUD's contents from top to bottom.
+struct Layout {
+    quads: Vec<Quad>,
+    // Top of the next line.
+    y: f32,
+    // Right edge of the widest line.
+    right: f32,
+}
+
+impl Layout {
+    fn new() -> Self {
+        // The first quad is reserved for the background.
+        let background = Quad {
+            rect: [0.0; 4],
+            uv: None,
+            color: BACKGROUND_COLOR,
+        };
+
+        Self {
+            quads: vec![background],
+            y: MARGIN,
+            right: MARGIN,
+        }
+    }
+
+    /// Adds a line of text.
+    fn text(&mut self, text: &str) {
+        let width = font::CELL_WIDTH as f32 * TEXT_SCALE;
+        let height = font::CELL_HEIGHT as f32 * TEXT_SCALE;
+
+        let mut x = MARGIN;
+
+        for c in text.chars() {
+            if c != ' ' {
+                let u = (font::glyph_index(c) * font::CELL_WIDTH) as f32 / font::ATLAS_WIDTH as f32;
+                let du = font::CELL_WIDTH as f32 / font::ATLAS_WIDTH as f32;
+
+                self.quads.push(Quad {
+                    rect: [x, self.y, x + width, self.y + height],
+                    uv: Some([u, 0.0, u + du, 1.0]),
+                    color: TEXT_COLOR,
+                });
+            }
+
+            x += width;
+        }
+
+        self.add_line(x, height);
+    }
+
+    /// Adds a graph of frame times, with a bar for each frame.
+    fn graph(&mut self, frame_times: &VecDeque<Duration>) {
+        let bottom = self.y + GRAPH_HEIGHT;
+
+        for (i, &time) in frame_times.iter().enumerate() {
+            let ms = milliseconds(time);
+            let left = MARGIN + i as f32 * GRAPH_BAR_WIDTH;
+            let top = bottom - GRAPH_HEIGHT * (ms / GRAPH_MAX_MS).min(1.0);
+
+            // Frames are good if the game runs at 60 FPS, and slow if it runs at 30.
+            let color = if ms <= 17.0 {
+                GOOD_COLOR
+            } else if ms <= 34.0 {
+                SLOW_COLOR
+            } else {
+                BAD_COLOR
+            };
+
+            self.quads.push(Quad {
+                rect: [left, top, left + GRAPH_BAR_WIDTH, bottom],
+                uv: None,
+                color,
+            });
+        }
+
+        let width = GRAPH_FRAMES as f32 * GRAPH_BAR_WIDTH;
+        self.add_line(MARGIN + width, GRAPH_HEIGHT);
+    }
+
+    fn add_line(&mut self, right: f32, height: f32) {
+        self.right = self.right.max(right);
+        self.y += height + MARGIN / 2.0;
+    }
+
+    /// Sizes the background to fit all the lines, and returns the quads.
+    fn finish(mut self) -> Vec<Quad> {
+        let padding = MARGIN / 2.0;
+        let bottom = self.y - MARGIN / 2.0;
+
+        self.quads[0].rect = [
+            MARGIN - padding,
+            MARGIN - padding,
+            self.right + padding,
+            bottom + padding,
+        ];
+
+        self.quads
+    }
+}
+
+fn seconds(time: Duration) -> f32 {
+    time.as_secs() as f32 + time.subsec_nanos() as f32 / 1e9
+}
+
+fn milliseconds(time: Duration) -> f32 {
+    seconds(time) * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements() {
+        assert_eq!(
+            parse_elements("fps, FrameTimes,fps"),
+            Some(vec![HudElement::Fps, HudElement::FrameTimes])
+        );
+        assert_eq!(parse_elements("full").map(|e| e.len()), Some(5));
+        assert_eq!(parse_elements(""), Some(vec![]));
+        assert_eq!(parse_elements("fps,memory"), None);
+    }
+
+    #[test]
+    fn frame_rate() {
+        let mut hud = Hud::new(vec![HudElement::Fps]);
+        let start = Instant::now();
+
+        // Present 61 frames, 10 ms apart.
+        for i in 0..=60 {
+            hud.present(start + Duration::from_millis(10 * i));
+        }
+
+        assert_eq!(hud.fps.round(), 100.0);
+        assert_eq!(hud.frame_times.len(), 60);
+
+        for i in 61..=200 {
+            hud.present(start + Duration::from_millis(10 * i));
+        }
+        assert_eq!(hud.frame_times.len(), GRAPH_FRAMES);
+    }
+
+    #[test]
+    fn layout() {
+        let info = HudInfo {
+            device: "GPU".to_owned(),
+            api: "D3D9",
+        };
+        let stats = FrameStats::default();
+
+        let mut hud = Hud::new(vec![HudElement::DevInfo, HudElement::FrameTimes]);
+        let start = Instant::now();
+        hud.present(start);
+        hud.present(start + Duration::from_millis(40));
+
+        let quads = hud.layout(&info, &stats);
+
+        // The background, "GPU", the spaces-free "Frame time: 40.0 ms" and a single bar.
+        assert_eq!(quads.len(), 1 + 3 + 16 + 1);
+
+        let line_height = font::CELL_HEIGHT as f32 * TEXT_SCALE;
+        assert_eq!(
+            quads[1].rect,
+            [MARGIN, MARGIN, MARGIN + 12.0, MARGIN + line_height]
+        );
+
+        let bar = quads.last().unwrap();
+        assert_eq!(bar.uv, None);
+        assert_eq!(bar.color, BAD_COLOR);
+        assert_eq!(
+            bar.rect[3] - bar.rect[1],
+            GRAPH_HEIGHT * 40.0 / GRAPH_MAX_MS
+        );
+
+        // The background covers the graph.
+        assert_eq!(quads[0].rect[2], MARGIN + 240.0 + MARGIN / 2.0);
+        assert_eq!(quads[0].rect[3], bar.rect[3] + MARGIN / 2.0);
+    }
+}
@@ -27,8 +27,18 @@ pub use self::error::{Error, Result};
 
 pub mod core;
 
+pub mod config;
+
 mod dev;
-pub use self::dev::Device;
+pub use self::dev::{Device, FrameStats};
+
+pub mod backend;
+
+pub mod trace;
+
+pub mod guard;
+
+pub mod hud;
 
 mod d3d11;
 
@@ -9,37 +9,91 @@ macro_rules! run_once {
     }};
 }
 
+/// Implements `IUnknown` for a COM object, which can be queried for the given interfaces.
+///
+/// The interfaces listed after `; flag:` are only handed out if `self.flag` is set,
+/// which is used for the D3D9Ex interfaces of objects created through the old API.
 macro_rules! impl_iunknown {
-    (struct $struct_name:ty : $($ifaces:ident),*) => {
+    (struct $struct_name:ty : $($ifaces:ident),* $(; $flag:ident : $($ex_ifaces:ident),*)*) => {
         #[implementation(IUnknown)]
         impl $struct_name {
             fn query_interface(&mut self, riid: &winapi::shared::guiddef::GUID, obj: &mut usize) -> i32 {
                 use winapi::Interface;
-                use winapi::shared::{guiddef::IsEqualGUID, winerror::{S_OK, E_NOTIMPL}};
+                use $crate::trace;
+                use winapi::shared::{guiddef::IsEqualGUID, winerror::{S_OK, E_NOINTERFACE}};
+
+                let ret = obj as *mut usize as *mut *mut u8;
 
-                *obj = 0;
+                traced!(self, "IUnknown::QueryInterface", [trace::data(riid), trace::returned(ret)], {
+                    *obj = 0;
 
-                if $(IsEqualGUID(riid, &$ifaces::uuidof())) || * {
-                    *obj = self as *mut _ as usize;
-                    self.add_ref();
-                    S_OK
-                } else {
-                    E_NOTIMPL
-                }
+                    let supported = $(IsEqualGUID(riid, &$ifaces::uuidof())) || *
+                        $($(|| (self.$flag && IsEqualGUID(riid, &$ex_ifaces::uuidof())))*)*;
+
+                    if supported {
+                        *obj = self as *mut _ as usize;
+                        self.add_ref();
+                        S_OK
+                    } else {
+                        E_NOINTERFACE
+                    }
+                })
             }
 
             fn add_ref(&mut self) -> u32 {
-                let prev = self.refs.fetch_add(1, Ordering::SeqCst);
-                prev + 1
+                traced!(self, "IUnknown::AddRef", [], {
+                    let prev = self.refs.fetch_add(1, Ordering::SeqCst);
+                    prev + 1
+                })
             }
 
             fn release(&mut self) -> u32 {
-                let prev = self.refs.fetch_sub(1, Ordering::SeqCst);
-                if prev == 1 {
-                    let _box = unsafe { Box::from_raw(self as *mut _) };
-                }
-                prev - 1
+                // The object might be freed by the time the call is recorded,
+                // but only its address is needed.
+                traced!(self, "IUnknown::Release", [], {
+                    let prev = self.refs.fetch_sub(1, Ordering::SeqCst);
+                    if prev == 1 {
+                        let _box = unsafe { Box::from_raw(self as *mut _) };
+                    }
+                    prev - 1
+                })
             }
         }
     };
 }
+
+/// Wraps the body of a COM method, so that calls to it are recorded when tracing.
+///
+/// The arguments are given as a list of expressions which can be converted
+/// to a `trace::Value`. They are evaluated after the body runs.
+///
+/// Like with `guarded`, panics in the body are converted to an error code.
+macro_rules! traced {
+    ($this:expr, $method:expr, [$($arg:expr),* $(,)*], $body:block) => {
+        $crate::trace::call(
+            $this as *const _ as usize as u64,
+            $method,
+            || $crate::guard::call($method, || $body),
+            || vec![$($crate::trace::Value::from($arg)),*],
+        )
+    };
+}
+
+/// Wraps the body of a COM method which is not traced, so that it does not panic
+/// across the FFI boundary.
+macro_rules! guarded {
+    ($method:expr, $body:block) => {
+        $crate::guard::call($method, || $body)
+    };
+}
+
+/// Body of a COM method which is not implemented yet.
+///
+/// The method is reported the first time it is called, and every call is counted.
+/// Returns an error code, or a neutral value for methods which do not return one.
+macro_rules! unimplemented_method {
+    ($method:expr) => {{
+        run_once!(|| error!("{} is not implemented", $method));
+        $crate::guard::unimplemented($method)
+    }};
+}
@@ -0,0 +1,1064 @@
+//! Target which replays captures through this library's implementation of D3D9.
+//!
+//! Replays always run in windowed mode, on a window provided by the user of the target,
+//! no matter which windows the app rendered to while the capture was recorded.
+//!
+//! The contexts are created on the system's adapters by default, but a target can also
+//! run on a given backend, such as the mock one, which does not need a GPU.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::{cmp, mem, ptr};
+
+use winapi::shared::guiddef::GUID;
+use winapi::shared::{d3d9::*, d3d9types::*, windef::*};
+use winapi::um::unknwnbase::IUnknown;
+
+use comptr::ComPtr;
+
+use crate::backend::Backend;
+use crate::core::Context;
+use crate::Error;
+
+use super::replay::{Executed, Target};
+use super::{Call, Value};
+
+/// Decodes the arguments of a recorded call.
+struct Args<'a>(&'a [Value]);
+
+impl<'a> Args<'a> {
+    fn u32(&self, i: usize) -> u32 {
+        match self.0.get(i) {
+            Some(&Value::U32(value)) => value,
+            Some(&Value::I32(value)) => value as u32,
+            _ => 0,
+        }
+    }
+
+    fn i32(&self, i: usize) -> i32 {
+        self.u32(i) as i32
+    }
+
+    fn object<T>(&self, i: usize) -> *mut T {
+        match self.0.get(i) {
+            Some(&Value::Object(object)) => object as usize as *mut T,
+            _ => ptr::null_mut(),
+        }
+    }
+
+    fn bytes(&self, i: usize) -> &'a [u8] {
+        match self.0.get(i) {
+            Some(&Value::Bytes(ref bytes)) => bytes,
+            _ => &[],
+        }
+    }
+
+    /// Decodes a structure, which is `None` if a null pointer was recorded.
+    fn data<T: Copy>(&self, i: usize) -> Option<T> {
+        let bytes = self.bytes(i);
+
+        if bytes.len() < mem::size_of::<T>() {
+            None
+        } else {
+            Some(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) })
+        }
+    }
+
+    /// Decodes an array of structures.
+    fn array<T: Copy>(&self, i: usize) -> Vec<T> {
+        self.bytes(i)
+            .chunks(mem::size_of::<T>())
+            .filter(|bytes| bytes.len() == mem::size_of::<T>())
+            .map(|bytes| unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) })
+            .collect()
+    }
+}
+
+/// Returns a pointer to an optional structure, or null.
+fn ptr_of<T>(data: &Option<T>) -> *const T {
+    data.as_ref().map_or(ptr::null(), |data| data)
+}
+
+/// The result of a call which does not return any objects.
+fn done(result: i32) -> Option<Executed> {
+    Some(Executed {
+        result: result as u32,
+        returned: Vec::new(),
+    })
+}
+
+/// The result of a call which returns an object through its argument with the given index.
+fn created<T>(result: i32, index: usize, object: *mut T) -> Option<Executed> {
+    Some(Executed {
+        result: result as u32,
+        returned: vec![(index, object as usize as u64)],
+    })
+}
+
+/// Memory of a locked resource, into which the recorded contents are copied when unlocking.
+struct Lock {
+    data: *mut u8,
+    pitch: usize,
+    read_only: bool,
+}
+
+impl Lock {
+    /// Copies the recorded contents of a resource into the locked memory.
+    ///
+    /// A row pitch of 0 means the contents are a single row, like for buffers.
+    unsafe fn write(&self, pitch: u32, contents: &[u8]) {
+        if self.read_only || contents.is_empty() {
+            return;
+        }
+
+        if pitch == 0 {
+            ptr::copy_nonoverlapping(contents.as_ptr(), self.data, contents.len());
+            return;
+        }
+
+        let pitch = pitch as usize;
+        let len = cmp::min(pitch, self.pitch);
+
+        for (i, row) in contents.chunks(pitch).enumerate() {
+            let dest = self.data.offset((i * self.pitch) as isize);
+            ptr::copy_nonoverlapping(row.as_ptr(), dest, cmp::min(len, row.len()));
+        }
+    }
+}
+
+/// Replays calls through this library's D3D9 objects.
+pub struct D3D9Target {
+    // Window on which all devices are created.
+    window: HWND,
+    // Backend on which contexts are created, instead of the system's adapters.
+    backend: Option<Rc<dyn Backend>>,
+    // The most recently created device, whose render target is dumped.
+    device: *mut IDirect3DDevice9,
+    // Resources which are locked, identified by their object and subresource.
+    locks: HashMap<(u64, u32), Lock>,
+}
+
+impl D3D9Target {
+    /// Creates a new target, which renders to a certain window.
+    pub fn new(window: HWND) -> Self {
+        Self {
+            window,
+            backend: None,
+            device: ptr::null_mut(),
+            locks: HashMap::new(),
+        }
+    }
+
+    /// Creates a new target, whose devices all execute their commands on the given backend.
+    pub fn with_backend(window: HWND, backend: Rc<dyn Backend>) -> Self {
+        Self {
+            backend: Some(backend),
+            ..Self::new(window)
+        }
+    }
+
+    /// Makes a device render to our window, instead of the one it was recorded with.
+    fn windowed(&self, pp: &mut Option<D3DPRESENT_PARAMETERS>) -> *mut D3DPRESENT_PARAMETERS {
+        match *pp {
+            Some(ref mut pp) => {
+                pp.hDeviceWindow = self.window;
+                pp.Windowed = 1;
+                pp.FullScreen_RefreshRateInHz = 0;
+                pp
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    fn locked(&mut self, key: (u64, u32), result: i32, data: *mut u8, pitch: i32, flags: u32) {
+        if result == 0 {
+            let lock = Lock {
+                data,
+                pitch: pitch as usize,
+                read_only: flags & D3DLOCK_READONLY != 0,
+            };
+
+            self.locks.insert(key, lock);
+        }
+    }
+
+    unsafe fn unlocked(&mut self, key: (u64, u32), pitch: u32, contents: &[u8]) {
+        if let Some(lock) = self.locks.remove(&key) {
+            lock.write(pitch, contents);
+        }
+    }
+
+    unsafe fn execute_unknown(&mut self, call: &Call, obj: &IUnknown) -> Option<Executed> {
+        let args = Args(&call.args);
+
+        match call.method.as_str() {
+            "IUnknown::QueryInterface" => {
+                let riid: GUID = args.data(0)?;
+                let mut ret = ptr::null_mut();
+                created(obj.QueryInterface(&riid, &mut ret), 1, ret)
+            }
+            "IUnknown::AddRef" => done(obj.AddRef() as i32),
+            "IUnknown::Release" => {
+                let refs = obj.Release();
+
+                if refs == 0 {
+                    if call.object == self.device as usize as u64 {
+                        self.device = ptr::null_mut();
+                    }
+
+                    self.locks.retain(|&(object, _), _| object != call.object);
+                }
+
+                done(refs as i32)
+            }
+            _ => None,
+        }
+    }
+
+    unsafe fn execute_context(&mut self, call: &Call, ctx: &IDirect3D9Ex) -> Option<Executed> {
+        let args = Args(&call.args);
+        let mut pp = args.data(4);
+        let mut device: *mut IDirect3DDevice9 = ptr::null_mut();
+
+        let (result, index) = match call.method.as_str() {
+            "IDirect3D9::CreateDevice" => (
+                ctx.CreateDevice(
+                    args.u32(0),
+                    args.u32(1),
+                    self.window,
+                    args.u32(3),
+                    self.windowed(&mut pp),
+                    &mut device,
+                ),
+                5,
+            ),
+            "IDirect3D9Ex::CreateDeviceEx" => (
+                ctx.CreateDeviceEx(
+                    args.u32(0),
+                    args.u32(1),
+                    self.window,
+                    args.u32(3),
+                    self.windowed(&mut pp),
+                    // Windowed devices do not have a full screen mode.
+                    ptr::null_mut(),
+                    &mut device as *mut _ as *mut *mut IDirect3DDevice9Ex,
+                ),
+                6,
+            ),
+            _ => return None,
+        };
+
+        if !device.is_null() {
+            self.device = device;
+        }
+
+        created(result, index, device)
+    }
+
+    unsafe fn execute_device(&mut self, call: &Call, dev: &IDirect3DDevice9Ex) -> Option<Executed> {
+        let args = Args(&call.args);
+
+        let method = match call.method.find("::") {
+            Some(pos) => &call.method[pos + 2..],
+            None => return None,
+        };
+
+        // Objects returned by the method are written here.
+        let mut ret: *mut IUnknown = ptr::null_mut();
+        let out: *mut *mut IUnknown = &mut ret;
+        let shared = ptr::null_mut();
+
+        match method {
+            "Reset" => {
+                let mut pp = args.data(0);
+                done(dev.Reset(self.windowed(&mut pp)))
+            }
+            "ResetEx" => {
+                let mut pp = args.data(0);
+                done(dev.ResetEx(self.windowed(&mut pp), ptr::null_mut()))
+            }
+            "EvictManagedResources" => done(dev.EvictManagedResources()),
+            "CreateAdditionalSwapChain" => {
+                let mut pp = args.data(0);
+                let result = dev.CreateAdditionalSwapChain(self.windowed(&mut pp), out as *mut _);
+                created(result, 1, ret)
+            }
+            "GetSwapChain" => created(dev.GetSwapChain(args.u32(0), out as *mut _), 1, ret),
+            "Present" | "PresentEx" => {
+                let src: Option<RECT> = args.data(0);
+                let dest: Option<RECT> = args.data(1);
+
+                // The rectangles of the dirty region are not recorded.
+                let (src, dest, dirty) = (ptr_of(&src), ptr_of(&dest), ptr::null());
+
+                if method == "Present" {
+                    done(dev.Present(src, dest, ptr::null_mut(), dirty))
+                } else {
+                    done(dev.PresentEx(src, dest, ptr::null_mut(), dirty, args.u32(4)))
+                }
+            }
+            "GetBackBuffer" => {
+                let result =
+                    dev.GetBackBuffer(args.u32(0), args.u32(1), args.u32(2), out as *mut _);
+                created(result, 3, ret)
+            }
+            "SetGammaRamp" => {
+                let ramp: Option<D3DGAMMARAMP> = args.data(2);
+                dev.SetGammaRamp(args.u32(0), args.u32(1), ptr_of(&ramp));
+                done(0)
+            }
+            "CreateRenderTarget" | "CreateRenderTargetEx" => {
+                let result = if method == "CreateRenderTarget" {
+                    dev.CreateRenderTarget(
+                        args.u32(0),
+                        args.u32(1),
+                        args.u32(2),
+                        args.u32(3),
+                        args.u32(4),
+                        args.i32(5),
+                        out as *mut _,
+                        shared,
+                    )
+                } else {
+                    dev.CreateRenderTargetEx(
+                        args.u32(0),
+                        args.u32(1),
+                        args.u32(2),
+                        args.u32(3),
+                        args.u32(4),
+                        args.i32(5),
+                        out as *mut _,
+                        shared,
+                        args.u32(8),
+                    )
+                };
+                created(result, 6, ret)
+            }
+            "SetRenderTarget" => done(dev.SetRenderTarget(args.u32(0), args.object(1))),
+            "GetRenderTarget" => created(dev.GetRenderTarget(args.u32(0), out as *mut _), 1, ret),
+            "GetRenderTargetData" => done(dev.GetRenderTargetData(args.object(0), args.object(1))),
+            "CreateDepthStencilSurface" | "CreateDepthStencilSurfaceEx" => {
+                let result = if method == "CreateDepthStencilSurface" {
+                    dev.CreateDepthStencilSurface(
+                        args.u32(0),
+                        args.u32(1),
+                        args.u32(2),
+                        args.u32(3),
+                        args.u32(4),
+                        args.i32(5),
+                        out as *mut _,
+                        shared,
+                    )
+                } else {
+                    dev.CreateDepthStencilSurfaceEx(
+                        args.u32(0),
+                        args.u32(1),
+                        args.u32(2),
+                        args.u32(3),
+                        args.u32(4),
+                        args.i32(5),
+                        out as *mut _,
+                        shared,
+                        args.u32(8),
+                    )
+                };
+                created(result, 6, ret)
+            }
+            "SetDepthStencilSurface" => done(dev.SetDepthStencilSurface(args.object(0))),
+            "GetDepthStencilSurface" => created(dev.GetDepthStencilSurface(out as *mut _), 0, ret),
+            "CreateOffscreenPlainSurface" | "CreateOffscreenPlainSurfaceEx" => {
+                let (width, height, fmt, pool) =
+                    (args.u32(0), args.u32(1), args.u32(2), args.u32(3));
+
+                let result = if method == "CreateOffscreenPlainSurface" {
+                    dev.CreateOffscreenPlainSurface(width, height, fmt, pool, out as *mut _, shared)
+                } else {
+                    let usage = args.u32(6);
+                    dev.CreateOffscreenPlainSurfaceEx(
+                        width,
+                        height,
+                        fmt,
+                        pool,
+                        out as *mut _,
+                        shared,
+                        usage,
+                    )
+                };
+                created(result, 4, ret)
+            }
+            "UpdateSurface" => {
+                let rect: Option<RECT> = args.data(1);
+                let point: Option<POINT> = args.data(3);
+                done(dev.UpdateSurface(
+                    args.object(0),
+                    ptr_of(&rect),
+                    args.object(2),
+                    ptr_of(&point),
+                ))
+            }
+            "StretchRect" => {
+                let src: Option<RECT> = args.data(1);
+                let dest: Option<RECT> = args.data(3);
+                done(dev.StretchRect(
+                    args.object(0),
+                    ptr_of(&src),
+                    args.object(2),
+                    ptr_of(&dest),
+                    args.u32(4),
+                ))
+            }
+            "CreateTexture" => {
+                let result = dev.CreateTexture(
+                    args.u32(0),
+                    args.u32(1),
+                    args.u32(2),
+                    args.u32(3),
+                    args.u32(4),
+                    args.u32(5),
+                    out as *mut _,
+                    shared,
+                );
+                created(result, 6, ret)
+            }
+            "CreateCubeTexture" => {
+                let result = dev.CreateCubeTexture(
+                    args.u32(0),
+                    args.u32(1),
+                    args.u32(2),
+                    args.u32(3),
+                    args.u32(4),
+                    out as *mut _,
+                    shared,
+                );
+                created(result, 5, ret)
+            }
+            "BeginScene" => done(dev.BeginScene()),
+            "EndScene" => done(dev.EndScene()),
+            "DrawPrimitive" => done(dev.DrawPrimitive(args.u32(0), args.u32(1), args.u32(2))),
+            "DrawIndexedPrimitive" => done(dev.DrawIndexedPrimitive(
+                args.u32(0),
+                args.i32(1),
+                args.u32(2),
+                args.u32(3),
+                args.u32(4),
+                args.u32(5),
+            )),
+            "DrawPrimitiveUP" => {
+                let vertices = args.bytes(2);
+                done(dev.DrawPrimitiveUP(
+                    args.u32(0),
+                    args.u32(1),
+                    vertices.as_ptr() as *const _,
+                    args.u32(3),
+                ))
+            }
+            "DrawIndexedPrimitiveUP" => {
+                let (indices, vertices) = (args.bytes(4), args.bytes(6));
+                done(dev.DrawIndexedPrimitiveUP(
+                    args.u32(0),
+                    args.u32(1),
+                    args.u32(2),
+                    args.u32(3),
+                    indices.as_ptr() as *const _,
+                    args.u32(5),
+                    vertices.as_ptr() as *const _,
+                    args.u32(7),
+                ))
+            }
+            "CreateStateBlock" => created(dev.CreateStateBlock(args.u32(0), out as *mut _), 1, ret),
+            "SetCursorPosition" => {
+                dev.SetCursorPosition(args.i32(0), args.i32(1), args.u32(2));
+                done(0)
+            }
+            "SetCursorProperties" => {
+                done(dev.SetCursorProperties(args.u32(0), args.u32(1), args.object(2)))
+            }
+            "ShowCursor" => done(dev.ShowCursor(args.i32(0))),
+            "SetRenderState" => done(dev.SetRenderState(args.u32(0), args.u32(1))),
+            "CreateVertexDeclaration" => {
+                let mut elems: Vec<D3DVERTEXELEMENT9> = args.array(0);
+                elems.push(D3DDECL_END);
+                created(
+                    dev.CreateVertexDeclaration(elems.as_ptr(), out as *mut _),
+                    1,
+                    ret,
+                )
+            }
+            "SetVertexDeclaration" => done(dev.SetVertexDeclaration(args.object(0))),
+            "CreateVertexShader" | "CreatePixelShader" => {
+                let tokens: Vec<u32> = args.array(0);
+                let tokens = if tokens.is_empty() {
+                    ptr::null()
+                } else {
+                    tokens.as_ptr()
+                };
+
+                let result = if method == "CreateVertexShader" {
+                    dev.CreateVertexShader(tokens, out as *mut _)
+                } else {
+                    dev.CreatePixelShader(tokens, out as *mut _)
+                };
+                created(result, 1, ret)
+            }
+            "SetVertexShader" => done(dev.SetVertexShader(args.object(0))),
+            "SetPixelShader" => done(dev.SetPixelShader(args.object(0))),
+            "CreateVertexBuffer" | "CreateIndexBuffer" => {
+                let (len, usage, fmt, pool) = (args.u32(0), args.u32(1), args.u32(2), args.u32(3));
+
+                let result = if method == "CreateVertexBuffer" {
+                    dev.CreateVertexBuffer(len, usage, fmt, pool, out as *mut _, shared)
+                } else {
+                    dev.CreateIndexBuffer(len, usage, fmt, pool, out as *mut _, shared)
+                };
+                created(result, 4, ret)
+            }
+            "SetStreamSource" => {
+                done(dev.SetStreamSource(args.u32(0), args.object(1), args.u32(2), args.u32(3)))
+            }
+            "SetIndices" => done(dev.SetIndices(args.object(0))),
+            "SetSamplerState" => done(dev.SetSamplerState(args.u32(0), args.u32(1), args.u32(2))),
+            "SetTexture" => done(dev.SetTexture(args.u32(0), args.object(1))),
+            "SetTextureStageState" => {
+                done(dev.SetTextureStageState(args.u32(0), args.u32(1), args.u32(2)))
+            }
+            "SetViewport" => {
+                let vp: Option<D3DVIEWPORT9> = args.data(0);
+                done(dev.SetViewport(ptr_of(&vp)))
+            }
+            "CreateQuery" => created(dev.CreateQuery(args.u32(0), out as *mut _), 1, ret),
+            "SetMaterial" => {
+                let mat: Option<D3DMATERIAL9> = args.data(0);
+                done(dev.SetMaterial(ptr_of(&mat)))
+            }
+            "SetTransform" => {
+                let mat: Option<D3DMATRIX> = args.data(1);
+                done(dev.SetTransform(args.u32(0), ptr_of(&mat)))
+            }
+            "SetGPUThreadPriority" => done(dev.SetGPUThreadPriority(args.i32(0))),
+            "SetMaximumFrameLatency" => done(dev.SetMaximumFrameLatency(args.u32(0))),
+            _ => None,
+        }
+    }
+
+    unsafe fn execute_resource(&mut self, call: &Call) -> Option<Executed> {
+        let args = Args(&call.args);
+        let object = call.object as usize;
+
+        let mut ret: *mut IUnknown = ptr::null_mut();
+        let out: *mut *mut IUnknown = &mut ret;
+        let mut rect = mem::zeroed::<D3DLOCKED_RECT>();
+
+        // Wait for the GPU, otherwise the replay could fail to lock resources
+        // which were locked successfully while recording.
+        let lock_flags = |i: usize| args.u32(i) & !D3DLOCK_DONOTWAIT;
+
+        // Whole subresources are locked, since that is what their recorded contents cover.
+        let whole = ptr::null();
+
+        match call.method.as_str() {
+            "IDirect3DSwapChain9::Present" => {
+                let sc = &*(object as *mut IDirect3DSwapChain9);
+                let src: Option<RECT> = args.data(0);
+                let dest: Option<RECT> = args.data(1);
+                let (src, dest) = (ptr_of(&src), ptr_of(&dest));
+                done(sc.Present(src, dest, ptr::null_mut(), ptr::null(), args.u32(4)))
+            }
+            "IDirect3DSwapChain9::GetBackBuffer" => {
+                let sc = &*(object as *mut IDirect3DSwapChain9);
+                created(
+                    sc.GetBackBuffer(args.u32(0), args.u32(1), out as *mut _),
+                    2,
+                    ret,
+                )
+            }
+            "IDirect3DSurface9::LockRect" => {
+                let surface = &*(object as *mut IDirect3DSurface9);
+                let result = surface.LockRect(&mut rect, whole, lock_flags(1));
+                let flags = args.u32(1);
+                self.locked((call.object, 0), result, rect.pBits as _, rect.Pitch, flags);
+                done(result)
+            }
+            "IDirect3DSurface9::UnlockRect" => {
+                let surface = &*(object as *mut IDirect3DSurface9);
+                self.unlocked((call.object, 0), args.u32(0), args.bytes(1));
+                done(surface.UnlockRect())
+            }
+            "IDirect3DTexture9::GetSurfaceLevel" => {
+                let texture = &*(object as *mut IDirect3DTexture9);
+                created(texture.GetSurfaceLevel(args.u32(0), out as *mut _), 1, ret)
+            }
+            "IDirect3DTexture9::LockRect" => {
+                let texture = &*(object as *mut IDirect3DTexture9);
+                let level = args.u32(0);
+                let result = texture.LockRect(level, &mut rect, whole, lock_flags(2));
+                let flags = args.u32(2);
+                self.locked(
+                    (call.object, level),
+                    result,
+                    rect.pBits as _,
+                    rect.Pitch,
+                    flags,
+                );
+                done(result)
+            }
+            "IDirect3DTexture9::UnlockRect" => {
+                let texture = &*(object as *mut IDirect3DTexture9);
+                let level = args.u32(0);
+                self.unlocked((call.object, level), args.u32(1), args.bytes(2));
+                done(texture.UnlockRect(level))
+            }
+            "IDirect3DTexture9::AddDirtyRect" => {
+                let texture = &*(object as *mut IDirect3DTexture9);
+                let r: Option<RECT> = args.data(0);
+                done(texture.AddDirtyRect(ptr_of(&r)))
+            }
+            "IDirect3DCubeTexture9::GetCubeMapSurface" => {
+                let cube = &*(object as *mut IDirect3DCubeTexture9);
+                created(
+                    cube.GetCubeMapSurface(args.u32(0), args.u32(1), out as *mut _),
+                    2,
+                    ret,
+                )
+            }
+            "IDirect3DCubeTexture9::LockRect" => {
+                let cube = &*(object as *mut IDirect3DCubeTexture9);
+                let (face, level) = (args.u32(0), args.u32(1));
+                let result = cube.LockRect(face, level, &mut rect, whole, lock_flags(3));
+                let key = (call.object, (face << 16) | level);
+                self.locked(key, result, rect.pBits as _, rect.Pitch, args.u32(3));
+                done(result)
+            }
+            "IDirect3DCubeTexture9::UnlockRect" => {
+                let cube = &*(object as *mut IDirect3DCubeTexture9);
+                let (face, level) = (args.u32(0), args.u32(1));
+                let key = (call.object, (face << 16) | level);
+                self.unlocked(key, args.u32(2), args.bytes(3));
+                done(cube.UnlockRect(face, level))
+            }
+            "IDirect3DCubeTexture9::AddDirtyRect" => {
+                let cube = &*(object as *mut IDirect3DCubeTexture9);
+                let r: Option<RECT> = args.data(1);
+                done(cube.AddDirtyRect(args.u32(0), ptr_of(&r)))
+            }
+            "IDirect3DVertexBuffer9::Lock" | "IDirect3DIndexBuffer9::Lock" => {
+                // Both buffer interfaces have the same layout.
+                let buffer = &*(object as *mut IDirect3DVertexBuffer9);
+                let offset = args.u32(0);
+                let mut data = ptr::null_mut();
+                let result = buffer.Lock(offset, args.u32(1), &mut data, lock_flags(2));
+
+                // The recorded contents start at the beginning of the buffer.
+                let data = (data as *mut u8).wrapping_offset(-(offset as isize));
+                self.locked((call.object, 0), result, data, 0, args.u32(2));
+                done(result)
+            }
+            "IDirect3DVertexBuffer9::Unlock" | "IDirect3DIndexBuffer9::Unlock" => {
+                let buffer = &*(object as *mut IDirect3DVertexBuffer9);
+                self.unlocked((call.object, 0), 0, args.bytes(0));
+                done(buffer.Unlock())
+            }
+            "IDirect3DQuery9::Issue" => {
+                let query = &*(object as *mut IDirect3DQuery9);
+                done(query.Issue(args.u32(0)))
+            }
+            "IDirect3DStateBlock9::Capture" => {
+                done((&*(object as *mut IDirect3DStateBlock9)).Capture())
+            }
+            "IDirect3DStateBlock9::Apply" => {
+                done((&*(object as *mut IDirect3DStateBlock9)).Apply())
+            }
+            _ => None,
+        }
+    }
+
+    /// Copies the image which is about to be presented, or the current render target,
+    /// into a surface the CPU can read.
+    unsafe fn read_back(&self, present: Option<&Call>) -> io::Result<ComPtr<IDirect3DSurface9>> {
+        let device = match self.device.as_ref() {
+            Some(device) => device,
+            None => return Err(other("no device was created")),
+        };
+
+        let mut surface = ptr::null_mut();
+
+        let result = match present {
+            Some(call) if call.method.starts_with("IDirect3DSwapChain9::") => {
+                let sc = &*(call.object as usize as *mut IDirect3DSwapChain9);
+                sc.GetBackBuffer(0, D3DBACKBUFFER_TYPE_MONO, &mut surface)
+            }
+            Some(_) => device.GetBackBuffer(0, 0, D3DBACKBUFFER_TYPE_MONO, &mut surface),
+            None => device.GetRenderTarget(0, &mut surface),
+        };
+        check(result, "retrieve the render target")?;
+
+        let surface = ComPtr::new(surface);
+
+        let mut desc = mem::zeroed::<D3DSURFACE_DESC>();
+        check(
+            surface.GetDesc(&mut desc),
+            "retrieve the render target's description",
+        )?;
+
+        let mut copy = ptr::null_mut();
+        let result = device.CreateOffscreenPlainSurface(
+            desc.Width,
+            desc.Height,
+            desc.Format,
+            D3DPOOL_SYSTEMMEM,
+            &mut copy,
+            ptr::null_mut(),
+        );
+        check(result, "create a surface for the copy")?;
+
+        let copy = ComPtr::new(copy);
+
+        let result = device.GetRenderTargetData(surface.as_mut(), copy.as_mut());
+        check(result, "copy the render target")?;
+
+        Ok(copy)
+    }
+}
+
+impl Target for D3D9Target {
+    fn execute(&mut self, call: &Call) -> Option<Executed> {
+        let object = call.object as usize;
+
+        if object == 0 && !call.method.starts_with("Direct3DCreate9") {
+            return None;
+        }
+
+        unsafe {
+            if call.method.starts_with("IUnknown::") {
+                return self.execute_unknown(call, &*(object as *mut IUnknown));
+            }
+
+            if call.method.starts_with("IDirect3D9") {
+                return self.execute_context(call, &*(object as *mut IDirect3D9Ex));
+            }
+
+            if call.method.starts_with("IDirect3DDevice9") {
+                return self.execute_device(call, &*(object as *mut IDirect3DDevice9Ex));
+            }
+
+            match call.method.as_str() {
+                "Direct3DCreate9" | "Direct3DCreate9Ex" => {
+                    let sdk_version = Args(&call.args).u32(0);
+                    let ex = call.method == "Direct3DCreate9Ex";
+
+                    let mut ctx: *mut Context = ptr::null_mut();
+                    let result = match self.backend {
+                        Some(ref backend) => {
+                            ctx = Context::with_backend(sdk_version, ex, backend.clone()).into();
+                            Error::Success
+                        }
+                        None if ex => crate::Direct3DCreate9Ex(sdk_version, &mut ctx),
+                        None => match crate::Direct3DCreate9(sdk_version) {
+                            Some(created) => {
+                                ctx = created.into();
+                                Error::Success
+                            }
+                            None => Error::NotAvailable,
+                        },
+                    };
+
+                    // The non-Ex entry point does not return an error code.
+                    let result = if ex { result as i32 } else { 0 };
+
+                    created(result, 1, ctx)
+                }
+                _ => self.execute_resource(call),
+            }
+        }
+    }
+
+    fn dump(&mut self, present: Option<&Call>, path: &Path) -> io::Result<()> {
+        unsafe {
+            let surface = self.read_back(present)?;
+
+            let mut desc = mem::zeroed::<D3DSURFACE_DESC>();
+            check(
+                surface.GetDesc(&mut desc),
+                "retrieve the copy's description",
+            )?;
+
+            match desc.Format {
+                D3DFMT_A8R8G8B8 | D3DFMT_X8R8G8B8 => (),
+                fmt => return Err(other(&format!("unsupported format {}", fmt))),
+            }
+
+            let mut rect = mem::zeroed::<D3DLOCKED_RECT>();
+            check(
+                surface.LockRect(&mut rect, ptr::null(), D3DLOCK_READONLY),
+                "lock the copy",
+            )?;
+
+            let len = rect.Pitch as usize * desc.Height as usize;
+            let data = std::slice::from_raw_parts(rect.pBits as *const u8, len);
+
+            let file = File::create(path).map(BufWriter::new);
+            let result = file.and_then(|mut file| {
+                write_bmp(
+                    &mut file,
+                    desc.Width,
+                    desc.Height,
+                    rect.Pitch as usize,
+                    data,
+                )?;
+                file.flush()
+            });
+
+            surface.UnlockRect();
+
+            result
+        }
+    }
+}
+
+fn other(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+fn check(result: i32, what: &str) -> io::Result<()> {
+    if result < 0 {
+        Err(other(&format!("failed to {}: {:#x}", what, result)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Writes an image in the BMP format.
+///
+/// The pixels have to be stored as 32-bit BGRA,
+/// which is what `D3DFMT_A8R8G8B8` looks like in memory.
+fn write_bmp<W: Write>(
+    out: &mut W,
+    width: u32,
+    height: u32,
+    pitch: usize,
+    data: &[u8],
+) -> io::Result<()> {
+    const HEADER_SIZE: u32 = 14 + 40;
+
+    fn write_u16<W: Write>(out: &mut W, value: u16) -> io::Result<()> {
+        out.write_all(&[value as u8, (value >> 8) as u8])
+    }
+
+    fn write_u32<W: Write>(out: &mut W, value: u32) -> io::Result<()> {
+        write_u16(out, value as u16)?;
+        write_u16(out, (value >> 16) as u16)
+    }
+
+    let row = width as usize * 4;
+    let size = row as u32 * height;
+
+    // File header.
+    out.write_all(b"BM")?;
+    write_u32(out, HEADER_SIZE + size)?;
+    write_u32(out, 0)?;
+    write_u32(out, HEADER_SIZE)?;
+
+    // Info header. A negative height means the rows are stored top to bottom.
+    write_u32(out, 40)?;
+    write_u32(out, width)?;
+    write_u32(out, (height as i32).wrapping_neg() as u32)?;
+    write_u16(out, 1)?;
+    write_u16(out, 32)?;
+    write_u32(out, 0)?;
+    write_u32(out, size)?;
+    // 72 DPI, in pixels per meter.
+    write_u32(out, 2835)?;
+    write_u32(out, 2835)?;
+    write_u32(out, 0)?;
+    write_u32(out, 0)?;
+
+    for y in 0..height as usize {
+        out.write_all(&data[y * pitch..y * pitch + row])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bmp_header() {
+        // A 2x2 image, with some padding at the end of each row.
+        let data = [
+            1, 2, 3, 4, 5, 6, 7, 8, 0xAA, 0xAA, //
+            9, 10, 11, 12, 13, 14, 15, 16, 0xAA, 0xAA,
+        ];
+
+        let mut bmp = Vec::new();
+        write_bmp(&mut bmp, 2, 2, 10, &data).unwrap();
+
+        assert_eq!(bmp.len(), 54 + 16);
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(&bmp[2..6], &[70, 0, 0, 0]);
+        assert_eq!(&bmp[22..26], &[0xFE, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(
+            &bmp[54..],
+            &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]
+        );
+    }
+
+    #[test]
+    fn replay_on_mock_backend() {
+        use crate::backend::mock::{Call as BackendCall, MockBackend};
+        use crate::trace::{self, Options, Replayer};
+
+        let call = |object: u64, method: &str, args: Vec<Value>| Call {
+            object,
+            method: method.into(),
+            args,
+            result: 0,
+        };
+
+        let (ctx, device, surface, rt) = (0x100, 0x200, 0x300, 0x400);
+
+        let mut pp: D3DPRESENT_PARAMETERS = unsafe { mem::zeroed() };
+        pp.BackBufferWidth = 2;
+        pp.BackBufferHeight = 2;
+        pp.BackBufferFormat = D3DFMT_X8R8G8B8;
+        pp.BackBufferCount = 1;
+        pp.SwapEffect = D3DSWAPEFFECT_DISCARD;
+        pp.Windowed = 1;
+
+        // The pixels are recorded with a larger pitch than the surface's.
+        let pixels = vec![
+            1, 2, 3, 0, 4, 5, 6, 0, 0xAA, 0xAA, //
+            7, 8, 9, 0, 10, 11, 12, 0, 0xAA, 0xAA,
+        ];
+
+        let calls = vec![
+            call(
+                0,
+                "Direct3DCreate9",
+                vec![D3D_SDK_VERSION.into(), Value::Object(ctx)],
+            ),
+            call(
+                ctx,
+                "IDirect3D9::CreateDevice",
+                vec![
+                    0u32.into(),
+                    D3DDEVTYPE_HAL.into(),
+                    Value::Object(0x1234),
+                    D3DCREATE_FPU_PRESERVE.into(),
+                    trace::data(&pp),
+                    Value::Object(device),
+                ],
+            ),
+            call(
+                device,
+                "IDirect3DDevice9::CreateOffscreenPlainSurface",
+                vec![
+                    2u32.into(),
+                    2u32.into(),
+                    D3DFMT_X8R8G8B8.into(),
+                    D3DPOOL_SYSTEMMEM.into(),
+                    Value::Object(surface),
+                ],
+            ),
+            call(
+                surface,
+                "IDirect3DSurface9::LockRect",
+                vec![trace::data::<RECT>(ptr::null()), 0u32.into()],
+            ),
+            call(
+                surface,
+                "IDirect3DSurface9::UnlockRect",
+                vec![10u32.into(), pixels.into()],
+            ),
+            call(
+                device,
+                "IDirect3DDevice9::GetRenderTarget",
+                vec![0u32.into(), Value::Object(rt)],
+            ),
+            call(
+                device,
+                "IDirect3DDevice9::UpdateSurface",
+                vec![
+                    Value::Object(surface),
+                    trace::data::<RECT>(ptr::null()),
+                    Value::Object(rt),
+                    trace::data(&POINT { x: 0, y: 0 }),
+                ],
+            ),
+            call(
+                device,
+                "IDirect3DDevice9::Present",
+                vec![
+                    trace::data::<RECT>(ptr::null()),
+                    trace::data::<RECT>(ptr::null()),
+                ],
+            ),
+        ];
+
+        let dir = std::env::temp_dir().join(format!("d3d9-replay-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let backend = Rc::new(MockBackend::new());
+        let target = D3D9Target::with_backend(1 as HWND, backend.clone());
+
+        let options = Options {
+            stop_at: None,
+            dump_dir: Some(dir.clone()),
+        };
+
+        let mut replayer = Replayer::new(target, options);
+        for call in &calls {
+            replayer.replay_call(call);
+        }
+
+        let stats = replayer.stats();
+        assert_eq!((stats.calls, stats.skipped, stats.mismatches), (8, 0, 0));
+        assert_eq!(stats.frames, 1);
+
+        // The device was created on the mock, with the recorded back buffer.
+        let backend_calls = backend.calls();
+        assert!(backend_calls.iter().any(|call| match *call {
+            BackendCall::CreateSwapChain {
+                width: 2,
+                height: 2,
+                windowed: true,
+                ..
+            } => true,
+            _ => false,
+        }));
+        assert!(backend_calls.iter().any(|call| match *call {
+            BackendCall::Present { .. } => true,
+            _ => false,
+        }));
+
+        // The frame was dumped before being presented, with the pixels written by the capture.
+        let bmp = std::fs::read(dir.join("frame-00000.bmp"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let bmp = bmp.unwrap();
+        assert_eq!(bmp.len(), 54 + 16);
+        assert_eq!(&bmp[54..57], &[1, 2, 3]);
+        assert_eq!(&bmp[58..61], &[4, 5, 6]);
+        assert_eq!(&bmp[62..65], &[7, 8, 9]);
+        assert_eq!(&bmp[66..69], &[10, 11, 12]);
+    }
+
+    #[test]
+    fn lock_contents() {
+        // The recorded pitch is larger than the replayed one.
+        let contents = [1, 2, 3, 0, 4, 5, 6, 0];
+        let mut memory = [0u8; 6];
+
+        let lock = Lock {
+            data: memory.as_mut_ptr(),
+            pitch: 3,
+            read_only: false,
+        };
+
+        unsafe { lock.write(4, &contents) };
+        assert_eq!(memory, [1, 2, 3, 4, 5, 6]);
+    }
+}
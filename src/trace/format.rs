@@ -0,0 +1,380 @@
+//! Binary format of capture files.
+//!
+//! A capture starts with a header, which identifies the file and the pointer size
+//! of the process it was recorded in. Structures are recorded as raw memory,
+//! so a capture can only be replayed by a build for the same architecture.
+//!
+//! The header is followed by a sequence of records, each starting with a tag byte:
+//!
+//! - a method name, which is assigned the next free index.
+//!   Names are only written the first time they are used.
+//! - a call, which refers to its method by index, followed by the object it was
+//!   called on, the value it returned and its arguments.
+//!
+//! Integers are encoded as LEB128 variable length integers, since most of them are small.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::mem;
+
+/// Identifies a capture file.
+const MAGIC: [u8; 4] = *b"D9TR";
+
+/// Version of the format, incremented whenever it changes.
+const VERSION: u8 = 1;
+
+const TAG_METHOD: u8 = 0;
+const TAG_CALL: u8 = 1;
+
+const TAG_U32: u8 = 0;
+const TAG_I32: u8 = 1;
+const TAG_F32: u8 = 2;
+const TAG_OBJECT: u8 = 3;
+const TAG_BYTES: u8 = 4;
+
+/// Argument of a recorded call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    U32(u32),
+    I32(i32),
+    F32(f32),
+    /// Address of a COM object, or null.
+    Object(u64),
+    /// Contents of a structure, an array or some locked memory.
+    Bytes(Vec<u8>),
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::U32(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::I32(value)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Value::F32(value)
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(bytes)
+    }
+}
+
+/// A call read back from a capture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    /// Address of the object the method was called on.
+    pub object: u64,
+    /// Name of the method, in the `Interface::Method` form.
+    pub method: String,
+    /// The arguments, in the order they were recorded in.
+    pub args: Vec<Value>,
+    /// The `HRESULT` or other value returned by the method.
+    pub result: u32,
+}
+
+/// Writes a capture to a stream.
+pub struct Writer<W: Write> {
+    stream: W,
+    methods: HashMap<String, u32>,
+}
+
+impl<W: Write> Writer<W> {
+    /// Writes the header of a capture, and returns a writer for the calls.
+    pub fn new(mut stream: W) -> io::Result<Self> {
+        stream.write_all(&MAGIC)?;
+        stream.write_all(&[VERSION, mem::size_of::<usize>() as u8])?;
+
+        Ok(Self {
+            stream,
+            methods: HashMap::new(),
+        })
+    }
+
+    /// Records a call.
+    pub fn write_call(
+        &mut self,
+        object: u64,
+        method: &str,
+        args: &[Value],
+        result: u32,
+    ) -> io::Result<()> {
+        let index = match self.methods.get(method) {
+            Some(&index) => index,
+            None => {
+                let index = self.methods.len() as u32;
+                self.stream.write_all(&[TAG_METHOD])?;
+                self.write_bytes(method.as_bytes())?;
+                self.methods.insert(method.to_owned(), index);
+                index
+            }
+        };
+
+        self.stream.write_all(&[TAG_CALL])?;
+        self.write_int(u64::from(index))?;
+        self.write_int(object)?;
+        self.write_int(u64::from(result))?;
+        self.write_int(args.len() as u64)?;
+
+        for arg in args {
+            self.write_value(arg)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes any buffered records to the underlying stream.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+
+    fn write_value(&mut self, value: &Value) -> io::Result<()> {
+        match *value {
+            Value::U32(value) => {
+                self.stream.write_all(&[TAG_U32])?;
+                self.write_int(u64::from(value))
+            }
+            Value::I32(value) => {
+                self.stream.write_all(&[TAG_I32])?;
+                self.write_int(u64::from(value as u32))
+            }
+            Value::F32(value) => {
+                self.stream.write_all(&[TAG_F32])?;
+                self.write_int(u64::from(value.to_bits()))
+            }
+            Value::Object(address) => {
+                self.stream.write_all(&[TAG_OBJECT])?;
+                self.write_int(address)
+            }
+            Value::Bytes(ref bytes) => {
+                self.stream.write_all(&[TAG_BYTES])?;
+                self.write_bytes(bytes)
+            }
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_int(bytes.len() as u64)?;
+        self.stream.write_all(bytes)
+    }
+
+    fn write_int(&mut self, mut value: u64) -> io::Result<()> {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                return self.stream.write_all(&[byte]);
+            }
+
+            self.stream.write_all(&[byte | 0x80])?;
+        }
+    }
+}
+
+/// Reads a capture from a stream.
+pub struct Reader<R: Read> {
+    stream: R,
+    methods: Vec<String>,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<R: Read> Reader<R> {
+    /// Checks the header of a capture, and returns a reader for the calls.
+    pub fn new(mut stream: R) -> io::Result<Self> {
+        let mut header = [0; 6];
+        stream.read_exact(&mut header)?;
+
+        if header[..4] != MAGIC {
+            return Err(invalid_data("not a capture file"));
+        }
+
+        if header[4] != VERSION {
+            return Err(invalid_data("unsupported capture version"));
+        }
+
+        if header[5] as usize != mem::size_of::<usize>() {
+            return Err(invalid_data(
+                "capture was recorded on a different architecture",
+            ));
+        }
+
+        Ok(Self {
+            stream,
+            methods: Vec::new(),
+        })
+    }
+
+    /// Reads the next call, or returns `None` at the end of the capture.
+    pub fn read_call(&mut self) -> io::Result<Option<Call>> {
+        loop {
+            let mut tag = [0];
+            if self.stream.read(&mut tag)? == 0 {
+                return Ok(None);
+            }
+
+            match tag[0] {
+                TAG_METHOD => {
+                    let name = String::from_utf8(self.read_bytes()?)
+                        .map_err(|_| invalid_data("method name is not valid UTF-8"))?;
+                    self.methods.push(name);
+                }
+                TAG_CALL => return self.read_call_record().map(Some),
+                _ => return Err(invalid_data("unknown record")),
+            }
+        }
+    }
+
+    fn read_call_record(&mut self) -> io::Result<Call> {
+        let index = self.read_int()? as usize;
+        let method = self
+            .methods
+            .get(index)
+            .cloned()
+            .ok_or_else(|| invalid_data("call to an undefined method"))?;
+
+        let object = self.read_int()?;
+        let result = self.read_int()? as u32;
+
+        let count = self.read_int()?;
+        let args = (0..count)
+            .map(|_| self.read_value())
+            .collect::<io::Result<_>>()?;
+
+        Ok(Call {
+            object,
+            method,
+            args,
+            result,
+        })
+    }
+
+    fn read_value(&mut self) -> io::Result<Value> {
+        let mut tag = [0];
+        self.stream.read_exact(&mut tag)?;
+
+        Ok(match tag[0] {
+            TAG_U32 => Value::U32(self.read_int()? as u32),
+            TAG_I32 => Value::I32(self.read_int()? as u32 as i32),
+            TAG_F32 => Value::F32(f32::from_bits(self.read_int()? as u32)),
+            TAG_OBJECT => Value::Object(self.read_int()?),
+            TAG_BYTES => Value::Bytes(self.read_bytes()?),
+            _ => return Err(invalid_data("unknown value type")),
+        })
+    }
+
+    fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.read_int()? as usize;
+        let mut bytes = vec![0; len];
+        self.stream.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_int(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte)?;
+
+            value |= u64::from(byte[0] & 0x7F) << shift;
+
+            if byte[0] & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(invalid_data("integer is too long"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut writer = Writer::new(Vec::new()).unwrap();
+
+        let args = [
+            Value::U32(7),
+            Value::I32(-1),
+            Value::F32(0.5),
+            Value::Object(0xDEAD_BEEF_0000),
+            Value::Bytes(vec![1, 2, 3]),
+        ];
+
+        writer
+            .write_call(0x1000, "IDirect3DDevice9::SetRenderState", &args, 0)
+            .unwrap();
+        writer
+            .write_call(0x2000, "IUnknown::Release", &[], 1)
+            .unwrap();
+        writer
+            .write_call(0x1000, "IDirect3DDevice9::SetRenderState", &[], 0x8876_086C)
+            .unwrap();
+
+        let data = writer.stream;
+        let mut reader = Reader::new(&data[..]).unwrap();
+
+        let call = reader.read_call().unwrap().unwrap();
+        assert_eq!(call.object, 0x1000);
+        assert_eq!(call.method, "IDirect3DDevice9::SetRenderState");
+        assert_eq!(call.args, args);
+        assert_eq!(call.result, 0);
+
+        let call = reader.read_call().unwrap().unwrap();
+        assert_eq!(call.method, "IUnknown::Release");
+        assert_eq!(call.result, 1);
+
+        let call = reader.read_call().unwrap().unwrap();
+        assert_eq!(call.method, "IDirect3DDevice9::SetRenderState");
+        assert_eq!(call.result, 0x8876_086C);
+
+        assert_eq!(reader.read_call().unwrap(), None);
+    }
+
+    #[test]
+    fn method_names_are_written_once() {
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        let name = "IDirect3DDevice9::SetTransform";
+
+        writer.write_call(1, name, &[], 0).unwrap();
+        let first = writer.stream.len();
+        writer.write_call(1, name, &[], 0).unwrap();
+        let second = writer.stream.len() - first;
+
+        // Tag, method index, object, result and argument count.
+        assert_eq!(second, 5);
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(Reader::new(&b"MZ\x90\x00\x03\x00"[..]).is_err());
+        assert!(Reader::new(&b"D9TR\x63\x08"[..]).is_err());
+
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&[VERSION, mem::size_of::<usize>() as u8]);
+
+        // A call to a method which was never named.
+        let mut data = header.clone();
+        data.extend_from_slice(&[TAG_CALL, 3, 0, 0, 0]);
+        assert!(Reader::new(&data[..]).unwrap().read_call().is_err());
+
+        // A truncated record.
+        let mut data = header;
+        data.extend_from_slice(&[TAG_METHOD, 10, b'a']);
+        assert!(Reader::new(&data[..]).unwrap().read_call().is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Recording of API calls to a capture file.
+//!
+//! When the user sets the `trace_file` option, every call the app makes which changes
+//! the state of an object, or which creates or returns an object, is written to a file.
+//! Together with the contents of locked resources and shaders' bytecode,
+//! this is enough to reproduce a game's rendering without owning the game.
+//!
+//! Calls which only query information (e.g. `GetRenderState` or `CheckDeviceFormat`)
+//! are not recorded, since replaying them has no effect.
+//!
+//! Calls made by the library itself while running another method are not recorded either:
+//! replaying the outer call will make them again.
+//!
+//! Captures are replayed by the `Replayer`, which can execute them on this library's own
+//! objects through the `D3D9Target`, or on any other implementation of `Target`.
+
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Mutex;
+use std::{mem, ptr, slice};
+
+use crate::Error;
+
+pub mod format;
+pub use self::format::{Call, Reader, Value, Writer};
+
+pub mod replay;
+pub use self::replay::{Executed, Options, Replayer, Stats, Target};
+
+mod d3d9;
+pub use self::d3d9::D3D9Target;
+
+/// Records calls into a capture file.
+pub struct Tracer {
+    writer: Mutex<Writer<BufWriter<File>>>,
+}
+
+/// The tracer for the whole process, if tracing is enabled.
+///
+/// Once set, it lives until the process exits.
+static TRACER: AtomicPtr<Tracer> = AtomicPtr::new(ptr::null_mut());
+
+thread_local! {
+    /// Number of traced calls currently running on this thread.
+    static DEPTH: Cell<u32> = Cell::new(0);
+}
+
+/// Starts recording calls to a file.
+///
+/// Does nothing if tracing was already started, since a process only records one capture.
+pub fn start(path: &Path) {
+    if tracer().is_some() {
+        return;
+    }
+
+    let tracer = match Tracer::create(path) {
+        Ok(tracer) => tracer,
+        Err(err) => {
+            error!("Failed to create trace file {}: {}", path.display(), err);
+            return;
+        }
+    };
+
+    let tracer = Box::into_raw(Box::new(tracer));
+
+    let result =
+        TRACER.compare_exchange(ptr::null_mut(), tracer, Ordering::SeqCst, Ordering::SeqCst);
+
+    if result.is_ok() {
+        info!("Recording API calls to {}", path.display());
+    } else {
+        // Another thread got there first.
+        drop(unsafe { Box::from_raw(tracer) });
+    }
+}
+
+/// Returns the tracer, if calls are being recorded.
+pub fn tracer() -> Option<&'static Tracer> {
+    unsafe { TRACER.load(Ordering::SeqCst).as_ref() }
+}
+
+impl Tracer {
+    /// Creates a tracer which records calls into a new file.
+    fn create(path: &Path) -> io::Result<Self> {
+        let writer = Writer::new(BufWriter::new(File::create(path)?))?;
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Writes a call to the capture file.
+    ///
+    /// The file is flushed at every present, so that a crash only loses the last frame.
+    pub fn record(&self, object: u64, method: &str, args: &[Value], result: u32) {
+        let mut writer = self.writer.lock().unwrap();
+
+        let mut write = || -> io::Result<()> {
+            writer.write_call(object, method, args, result)?;
+
+            if is_present(method) {
+                writer.flush()?;
+            }
+
+            Ok(())
+        };
+
+        if let Err(err) = write() {
+            error!("Failed to write to trace file: {}", err);
+        }
+    }
+}
+
+/// Checks if a method presents a frame.
+pub fn is_present(method: &str) -> bool {
+    method.ends_with("::Present") || method.ends_with("::PresentEx")
+}
+
+/// Values returned by COM methods, which are recorded as a 32-bit integer.
+pub trait ReturnValue {
+    fn code(&self) -> u32;
+}
+
+impl ReturnValue for Error {
+    fn code(&self) -> u32 {
+        *self as u32
+    }
+}
+
+impl ReturnValue for u32 {
+    fn code(&self) -> u32 {
+        *self
+    }
+}
+
+impl ReturnValue for i32 {
+    fn code(&self) -> u32 {
+        *self as u32
+    }
+}
+
+impl ReturnValue for () {
+    fn code(&self) -> u32 {
+        0
+    }
+}
+
+/// Restores the call depth when a traced call returns, or panics.
+struct DepthGuard(u32);
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(self.0));
+    }
+}
+
+/// Runs the body of a method, then records it if it was called by the app.
+///
+/// The arguments are only collected after the method returns,
+/// so that objects returned through pointers can be recorded.
+/// Use the `traced` macro instead of calling this directly.
+pub fn call<R, F, A>(object: u64, method: &str, body: F, args: A) -> R
+where
+    R: ReturnValue,
+    F: FnOnce() -> R,
+    A: FnOnce() -> Vec<Value>,
+{
+    call_with(tracer(), object, method, body, args)
+}
+
+/// Runs the body of a method, then records it with a tracer if it was called by the app.
+fn call_with<R, F, A>(tracer: Option<&Tracer>, object: u64, method: &str, body: F, args: A) -> R
+where
+    R: ReturnValue,
+    F: FnOnce() -> R,
+    A: FnOnce() -> Vec<Value>,
+{
+    let tracer = match tracer {
+        Some(tracer) => tracer,
+        None => return body(),
+    };
+
+    let depth = DEPTH.with(|depth| depth.replace(depth.get() + 1));
+
+    let result = {
+        let _guard = DepthGuard(depth);
+        body()
+    };
+
+    if depth == 0 {
+        tracer.record(object, method, &args(), result.code());
+    }
+
+    result
+}
+
+/// Records the address of an object.
+pub fn object<T>(ptr: *const T) -> Value {
+    Value::Object(ptr as usize as u64)
+}
+
+/// Records the object a method returned through a pointer.
+pub fn returned<T>(ret: *mut *mut T) -> Value {
+    let ptr = unsafe { ret.as_ref() }.map_or(ptr::null_mut(), |ptr| *ptr);
+    object(ptr)
+}
+
+/// Records the contents of a structure passed by pointer.
+///
+/// A null pointer is recorded as no data.
+pub fn data<T>(ptr: *const T) -> Value {
+    match unsafe { ptr.as_ref() } {
+        Some(data) => array(slice::from_ref(data)),
+        None => Value::Bytes(Vec::new()),
+    }
+}
+
+/// Records the contents of an array.
+pub fn array<T>(data: &[T]) -> Value {
+    let bytes = unsafe {
+        slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * mem::size_of::<T>())
+    };
+    Value::Bytes(bytes.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::{env, fs, process};
+
+    #[test]
+    fn returned_objects() {
+        let object = 0x1234usize as *mut u8;
+        let mut ret = object;
+
+        assert_eq!(returned(&mut ret), Value::Object(0x1234));
+        assert_eq!(returned::<u8>(ptr::null_mut()), Value::Object(0));
+    }
+
+    #[test]
+    fn structures() {
+        let rect = [1u16, 2, 3, 4];
+
+        assert_eq!(data(&rect), Value::Bytes(vec![1, 0, 2, 0, 3, 0, 4, 0]));
+        assert_eq!(data::<u32>(ptr::null()), Value::Bytes(Vec::new()));
+        assert_eq!(array(&[0xFFFFu32]), Value::Bytes(vec![0xFF, 0xFF, 0, 0]));
+    }
+
+    /// Records calls with its own tracer, instead of the process' one.
+    struct Object<'a> {
+        tracer: &'a Tracer,
+    }
+
+    impl<'a> Object<'a> {
+        fn trace(&self, method: &str, arg: u32, body: impl FnOnce() -> Error) -> Error {
+            let object = self as *const _ as usize as u64;
+            call_with(Some(self.tracer), object, method, body, || vec![arg.into()])
+        }
+
+        fn inner(&self, value: u32) -> Error {
+            self.trace("IObject::Inner", value, || Error::Success)
+        }
+
+        fn present(&self, value: u32) -> Error {
+            self.trace("IObject::Present", value + 1, || {
+                self.inner(value);
+                Error::InvalidCall
+            })
+        }
+    }
+
+    /// Deletes a file when the test ends, even if it fails.
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn nested_calls_are_not_recorded() {
+        let name = format!("d3d9-trace-test-{}.bin", process::id());
+        let temp = TempFile(env::temp_dir().join(name));
+        let path = &temp.0;
+
+        let tracer = Tracer::create(path).unwrap();
+
+        let object = Object { tracer: &tracer };
+        object.present(5);
+
+        let file = File::open(path).unwrap();
+        let mut reader = Reader::new(file).unwrap();
+
+        let call = reader.read_call().unwrap().unwrap();
+        assert_eq!(call.object, &object as *const _ as usize as u64);
+        assert_eq!(call.method, "IObject::Present");
+        assert_eq!(call.args, [Value::U32(6)]);
+        assert_eq!(call.result, Error::InvalidCall as u32);
+
+        assert_eq!(reader.read_call().unwrap(), None);
+    }
+}
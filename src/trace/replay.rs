@@ -0,0 +1,352 @@
+//! Replaying of captures.
+//!
+//! The replayer reads the calls from a capture, and executes them one by one on a `Target`.
+//! Objects created during the replay have different addresses than the recorded ones,
+//! so the replayer keeps a map from the recorded addresses to the new objects,
+//! and translates the arguments of each call before executing it.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use super::{is_present, Call, Reader, Value};
+
+/// Options which control how a capture is replayed.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Index of the call at which to stop, without executing it.
+    ///
+    /// Useful for finding the call which breaks the rendering, by bisecting.
+    pub stop_at: Option<usize>,
+    /// Directory to which the render target is saved before each present,
+    /// and when the replay stops.
+    pub dump_dir: Option<PathBuf>,
+}
+
+/// The result of executing a call.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Executed {
+    /// The value the method returned.
+    pub result: u32,
+    /// Objects returned through pointers, along with the index of the argument
+    /// which holds the recorded object.
+    pub returned: Vec<(usize, u64)>,
+}
+
+/// Implementation of the API, on which the calls are replayed.
+pub trait Target {
+    /// Executes a call.
+    ///
+    /// The objects passed to the method were already translated to the ones created
+    /// by the target, while the objects returned by the method still have the recorded addresses.
+    ///
+    /// Returns `None` if the method is not supported.
+    fn execute(&mut self, call: &Call) -> Option<Executed>;
+
+    /// Saves the current render target to an image file.
+    ///
+    /// When called before a present, the call is passed in too,
+    /// since it determines which image will be presented.
+    fn dump(&mut self, present: Option<&Call>, path: &Path) -> io::Result<()>;
+}
+
+/// Statistics about a replay.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Stats {
+    /// Number of calls which were executed.
+    pub calls: usize,
+    /// Number of calls which were not supported, or which were made on unknown objects.
+    pub skipped: usize,
+    /// Number of calls which returned a different value than when they were recorded.
+    pub mismatches: usize,
+    /// Number of presented frames.
+    pub frames: usize,
+}
+
+/// Replays captures on a target.
+pub struct Replayer<T: Target> {
+    target: T,
+    options: Options,
+    // Maps the recorded objects to the target's objects.
+    objects: HashMap<u64, u64>,
+    // Index of the next call.
+    position: usize,
+    stats: Stats,
+}
+
+impl<T: Target> Replayer<T> {
+    /// Creates a new replayer.
+    pub fn new(target: T, options: Options) -> Self {
+        Self {
+            target,
+            options,
+            objects: HashMap::new(),
+            position: 0,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Retrieves the target on which calls are replayed.
+    pub fn target(&self) -> &T {
+        &self.target
+    }
+
+    /// Retrieves the statistics of the calls replayed so far.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Replays the calls from a capture, until its end or the call at which to stop.
+    pub fn replay<R: Read>(&mut self, reader: &mut Reader<R>) -> io::Result<Stats> {
+        while let Some(call) = reader.read_call()? {
+            if self.options.stop_at == Some(self.position) {
+                info!("Stopped before call {}: {}", self.position, call.method);
+
+                if let Some(path) = self.dump_path(format!("call-{}.bmp", self.position)) {
+                    self.dump(None, &path);
+                }
+
+                break;
+            }
+
+            self.replay_call(&call);
+        }
+
+        Ok(self.stats)
+    }
+
+    /// Executes a single call.
+    pub fn replay_call(&mut self, call: &Call) {
+        let position = self.position;
+        self.position += 1;
+
+        let object = match self.translate(call.object) {
+            Some(object) => object,
+            None => {
+                warn!("Call {} to {} on unknown object", position, call.method);
+                self.stats.skipped += 1;
+                return;
+            }
+        };
+
+        let translated = Call {
+            object,
+            method: call.method.clone(),
+            args: call
+                .args
+                .iter()
+                .map(|arg| self.translate_value(arg))
+                .collect(),
+            result: call.result,
+        };
+
+        if is_present(&call.method) {
+            let frame = self.stats.frames;
+            self.stats.frames += 1;
+
+            if let Some(path) = self.dump_path(format!("frame-{:05}.bmp", frame)) {
+                self.dump(Some(&translated), &path);
+            }
+        }
+
+        let executed = match self.target.execute(&translated) {
+            Some(executed) => executed,
+            None => {
+                warn!("Call {} to unsupported method {}", position, call.method);
+                self.stats.skipped += 1;
+                return;
+            }
+        };
+
+        self.stats.calls += 1;
+
+        if executed.result != call.result {
+            warn!(
+                "Call {} to {} returned {:#x}, but {:#x} was recorded",
+                position, call.method, executed.result, call.result
+            );
+            self.stats.mismatches += 1;
+        }
+
+        for (index, new) in executed.returned {
+            if let Some(&Value::Object(old)) = call.args.get(index) {
+                if old != 0 && new != 0 {
+                    self.objects.insert(old, new);
+                }
+            }
+        }
+
+        // The address could be reused by the next object the app creates.
+        if call.method == "IUnknown::Release" && call.result == 0 {
+            self.objects.remove(&call.object);
+        }
+    }
+
+    fn translate(&self, object: u64) -> Option<u64> {
+        match object {
+            0 => Some(0),
+            object => self.objects.get(&object).cloned(),
+        }
+    }
+
+    fn translate_value(&self, value: &Value) -> Value {
+        match *value {
+            Value::Object(object) => Value::Object(self.translate(object).unwrap_or(object)),
+            ref value => value.clone(),
+        }
+    }
+
+    fn dump_path(&self, name: String) -> Option<PathBuf> {
+        self.options.dump_dir.as_ref().map(|dir| dir.join(name))
+    }
+
+    fn dump(&mut self, present: Option<&Call>, path: &Path) {
+        if let Err(err) = self.target.dump(present, path) {
+            error!(
+                "Failed to save render target to {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::Writer;
+
+    /// Target which creates objects with consecutive addresses, and remembers the calls.
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<Call>,
+        dumps: Vec<(Option<String>, PathBuf)>,
+        next: u64,
+    }
+
+    impl Target for Recorder {
+        fn execute(&mut self, call: &Call) -> Option<Executed> {
+            self.calls.push(call.clone());
+
+            match call.method.as_str() {
+                "ICreator::Create" => {
+                    self.next += 0x10;
+                    Some(Executed {
+                        result: 0,
+                        returned: vec![(0, self.next)],
+                    })
+                }
+                "IUnknown::Release" => Some(Executed::default()),
+                "IObject::Use" | "IDevice::Present" => Some(Executed::default()),
+                _ => None,
+            }
+        }
+
+        fn dump(&mut self, present: Option<&Call>, path: &Path) -> io::Result<()> {
+            let method = present.map(|call| call.method.clone());
+            self.dumps.push((method, path.to_owned()));
+            Ok(())
+        }
+    }
+
+    fn capture(calls: &[(u64, &str, Vec<Value>, u32)]) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        {
+            let mut writer = Writer::new(&mut data).unwrap();
+            for &(object, method, ref args, result) in calls {
+                writer.write_call(object, method, args, result).unwrap();
+            }
+        }
+
+        data
+    }
+
+    fn replay(data: &[u8], options: Options) -> (Stats, Recorder) {
+        let mut reader = Reader::new(data).unwrap();
+        let mut replayer = Replayer::new(Recorder::default(), options);
+        let stats = replayer.replay(&mut reader).unwrap();
+        (stats, replayer.target)
+    }
+
+    #[test]
+    fn objects_are_translated() {
+        let data = capture(&[
+            (0, "ICreator::Create", vec![Value::Object(0xA000)], 0),
+            (
+                0xA000,
+                "IObject::Use",
+                vec![Value::Object(0xA000), 7u32.into()],
+                0,
+            ),
+            (0xB000, "IObject::Use", vec![], 0),
+            (0xA000, "IObject::Unknown", vec![], 0),
+        ]);
+
+        let (stats, target) = replay(&data, Options::default());
+
+        assert_eq!(target.calls[1].object, 0x10);
+        assert_eq!(target.calls[1].args, [Value::Object(0x10), Value::U32(7)]);
+
+        // The call on an unknown object is not executed at all.
+        assert_eq!(target.calls.len(), 3);
+        assert_eq!(stats.calls, 2);
+        assert_eq!(stats.skipped, 2);
+    }
+
+    #[test]
+    fn released_objects_are_forgotten() {
+        let data = capture(&[
+            (0, "ICreator::Create", vec![Value::Object(0xA000)], 0),
+            (0xA000, "IUnknown::Release", vec![], 0),
+            (0xA000, "IObject::Use", vec![], 0),
+            (0, "ICreator::Create", vec![Value::Object(0xA000)], 0),
+            (0xA000, "IObject::Use", vec![], 0),
+        ]);
+
+        let (stats, target) = replay(&data, Options::default());
+
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(target.calls.last().unwrap().object, 0x20);
+    }
+
+    #[test]
+    fn mismatched_results() {
+        let data = capture(&[(0, "IObject::Use", vec![], 0x8876_086C)]);
+
+        let (stats, _) = replay(&data, Options::default());
+
+        assert_eq!(stats.mismatches, 1);
+    }
+
+    #[test]
+    fn stop_and_dump() {
+        let data = capture(&[
+            (0, "IDevice::Present", vec![], 0),
+            (0, "IObject::Use", vec![], 0),
+            (0, "IDevice::Present", vec![], 0),
+            (0, "IObject::Use", vec![], 0),
+        ]);
+
+        let dir = PathBuf::from("dumps");
+        let options = Options {
+            stop_at: Some(3),
+            dump_dir: Some(dir.clone()),
+        };
+
+        let (stats, target) = replay(&data, options);
+
+        assert_eq!(stats.calls, 3);
+        assert_eq!(stats.frames, 2);
+
+        let present = Some("IDevice::Present".to_owned());
+        assert_eq!(
+            target.dumps,
+            [
+                (present.clone(), dir.join("frame-00000.bmp")),
+                (present, dir.join("frame-00001.bmp")),
+                (None, dir.join("call-3.bmp")),
+            ]
+        );
+    }
+}
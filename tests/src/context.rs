@@ -1,40 +1,69 @@
 use comptr::ComPtr;
-use winapi::shared::{d3d9::*, d3d9types::*};
+use std::rc::Rc;
+use winapi::shared::{d3d9::*, d3d9caps::*, d3d9types::*, winerror::E_NOINTERFACE};
+use winapi::Interface;
 
-pub fn create_context() -> ComPtr<IDirect3D9> {
-    let sdk_version = D3D_SDK_VERSION;
+use d3d9::backend::mock::{Call, MockBackend};
+use d3d9::Error;
 
-    let d3d9 = unsafe { Direct3DCreate9(sdk_version) };
-    assert!(!d3d9.is_null(), "Failed to create IDirect3D9");
+use crate::harness::{ref_count, Test};
 
-    ComPtr::new(d3d9)
+/// A D3D9 context, whose devices run on a mock backend.
+pub struct Context {
+    pub ctx: ComPtr<IDirect3D9>,
+    backend: Rc<MockBackend>,
 }
 
-pub fn run_tests(ctx: &ComPtr<IDirect3D9>) {
-    lifetime(ctx.clone());
-    check_common_formats(&ctx);
+impl Context {
+    pub fn new(backend: Rc<MockBackend>) -> Self {
+        let ctx = create_context(backend.clone(), false);
+        Self { ctx, backend }
+    }
+}
+
+/// Creates a D3D9 context with a single adapter, backed by the given mock.
+///
+/// If `ex` is set, the context is created like `Direct3DCreate9Ex` would.
+pub fn create_context(backend: Rc<MockBackend>, ex: bool) -> ComPtr<IDirect3D9> {
+    let ctx = d3d9::core::Context::with_backend(D3D_SDK_VERSION, ex, backend);
+
+    let ptr: *mut d3d9::core::Context = ctx.into();
+    ComPtr::new(ptr as *mut IDirect3D9)
+}
+
+pub fn tests() -> Vec<Test<Context>> {
+    tests![
+        lifetime,
+        check_common_formats,
+        check_ex_context,
+        check_ex_device
+    ]
 }
 
 // Tests the Context's reference counting mechanisms.
-fn lifetime(ctx: ComPtr<IDirect3D9>) {
-    let count = unsafe { ctx.GetAdapterCount() };
+fn lifetime(ctx: &mut Context) {
+    let count = unsafe { ctx.ctx.GetAdapterCount() };
+    assert_eq!(count, 1);
 
-    let clone;
+    let refs = ref_count(ctx.ctx.as_ref());
 
-    {
-        clone = ctx.clone();
-        std::mem::drop(ctx);
-    }
+    let clone = ctx.ctx.clone();
+    assert_eq!(ref_count(clone.as_ref()), refs + 1);
 
     let new_count = unsafe { clone.GetAdapterCount() };
-
     assert_eq!(count, new_count);
+
+    drop(clone);
+    assert_eq!(ref_count(ctx.ctx.as_ref()), refs);
+
+    // Querying the context must not create anything on the device.
+    assert_eq!(ctx.backend.calls(), vec![]);
 }
 
 // Ensures that some basic formats are supported.
-fn check_common_formats(ctx: &IDirect3D9) {
+fn check_common_formats(ctx: &mut Context) {
     let check_support = |fmt| unsafe {
-        let result = ctx.CheckDeviceFormat(
+        let result = ctx.ctx.CheckDeviceFormat(
             D3DADAPTER_DEFAULT,
             D3DDEVTYPE_HAL,
             D3DFMT_A8R8G8B8,
@@ -42,10 +71,158 @@ fn check_common_formats(ctx: &IDirect3D9) {
             D3DRTYPE_TEXTURE,
             fmt,
         );
-        assert_eq!(result, 0, "Format is not supported: {}", fmt);
+        assert_hr!(result, 0, format!("Format is not supported: {}", fmt));
     };
 
     check_support(D3DFMT_A8B8G8R8);
     check_support(D3DFMT_A16B16G16R16F);
     check_support(D3DFMT_DXT1);
+
+    // Mipmaps are never generated, which the app has to be told about.
+    let result = unsafe {
+        ctx.ctx.CheckDeviceFormat(
+            D3DADAPTER_DEFAULT,
+            D3DDEVTYPE_HAL,
+            D3DFMT_X8R8G8B8,
+            D3DUSAGE_AUTOGENMIPMAP,
+            D3DRTYPE_TEXTURE,
+            D3DFMT_A8R8G8B8,
+        )
+    };
+    assert_hr!(
+        result,
+        Error::NoAutoGen as i32,
+        "Mipmap generation is reported"
+    );
+
+    // Format support is only looked up, no resources are created to check it.
+    assert_eq!(ctx.backend.calls(), vec![]);
+}
+
+/// Queries a D3D9 context for the D3D9Ex interface.
+fn query_ex(ctx: &IDirect3D9) -> Result<ComPtr<IDirect3D9Ex>, i32> {
+    unsafe {
+        let mut ptr = std::ptr::null_mut();
+        match ctx.QueryInterface(&IDirect3D9Ex::uuidof(), &mut ptr) {
+            0 => Ok(ComPtr::new(ptr as *mut IDirect3D9Ex)),
+            result => Err(result),
+        }
+    }
+}
+
+// Checks that the D3D9Ex interface is only available on Ex contexts,
+// and that it can enumerate display modes.
+fn check_ex_context(ctx: &mut Context) {
+    // Apps use this to find out whether they were created through `Direct3DCreate9Ex`.
+    assert_eq!(query_ex(&ctx.ctx).err(), Some(E_NOINTERFACE));
+
+    let old_ctx = ctx.ctx.clone();
+    let ctx =
+        query_ex(&create_context(ctx.backend.clone(), true)).expect("Failed to get IDirect3D9Ex");
+
+    let filter = D3DDISPLAYMODEFILTER {
+        Size: std::mem::size_of::<D3DDISPLAYMODEFILTER>() as u32,
+        Format: D3DFMT_X8R8G8B8,
+        ScanLineOrdering: D3DSCANLINEORDERING_UNKNOWN,
+    };
+
+    // The mock adapter has no outputs, but the Ex modes
+    // must still match the ones reported by the old interface.
+    let count = unsafe { ctx.GetAdapterModeCountEx(D3DADAPTER_DEFAULT, &filter) };
+    let old_count = unsafe { ctx.GetAdapterModeCount(D3DADAPTER_DEFAULT, D3DFMT_X8R8G8B8) };
+    assert_eq!(count, old_count);
+
+    let result = unsafe {
+        let mut luid = std::mem::zeroed();
+        ctx.GetAdapterLUID(D3DADAPTER_DEFAULT, &mut luid)
+    };
+    assert_hr!(result, 0, "Failed to get adapter LUID");
+
+    // Only D3D9Ex devices can share resources.
+    let caps = |ctx: &IDirect3D9| unsafe {
+        let mut caps = std::mem::zeroed();
+        let result = ctx.GetDeviceCaps(D3DADAPTER_DEFAULT, D3DDEVTYPE_HAL, &mut caps);
+        assert_hr!(result, 0, "Failed to get device caps");
+        caps
+    };
+
+    assert_eq!(caps(&old_ctx).Caps2 & D3DCAPS2_CANSHARERESOURCE, 0);
+    assert_ne!(caps(&ctx).Caps2 & D3DCAPS2_CANSHARERESOURCE, 0);
+}
+
+// Checks that Ex devices switch to the display mode they are reset with.
+fn check_ex_device(ctx: &mut Context) {
+    let ex_ctx = query_ex(&create_context(ctx.backend.clone(), true)).unwrap();
+
+    let window = crate::harness::dummy_window();
+    let mut pp = D3DPRESENT_PARAMETERS {
+        BackBufferWidth: 64,
+        BackBufferHeight: 64,
+        BackBufferFormat: D3DFMT_X8R8G8B8,
+        BackBufferCount: 1,
+        SwapEffect: D3DSWAPEFFECT_DISCARD,
+        hDeviceWindow: window,
+        Windowed: 1,
+        ..unsafe { std::mem::zeroed() }
+    };
+
+    let device = unsafe {
+        let mut ptr = std::ptr::null_mut();
+        let result = ex_ctx.CreateDeviceEx(
+            D3DADAPTER_DEFAULT,
+            D3DDEVTYPE_HAL,
+            window,
+            D3DCREATE_HARDWARE_VERTEXPROCESSING,
+            &mut pp,
+            std::ptr::null_mut(),
+            &mut ptr,
+        );
+        assert_hr!(result, 0, "Failed to create Ex device");
+        ComPtr::new(ptr)
+    };
+
+    ctx.backend.take_calls();
+
+    pp.Windowed = 0;
+    let mut mode = D3DDISPLAYMODEEX {
+        Size: std::mem::size_of::<D3DDISPLAYMODEEX>() as u32,
+        Width: 32,
+        Height: 64,
+        RefreshRate: 60,
+        Format: D3DFMT_X8R8G8B8,
+        ScanLineOrdering: D3DSCANLINEORDERING_PROGRESSIVE,
+    };
+
+    // The mode has to match the back buffer.
+    let result = unsafe { device.ResetEx(&mut pp, &mut mode) };
+    assert_hr!(
+        result,
+        Error::InvalidCall as i32,
+        "Mismatched mode was accepted"
+    );
+    assert_eq!(ctx.backend.take_calls(), vec![]);
+
+    mode.Width = 64;
+    let result = unsafe { device.ResetEx(&mut pp, &mut mode) };
+    assert_hr!(result, 0, "Failed to reset to full-screen");
+    assert_eq!(pp.FullScreen_RefreshRateInHz, 60);
+
+    let calls = ctx.backend.take_calls();
+    let resized = calls.iter().any(|call| match *call {
+        Call::ResizeTarget {
+            width: 64,
+            height: 64,
+            ..
+        } => true,
+        _ => false,
+    });
+    assert!(resized, "Display mode was not applied");
+
+    let fullscreen = calls.iter().any(|call| match *call {
+        Call::SetFullscreenState {
+            fullscreen: true, ..
+        } => true,
+        _ => false,
+    });
+    assert!(fullscreen, "Swap chain was not made full-screen");
 }
@@ -1,19 +1,70 @@
 use comptr::ComPtr;
-use std::{mem, ptr, slice};
-use winapi::shared::{d3d9::*, d3d9types::*, windef::*};
+use std::{mem, ptr, rc::Rc, slice};
+use winapi::shared::{
+    d3d9::*,
+    d3d9types::*,
+    dxgi::DXGI_PRESENT_DO_NOT_WAIT,
+    dxgiformat::*,
+    windef::*,
+    winerror::{E_NOINTERFACE, S_FALSE, S_OK},
+};
+use winapi::um::d3d11::D3D11_QUERY_EVENT;
+use winapi::um::d3dcommon::{
+    D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST, D3D11_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+};
+use winapi::Interface;
+
+use d3d9::backend::{
+    mock::{Call, MockBackend},
+    ShaderStage,
+};
+use d3d9::core::fmt::Swizzle;
+use d3d9::Error;
+
+use crate::harness::{ref_count, Test};
+
+pub fn tests() -> Vec<Test<Device>> {
+    tests![
+        check_interfaces,
+        check_reset,
+        check_failed_reset,
+        check_auto_rt_ds,
+        check_child_references,
+        check_shared_resources,
+        check_queries,
+        check_texture_swizzles,
+        fill_default_render_target,
+        stretch_into_render_target,
+        check_depth_multisampling,
+        present_frames,
+        present_to_other_window,
+        count_draws,
+        route_draws,
+        check_srgb_writes,
+        check_cursor_properties,
+    ]
+}
 
 pub struct Device {
     device: ComPtr<IDirect3DDevice9>,
+    // Used for resetting the device.
+    pp: D3DPRESENT_PARAMETERS,
+    // Records what the device asked the backend to do.
+    backend: Rc<MockBackend>,
+    // ID of the back buffer the app draws into in the backend.
+    back_buffer: u32,
+    // ID of the swap chain's buffer, which the back buffer is presented into.
+    output: u32,
 }
 
 impl Device {
     /// Creates a new D3D9 device.
-    pub fn new(ctx: &IDirect3D9, window: HWND) -> Device {
+    pub fn new(ctx: &IDirect3D9, backend: Rc<MockBackend>, window: HWND) -> Device {
         let mut pp = D3DPRESENT_PARAMETERS {
-            // Leave these all blank to automatically select them.
-            BackBufferWidth: 0,
-            BackBufferHeight: 0,
-            BackBufferFormat: D3DFMT_UNKNOWN,
+            // The dummy window has no size, so the back buffer's has to be given.
+            BackBufferWidth: 64,
+            BackBufferHeight: 64,
+            BackBufferFormat: D3DFMT_X8R8G8B8,
             BackBufferCount: 1,
             // No need to use multisampling in the tests.
             MultiSampleType: 0,
@@ -43,35 +94,68 @@ impl Device {
             )
         };
 
-        assert_eq!(result, 0, "Failed to create device");
+        assert_hr!(result, 0, "Failed to create device");
 
         let device = ComPtr::new(device);
+        assert_eq!(ref_count(device.as_ref()), 1);
+
+        let calls = backend.take_calls();
+        let output = find_swap_chain(&calls).expect("No swap chain was created");
+
+        // With DISCARD, the app draws straight into the swap chain's buffer.
+        let back_buffer = output;
+
+        // The implicit render target and depth buffer must be bound right away.
+        let bound = calls.iter().any(|call| match call {
+            Call::SetRenderTargets {
+                targets,
+                depth_stencil: Some(_),
+            } => targets[0] == Some((back_buffer, DXGI_FORMAT_B8G8R8A8_UNORM)),
+            _ => false,
+        });
+        assert!(bound, "Default render target was not bound");
+
+        Self {
+            device,
+            pp,
+            backend,
+            back_buffer,
+            output,
+        }
+    }
+
+    /// Checks that a texture of the backend was filled with the `gradient`.
+    fn check_gradient(&self, id: u32, width: usize, height: usize) {
+        let texture = self.backend.texture(id).expect("Texture does not exist");
+        let data = texture.data(0);
+        let pitch = texture.row_pitch(0) as usize;
+
+        let pixel = |i: usize, j: usize| {
+            let p = &data[i * pitch + j * 4..];
+            u32::from(p[0]) | u32::from(p[1]) << 8 | u32::from(p[2]) << 16
+        };
 
-        Self { device }
+        for &(i, j) in &[(0, 0), (height / 2, width / 3), (height - 1, width - 1)] {
+            assert_eq!(pixel(i, j), gradient(i, j, width, height));
+        }
     }
 
-    pub fn present(&self) {
+    fn present(&self) -> i32 {
         unsafe {
             self.device.Present(
                 ptr::null_mut(),
                 ptr::null_mut(),
                 ptr::null_mut(),
                 ptr::null_mut(),
-            );
+            )
         }
     }
 
-    /// Runs the device tests.
-    pub fn run_tests(&mut self) {
-        self.check_auto_rt_ds();
-        self.fill_default_render_target();
-    }
-
     fn get_render_target(&self, i: u32) -> Surface {
         let surface = unsafe {
             let mut ptr = ptr::null_mut();
             let result = self.device.GetRenderTarget(i, &mut ptr);
-            assert_eq!(result, 0, "Failed to get default render target");
+            assert_hr!(result, 0, "Failed to get default render target");
             ComPtr::new(ptr)
         };
 
@@ -82,96 +166,909 @@ impl Device {
         let surface = unsafe {
             let mut ptr = ptr::null_mut();
             let result = self.device.GetDepthStencilSurface(&mut ptr);
-            assert_eq!(result, 0, "Failed to get depth buffer");
+            assert_hr!(result, 0, "Failed to get depth buffer");
             ComPtr::new(ptr)
         };
 
         Surface { surface }
     }
+}
 
-    // Checks that the default render target / depth buffer is correctly constructed.
-    fn check_auto_rt_ds(&self) {
-        let rt = self.get_render_target(0);
+// Checks that devices created through the old API do not expose the D3D9Ex interface.
+fn check_interfaces(dev: &mut Device) {
+    let result = unsafe {
+        let mut ptr = ptr::null_mut();
+        dev.device
+            .QueryInterface(&IDirect3DDevice9Ex::uuidof(), &mut ptr)
+    };
+    assert_hr!(
+        result,
+        E_NOINTERFACE,
+        "Non-Ex device exposes IDirect3DDevice9Ex"
+    );
+}
 
-        // Ensure that children objects have references to the parent device.
-        let device = rt.device();
-        assert_eq!(
-            device.as_ref() as *const _,
-            self.device.as_ref() as *const _
-        );
+// Checks that the device can only be reset after default pool resources are released.
+fn check_reset(dev: &mut Device) {
+    let texture = Texture::new(&dev.device, 64, 64, 1, 0, D3DFMT_A8R8G8B8, D3DPOOL_DEFAULT);
+
+    let result = unsafe { dev.device.Reset(&mut dev.pp) };
+    assert_hr!(
+        result,
+        Error::InvalidCall as i32,
+        "Reset with outstanding resources"
+    );
+
+    drop(texture);
+
+    dev.backend.take_calls();
+
+    let result = unsafe { dev.device.Reset(&mut dev.pp) };
+    assert_hr!(result, 0, "Failed to reset device");
+
+    // The old state must be dropped, and a new swap chain created.
+    let calls = dev.backend.take_calls();
+    assert_eq!(calls.first(), Some(&Call::ClearState));
+    dev.output = find_swap_chain(&calls).expect("Swap chain was not recreated");
+    dev.back_buffer = dev.output;
+
+    let result = unsafe { dev.device.TestCooperativeLevel() };
+    assert_hr!(result, 0, "Device is not usable after reset");
+
+    // The implicit render target and depth buffer must have been recreated.
+    dev.get_render_target(0);
+    dev.get_depth_stencil();
+}
+
+// Checks that a device which fails to reset is lost,
+// and that its resources can still be released after it.
+fn check_failed_reset(dev: &mut Device) {
+    let ctx = unsafe {
+        let mut ptr = ptr::null_mut();
+        let result = dev.device.GetDirect3D(&mut ptr);
+        assert_hr!(result, 0, "Failed to get context");
+        ComPtr::new(ptr)
+    };
+
+    let mut other = Device::new(&ctx, dev.backend.clone(), dev.pp.hDeviceWindow);
+
+    // The swap chain and render target can be created, but not the depth buffer.
+    other.pp.AutoDepthStencilFormat = D3DFMT_D15S1;
+
+    let result = unsafe { other.device.Reset(&mut other.pp) };
+    assert_hr!(
+        result,
+        Error::NotAvailable as i32,
+        "Reset with invalid depth format"
+    );
+
+    // The harness' window is never in the foreground, so the device cannot be reset yet.
+    let result = unsafe { other.device.TestCooperativeLevel() };
+    assert_hr!(
+        result,
+        Error::DeviceLost as i32,
+        "Device is usable after failed reset"
+    );
+
+    let result = unsafe {
+        let mut ptr = ptr::null_mut();
+        other.device.GetRenderTarget(0, &mut ptr)
+    };
+    assert_hr!(
+        result,
+        Error::NotFound as i32,
+        "Render target survived failed reset"
+    );
+
+    let texture = Texture::new(
+        &other.device,
+        16,
+        16,
+        1,
+        0,
+        D3DFMT_A8R8G8B8,
+        D3DPOOL_DEFAULT,
+    );
+
+    // Releasing the texture must not touch the destroyed device.
+    drop(other);
+    drop(texture);
+
+    dev.backend.take_calls();
+}
+
+// Checks that the default render target / depth buffer is correctly constructed.
+fn check_auto_rt_ds(dev: &mut Device) {
+    let rt = dev.get_render_target(0);
+
+    // Ensure that children objects have references to the parent device.
+    let device = rt.device();
+    assert_eq!(device.as_ref() as *const _, dev.device.as_ref() as *const _);
+
+    let rt_desc = rt.desc();
+    assert_eq!(rt_desc.Usage, D3DUSAGE_RENDERTARGET);
+
+    let ds = dev.get_depth_stencil();
+
+    let ds_desc = ds.desc();
+    assert_eq!(ds_desc.Usage, D3DUSAGE_DEPTHSTENCIL);
+
+    assert_eq!(rt_desc.Width, ds_desc.Width);
+    assert_eq!(rt_desc.Height, ds_desc.Height);
+}
+
+// Checks that retrieving objects from the device adds references to them.
+fn check_child_references(dev: &mut Device) {
+    let device_refs = ref_count(dev.device.as_ref());
+
+    let rt = dev.get_render_target(0);
+    let rt_refs = ref_count(rt.surface.as_ref());
+
+    // The same surface must be returned every time.
+    let other = dev.get_render_target(0);
+    assert_eq!(
+        rt.surface.as_ref() as *const _,
+        other.surface.as_ref() as *const _
+    );
+    assert_eq!(ref_count(rt.surface.as_ref()), rt_refs + 1);
+
+    drop(other);
+    assert_eq!(ref_count(rt.surface.as_ref()), rt_refs);
+
+    let device = rt.device();
+    assert_eq!(ref_count(dev.device.as_ref()), device_refs + 1);
+
+    drop(device);
+    assert_eq!(ref_count(dev.device.as_ref()), device_refs);
+}
+
+// Checks that only D3D9Ex devices are allowed to share resources.
+fn check_shared_resources(dev: &mut Device) {
+    dev.backend.take_calls();
+
+    let result = unsafe {
+        let mut ptr = ptr::null_mut();
+        let mut handle = ptr::null_mut();
+        dev.device.CreateTexture(
+            64,
+            64,
+            1,
+            0,
+            D3DFMT_A8R8G8B8,
+            D3DPOOL_DEFAULT,
+            &mut ptr,
+            &mut handle,
+        )
+    };
+
+    assert_hr!(
+        result,
+        Error::InvalidCall as i32,
+        "Non-Ex device created a shared texture"
+    );
+
+    assert_eq!(
+        dev.backend.calls(),
+        vec![],
+        "Rejected texture was still created"
+    );
+}
+
+// Checks that event queries eventually signal, and that unsupported queries are reported.
+fn check_queries(dev: &mut Device) {
+    dev.backend.take_calls();
+
+    let result = unsafe {
+        dev.device
+            .CreateQuery(D3DQUERYTYPE_PIPELINETIMINGS, ptr::null_mut())
+    };
+    assert_hr!(
+        result,
+        Error::NotAvailable as i32,
+        "Unsupported query type was accepted"
+    );
+
+    let query: ComPtr<IDirect3DQuery9> = unsafe {
+        let mut ptr = ptr::null_mut();
+        let result = dev.device.CreateQuery(D3DQUERYTYPE_EVENT, &mut ptr);
+        assert_hr!(result, 0, "Failed to create event query");
+        ComPtr::new(ptr)
+    };
+
+    // Event queries only have an end.
+    let result = unsafe { query.Issue(D3DISSUE_BEGIN) };
+    assert_hr!(result, Error::InvalidCall as i32, "Event query was begun");
+
+    let result = unsafe { query.Issue(D3DISSUE_END) };
+    assert_hr!(result, 0, "Failed to issue event query");
+
+    let mut done = 0;
+    loop {
+        let result = unsafe { query.GetData(&mut done as *mut _ as *mut _, 4, D3DGETDATA_FLUSH) };
+
+        match result {
+            S_OK => break,
+            S_FALSE => continue,
+            hr => panic!("Failed to retrieve query data: {:#x}", hr),
+        }
+    }
+
+    assert_ne!(done, 0, "Event query did not signal");
+
+    // The query must be backed by a D3D11 event query, which was ended.
+    let calls = dev.backend.take_calls();
+    let id = calls
+        .iter()
+        .filter_map(|call| match *call {
+            Call::CreateQuery { id, ty } if ty == D3D11_QUERY_EVENT => Some(id),
+            _ => None,
+        }).last()
+        .expect("No event query was created");
+    assert!(calls.contains(&Call::EndQuery { id }));
+    assert!(!calls.contains(&Call::BeginQuery { id }));
+
+    // Neither do timestamp queries.
+    let timestamp: ComPtr<IDirect3DQuery9> = unsafe {
+        let mut ptr = ptr::null_mut();
+        let result = dev.device.CreateQuery(D3DQUERYTYPE_TIMESTAMP, &mut ptr);
+        assert_hr!(result, 0, "Failed to create timestamp query");
+        ComPtr::new(ptr)
+    };
+
+    let result = unsafe { timestamp.Issue(D3DISSUE_BEGIN) };
+    assert_hr!(
+        result,
+        Error::InvalidCall as i32,
+        "Timestamp query was begun"
+    );
+}
+
+// Checks that textures in formats D3D11 does not have are bound with their channels remapped.
+fn check_texture_swizzles(dev: &mut Device) {
+    let formats = [
+        (
+            D3DFMT_A8R8G8B8,
+            DXGI_FORMAT_B8G8R8A8_UNORM,
+            Swizzle::IDENTITY,
+        ),
+        (D3DFMT_X8R8G8B8, DXGI_FORMAT_B8G8R8A8_UNORM, Swizzle::OPAQUE),
+        (D3DFMT_G16R16, DXGI_FORMAT_R16G16_UNORM, Swizzle::RG),
+        // Every luminance format D3D11 can store.
+        (D3DFMT_L8, DXGI_FORMAT_R8_UNORM, Swizzle::LUMINANCE),
+        (D3DFMT_L16, DXGI_FORMAT_R16_UNORM, Swizzle::LUMINANCE),
+        (
+            D3DFMT_A8L8,
+            DXGI_FORMAT_R8G8_UNORM,
+            Swizzle::LUMINANCE_ALPHA,
+        ),
+        // Every bump map format D3D11 can store.
+        (D3DFMT_V8U8, DXGI_FORMAT_R8G8_SNORM, Swizzle::RG),
+        (D3DFMT_V16U16, DXGI_FORMAT_R16G16_SNORM, Swizzle::RG),
+        (
+            D3DFMT_Q8W8V8U8,
+            DXGI_FORMAT_R8G8B8A8_SNORM,
+            Swizzle::IDENTITY,
+        ),
+        (
+            D3DFMT_Q16W16V16U16,
+            DXGI_FORMAT_R16G16B16A16_SNORM,
+            Swizzle::IDENTITY,
+        ),
+    ];
+
+    for &(fmt, dxgi_fmt, swizzle) in &formats {
+        let texture = Texture::new(&dev.device, 4, 4, 1, 0, fmt, D3DPOOL_MANAGED);
+        dev.backend.take_calls();
+
+        let result = unsafe {
+            dev.device
+                .SetTexture(1, texture.texture.as_mut() as *mut _ as *mut _)
+        };
+        assert_hr!(result, 0, "Failed to set texture");
+
+        let bound = dev.backend.calls().into_iter().any(|call| match call {
+            Call::BindTexture {
+                stage: ShaderStage::Pixel,
+                slot: 1,
+                texture: Some((_, f, sw)),
+            } => f == dxgi_fmt && sw == swizzle,
+            _ => false,
+        });
+        assert!(bound, "Format {} was bound without its swizzle", fmt);
+    }
+
+    let result = unsafe { dev.device.SetTexture(1, ptr::null_mut()) };
+    assert_hr!(result, 0, "Failed to unbind texture");
+}
+
+// Creates a CPU-mappable texture, maps it, fills it with color manually,
+// then copies it onto the back buffer.
+fn fill_default_render_target(dev: &mut Device) {
+    let rt = dev.get_render_target(0);
+
+    let rt_desc = rt.desc();
+
+    let width = rt_desc.Width as usize;
+    let height = rt_desc.Height as usize;
+
+    let texture = Texture::new(
+        &dev.device,
+        width,
+        height,
+        1,
+        D3DUSAGE_DYNAMIC | D3DUSAGE_WRITEONLY,
+        D3DFMT_A8R8G8B8,
+        D3DPOOL_SYSTEMMEM,
+    );
+
+    let surface = texture.get_level(0);
+
+    // Map the pixel data directly to memory.
+    let (ptr, stride) = surface.map(D3DLOCK_DISCARD);
+
+    let pixels = unsafe {
+        let size = height as usize * stride;
+        slice::from_raw_parts_mut(ptr as *mut u32, size)
+    };
+
+    // Fill out the raw texture data.
+    for i in 0..height {
+        for j in 0..width {
+            let index = (i * stride) + j;
+            pixels[index] = gradient(i, j, width, height);
+        }
+    }
+
+    surface.unmap();
 
-        let rt_desc = rt.desc();
-        assert_eq!(rt_desc.Usage, D3DUSAGE_RENDERTARGET);
+    let result = unsafe {
+        let dest = POINT { x: 0, y: 0 };
 
-        let ds = self.get_depth_stencil();
+        dev.device.UpdateSurface(
+            surface.surface.as_mut(),
+            ptr::null(),
+            rt.surface.as_mut(),
+            &dest,
+        )
+    };
+    assert_hr!(result, 0, "Failed to update the render target");
 
-        let ds_desc = ds.desc();
-        assert_eq!(ds_desc.Usage, D3DUSAGE_DEPTHSTENCIL);
+    // The pixels must have been copied to the back buffer,
+    // and end up in the swap chain's buffer once presented.
+    dev.check_gradient(dev.back_buffer, width, height);
 
-        assert_eq!(rt_desc.Width, ds_desc.Width);
-        assert_eq!(rt_desc.Height, ds_desc.Height);
+    assert_hr!(dev.present(), 0, "Failed to present");
+    dev.check_gradient(dev.output, width, height);
+}
+
+// Checks that StretchRect can scale a surface into a render target.
+fn stretch_into_render_target(dev: &mut Device) {
+    const COLOR: u32 = 0x0012_3456;
+
+    let texture = Texture::new(
+        &dev.device,
+        16,
+        8,
+        1,
+        D3DUSAGE_DYNAMIC,
+        D3DFMT_X8R8G8B8,
+        D3DPOOL_DEFAULT,
+    );
+    let surface = texture.get_level(0);
+
+    let (ptr, stride) = surface.map::<u32>(D3DLOCK_DISCARD);
+    let pixels = unsafe { slice::from_raw_parts_mut(ptr, 8 * stride) };
+    for i in 0..8 {
+        for j in 0..16 {
+            pixels[i * stride + j] = COLOR;
+        }
     }
+    surface.unmap();
+
+    dev.backend.take_calls();
 
-    // Creates a CPU-mappable texture, maps it, fills it with color manually,
-    // then copies it onto the back buffer.
-    pub fn fill_default_render_target(&self) {
-        let rt = self.get_render_target(0);
+    let rt = dev.get_render_target(0);
+    let result = unsafe {
+        dev.device.StretchRect(
+            surface.surface.as_mut(),
+            ptr::null(),
+            rt.surface.as_mut(),
+            ptr::null(),
+            D3DTEXF_LINEAR,
+        )
+    };
+    assert_hr!(result, 0, "Failed to stretch surface");
 
-        let rt_desc = rt.desc();
+    let stretched = dev.backend.calls().iter().any(|call| match *call {
+        Call::Blit { dest, .. } => dest == dev.back_buffer,
+        _ => false,
+    });
+    assert!(stretched, "Surface was not drawn into the back buffer");
 
-        let width = rt_desc.Width as usize;
-        let height = rt_desc.Height as usize;
+    let back_buffer = dev.backend.texture(dev.back_buffer).unwrap();
+    let data = back_buffer.data(0);
+    let pitch = back_buffer.row_pitch(0) as usize;
 
-        let texture = Texture::new(
-            &self.device,
+    for &(i, j) in &[(0, 0), (31, 17), (63, 63)] {
+        let p = &data[i * pitch + j * 4..];
+        let pixel = u32::from(p[0]) | u32::from(p[1]) << 8 | u32::from(p[2]) << 16;
+        assert_eq!(pixel, COLOR, "Pixel ({}, {}) was not filled", i, j);
+    }
+}
+
+// Checks that depth buffers created for the back buffer match its multisampling,
+// even if it differs from what the app requested.
+fn check_depth_multisampling(dev: &mut Device) {
+    // Multisampling is only supported with DISCARD, so the back buffer is not multisampled.
+    let mut pp = D3DPRESENT_PARAMETERS {
+        SwapEffect: D3DSWAPEFFECT_COPY,
+        MultiSampleType: D3DMULTISAMPLE_4_SAMPLES,
+        ..dev.pp
+    };
+
+    let result = unsafe { dev.device.Reset(&mut pp) };
+    assert_hr!(result, 0, "Failed to reset device");
+
+    let create = |width, height| unsafe {
+        let mut ptr = ptr::null_mut();
+        let result = dev.device.CreateDepthStencilSurface(
             width,
             height,
-            1,
-            D3DUSAGE_DYNAMIC | D3DUSAGE_WRITEONLY,
-            D3DFMT_A8R8G8B8,
-            D3DPOOL_SYSTEMMEM,
+            D3DFMT_D24S8,
+            D3DMULTISAMPLE_4_SAMPLES,
+            0,
+            0,
+            &mut ptr,
+            ptr::null_mut(),
         );
+        assert_hr!(result, 0, "Failed to create depth buffer");
+        Surface {
+            surface: ComPtr::new(ptr),
+        }
+    };
+
+    let ds = create(64, 64);
+    assert_eq!(ds.desc().MultiSampleType, D3DMULTISAMPLE_NONE);
+
+    // Depth buffers of other sizes are for other render targets.
+    let other = create(32, 32);
+    assert_eq!(other.desc().MultiSampleType, D3DMULTISAMPLE_4_SAMPLES);
+
+    drop((ds, other));
+
+    dev.backend.take_calls();
+
+    let result = unsafe { dev.device.Reset(&mut dev.pp) };
+    assert_hr!(result, 0, "Failed to reset device");
+
+    let calls = dev.backend.take_calls();
+    dev.output = find_swap_chain(&calls).unwrap();
+    dev.back_buffer = dev.output;
+}
+
+/// Color of a pixel in the gradient used to fill the render target.
+fn gradient(i: usize, j: usize, width: usize, height: usize) -> u32 {
+    let r = ((i as f32 / height as f32) * 255.0) as u32;
+    let g = ((j as f32 / width as f32) * 255.0) as u32;
+    let b = 192;
+
+    b | (g << 8) | (r << 16)
+}
 
-        let surface = texture.get_level(0);
+// Presents a few frames, which must all succeed.
+fn present_frames(dev: &mut Device) {
+    dev.backend.take_calls();
 
-        // Map the pixel data directly to memory.
-        let (ptr, stride) = surface.map(D3DLOCK_DISCARD);
+    for _ in 0..3 {
+        assert_hr!(dev.present(), 0, "Failed to present");
+    }
+
+    let presents = dev
+        .backend
+        .calls()
+        .into_iter()
+        .filter(|call| match call {
+            Call::Present { sync_interval, .. } => *sync_interval == 1,
+            _ => false,
+        }).count();
+    assert_eq!(presents, 3);
+
+    // Apps which do not want to wait must not be made to, since DISCARD retains nothing.
+    // This flag is missing from `winapi`.
+    const D3DPRESENT_DONOTWAIT: u32 = 1;
+
+    let swap_chain = unsafe {
+        let mut ptr = ptr::null_mut();
+        let result = dev.device.GetSwapChain(0, &mut ptr);
+        assert_hr!(result, 0, "Failed to get swap chain");
+        ComPtr::new(ptr)
+    };
+
+    dev.backend.take_calls();
+
+    let result = unsafe {
+        swap_chain.Present(
+            ptr::null(),
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null(),
+            D3DPRESENT_DONOTWAIT,
+        )
+    };
+    assert_hr!(result, 0, "Failed to present without waiting");
 
-        let pixels = unsafe {
-            let size = height as usize * stride;
-            slice::from_raw_parts_mut(ptr as *mut u32, size)
+    let flags = dev
+        .backend
+        .calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            Call::Present { flags, .. } => Some(flags),
+            _ => None,
+        }).next();
+    assert_eq!(flags, Some(DXGI_PRESENT_DO_NOT_WAIT));
+}
+
+// Checks that apps can present into another window than the device's.
+fn present_to_other_window(dev: &mut Device) {
+    let window = 2 as HWND;
+
+    dev.backend.take_calls();
+
+    for _ in 0..2 {
+        let result = unsafe {
+            dev.device
+                .Present(ptr::null_mut(), ptr::null_mut(), window, ptr::null_mut())
         };
+        assert_hr!(result, 0, "Failed to present to another window");
+    }
 
-        // Fill out the raw texture data.
-        for i in 0..height {
-            for j in 0..width {
-                let index = (i * stride) + j;
+    let calls = dev.backend.take_calls();
 
-                let color = {
-                    let r = ((i as f32 / height as f32) * 255.0) as u32;
-                    let g = ((j as f32 / width as f32) * 255.0) as u32;
-                    let b = 192;
+    // The swap chain for the window is only created once.
+    let created: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match *call {
+            Call::CreateSwapChain { id, buffer, .. } => Some((id, buffer)),
+            _ => None,
+        }).collect();
+    assert_eq!(created.len(), 1);
 
-                    b | (g << 8) | (r << 16)
-                };
+    let (id, buffer) = created[0];
+
+    let presents: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match *call {
+            Call::Present { id, .. } => Some(id),
+            _ => None,
+        }).collect();
+    assert_eq!(presents, [id, id]);
+
+    let back_buffer = dev.backend.texture(dev.back_buffer).unwrap();
+    let output = dev.backend.texture(buffer).unwrap();
+    assert!(
+        output.data(0) == back_buffer.data(0),
+        "Back buffer was not presented into the window"
+    );
+}
+
+// Checks that the draw calls of each frame are counted.
+fn count_draws(dev: &mut Device) {
+    let device = unsafe { &*(dev.device.as_ref() as *const _ as *const d3d9::Device) };
+
+    unsafe {
+        assert_hr!(dev.device.BeginScene(), 0, "Failed to begin scene");
+
+        let result = dev.device.DrawPrimitive(D3DPT_TRIANGLELIST, 0, 2);
+        assert_hr!(result, 0, "DrawPrimitive");
+        let result = dev.device.DrawPrimitive(D3DPT_TRIANGLESTRIP, 4, 8);
+        assert_hr!(result, 0, "DrawPrimitive");
+
+        assert_hr!(dev.device.EndScene(), 0, "Failed to end scene");
+    }
+
+    assert_hr!(dev.present(), 0, "Failed to present");
+
+    let stats = device.frame_stats();
+    assert_eq!((stats.draws, stats.primitives), (2, 10));
+
+    // The counters start over with each frame.
+    assert_hr!(dev.present(), 0, "Failed to present");
+    assert_eq!(device.frame_stats().draws, 0);
+}
 
-                pixels[index] = color;
-            }
+// Checks that the streams, the index buffer and the draw calls are passed to the backend.
+fn route_draws(dev: &mut Device) {
+    let vb: ComPtr<IDirect3DVertexBuffer9> = unsafe {
+        let mut ptr = ptr::null_mut();
+        let result = dev.device.CreateVertexBuffer(
+            256,
+            D3DUSAGE_WRITEONLY,
+            0,
+            D3DPOOL_MANAGED,
+            &mut ptr,
+            ptr::null_mut(),
+        );
+        assert_hr!(result, 0, "Failed to create vertex buffer");
+        ComPtr::new(ptr)
+    };
+
+    let ib: ComPtr<IDirect3DIndexBuffer9> = unsafe {
+        let mut ptr = ptr::null_mut();
+        let result = dev.device.CreateIndexBuffer(
+            64,
+            D3DUSAGE_WRITEONLY,
+            D3DFMT_INDEX16,
+            D3DPOOL_MANAGED,
+            &mut ptr,
+            ptr::null_mut(),
+        );
+        assert_hr!(result, 0, "Failed to create index buffer");
+        ComPtr::new(ptr)
+    };
+
+    let buffers: Vec<u32> = dev
+        .backend
+        .take_calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            Call::CreateBuffer { id, .. } => Some(id),
+            _ => None,
+        }).collect();
+    let (vb_id, ib_id) = (buffers[0], buffers[1]);
+
+    unsafe {
+        let result = dev.device.SetStreamSource(1, vb.as_mut(), 32, 16);
+        assert_hr!(result, 0, "Failed to set stream source");
+
+        let (mut buffer, mut offset, mut stride) = (ptr::null_mut(), 0, 0);
+        let result = dev
+            .device
+            .GetStreamSource(1, &mut buffer, &mut offset, &mut stride);
+        assert_hr!(result, 0, "Failed to get stream source");
+        assert_eq!((buffer, offset, stride), (vb.as_mut() as *mut _, 32, 16));
+        (*buffer).Release();
+
+        let result = dev.device.SetStreamSource(16, vb.as_mut(), 0, 16);
+        assert_hr!(result, Error::InvalidCall as i32, "Stream 16 was accepted");
+
+        assert_hr!(dev.device.BeginScene(), 0, "Failed to begin scene");
+
+        let result = dev
+            .device
+            .DrawIndexedPrimitive(D3DPT_TRIANGLELIST, 0, 0, 4, 0, 2);
+        assert_hr!(
+            result,
+            Error::InvalidCall as i32,
+            "Indexed draw without indices was accepted"
+        );
+
+        let result = dev.device.SetIndices(ib.as_mut());
+        assert_hr!(result, 0, "Failed to set indices");
+
+        let result = dev
+            .device
+            .DrawIndexedPrimitive(D3DPT_TRIANGLELIST, 1, 0, 4, 3, 2);
+        assert_hr!(result, 0, "DrawIndexedPrimitive");
+        let result = dev.device.DrawPrimitive(D3DPT_TRIANGLESTRIP, 2, 2);
+        assert_hr!(result, 0, "DrawPrimitive");
+    }
+
+    let calls = dev.backend.take_calls();
+    assert_eq!(
+        calls,
+        vec![
+            Call::SetVertexBuffer {
+                slot: 1,
+                id: Some(vb_id),
+                stride: 16,
+                offset: 32,
+            },
+            Call::SetIndexBuffer {
+                id: Some(ib_id),
+                format: DXGI_FORMAT_R16_UINT,
+                offset: 0,
+            },
+            Call::SetPrimitiveTopology {
+                topology: D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+            },
+            Call::DrawIndexed {
+                index_count: 6,
+                start_index: 3,
+                base_vertex: 1,
+            },
+            Call::SetPrimitiveTopology {
+                topology: D3D11_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+            },
+            Call::Draw {
+                vertex_count: 4,
+                start_vertex: 2,
+            },
+        ]
+    );
+
+    // Fans are drawn as lists, whose indices replace the app's until the draw is done.
+    let result = unsafe { dev.device.DrawPrimitive(D3DPT_TRIANGLEFAN, 5, 3) };
+    assert_hr!(result, 0, "DrawPrimitive with a triangle fan");
+
+    let calls = dev.backend.take_calls();
+    assert!(calls.contains(&Call::DrawIndexed {
+        index_count: 9,
+        start_index: 0,
+        base_vertex: 5,
+    }));
+    assert_eq!(
+        calls.last(),
+        Some(&Call::SetIndexBuffer {
+            id: Some(ib_id),
+            format: DXGI_FORMAT_R16_UINT,
+            offset: 0,
+        })
+    );
+
+    // Vertices in the app's memory are drawn from a dynamic buffer, and stream 0 is unbound.
+    let vertices = [0u8; 48];
+    let result = unsafe {
+        dev.device
+            .DrawPrimitiveUP(D3DPT_TRIANGLELIST, 1, vertices.as_ptr() as *const _, 16)
+    };
+    assert_hr!(result, 0, "DrawPrimitiveUP");
+
+    let calls = dev.backend.take_calls();
+    let draw = Call::Draw {
+        vertex_count: 3,
+        start_vertex: 0,
+    };
+    let draw = calls
+        .iter()
+        .position(|call| *call == draw)
+        .expect("DrawPrimitiveUP did not draw");
+    assert!(
+        calls[..draw].iter().any(|call| match *call {
+            Call::SetVertexBuffer {
+                slot: 0,
+                id: Some(_),
+                stride: 16,
+                ..
+            } => true,
+            _ => false,
+        }),
+        "The app's vertices were not bound"
+    );
+    assert_eq!(
+        calls.last(),
+        Some(&Call::SetVertexBuffer {
+            slot: 0,
+            id: None,
+            stride: 0,
+            offset: 0,
+        })
+    );
+
+    unsafe {
+        assert_hr!(dev.device.EndScene(), 0, "Failed to end scene");
+
+        // The buffers must not stay bound to the device.
+        dev.device.SetStreamSource(1, ptr::null_mut(), 0, 0);
+        dev.device.SetIndices(ptr::null_mut());
+    }
+}
+
+// Checks that sRGB writes switch the render targets to their sRGB views.
+fn check_srgb_writes(dev: &mut Device) {
+    dev.backend.take_calls();
+
+    let result = unsafe { dev.device.SetRenderState(D3DRS_SRGBWRITEENABLE, 1) };
+    assert_hr!(result, 0, "Failed to enable sRGB writes");
+
+    // This includes the implicit back buffer, which is replaced by a typeless one.
+    let calls = dev.backend.take_calls();
+    dev.back_buffer = find_back_buffer(&calls).expect("Back buffer was not replaced");
+
+    let bound = calls.iter().any(|call| match call {
+        Call::SetRenderTargets { targets, .. } => {
+            targets[0] == Some((dev.back_buffer, DXGI_FORMAT_B8G8R8A8_UNORM_SRGB))
         }
+        _ => false,
+    });
+    assert!(bound, "Back buffer was not bound with an sRGB view");
 
-        surface.unmap();
+    let result = unsafe { dev.device.SetRenderState(D3DRS_SRGBWRITEENABLE, 0) };
+    assert_hr!(result, 0, "Failed to disable sRGB writes");
 
-        unsafe {
-            let dest = POINT { x: 0, y: 0 };
+    // The app keeps drawing into the replacement, which is copied when presenting.
+    let bound = dev.backend.calls().iter().any(|call| match call {
+        Call::SetRenderTargets { targets, .. } => {
+            targets[0] == Some((dev.back_buffer, DXGI_FORMAT_B8G8R8A8_UNORM))
+        }
+        _ => false,
+    });
+    assert!(bound, "Back buffer was not bound with a linear view");
 
-            self.device.UpdateSurface(
-                surface.surface.as_mut(),
-                ptr::null(),
-                rt.surface.as_mut(),
-                &dest,
-            );
+    assert_hr!(dev.present(), 0, "Failed to present");
+
+    let back_buffer = dev.backend.texture(dev.back_buffer).unwrap();
+    let output = dev.backend.texture(dev.output).unwrap();
+    assert!(
+        output.data(0) == back_buffer.data(0),
+        "Replaced back buffer was not presented"
+    );
+}
+
+// Checks which images can be used for the cursor.
+fn check_cursor_properties(dev: &mut Device) {
+    let create = |width, height, fmt| unsafe {
+        let mut ptr = ptr::null_mut();
+        let result = dev.device.CreateOffscreenPlainSurface(
+            width,
+            height,
+            fmt,
+            D3DPOOL_SCRATCH,
+            &mut ptr,
+            ptr::null_mut(),
+        );
+        assert_hr!(result, 0, "Failed to create cursor image");
+        Surface {
+            surface: ComPtr::new(ptr),
         }
-    }
+    };
+
+    let set = |surface: &Surface, x_hot, y_hot| unsafe {
+        dev.device
+            .SetCursorProperties(x_hot, y_hot, surface.surface.as_mut())
+    };
+
+    let image = create(32, 32, D3DFMT_A8R8G8B8);
+    assert_hr!(set(&image, 0, 0), 0, "Failed to set cursor image");
+    assert_hr!(set(&image, 31, 31), 0, "Failed to set cursor hotspot");
+
+    let invalid = Error::InvalidCall as i32;
+
+    assert_hr!(set(&image, 32, 0), invalid, "Hotspot outside of the image");
+    assert_hr!(set(&image, 0, 32), invalid, "Hotspot outside of the image");
+
+    let no_alpha = create(32, 32, D3DFMT_X8R8G8B8);
+    assert_hr!(set(&no_alpha, 0, 0), invalid, "Image without alpha");
+
+    let odd_size = create(24, 32, D3DFMT_A8R8G8B8);
+    assert_hr!(set(&odd_size, 0, 0), invalid, "Width not a power of 2");
+
+    let odd_size = create(32, 24, D3DFMT_A8R8G8B8);
+    assert_hr!(set(&odd_size, 0, 0), invalid, "Height not a power of 2");
+}
+
+/// Finds the back buffer of the last swap chain created by the device.
+fn find_swap_chain(calls: &[Call]) -> Option<u32> {
+    calls
+        .iter()
+        .filter_map(|call| match *call {
+            Call::CreateSwapChain {
+                buffer,
+                width: 64,
+                height: 64,
+                windowed: true,
+                ..
+            } => Some(buffer),
+            _ => None,
+        }).last()
+}
+
+/// Finds the last back buffer created for the app to draw into.
+///
+/// The swap chain's buffers cannot be typeless, so the app is given its own typeless buffers
+/// once it writes to them through an sRGB view.
+fn find_back_buffer(calls: &[Call]) -> Option<u32> {
+    calls
+        .iter()
+        .filter_map(|call| match *call {
+            Call::CreateTexture {
+                id,
+                width: 64,
+                height: 64,
+                format: DXGI_FORMAT_B8G8R8A8_TYPELESS,
+            } => Some(id),
+            _ => None,
+        })
+        .last()
 }
 
 struct Surface {
@@ -194,7 +1091,7 @@ impl Surface {
         unsafe {
             let mut desc = mem::uninitialized();
             let result = self.surface.GetDesc(&mut desc);
-            assert_eq!(result, 0, "Failed to get surface description");
+            assert_hr!(result, 0, "Failed to get surface description");
             desc
         }
     }
@@ -206,7 +1103,7 @@ impl Surface {
             let mut lr = mem::uninitialized();
 
             let result = self.surface.LockRect(&mut lr, ptr::null(), flags);
-            assert_eq!(result, 0, "Failed to map surface");
+            assert_hr!(result, 0, "Failed to map surface");
 
             let ptr = lr.pBits as *mut T;
             let stride = lr.Pitch as usize / mem::size_of::<T>();
@@ -218,7 +1115,7 @@ impl Surface {
     fn unmap(&self) {
         unsafe {
             let result = self.surface.UnlockRect();
-            assert_eq!(result, 0, "Failed to unmap surface");
+            assert_hr!(result, 0, "Failed to unmap surface");
         }
     }
 }
@@ -249,7 +1146,7 @@ impl Texture {
                 &mut ptr,
                 ptr::null_mut(),
             );
-            assert_eq!(result, 0, "Failed to create texture");
+            assert_hr!(result, 0, "Failed to create texture");
             ComPtr::new(ptr)
         };
 
@@ -261,7 +1158,7 @@ impl Texture {
         let surface = unsafe {
             let mut ptr = ptr::null_mut();
             let result = self.texture.GetSurfaceLevel(lvl, &mut ptr);
-            assert_eq!(result, 0, "Failed to get texture mip map level");
+            assert_hr!(result, 0, "Failed to get texture mip map level");
             ComPtr::new(ptr)
         };
 
@@ -0,0 +1,77 @@
+//! Minimal test harness, which runs each test and reports the failures.
+//!
+//! Rust's built-in harness runs tests on separate threads, while D3D9 objects
+//! are created for a single window and are not meant to be shared like that.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use winapi::shared::windef::HWND;
+use winapi::um::unknwnbase::IUnknown;
+
+/// A single test, which receives some shared fixture.
+pub struct Test<T: ?Sized> {
+    pub name: &'static str,
+    pub run: fn(&mut T),
+}
+
+/// Shorthand for building a list of tests out of functions.
+macro_rules! tests {
+    ($($name:ident),* $(,)*) => {
+        vec![$($crate::harness::Test {
+            name: stringify!($name),
+            run: $name as fn(&mut _),
+        }),*]
+    };
+}
+
+/// Runs a list of tests, and returns the number of failed tests.
+pub fn run<T: ?Sized>(suite: &str, fixture: &mut T, tests: &[Test<T>]) -> usize {
+    println!("running {} {} tests", tests.len(), suite);
+
+    let mut failed = 0;
+
+    for test in tests {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| (test.run)(fixture)));
+
+        match result {
+            Ok(()) => println!("test {}::{} ... ok", suite, test.name),
+            Err(_) => {
+                println!("test {}::{} ... FAILED", suite, test.name);
+                failed += 1;
+            }
+        }
+    }
+
+    failed
+}
+
+/// Returns a window handle which can be used to create devices,
+/// without having to create and pump the messages of a real window.
+///
+/// The tests run on the mock backend, which never presents to the window,
+/// and they always give the back buffer's size, so the handle is never used.
+pub fn dummy_window() -> HWND {
+    1 as HWND
+}
+
+/// Retrieves the current reference count of a COM object.
+pub fn ref_count<T>(object: &T) -> u32 {
+    unsafe {
+        let unknown = &*(object as *const T as *const IUnknown);
+        unknown.AddRef();
+        unknown.Release()
+    }
+}
+
+/// Checks that a call returned the expected `HRESULT`.
+macro_rules! assert_hr {
+    ($result:expr, $expected:expr, $what:expr) => {{
+        let result = $result;
+        let expected = $expected;
+        assert_eq!(
+            result, expected,
+            "{}: got {:#x}, expected {:#x}",
+            $what, result as u32, expected as u32
+        );
+    }};
+}